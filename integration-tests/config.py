@@ -1,3 +1,6 @@
+import os
+import subprocess
+import tempfile
 from uuid import uuid4
 
 from ic import Principal
@@ -19,7 +22,7 @@ def assert_ok(res):
 def assert_err(res, error=""):
     assert 'Err' in res[0], 'Result not err'
     if error:
-        assert res[0]['Err'] == error, 'Error not match'
+        assert error in res[0]['Err'], 'Error not match'
 
 
 def get_id(container):
@@ -38,15 +41,50 @@ def get_default_principals():
     return [_.sender().to_str() for _ in get_default_identities()]
 
 
-def get_agent():
+def get_agent(identity=0):
     return Agent(
-        get_default_identities()[0], Client(url="http://127.0.0.1:4943")
+        get_default_identities()[identity], Client(url="http://127.0.0.1:4943")
     )
 
 
-def create_safe():
+def create_safe(identity=0):
     return Canister(
-        agent=get_agent(),
+        agent=get_agent(identity),
         canister_id=get_id("blend_safe_backend"),
         candid=open("./src/blend_safe_backend/blend_safe_backend.did").read()
     )
+
+
+def upgrade_safe(env="local"):
+    subprocess.run(
+        ["dfx", "deploy", "blend_safe_backend", "--upgrade-unchanged", "--argument", f'("{env}")'],
+        check=True
+    )
+
+
+BASELINE_REVISION = os.environ.get("BASELINE_REVISION", "fe30988f2f78e39c5984e04718fb86e2e52b3172")
+
+
+def install_baseline_safe(env="local", revision=BASELINE_REVISION):
+    """Build the canister at `revision`, a release without upgrade hooks, and reinstall it with that wasm."""
+    with tempfile.TemporaryDirectory() as directory:
+        subprocess.run(["git", "worktree", "add", "--detach", directory, revision], check=True)
+        try:
+            subprocess.run(
+                ["cargo", "build", "--target", "wasm32-unknown-unknown", "--release", "-p", "blend_safe_backend"],
+                cwd=directory,
+                check=True
+            )
+            wasm = os.path.join(directory, "target", "wasm32-unknown-unknown", "release", "blend_safe_backend.wasm")
+            subprocess.run(
+                ["dfx", "canister", "install", "blend_safe_backend", "--mode", "reinstall", "--yes",
+                 "--wasm", wasm, "--argument", f'("{env}")'],
+                check=True
+            )
+            candid = subprocess.run(
+                ["git", "show", f"{revision}:src/blend_safe_backend/blend_safe_backend.did"],
+                capture_output=True, text=True, check=True
+            ).stdout
+        finally:
+            subprocess.run(["git", "worktree", "remove", "--force", directory], check=True)
+    return Canister(agent=get_agent(), canister_id=get_id("blend_safe_backend"), candid=candid)
@@ -0,0 +1,125 @@
+import os
+
+from config import create_safe, get_wallet_id, assert_ok, assert_err, get_default_principals
+
+UNKNOWN_WALLET = 'unknown_wallet'
+NOT_HEX = 'not hex'
+HASH = '00' * 32
+SIGNATURE = '00' * 65
+
+
+def test_unknown_wallet():
+    safe = create_safe()
+    principal = get_default_principals()[1]
+
+    assert safe.get_wallet(UNKNOWN_WALLET)[0] == []
+    assert not safe.can_sign(UNKNOWN_WALLET, HASH)[0]
+    assert not safe.can_sign_proposal(UNKNOWN_WALLET, 0)[0]
+
+    assert_err(safe.propose(UNKNOWN_WALLET, HASH, []), 'WalletNotFound')
+    assert_err(safe.propose_with_metadata(UNKNOWN_WALLET, HASH, 'metadata'), 'WalletNotFound')
+    assert_err(safe.approve(UNKNOWN_WALLET, HASH), 'WalletNotFound')
+    assert_err(safe.approve_proposal(UNKNOWN_WALLET, 0), 'WalletNotFound')
+    assert_err(safe.revoke_approval(UNKNOWN_WALLET, 0), 'WalletNotFound')
+    assert_err(safe.reject(UNKNOWN_WALLET, 0), 'WalletNotFound')
+    assert_err(safe.sign(UNKNOWN_WALLET, HASH), 'WalletNotFound')
+    assert_err(safe.execute(UNKNOWN_WALLET, 0), 'WalletNotFound')
+    assert_err(safe.cancel_proposal(UNKNOWN_WALLET, 0), 'WalletNotFound')
+    assert_err(safe.eth_address(UNKNOWN_WALLET, []), 'WalletNotFound')
+    assert_err(safe.verify_signature(UNKNOWN_WALLET, HASH, SIGNATURE, []), 'WalletNotFound')
+
+    assert_err(safe.get_proposal(UNKNOWN_WALLET, 0), 'WalletNotFound')
+    assert_err(safe.get_proposals(UNKNOWN_WALLET), 'WalletNotFound')
+    assert_err(safe.get_proposal_history(UNKNOWN_WALLET, [], 10), 'WalletNotFound')
+    assert_err(safe.get_messages_to_sign(UNKNOWN_WALLET), 'WalletNotFound')
+    assert_err(safe.get_proposed_messages(UNKNOWN_WALLET), 'WalletNotFound')
+    assert_err(safe.get_messages_with_signers(UNKNOWN_WALLET), 'WalletNotFound')
+    assert_err(safe.get_messages_with_rejections(UNKNOWN_WALLET), 'WalletNotFound')
+
+    assert_err(safe.add_signer(UNKNOWN_WALLET, principal), 'WalletNotFound')
+    assert_err(safe.remove_signer(UNKNOWN_WALLET, principal), 'WalletNotFound')
+    assert_err(safe.set_threshold(UNKNOWN_WALLET, 1), 'WalletNotFound')
+
+    assert_err(safe.register_abi(UNKNOWN_WALLET, '0x' + '00' * 20, 'name', '[]'), 'WalletNotFound')
+    assert_err(safe.remove_abi(UNKNOWN_WALLET, '0x' + '00' * 20), 'WalletNotFound')
+    assert_err(safe.replace_abi(UNKNOWN_WALLET, '0x' + '00' * 20, 'name', '[]'), 'WalletNotFound')
+    assert_err(safe.get_abis(UNKNOWN_WALLET), 'WalletNotFound')
+    assert_err(safe.get_next_nonce(UNKNOWN_WALLET, 1, []), 'WalletNotFound')
+    assert_err(safe.btc_address(UNKNOWN_WALLET, {'Mainnet': None}, {'P2wpkh': None}, []), 'WalletNotFound')
+    assert_err(safe.propose_btc_transaction(UNKNOWN_WALLET, {'Mainnet': None}, '', []), 'WalletNotFound')
+    assert_err(safe.get_btc_transaction(UNKNOWN_WALLET, 0), 'WalletNotFound')
+    assert_err(safe.taproot_address(UNKNOWN_WALLET, {'Mainnet': None}, []), 'WalletNotFound')
+    assert_err(safe.propose_taproot_transaction(UNKNOWN_WALLET, {'Mainnet': None}, '', []), 'WalletNotFound')
+    assert_err(safe.sol_address(UNKNOWN_WALLET, []), 'WalletNotFound')
+    assert_err(safe.propose_sol_transaction(UNKNOWN_WALLET, '', []), 'WalletNotFound')
+    assert_err(safe.get_sol_transaction(UNKNOWN_WALLET, 0), 'WalletNotFound')
+    assert_err(safe.resync_nonce(UNKNOWN_WALLET, 1, 0, []), 'WalletNotFound')
+    assert_err(safe.add_account(UNKNOWN_WALLET, 'ops'), 'WalletNotFound')
+    assert_err(safe.get_accounts(UNKNOWN_WALLET), 'WalletNotFound')
+    assert_err(safe.propose_key_migration(UNKNOWN_WALLET, 'key_1'), 'WalletNotFound')
+    assert_err(safe.get_key_info(UNKNOWN_WALLET), 'WalletNotFound')
+
+    assert_err(safe.add_metadata(UNKNOWN_WALLET, HASH, 'metadata'), 'WalletNotFound')
+    assert_err(safe.add_proposal_metadata(UNKNOWN_WALLET, 0, 'metadata'), 'WalletNotFound')
+    assert_err(safe.get_proposal_metadata(UNKNOWN_WALLET, 0), 'WalletNotFound')
+    assert_err(safe.get_metadata(UNKNOWN_WALLET, HASH), 'WalletNotFound')
+
+
+def test_malformed_hex():
+    wallet_id = get_wallet_id()
+    safe = create_safe()
+    assert_ok(safe.create_wallet(wallet_id, get_default_principals(), 1))
+
+    assert not safe.can_sign(wallet_id, NOT_HEX)[0]
+    assert_err(safe.propose(wallet_id, NOT_HEX, []), 'DecodeError')
+    assert_err(safe.propose_with_metadata(wallet_id, NOT_HEX, 'metadata'), 'DecodeError')
+    assert_err(safe.approve(wallet_id, NOT_HEX), 'DecodeError')
+    assert_err(safe.sign(wallet_id, NOT_HEX), 'DecodeError')
+    assert_err(safe.add_metadata(wallet_id, NOT_HEX, 'metadata'), 'DecodeError')
+    assert_err(safe.get_metadata(wallet_id, NOT_HEX), 'DecodeError')
+    assert_err(safe.get_metadata(wallet_id, HASH), 'MetadataNotFound')
+    assert_err(safe.verify_signature(wallet_id, NOT_HEX, SIGNATURE, []), 'DecodeError')
+    assert_err(safe.verify_signature(wallet_id, HASH, NOT_HEX, []), 'DecodeError')
+
+
+def test_wrong_length_hash():
+    wallet_id = get_wallet_id()
+    safe = create_safe()
+    assert_ok(safe.create_wallet(wallet_id, get_default_principals(), 1))
+
+    for msg in ['', '00', os.urandom(31).hex(), os.urandom(33).hex()]:
+        assert_err(safe.propose(wallet_id, msg, []), 'InvalidHashLength')
+        assert_err(safe.propose_with_metadata(wallet_id, msg, 'metadata'), 'InvalidHashLength')
+        assert_err(safe.verify_signature(wallet_id, msg, SIGNATURE, []), 'InvalidHashLength')
+
+
+def test_short_signature():
+    wallet_id = get_wallet_id()
+    safe = create_safe()
+    assert_ok(safe.create_wallet(wallet_id, get_default_principals(), 1))
+
+    for signature in ['', '00' * 64, '00' * 66]:
+        assert_err(safe.verify_signature(wallet_id, HASH, signature, []), 'InvalidSignatureLength')
+
+    # verification needs the public key, which is cached once it has been derived
+    assert_err(safe.verify_signature(wallet_id, HASH, SIGNATURE, []), 'PublicKeyNotCached')
+    assert_err(safe.verify_personal_signature(wallet_id, 'message', SIGNATURE, []), 'PublicKeyNotCached')
+    assert 'Ok' in safe.eth_address(wallet_id, [])[0]
+
+    # a well formed signature that does not verify is not an error
+    assert safe.verify_signature(wallet_id, HASH, SIGNATURE, [])[0]['Ok'] is False
+
+
+def test_unknown_proposal():
+    wallet_id = get_wallet_id()
+    safe = create_safe()
+    assert_ok(safe.create_wallet(wallet_id, get_default_principals(), 1))
+
+    assert_err(safe.get_proposal(wallet_id, 42), 'ProposalNotFound')
+    assert_err(safe.approve_proposal(wallet_id, 42), 'ProposalNotFound')
+    assert_err(safe.reject(wallet_id, 42), 'ProposalNotFound')
+    assert_err(safe.revoke_approval(wallet_id, 42), 'ProposalNotFound')
+    assert_err(safe.cancel_proposal(wallet_id, 42), 'ProposalNotFound')
+    assert_err(safe.execute(wallet_id, 42), 'CannotExecute')
+    assert_err(safe.approve(wallet_id, HASH), 'ProposalNotFound')
+    assert_err(safe.sign(wallet_id, HASH), 'ProposalNotFound')
@@ -0,0 +1,57 @@
+from config import create_safe, get_wallet_id, assert_ok, get_default_principals, upgrade_safe, install_baseline_safe
+
+
+def test_wallets_survive_upgrade():
+    safe = create_safe()
+    principals = get_default_principals()
+
+    wallet_ids = [get_wallet_id(), get_wallet_id()]
+    assert_ok(safe.create_wallet(wallet_ids[0], principals, 1))
+    assert_ok(safe.create_wallet(wallet_ids[1], principals[:2], 2))
+
+    msg = "00" * 32
+    assert_ok(safe.propose(wallet_ids[1], msg, []))
+    assert_ok(safe.approve(wallet_ids[1], msg))
+
+    wallets_before = [safe.get_wallet(wallet_id)[0] for wallet_id in wallet_ids]
+    key_info_before = [safe.get_key_info(wallet_id)[0] for wallet_id in wallet_ids]
+    default_key_before = safe.get_default_key_id()[0]
+    principal_wallets_before = [sorted(safe.get_wallets_for_principal(p)[0]) for p in principals]
+
+    upgrade_safe()
+
+    safe = create_safe()
+    wallets_after = [safe.get_wallet(wallet_id)[0] for wallet_id in wallet_ids]
+    principal_wallets_after = [sorted(safe.get_wallets_for_principal(p)[0]) for p in principals]
+
+    assert wallets_after == wallets_before
+    assert [safe.get_key_info(wallet_id)[0] for wallet_id in wallet_ids] == key_info_before
+    assert safe.get_default_key_id()[0] == default_key_before
+    assert principal_wallets_after == principal_wallets_before
+    for wallet_id in wallet_ids:
+        assert wallet_id in principal_wallets_after[0]
+    assert wallet_ids[1] not in principal_wallets_after[2]
+
+    result = safe.get_messages_with_signers(wallet_ids[1])
+    assert_ok(result)
+    assert result[0]['Ok'][0][0] == msg
+
+
+def test_upgrade_from_baseline():
+    baseline = install_baseline_safe()
+    principals = get_default_principals()
+    assert_ok(baseline.create_wallet(get_wallet_id(), principals, 1))
+
+    # the baseline keeps no snapshot in stable memory, so the upgrade starts from an empty state
+    upgrade_safe()
+
+    safe = create_safe()
+    wallet_id = get_wallet_id()
+    assert_ok(safe.create_wallet(wallet_id, principals, 1))
+    assert safe.get_wallet(wallet_id)[0][0]['threshold'] == 1
+    assert wallet_id in safe.get_wallets_for_principal(principals[0])[0]
+
+    upgrade_safe()
+
+    safe = create_safe()
+    assert safe.get_wallet(wallet_id)[0][0]['threshold'] == 1
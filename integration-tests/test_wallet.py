@@ -21,7 +21,7 @@ def test_wallet_lifecycle():
 
     for p in wallet['signers']:
         assert p.to_str() in principals
-    assert len(wallet['message_queue']) == 0
+    assert len(wallet['proposals']) == 0
     assert wallet['threshold'] == 1
 
 
@@ -41,11 +41,11 @@ def test_signing_lifecycle():
     safe = create_safe()
     assert_ok(safe.create_wallet(wallet_id, get_default_principals(), 1))
 
-    eth_address = safe.eth_address(wallet_id)[0]['Ok']
+    eth_address = safe.eth_address(wallet_id, [])[0]['Ok']
 
     challenge = os.urandom(32)
     challenge_enc = challenge.hex()
-    safe.propose(wallet_id, challenge_enc)
+    safe.propose(wallet_id, challenge_enc, [])
     safe.approve(wallet_id, challenge_enc)
 
     signature_enc = safe.sign(wallet_id, challenge_enc)[0]['Ok']
@@ -57,10 +57,10 @@ def test_signing_lifecycle():
     keccak_hash = Web3.keccak(uncompressed_pubkey[1:])
 
     rec_address = keccak_hash[-20:].hex()
-    assert rec_address == eth_address
+    assert Web3.to_checksum_address(rec_address) == eth_address
 
     # check in canister
-    valid = safe.verify_signature(wallet_id, challenge_enc, signature_enc)
+    valid = safe.verify_signature(wallet_id, challenge_enc, signature_enc, [])
     assert valid[0]['Ok']
 
     # check if the message and its metadata have been removed
@@ -80,14 +80,14 @@ def test_add_remove_signer():
 
     # Create wallet with initial signers and threshold
     assert_ok(safe.create_wallet(wallet_id, principals, 1))
-    add_msg = safe.add_signer(wallet_id, new_signer)[0]['Ok']
+    add_id = safe.add_signer(wallet_id, new_signer)[0]['Ok']
 
     # Initially, new signer should not be in the wallet
     wallet = safe.get_wallet(wallet_id)[0][0]
     assert new_signer not in [p.to_str() for p in wallet['signers']]
 
-    assert_ok(safe.approve(wallet_id, add_msg))
-    assert_ok(safe.sign(wallet_id, add_msg))
+    assert_ok(safe.approve_proposal(wallet_id, add_id))
+    assert_ok(safe.execute(wallet_id, add_id))
 
     # After approvals, new signer should be in the wallet
     wallet = safe.get_wallet(wallet_id)[0][0]
@@ -96,10 +96,10 @@ def test_add_remove_signer():
 
     # Now, propose to remove a signer
     signer_to_remove = new_signer
-    remove_msg = safe.remove_signer(wallet_id, signer_to_remove)[0]['Ok']
+    remove_id = safe.remove_signer(wallet_id, signer_to_remove)[0]['Ok']
 
-    assert_ok(safe.approve(wallet_id, remove_msg))
-    assert_ok(safe.sign(wallet_id, remove_msg))
+    assert_ok(safe.approve_proposal(wallet_id, remove_id))
+    assert_ok(safe.execute(wallet_id, remove_id))
 
     # After approvals, removed signer should not be in the wallet
     wallet = safe.get_wallet(wallet_id)[0][0]
@@ -122,10 +122,10 @@ def test_change_threshold():
 
     # Propose to change threshold to 2
     new_threshold = 2
-    threshold_msg = safe.set_threshold(wallet_id, new_threshold)[0]['Ok']
+    threshold_id = safe.set_threshold(wallet_id, new_threshold)[0]['Ok']
 
-    assert_ok(safe.approve(wallet_id, threshold_msg))
-    assert_ok(safe.sign(wallet_id, threshold_msg))
+    assert_ok(safe.approve_proposal(wallet_id, threshold_id))
+    assert_ok(safe.execute(wallet_id, threshold_id))
 
     # Verify the new threshold is set
     wallet = safe.get_wallet(wallet_id)[0][0]
@@ -147,18 +147,18 @@ def test_principal_wallets_map_update():
         assert wallet_id in wallets_for_principal
 
     # Add a new signer to the wallet
-    add_msg = safe.add_signer(wallet_id, new_signer)[0]['Ok']
-    assert_ok(safe.approve(wallet_id, add_msg))
-    assert_ok(safe.sign(wallet_id, add_msg))
+    add_id = safe.add_signer(wallet_id, new_signer)[0]['Ok']
+    assert_ok(safe.approve_proposal(wallet_id, add_id))
+    assert_ok(safe.execute(wallet_id, add_id))
 
     # Check if the wallet is now associated with the new signer
     wallets_for_new_signer = safe.get_wallets_for_principal(new_signer)[0]
     assert wallet_id in wallets_for_new_signer
 
     # Remove the new signer from the wallet
-    remove_msg = safe.remove_signer(wallet_id, new_signer)[0]['Ok']
-    assert_ok(safe.approve(wallet_id, remove_msg))
-    assert_ok(safe.sign(wallet_id, remove_msg))
+    remove_id = safe.remove_signer(wallet_id, new_signer)[0]['Ok']
+    assert_ok(safe.approve_proposal(wallet_id, remove_id))
+    assert_ok(safe.execute(wallet_id, remove_id))
 
     # Check if the wallet is no longer associated with the removed signer
     wallets_for_removed_signer = safe.get_wallets_for_principal(new_signer)[0]
@@ -173,7 +173,7 @@ def test_metadata_lifecycle():
     msg = os.urandom(32).hex()
     metadata = "test metadata"
 
-    safe.propose(wallet_id, msg)
+    safe.propose(wallet_id, msg, [])
 
     # Add metadata to a message
     assert_ok(safe.add_metadata(wallet_id, msg, metadata))
@@ -184,7 +184,7 @@ def test_metadata_lifecycle():
 
     # Try to add metadata again to the same message
     result = safe.add_metadata(wallet_id, msg, "new metadata")
-    assert result[0]['Err'] == "Metadata already exists for this message"
+    assert_err(result, 'MetadataAlreadyExists')
 
 
 def test_propose_with_metadata():
@@ -201,3 +201,753 @@ def test_propose_with_metadata():
     # Get metadata for the message
     result = safe.get_metadata(wallet_id, msg)
     assert result[0]['Ok'] == metadata
+
+    # a failed call leaves no proposal behind
+    outsider_wallet_id = get_wallet_id()
+    assert_ok(safe.create_wallet(outsider_wallet_id, get_default_principals()[1:], 1))
+    assert_err(safe.propose_with_metadata(outsider_wallet_id, msg, metadata), 'NotASigner')
+    assert safe.get_wallet(outsider_wallet_id)[0][0]['proposals'] == []
+
+
+def test_same_payload_proposed_twice():
+    wallet_id = get_wallet_id()
+    safe = create_safe()
+    assert_ok(safe.create_wallet(wallet_id, get_default_principals(), 1))
+
+    msg = os.urandom(32).hex()
+    first_id = safe.propose(wallet_id, msg, [])[0]['Ok']
+    second_id = safe.propose(wallet_id, msg, [])[0]['Ok']
+    assert first_id != second_id
+
+    assert_ok(safe.approve_proposal(wallet_id, second_id))
+    assert safe.can_sign_proposal(wallet_id, second_id)[0]
+    assert not safe.can_sign_proposal(wallet_id, first_id)[0]
+
+    signature = safe.execute(wallet_id, second_id)[0]['Ok']
+    assert len(bytes.fromhex(signature)) == 65
+
+    proposal = safe.get_proposal(wallet_id, first_id)[0]['Ok']
+    assert bytes(proposal['payload']).hex() == msg
+    assert 'Open' in proposal['status']
+    proposal = safe.get_proposal(wallet_id, second_id)[0]['Ok']
+    assert 'Executed' in proposal['status']
+
+
+def test_proposal_history():
+    wallet_id = get_wallet_id()
+    safe = create_safe()
+    principals = get_default_principals()
+    assert_ok(safe.create_wallet(wallet_id, principals, 1))
+
+    msg = os.urandom(32).hex()
+    signed_id = safe.propose(wallet_id, msg, [])[0]['Ok']
+    assert_ok(safe.approve_proposal(wallet_id, signed_id))
+    signature = safe.execute(wallet_id, signed_id)[0]['Ok']
+
+    cancelled_id = safe.propose(wallet_id, msg, [])[0]['Ok']
+    assert_ok(safe.cancel_proposal(wallet_id, cancelled_id))
+    assert_err(safe.cancel_proposal(wallet_id, cancelled_id), 'ProposalNotPending')
+
+    page = safe.get_proposal_history(wallet_id, [], 1)[0]['Ok']
+    assert len(page['items']) == 1
+    assert page['items'][0]['id'] == cancelled_id
+    assert 'Cancelled' in page['items'][0]['status']
+
+    page = safe.get_proposal_history(wallet_id, page['next_cursor'], 1)[0]['Ok']
+    executed = page['items'][0]
+    assert executed['id'] == signed_id
+    assert 'Executed' in executed['status']
+    assert executed['signature'] == [signature]
+    assert executed['payload'] == msg
+    assert executed['approvals'][0].to_str() == principals[0]
+
+    # the wallet itself only carries the pending proposals
+    pending_id = safe.propose(wallet_id, msg, [])[0]['Ok']
+    wallet = safe.get_wallet(wallet_id)[0][0]
+    assert [proposal[0] for proposal in wallet['proposals']] == [pending_id]
+
+
+def test_reject_proposal():
+    wallet_id = get_wallet_id()
+    safe = create_safe()
+    other_safe = create_safe(identity=1)
+    assert_ok(safe.create_wallet(wallet_id, get_default_principals(), 2))
+
+    msg = os.urandom(32).hex()
+    proposal_id = safe.propose(wallet_id, msg, [])[0]['Ok']
+
+    # two of three signers can still approve
+    assert other_safe.reject(wallet_id, proposal_id)[0]['Ok'] == 1
+    assert_err(other_safe.reject(wallet_id, proposal_id), 'AlreadyRejected')
+
+    rejections = safe.get_messages_with_rejections(wallet_id)[0]['Ok']
+    assert rejections[0][0] == msg
+    assert rejections[0][1][0].to_str() == get_default_principals()[1]
+
+    # now only one signer is left to approve
+    assert safe.reject(wallet_id, proposal_id)[0]['Ok'] == 2
+    proposal = safe.get_proposal(wallet_id, proposal_id)[0]['Ok']
+    assert 'Rejected' in proposal['status']
+    assert len(proposal['rejections']) == 2
+    assert_err(safe.approve_proposal(wallet_id, proposal_id), 'ProposalNotPending')
+
+
+def test_rejected_config_change_executes():
+    wallet_id = get_wallet_id()
+    safe = create_safe()
+    other_safe = create_safe(identity=1)
+    assert_ok(safe.create_wallet(wallet_id, get_default_principals(), 1))
+
+    # a rejection that the new threshold makes decisive does not reject the change itself
+    proposal_id = safe.set_threshold(wallet_id, 3)[0]['Ok']
+    assert_ok(safe.approve_proposal(wallet_id, proposal_id))
+    assert_ok(other_safe.reject(wallet_id, proposal_id))
+    assert_ok(safe.execute(wallet_id, proposal_id))
+
+    assert 'Executed' in safe.get_proposal(wallet_id, proposal_id)[0]['Ok']['status']
+    assert safe.get_wallet(wallet_id)[0][0]['threshold'] == 3
+
+
+def test_repeated_approval():
+    wallet_id = get_wallet_id()
+    safe = create_safe()
+    assert_ok(safe.create_wallet(wallet_id, get_default_principals(), 2))
+
+    proposal_id = safe.propose(wallet_id, os.urandom(32).hex(), [])[0]['Ok']
+    assert safe.approve_proposal(wallet_id, proposal_id)[0]['Ok'] == 1
+    assert_err(safe.approve_proposal(wallet_id, proposal_id), 'AlreadyApproved')
+    assert not safe.can_sign_proposal(wallet_id, proposal_id)[0]
+    assert_err(safe.execute(wallet_id, proposal_id), 'CannotExecute')
+
+    assert safe.revoke_approval(wallet_id, proposal_id)[0]['Ok'] == 0
+    assert_err(safe.revoke_approval(wallet_id, proposal_id), 'NotApproved')
+
+
+def test_invalid_wallet_config():
+    safe = create_safe()
+    principals = get_default_principals()[:2]
+
+    assert_err(safe.create_wallet(get_wallet_id(), [], 1), 'NoSigners')
+    assert_err(safe.create_wallet(get_wallet_id(), principals, 0), 'InvalidThreshold')
+    assert_err(safe.create_wallet(get_wallet_id(), principals, 3), 'NotEnoughSigners')
+
+    # removing a signer must not leave fewer signers than the threshold
+    wallet_id = get_wallet_id()
+    assert_ok(safe.create_wallet(wallet_id, principals, 2))
+    assert_err(safe.remove_signer(wallet_id, principals[1]), 'NotEnoughSigners')
+    assert_err(safe.set_threshold(wallet_id, 0), 'InvalidThreshold')
+
+
+def test_eth_transaction():
+    wallet_id = get_wallet_id()
+    safe = create_safe()
+    assert_ok(safe.create_wallet(wallet_id, get_default_principals(), 1))
+    eth_address = safe.eth_address(wallet_id, [])[0]['Ok']
+
+    tx = {
+        'chain_id': 1,
+        'nonce': 0,
+        'to': ['0x' + '35' * 20],
+        'value': 10 ** 18,
+        'data': '0x',
+        'gas_limit': 21000,
+        'max_fee': 30 * 10 ** 9,
+        'max_priority_fee': 10 ** 9,
+        'access_list': [],
+    }
+    proposal_id = safe.propose_eth_transaction(wallet_id, tx, [])[0]['Ok']
+    proposal = safe.get_proposal(wallet_id, proposal_id)[0]['Ok']
+    assert 'Eip1559Transaction' in proposal['kind']
+
+    assert_ok(safe.approve_proposal(wallet_id, proposal_id))
+    raw_tx = safe.execute(wallet_id, proposal_id)[0]['Ok']
+    assert raw_tx.startswith('0x02')
+
+    # the signed transaction recovers to the wallet's address
+    sender = Web3().eth.account.recover_transaction(raw_tx)
+    # the canister returns EIP-55 checksummed addresses
+    assert sender == eth_address
+
+    tx['to'] = ['0x1234']
+    assert_err(safe.propose_eth_transaction(wallet_id, tx, []), 'InvalidTransaction')
+
+    # mixed case addresses must carry a valid EIP-55 checksum
+    checksummed = Web3.to_checksum_address('0x' + '7a' * 20)
+    tx['to'] = [checksummed.swapcase().replace('0X', '0x')]
+    assert_err(safe.propose_eth_transaction(wallet_id, tx, []), 'InvalidTransaction')
+    tx['to'] = [checksummed]
+    assert 'Ok' in safe.propose_eth_transaction(wallet_id, tx, [])[0]
+
+
+def test_decoded_call():
+    wallet_id = get_wallet_id()
+    safe = create_safe()
+    assert_ok(safe.create_wallet(wallet_id, get_default_principals(), 1))
+
+    token = '0x' + '6b' * 20
+    recipient = '35' * 20
+    tx = {
+        'chain_id': 1,
+        'nonce': 0,
+        'to': [token],
+        'value': 0,
+        # transfer(recipient, 1 token)
+        'data': '0xa9059cbb' + recipient.rjust(64, '0') + hex(10 ** 18)[2:].rjust(64, '0'),
+        'gas_limit': 60000,
+        'max_fee': 30 * 10 ** 9,
+        'max_priority_fee': 10 ** 9,
+        'access_list': [],
+    }
+    transfer_id = safe.propose_eth_transaction(wallet_id, tx, [])[0]['Ok']
+    decoded = safe.get_decoded_call(wallet_id, transfer_id)[0]['Ok'][0]
+    assert decoded['contract'] == Web3.to_checksum_address(token)
+    assert decoded['standard'] == 'ERC-20'
+    assert decoded['function'] == 'transfer(address,uint256)'
+    assert [(arg['name'], arg['value']) for arg in decoded['arguments']] == [
+        ('to', '0x' + recipient), ('amount', str(10 ** 18))
+    ]
+
+    assert not decoded['unknown_selector']
+
+    tx['data'] = '0xdeadbeef'
+    unknown_id = safe.propose_eth_transaction(wallet_id, tx, [])[0]['Ok']
+    assert safe.get_decoded_call(wallet_id, unknown_id)[0]['Ok'][0]['unknown_selector']
+
+    tx['data'] = '0x'
+    plain_transfer_id = safe.propose_eth_transaction(wallet_id, tx, [])[0]['Ok']
+    assert safe.get_decoded_call(wallet_id, plain_transfer_id)[0]['Ok'] == []
+
+    proposals = safe.get_proposals_with_calls(wallet_id)[0]['Ok']
+    assert [len(item['decoded_call']) for item in proposals] == [1, 1, 0]
+
+
+def test_abi_registry():
+    wallet_id = get_wallet_id()
+    safe = create_safe()
+    assert_ok(safe.create_wallet(wallet_id, get_default_principals(), 1))
+
+    vault = '0x' + '7a' * 20
+    abi = '[{"type": "function", "name": "deposit", "inputs": [{"name": "assets", "type": "uint256"}]}]'
+    register_id = safe.register_abi(wallet_id, vault.upper().replace('0X', '0x'), 'Vault', abi)[0]['Ok']
+    # nothing is registered until the proposal is executed
+    assert safe.get_abis(wallet_id)[0]['Ok'] == []
+    assert_ok(safe.approve_proposal(wallet_id, register_id))
+    assert_ok(safe.execute(wallet_id, register_id))
+    registered = safe.get_abis(wallet_id)[0]['Ok']
+    assert [(item['address'], item['name']) for item in registered] == [(Web3.to_checksum_address(vault), 'Vault')]
+    assert registered[0]['functions'][0]['signature'] == 'deposit(uint256)'
+
+    tx = {
+        'chain_id': 1,
+        'nonce': 0,
+        'to': [vault],
+        'value': 0,
+        # deposit(100)
+        'data': '0xb6b55f25' + hex(100)[2:].rjust(64, '0'),
+        'gas_limit': 100000,
+        'max_fee': 30 * 10 ** 9,
+        'max_priority_fee': 10 ** 9,
+        'access_list': [],
+    }
+    proposal_id = safe.propose_eth_transaction(wallet_id, tx, [])[0]['Ok']
+    decoded = safe.get_decoded_call(wallet_id, proposal_id)[0]['Ok'][0]
+    assert decoded['standard'] == 'Vault'
+    assert decoded['function'] == 'deposit(uint256)'
+    assert [(arg['name'], arg['value']) for arg in decoded['arguments']] == [('assets', '100')]
+
+    # a registered ABI is only replaced or removed by a proposal
+    assert_err(safe.register_abi(wallet_id, vault, 'Token', abi), 'AbiAlreadyRegistered')
+    replace_id = safe.replace_abi(wallet_id, vault, 'Token', abi)[0]['Ok']
+    assert safe.get_decoded_call(wallet_id, proposal_id)[0]['Ok'][0]['standard'] == 'Vault'
+    assert_ok(safe.approve_proposal(wallet_id, replace_id))
+    assert_ok(safe.execute(wallet_id, replace_id))
+    assert safe.get_decoded_call(wallet_id, proposal_id)[0]['Ok'][0]['standard'] == 'Token'
+
+    remove_id = safe.remove_abi(wallet_id, vault)[0]['Ok']
+    assert_ok(safe.approve_proposal(wallet_id, remove_id))
+    assert_ok(safe.execute(wallet_id, remove_id))
+    assert safe.get_decoded_call(wallet_id, proposal_id)[0]['Ok'][0]['unknown_selector']
+    assert_err(safe.remove_abi(wallet_id, vault), 'AbiNotFound')
+
+    # built-in functions are decoded as such whatever the registered ABI says
+    transfer = '[{"type": "function", "name": "transfer", "inputs": [{"name": "shares", "type": "address"}, {"name": "assets", "type": "uint256"}]}]'
+    register_id = safe.register_abi(wallet_id, vault, 'Vault', transfer)[0]['Ok']
+    assert_ok(safe.approve_proposal(wallet_id, register_id))
+    assert_ok(safe.execute(wallet_id, register_id))
+    transfer_id = safe.propose_eth_transaction(wallet_id, {**tx, 'data': '0xa9059cbb' + '00' * 12 + '11' * 20 + hex(100)[2:].rjust(64, '0')}, [])[0]['Ok']
+    assert safe.get_decoded_call(wallet_id, transfer_id)[0]['Ok'][0]['standard'] == 'ERC-20'
+    assert_err(safe.replace_abi(wallet_id, vault, 'Vault', '[' + ' ' * 64 * 1024 + ']'), 'InvalidAbi')
+
+    assert_err(safe.register_abi(wallet_id, '0x1234', 'Vault', abi), 'InvalidAddress')
+    wrong_checksum = Web3.to_checksum_address(vault).swapcase().replace('0X', '0x')
+    assert_err(safe.register_abi(wallet_id, wrong_checksum, 'Vault', abi), 'InvalidAddress')
+    assert_err(safe.register_abi(wallet_id, vault, 'Vault', 'not json'), 'InvalidAbi')
+
+
+def test_nonce_tracking():
+    wallet_id = get_wallet_id()
+    safe = create_safe()
+    assert_ok(safe.create_wallet(wallet_id, get_default_principals(), 1))
+
+    tx = {
+        'chain_id': 1,
+        'nonce': 42,
+        'to': ['0x' + '35' * 20],
+        'value': 10 ** 18,
+        'data': '0x',
+        'gas_limit': 21000,
+        'max_fee': 30 * 10 ** 9,
+        'max_priority_fee': 10 ** 9,
+        'access_list': [],
+    }
+    assert safe.get_next_nonce(wallet_id, 1, [])[0]['Ok'] == 0
+
+    # concurrent proposals are assigned consecutive nonces, whatever nonce they were proposed with
+    first = safe.propose_eth_transaction(wallet_id, tx, [])[0]['Ok']
+    second = safe.propose_eth_transaction(wallet_id, tx, [])[0]['Ok']
+    nonces = [safe.get_proposal(wallet_id, id)[0]['Ok']['kind']['Eip1559Transaction']['nonce'] for id in [first, second]]
+    assert nonces == [0, 1]
+    assert safe.get_next_nonce(wallet_id, 1, [])[0]['Ok'] == 2
+    assert safe.get_next_nonce(wallet_id, 137, [])[0]['Ok'] == 0
+
+    # a cancelled proposal releases its nonce
+    assert_ok(safe.cancel_proposal(wallet_id, second))
+    assert safe.get_next_nonce(wallet_id, 1, [])[0]['Ok'] == 1
+
+    # execution advances the nonce
+    assert_ok(safe.approve_proposal(wallet_id, first))
+    safe.execute(wallet_id, first)
+    assert safe.get_next_nonce(wallet_id, 1, [])[0]['Ok'] == 1
+
+    # resyncing needs the threshold of approvals like other configuration changes
+    resync_id = safe.resync_nonce(wallet_id, 1, 10, [])[0]['Ok']
+    assert safe.get_next_nonce(wallet_id, 1, [])[0]['Ok'] == 1
+    assert_ok(safe.approve_proposal(wallet_id, resync_id))
+    assert_ok(safe.execute(wallet_id, resync_id))
+    assert safe.get_next_nonce(wallet_id, 1, [])[0]['Ok'] == 10
+
+
+def test_accounts():
+    wallet_id = get_wallet_id()
+    safe = create_safe()
+    assert_ok(safe.create_wallet(wallet_id, get_default_principals(), 1))
+    assert safe.get_accounts(wallet_id)[0]['Ok'] == [{'index': 0, 'name': 'default'}]
+
+    assert safe.add_account(wallet_id, 'ops')[0]['Ok'] == 1
+    assert_err(safe.add_account(wallet_id, 'ops'), 'AccountAlreadyExists')
+    assert_err(safe.add_account(wallet_id, ' '), 'InvalidAccountName')
+    assert [a['name'] for a in safe.get_accounts(wallet_id)[0]['Ok']] == ['default', 'ops']
+
+    # every account has its own key, selected by index or name
+    default_address = safe.eth_address(wallet_id, [])[0]['Ok']
+    ops_address = safe.eth_address(wallet_id, [{'Name': 'ops'}])[0]['Ok']
+    assert safe.eth_address(wallet_id, [{'Index': 0}])[0]['Ok'] == default_address
+    assert safe.eth_address(wallet_id, [{'Index': 1}])[0]['Ok'] == ops_address
+    assert ops_address != default_address
+    assert_err(safe.eth_address(wallet_id, [{'Name': 'cold'}]), 'AccountNotFound')
+    assert_err(safe.eth_address(wallet_id, [{'Index': 2}]), 'AccountNotFound')
+
+    # a proposal is signed by the account it was proposed for
+    message = os.urandom(32)
+    proposal_id = safe.propose(wallet_id, message.hex(), [{'Name': 'ops'}])[0]['Ok']
+    assert safe.get_proposal(wallet_id, proposal_id)[0]['Ok']['account'] == [1]
+    assert_ok(safe.approve_proposal(wallet_id, proposal_id))
+    signature = bytes.fromhex(safe.execute(wallet_id, proposal_id)[0]['Ok'])
+    public_key = PublicKey.from_signature_and_message(signature, message, hasher=None)
+    rec_address = Web3.keccak(public_key.format(compressed=False)[1:])[-20:].hex()
+    assert Web3.to_checksum_address(rec_address) == ops_address
+    assert safe.verify_signature(wallet_id, message.hex(), signature.hex(), [{'Name': 'ops'}])[0]['Ok']
+    assert not safe.verify_signature(wallet_id, message.hex(), signature.hex(), [])[0]['Ok']
+
+    # nonces are tracked per account
+    tx = {
+        'chain_id': 1,
+        'nonce': 0,
+        'to': ['0x' + '35' * 20],
+        'value': 10 ** 18,
+        'data': '0x',
+        'gas_limit': 21000,
+        'max_fee': 30 * 10 ** 9,
+        'max_priority_fee': 10 ** 9,
+        'access_list': [],
+    }
+    proposal_id = safe.propose_eth_transaction(wallet_id, tx, [{'Name': 'ops'}])[0]['Ok']
+    assert safe.get_next_nonce(wallet_id, 1, [{'Name': 'ops'}])[0]['Ok'] == 1
+    assert safe.get_next_nonce(wallet_id, 1, [])[0]['Ok'] == 0
+    assert_ok(safe.approve_proposal(wallet_id, proposal_id))
+    raw_tx = safe.execute(wallet_id, proposal_id)[0]['Ok']
+    assert Web3().eth.account.recover_transaction(raw_tx) == ops_address
+
+
+def test_key_migration():
+    wallet_id = get_wallet_id()
+    safe = create_safe()
+    assert_ok(safe.create_wallet(wallet_id, get_default_principals(), 1))
+
+    old_key = safe.get_default_key_id()[0]
+    assert safe.get_key_info(wallet_id)[0]['Ok'] == {'key_name': old_key, 'migrations': []}
+    assert_err(safe.set_default_key_id('key_1'), 'NotAController')
+
+    assert_err(safe.propose_key_migration(wallet_id, old_key), 'KeyAlreadyInUse')
+    assert_err(safe.propose_key_migration(wallet_id, ' '), 'InvalidKeyName')
+
+    old_address = safe.eth_address(wallet_id, [])[0]['Ok']
+    payload_id = safe.propose(wallet_id, os.urandom(32).hex(), [])[0]['Ok']
+    migration_id = safe.propose_key_migration(wallet_id, 'test_key_1')[0]['Ok']
+    assert safe.get_proposal(wallet_id, migration_id)[0]['Ok']['kind'] == {'MigrateKey': 'test_key_1'}
+    assert_ok(safe.approve_proposal(wallet_id, migration_id))
+    new_address = safe.execute(wallet_id, migration_id)[0]['Ok']
+    assert new_address != old_address
+    assert safe.eth_address(wallet_id, [])[0]['Ok'] == new_address
+
+    migration = safe.get_key_info(wallet_id)[0]['Ok']['migrations'][0]
+    assert (migration['from_key'], migration['to_key']) == (old_key, 'test_key_1')
+    assert (migration['from_address'], migration['to_address']) == ([old_address], new_address)
+
+    # payloads proposed for the old key are never signed
+    assert 'Rejected' in safe.get_proposal(wallet_id, payload_id)[0]['Ok']['status']
+    assert_err(safe.propose_key_migration(wallet_id, old_key), 'KeyRetired')
+
+    # new proposals are signed with the new key
+    message = os.urandom(32)
+    proposal_id = safe.propose(wallet_id, message.hex(), [])[0]['Ok']
+    assert_ok(safe.approve_proposal(wallet_id, proposal_id))
+    signature = bytes.fromhex(safe.execute(wallet_id, proposal_id)[0]['Ok'])
+    public_key = PublicKey.from_signature_and_message(signature, message, hasher=None)
+    rec_address = Web3.keccak(public_key.format(compressed=False)[1:])[-20:].hex()
+    assert Web3.to_checksum_address(rec_address) == new_address
+
+
+def test_legacy_and_eip2930_transactions():
+    wallet_id = get_wallet_id()
+    safe = create_safe()
+    assert_ok(safe.create_wallet(wallet_id, get_default_principals(), 1))
+    eth_address = safe.eth_address(wallet_id, [])[0]['Ok']
+
+    tx = {
+        'chain_id': 137,
+        'nonce': 0,
+        'gas_price': 30 * 10 ** 9,
+        'gas_limit': 21000,
+        'to': ['0x' + '35' * 20],
+        'value': 10 ** 18,
+        'data': '0x',
+    }
+    legacy_id = safe.propose_legacy_transaction(wallet_id, tx, [])[0]['Ok']
+    access_list_id = safe.propose_eip2930_transaction(wallet_id, {**tx, 'access_list': []}, [])[0]['Ok']
+
+    for proposal_id, prefix in [(legacy_id, '0xf8'), (access_list_id, '0x01')]:
+        assert_ok(safe.approve_proposal(wallet_id, proposal_id))
+        raw_tx = safe.execute(wallet_id, proposal_id)[0]['Ok']
+        assert raw_tx.startswith(prefix)
+        assert Web3().eth.account.recover_transaction(raw_tx) == eth_address
+
+
+def test_personal_message():
+    from eth_account.messages import encode_defunct
+
+    wallet_id = get_wallet_id()
+    safe = create_safe()
+    assert_ok(safe.create_wallet(wallet_id, get_default_principals(), 1))
+    eth_address = safe.eth_address(wallet_id, [])[0]['Ok']
+
+    message = 'Sign in to example.com\nNonce: ' + os.urandom(8).hex()
+    proposal_id = safe.propose_personal_message(wallet_id, message, [])[0]['Ok']
+    proposal = safe.get_proposal(wallet_id, proposal_id)[0]['Ok']
+    assert proposal['kind']['PersonalMessage'] == message
+
+    assert_ok(safe.approve_proposal(wallet_id, proposal_id))
+    signature = safe.execute(wallet_id, proposal_id)[0]['Ok']
+    assert int(signature[-2:], 16) in (27, 28)
+
+    signer = Web3().eth.account.recover_message(encode_defunct(text=message), signature=signature)
+    assert signer == eth_address
+    assert safe.verify_personal_signature(wallet_id, message, signature, [])[0]['Ok']
+    assert not safe.verify_personal_signature(wallet_id, message + '!', signature, [])[0]['Ok']
+
+
+def test_typed_data():
+    import json
+    from eth_account.messages import encode_typed_data
+
+    wallet_id = get_wallet_id()
+    safe = create_safe()
+    assert_ok(safe.create_wallet(wallet_id, get_default_principals(), 1))
+    eth_address = safe.eth_address(wallet_id, [])[0]['Ok']
+
+    typed_data = {
+        'types': {
+            'EIP712Domain': [
+                {'name': 'name', 'type': 'string'},
+                {'name': 'version', 'type': 'string'},
+                {'name': 'chainId', 'type': 'uint256'},
+                {'name': 'verifyingContract', 'type': 'address'},
+            ],
+            'Person': [
+                {'name': 'name', 'type': 'string'},
+                {'name': 'wallet', 'type': 'address'},
+            ],
+            'Mail': [
+                {'name': 'from', 'type': 'Person'},
+                {'name': 'to', 'type': 'Person'},
+                {'name': 'contents', 'type': 'string'},
+            ],
+        },
+        'primaryType': 'Mail',
+        'domain': {
+            'name': 'Ether Mail',
+            'version': '1',
+            'chainId': 1,
+            'verifyingContract': '0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC',
+        },
+        'message': {
+            'from': {'name': 'Cow', 'wallet': '0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826'},
+            'to': {'name': 'Bob', 'wallet': '0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB'},
+            'contents': 'Hello, Bob!',
+        },
+    }
+    proposal_id = safe.propose_typed_data(wallet_id, json.dumps(typed_data), [])[0]['Ok']
+
+    review = safe.get_typed_data(wallet_id, proposal_id)[0]['Ok']
+    assert review['primary_type'] == 'Mail'
+    assert [field['path'] for field in review['message']] == \
+        ['from.name', 'from.wallet', 'to.name', 'to.wallet', 'contents']
+
+    assert_ok(safe.approve_proposal(wallet_id, proposal_id))
+    signature = safe.execute(wallet_id, proposal_id)[0]['Ok']
+
+    signer = Web3().eth.account.recover_message(encode_typed_data(full_message=typed_data), signature=signature)
+    assert signer == eth_address
+
+    assert_err(safe.propose_typed_data(wallet_id, '{}', []), 'InvalidTypedData')
+
+    assert safe.verify_eth_signature({'Address': eth_address}, {'TypedData': json.dumps(typed_data)}, signature)[0]['Ok']
+
+
+def test_verify_eth_signature():
+    from coincurve import PrivateKey
+    from eth_account.messages import encode_defunct
+
+    safe = create_safe()
+    account = Web3().eth.account.create()
+    public_key = PrivateKey(account.key).public_key
+
+    # personal_sign with v in the 27/28 convention
+    message = 'relay ' + os.urandom(8).hex()
+    signature = account.sign_message(encode_defunct(text=message)).signature.hex()
+    assert safe.verify_eth_signature({'Address': account.address}, {'PersonalMessage': message}, signature)[0]['Ok']
+    assert safe.verify_eth_signature({'Address': account.address.lower()}, {'PersonalMessage': message}, signature)[0]['Ok']
+    assert not safe.verify_eth_signature({'Address': account.address}, {'PersonalMessage': message + '!'}, signature)[0]['Ok']
+
+    # a raw hash with v in the 0/1 convention, against a compressed and an uncompressed public key
+    digest = os.urandom(32)
+    signature = PrivateKey(account.key).sign_recoverable(digest, hasher=None).hex()
+    for key in [public_key.format(compressed=True).hex(), public_key.format(compressed=False).hex()]:
+        assert safe.verify_eth_signature({'PublicKey': key}, {'Hash': digest.hex()}, signature)[0]['Ok']
+    assert not safe.verify_eth_signature({'PublicKey': key}, {'Hash': os.urandom(32).hex()}, signature)[0]['Ok']
+
+    assert_err(safe.verify_eth_signature({'Address': '0x1234'}, {'Hash': digest.hex()}, signature), 'InvalidAddress')
+    assert_err(safe.verify_eth_signature({'PublicKey': '00' * 33}, {'Hash': digest.hex()}, signature), 'DecodeError')
+    assert_err(safe.verify_eth_signature({'Address': account.address}, {'Hash': '00'}, signature), 'InvalidHashLength')
+    assert_err(safe.verify_eth_signature({'Address': account.address}, {'TypedData': '{}'}, signature), 'InvalidTypedData')
+    assert_err(safe.verify_eth_signature({'Address': account.address}, {'Hash': digest.hex()}, '00'), 'InvalidSignatureLength')
+
+
+def var_bytes(data):
+    return bytes([len(data)]) + data
+
+
+def read_var_bytes(data, position):
+    return data[position + 1:position + 1 + data[position]], position + 1 + data[position]
+
+
+def decode_segwit_program(address, length):
+    # regroup the bech32 data part, without the version and the checksum, into bytes
+    charset = 'qpzry9x8gf2tvdw0s3jn54khce6mua7l'
+    data = address[address.rindex('1') + 2:-6]
+    bits = ''.join(format(charset.index(c), '05b') for c in data)
+    return int(bits[:length * 8], 2).to_bytes(length, 'big')
+
+
+def unsigned_psbt(script, value, outputs):
+    """A PSBT with one input spending `value` satoshis from `script`, paying `outputs` of (value, script)."""
+    import struct
+
+    unsigned_tx = struct.pack('<I', 2) + b'\x01' + os.urandom(32) + struct.pack('<I', 0) + b'\x00' \
+        + struct.pack('<I', 0xfffffffd) + bytes([len(outputs)]) \
+        + b''.join(struct.pack('<Q', amount) + var_bytes(output) for amount, output in outputs) + struct.pack('<I', 0)
+    witness_utxo = struct.pack('<Q', value) + var_bytes(script)
+    return b'psbt\xff' + var_bytes(b'\x00') + var_bytes(unsigned_tx) + b'\x00' \
+        + var_bytes(b'\x01') + var_bytes(witness_utxo) + b'\x00' + b'\x00' * len(outputs)
+
+
+def final_witness(psbt):
+    """The items of the final witness of the first input of a PSBT."""
+    # skip the magic and the global map
+    position = len(b'psbt\xff')
+    while psbt[position] != 0:
+        _, position = read_var_bytes(psbt, position)
+        _, position = read_var_bytes(psbt, position)
+    position += 1
+    while psbt[position] != 0:
+        key, position = read_var_bytes(psbt, position)
+        value, position = read_var_bytes(psbt, position)
+        if key == b'\x08':
+            items, offset = [], 1
+            for _ in range(value[0]):
+                item, offset = read_var_bytes(value, offset)
+                items.append(item)
+            return items
+    return None
+
+
+def test_btc_transaction():
+    import base64
+    import hashlib
+
+    wallet_id = get_wallet_id()
+    safe = create_safe()
+    assert_ok(safe.create_wallet(wallet_id, get_default_principals(), 1))
+
+    address = safe.btc_address(wallet_id, {'Testnet': None}, {'P2wpkh': None}, [])[0]['Ok']
+    assert address.startswith('tb1q')
+    assert safe.btc_address(wallet_id, {'Mainnet': None}, {'P2wpkh': None}, [])[0]['Ok'].startswith('bc1q')
+    assert safe.btc_address(wallet_id, {'Mainnet': None}, {'P2pkh': None}, [])[0]['Ok'].startswith('1')
+
+    key_hash = decode_segwit_program(address, 20)
+    script = b'\x00\x14' + key_hash
+    psbt = unsigned_psbt(script, 100_000, [(40_000, b'\x00\x14' + b'\x11' * 20), (59_000, script)])
+
+    proposal_id = safe.propose_btc_transaction(wallet_id, {'Testnet': None}, base64.b64encode(psbt).decode(), [])[0]['Ok']
+    tx = safe.get_btc_transaction(wallet_id, proposal_id)[0]['Ok']
+    assert tx['fee'] == 1_000
+    assert tx['inputs'][0]['address'] == [address]
+    assert [output['is_change'] for output in tx['outputs']] == [False, True]
+    sighash = bytes.fromhex(tx['inputs'][0]['sighash'][0])
+
+    assert_ok(safe.approve_proposal(wallet_id, proposal_id))
+    encoded = safe.execute(wallet_id, proposal_id)[0]['Ok']
+    finalized = base64.b64decode(encoded)
+
+    # the finalized PSBT is kept in the history
+    [executed] = safe.get_proposal_history(wallet_id, [], 1)[0]['Ok']['items']
+    assert executed['id'] == proposal_id
+    assert executed['signed_transaction'] == [encoded]
+
+    signature, public_key = final_witness(finalized)
+    assert hashlib.new('ripemd160', hashlib.sha256(public_key).digest()).digest() == key_hash
+    assert signature[-1] == 0x01
+    assert PublicKey(public_key).verify(signature[:-1], sighash, hasher=None)
+
+    # the wallet owns none of the inputs
+    foreign = unsigned_psbt(b'\x00\x14' + b'\x22' * 20, 100_000, [(90_000, script)])
+    assert_err(safe.propose_btc_transaction(wallet_id, {'Testnet': None}, base64.b64encode(foreign).decode(), []), 'InvalidPsbt')
+    assert_err(safe.propose_btc_transaction(wallet_id, {'Testnet': None}, 'not a psbt', []), 'InvalidPsbt')
+
+    # only signers may propose
+    single_signer_wallet = get_wallet_id()
+    assert_ok(safe.create_wallet(single_signer_wallet, get_default_principals()[:1], 1))
+    other_safe = create_safe(identity=1)
+    assert_err(other_safe.propose_btc_transaction(single_signer_wallet, {'Testnet': None}, 'not a psbt', []), 'NotASigner')
+    assert_err(safe.get_typed_data(wallet_id, proposal_id), 'WrongProposalKind')
+
+
+def test_taproot_transaction():
+    import base64
+    from coincurve import PublicKeyXOnly
+
+    wallet_id = get_wallet_id()
+    safe = create_safe()
+    assert_ok(safe.create_wallet(wallet_id, get_default_principals(), 1))
+
+    address = safe.taproot_address(wallet_id, {'Regtest': None}, [])[0]['Ok']
+    assert address.startswith('bcrt1p')
+    assert safe.taproot_address(wallet_id, {'Mainnet': None}, [])[0]['Ok'].startswith('bc1p')
+
+    output_key = decode_segwit_program(address, 32)
+    script = b'\x51\x20' + output_key
+    psbt = base64.b64encode(unsigned_psbt(script, 100_000, [(99_000, b'\x00\x14' + b'\x11' * 20)])).decode()
+
+    # the Taproot output is not spendable with the ECDSA key, and vice versa
+    assert_err(safe.propose_btc_transaction(wallet_id, {'Regtest': None}, psbt, []), 'InvalidPsbt')
+
+    proposal_id = safe.propose_taproot_transaction(wallet_id, {'Regtest': None}, psbt, [])[0]['Ok']
+    tx = safe.get_btc_transaction(wallet_id, proposal_id)[0]['Ok']
+    assert tx['fee'] == 1_000
+    assert tx['inputs'][0]['address'] == [address]
+    sighash = bytes.fromhex(tx['inputs'][0]['sighash'][0])
+
+    assert_ok(safe.approve_proposal(wallet_id, proposal_id))
+    encoded = safe.execute(wallet_id, proposal_id)[0]['Ok']
+    finalized = base64.b64decode(encoded)
+    assert safe.get_proposal(wallet_id, proposal_id)[0]['Ok']['signed_transaction'] == [encoded]
+
+    [signature] = final_witness(finalized)
+    assert len(signature) == 64
+    assert PublicKeyXOnly(output_key).verify(signature, sighash)
+
+    # only signers may propose
+    single_signer_wallet = get_wallet_id()
+    assert_ok(safe.create_wallet(single_signer_wallet, get_default_principals()[:1], 1))
+    other_safe = create_safe(identity=1)
+    assert_err(other_safe.propose_taproot_transaction(single_signer_wallet, {'Regtest': None}, psbt, []), 'NotASigner')
+
+
+def b58decode(address):
+    alphabet = '123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz'
+    number = 0
+    for char in address:
+        number = number * 58 + alphabet.index(char)
+    zeros = len(address) - len(address.lstrip('1'))
+    return b'\x00' * zeros + number.to_bytes((number.bit_length() + 7) // 8, 'big')
+
+
+def test_sol_transaction():
+    import base64
+    from cryptography.hazmat.primitives.asymmetric.ed25519 import Ed25519PublicKey
+
+    wallet_id = get_wallet_id()
+    safe = create_safe()
+    assert_ok(safe.create_wallet(wallet_id, get_default_principals(), 1))
+
+    address = safe.sol_address(wallet_id, [])[0]['Ok']
+    public_key = b58decode(address)
+    assert len(public_key) == 32
+    assert safe.sol_address(wallet_id, [])[0]['Ok'] == address
+
+    # a legacy message transferring 1 SOL from the wallet
+    recipient = b'\x22' * 32
+    message = bytes([1, 0, 1, 3]) + public_key + recipient + b'\x00' * 32 + b'\x33' * 32
+    message += bytes([1, 2, 2, 0, 1, 12]) + (2).to_bytes(4, 'little') + (10 ** 9).to_bytes(8, 'little')
+    encoded = base64.b64encode(message).decode()
+
+    # the wallet has to be a signer
+    other = base64.b64encode(bytes([1, 0, 1, 3]) + recipient + message[36:]).decode()
+    assert_err(safe.propose_sol_transaction(wallet_id, other, []), 'InvalidSolanaTransaction')
+    assert_err(safe.propose_sol_transaction(wallet_id, 'not base64!', []), 'InvalidSolanaTransaction')
+
+    # only signers may propose
+    single_signer_wallet = get_wallet_id()
+    assert_ok(safe.create_wallet(single_signer_wallet, get_default_principals()[:1], 1))
+    other_safe = create_safe(identity=1)
+    assert_err(other_safe.propose_sol_transaction(single_signer_wallet, encoded, []), 'NotASigner')
+
+    proposal_id = safe.propose_sol_transaction(wallet_id, encoded, [])[0]['Ok']
+    tx = safe.get_sol_transaction(wallet_id, proposal_id)[0]['Ok']
+    assert tx['fee_payer'] == address
+    assert tx['version'] == []
+    [transfer] = tx['instructions']
+    assert transfer['program'] == ['System']
+    assert transfer['instruction'] == ['Transfer']
+    assert transfer['arguments'] == [{'name': 'lamports', 'type_name': 'u64', 'value': '1000000000'}]
+    assert transfer['accounts'][0] == {'address': address, 'is_signer': True, 'is_writable': True}
+    assert_err(safe.get_btc_transaction(wallet_id, proposal_id), 'WrongProposalKind')
+
+    assert_ok(safe.approve_proposal(wallet_id, proposal_id))
+    output = safe.execute(wallet_id, proposal_id)[0]['Ok']
+    [executed] = safe.get_proposal_history(wallet_id, [], 1)[0]['Ok']['items']
+    assert executed['signed_transaction'] == [output]
+    signed = base64.b64decode(output)
+    assert signed[0] == 1
+    assert signed[65:] == message
+    Ed25519PublicKey.from_public_bytes(public_key).verify(signed[1:65], message)
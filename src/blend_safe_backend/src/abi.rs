@@ -0,0 +1,442 @@
+use candid::CandidType;
+use num_bigint::BigUint;
+use serde::Deserialize;
+use crate::ecdsa::{keccak256, to_checksum_address};
+
+/// The size of an ABI word in bytes.
+const WORD: usize = 32;
+
+/// The maximum length of a JSON ABI in bytes, which keeps a single registration from filling the canister's memory.
+pub const MAX_ABI_LENGTH: usize = 64 * 1024;
+
+/// A named input of a function.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct AbiParameter {
+    /// The name of the input, `arg<index>` if the ABI leaves it unnamed.
+    pub name: String,
+    /// The canonical Solidity type, with tuples written as `(type,...)`.
+    pub type_name: String,
+}
+
+/// A function of a registered contract.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct AbiFunction {
+    /// The hex encoded 4 byte function selector.
+    pub selector: String,
+    /// The canonical function signature, e.g. `transfer(address,uint256)`.
+    pub signature: String,
+    /// The inputs of the function.
+    pub inputs: Vec<AbiParameter>,
+}
+
+/// The functions of a contract, parsed from its JSON ABI.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct ContractAbi {
+    /// The checksummed address of the contract.
+    pub address: String,
+    /// A label for the contract that is shown in place of a token standard.
+    pub name: String,
+    /// The functions of the contract.
+    pub functions: Vec<AbiFunction>,
+}
+
+/// An entry of a JSON ABI. Entries without a type are functions.
+#[derive(Deserialize)]
+struct JsonAbiEntry {
+    #[serde(rename = "type", default)]
+    entry_type: Option<String>,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    inputs: Vec<JsonAbiParameter>,
+}
+
+/// An input of a JSON ABI entry.
+#[derive(Deserialize)]
+struct JsonAbiParameter {
+    #[serde(default)]
+    name: String,
+    #[serde(rename = "type")]
+    type_name: String,
+    #[serde(default)]
+    components: Vec<JsonAbiParameter>,
+}
+
+impl JsonAbiParameter {
+    /// The canonical type, replacing `tuple` with the types of its components.
+    fn canonical_type(&self) -> String {
+        match self.type_name.strip_prefix("tuple") {
+            Some(suffix) => {
+                let components: Vec<String> = self.components.iter().map(JsonAbiParameter::canonical_type).collect();
+                format!("({}){}", components.join(","), suffix)
+            }
+            None => self.type_name.clone(),
+        }
+    }
+}
+
+impl ContractAbi {
+    /// Parse the functions of a JSON ABI as emitted by `solc`. Events, errors and constructors are ignored.
+    ///
+    /// * `address` - The checksummed address of the contract.
+    /// * `name` - A label for the contract.
+    /// * `json` - The JSON ABI, at most `MAX_ABI_LENGTH` bytes.
+    ///
+    /// Returns `Result<ContractAbi, String>` containing the parsed ABI or an error message.
+    pub fn parse(address: String, name: String, json: &str) -> Result<ContractAbi, String> {
+        if json.len() > MAX_ABI_LENGTH {
+            return Err(format!("ABI exceeds {} bytes", MAX_ABI_LENGTH));
+        }
+        let entries: Vec<JsonAbiEntry> = serde_json::from_str(json).map_err(|err| err.to_string())?;
+        let mut functions: Vec<AbiFunction> = Vec::new();
+        for entry in entries.iter().filter(|entry| entry.entry_type.as_deref().unwrap_or("function") == "function") {
+            let inputs: Vec<AbiParameter> = entry.inputs.iter()
+                .enumerate()
+                .map(|(index, input)| AbiParameter {
+                    name: if input.name.is_empty() { format!("arg{}", index) } else { input.name.clone() },
+                    type_name: input.canonical_type(),
+                })
+                .collect();
+            for input in &inputs {
+                ParamType::parse(&input.type_name)?;
+            }
+            let types: Vec<&str> = inputs.iter().map(|input| input.type_name.as_str()).collect();
+            let signature = format!("{}({})", entry.name, types.join(","));
+            let selector = hex::encode(selector(&signature));
+            if functions.iter().any(|function| function.selector == selector) {
+                return Err(format!("Duplicate selector: {}", signature));
+            }
+            functions.push(AbiFunction { selector, signature, inputs });
+        }
+        Ok(ContractAbi { address, name, functions })
+    }
+}
+
+/// The 4 byte selector of a function signature: the first bytes of its Keccak-256 hash.
+///
+/// * `signature` - The canonical function signature, e.g. `transfer(address,uint256)`.
+pub fn selector(signature: &str) -> [u8; 4] {
+    let hash = keccak256(signature.as_bytes());
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// A Solidity ABI parameter type.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParamType {
+    Address,
+    Bool,
+    Uint(usize),
+    Int(usize),
+    FixedBytes(usize),
+    Bytes,
+    String,
+    Array(Box<ParamType>),
+    FixedArray(Box<ParamType>, usize),
+    Tuple(Vec<ParamType>),
+}
+
+impl ParamType {
+    /// Parse a canonical Solidity type, e.g. `uint256`, `address[]` or `(address,uint256)[2]`.
+    ///
+    /// Returns `Result<ParamType, String>` containing the type or an error message.
+    pub fn parse(type_name: &str) -> Result<ParamType, String> {
+        if let Some(stripped) = type_name.strip_suffix(']') {
+            let open = stripped.rfind('[').ok_or(format!("Unknown type: {}", type_name))?;
+            let item = Box::new(ParamType::parse(&stripped[..open])?);
+            let len = &stripped[open + 1..];
+            if len.is_empty() {
+                return Ok(ParamType::Array(item));
+            }
+            let len = len.parse().map_err(|_| format!("Unknown type: {}", type_name))?;
+            return Ok(ParamType::FixedArray(item, len));
+        }
+        if let Some(inner) = type_name.strip_prefix('(').and_then(|inner| inner.strip_suffix(')')) {
+            return split_tuple(inner)?
+                .into_iter()
+                .map(ParamType::parse)
+                .collect::<Result<_, _>>()
+                .map(ParamType::Tuple);
+        }
+
+        let sized = |prefix: &str, default: usize, valid: fn(usize) -> bool| -> Result<usize, String> {
+            let suffix = &type_name[prefix.len()..];
+            let size = if suffix.is_empty() { default } else {
+                suffix.parse().map_err(|_| format!("Unknown type: {}", type_name))?
+            };
+            if !valid(size) {
+                return Err(format!("Unknown type: {}", type_name));
+            }
+            Ok(size)
+        };
+        match type_name {
+            "address" => Ok(ParamType::Address),
+            "bool" => Ok(ParamType::Bool),
+            "bytes" => Ok(ParamType::Bytes),
+            "string" => Ok(ParamType::String),
+            _ if type_name.starts_with("uint") => {
+                sized("uint", 256, |bits| bits % 8 == 0 && (8..=256).contains(&bits)).map(ParamType::Uint)
+            }
+            _ if type_name.starts_with("int") => {
+                sized("int", 256, |bits| bits % 8 == 0 && (8..=256).contains(&bits)).map(ParamType::Int)
+            }
+            _ if type_name.starts_with("bytes") => {
+                sized("bytes", 32, |size| (1..=32).contains(&size)).map(ParamType::FixedBytes)
+            }
+            _ => Err(format!("Unknown type: {}", type_name)),
+        }
+    }
+
+    /// Whether the value is stored in the tail and referenced by an offset in the head.
+    fn is_dynamic(&self) -> bool {
+        match self {
+            ParamType::Bytes | ParamType::String | ParamType::Array(_) => true,
+            ParamType::FixedArray(item, _) => item.is_dynamic(),
+            ParamType::Tuple(items) => items.iter().any(ParamType::is_dynamic),
+            _ => false,
+        }
+    }
+
+    /// The number of bytes a static value takes in the head.
+    fn head_size(&self) -> usize {
+        match self {
+            ParamType::FixedArray(item, len) if !self.is_dynamic() => item.head_size() * len,
+            ParamType::Tuple(items) if !self.is_dynamic() => items.iter().map(ParamType::head_size).sum(),
+            _ => WORD,
+        }
+    }
+}
+
+/// Decode ABI encoded values to human-readable strings.
+///
+/// # Arguments
+///
+/// * `types` - The types of the encoded values.
+/// * `data` - The encoded values, without a function selector.
+///
+/// # Returns
+///
+/// * `Result<Vec<String>, String>` - The decoded values or an error message if `data` is malformed.
+pub fn decode(types: &[ParamType], data: &[u8]) -> Result<Vec<String>, String> {
+    decode_params(types, data, 0)
+}
+
+/// Decode a sequence of values whose head starts at `offset`.
+fn decode_params(types: &[ParamType], data: &[u8], offset: usize) -> Result<Vec<String>, String> {
+    let mut head = offset;
+    let mut values = Vec::with_capacity(types.len());
+    for param_type in types {
+        if param_type.is_dynamic() {
+            let tail = offset.checked_add(read_usize(data, head)?).ok_or("Offset out of range".to_string())?;
+            values.push(decode_value(param_type, data, tail)?);
+            head += WORD;
+        } else {
+            values.push(decode_value(param_type, data, head)?);
+            head += param_type.head_size();
+        }
+    }
+    Ok(values)
+}
+
+/// Decode a single value starting at `position`.
+fn decode_value(param_type: &ParamType, data: &[u8], position: usize) -> Result<String, String> {
+    match param_type {
+        ParamType::Address => {
+            let mut address = [0u8; 20];
+            address.copy_from_slice(&word(data, position)?[12..]);
+            Ok(to_checksum_address(&address))
+        }
+        ParamType::Bool => match word(data, position)?[31] {
+            0 => Ok("false".to_string()),
+            1 => Ok("true".to_string()),
+            _ => Err("Invalid bool".to_string()),
+        },
+        ParamType::Uint(_) => Ok(BigUint::from_bytes_be(word(data, position)?).to_string()),
+        ParamType::Int(_) => {
+            let value = word(data, position)?;
+            if value[0] & 0x80 == 0 {
+                return Ok(BigUint::from_bytes_be(value).to_string());
+            }
+            // two's complement in 256 bits
+            let magnitude: BigUint = (BigUint::from(1u8) << 256usize) - BigUint::from_bytes_be(value);
+            Ok(format!("-{}", magnitude))
+        }
+        ParamType::FixedBytes(size) => Ok(format!("0x{}", hex::encode(&word(data, position)?[..*size]))),
+        ParamType::Bytes => Ok(format!("0x{}", hex::encode(read_bytes(data, position)?))),
+        ParamType::String => Ok(String::from_utf8_lossy(read_bytes(data, position)?).to_string()),
+        ParamType::Array(item) => {
+            let len = read_usize(data, position)?;
+            // every item takes at least one word, which bounds the length by the data
+            if len > data.len() / WORD {
+                return Err("Array length out of range".to_string());
+            }
+            let items = decode_params(&vec![(**item).clone(); len], data, position + WORD)?;
+            Ok(format!("[{}]", items.join(", ")))
+        }
+        ParamType::FixedArray(item, len) => {
+            if *len > data.len() / WORD {
+                return Err("Array length out of range".to_string());
+            }
+            let items = decode_params(&vec![(**item).clone(); *len], data, position)?;
+            Ok(format!("[{}]", items.join(", ")))
+        }
+        ParamType::Tuple(items) => Ok(format!("({})", decode_params(items, data, position)?.join(", "))),
+    }
+}
+
+/// Read the 32 byte word at `position`.
+fn word(data: &[u8], position: usize) -> Result<&[u8], String> {
+    position.checked_add(WORD)
+        .and_then(|end| data.get(position..end))
+        .ok_or("Unexpected end of data".to_string())
+}
+
+/// Read a word that holds a length or an offset.
+fn read_usize(data: &[u8], position: usize) -> Result<usize, String> {
+    let value = word(data, position)?;
+    if value[..24].iter().any(|byte| *byte != 0) {
+        return Err("Length out of range".to_string());
+    }
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&value[24..]);
+    usize::try_from(u64::from_be_bytes(bytes)).map_err(|_| "Length out of range".to_string())
+}
+
+/// Read length-prefixed bytes at `position`.
+fn read_bytes(data: &[u8], position: usize) -> Result<&[u8], String> {
+    let len = read_usize(data, position)?;
+    let start = position + WORD;
+    start.checked_add(len)
+        .and_then(|end| data.get(start..end))
+        .ok_or("Unexpected end of data".to_string())
+}
+
+/// Split the members of a tuple type at the top-level commas.
+fn split_tuple(inner: &str) -> Result<Vec<&str>, String> {
+    if inner.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut parts = Vec::new();
+    let (mut depth, mut start) = (0i32, 0);
+    for (index, c) in inner.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&inner[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+        if depth < 0 {
+            return Err(format!("Unbalanced tuple: ({})", inner));
+        }
+    }
+    parts.push(&inner[start..]);
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word_of(value: u64) -> Vec<u8> {
+        let mut out = vec![0u8; 24];
+        out.extend(value.to_be_bytes());
+        out
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(ParamType::parse("uint"), Ok(ParamType::Uint(256)));
+        assert_eq!(ParamType::parse("bytes4"), Ok(ParamType::FixedBytes(4)));
+        assert_eq!(
+            ParamType::parse("(address,uint256[])[2]"),
+            Ok(ParamType::FixedArray(
+                Box::new(ParamType::Tuple(vec![ParamType::Address, ParamType::Array(Box::new(ParamType::Uint(256)))])),
+                2,
+            ))
+        );
+        assert!(ParamType::parse("uint7").is_err());
+        assert!(ParamType::parse("bytes33").is_err());
+        assert!(ParamType::parse("foo").is_err());
+        assert!(ParamType::parse("uint256[x]").is_err());
+    }
+
+    #[test]
+    fn test_decode_static() {
+        let mut data = vec![0u8; 12];
+        data.extend([0x11; 20]);
+        data.extend(word_of(1000));
+        data.extend(word_of(1));
+        data.extend([0xff; 32]);
+
+        let types = [ParamType::Address, ParamType::Uint(256), ParamType::Bool, ParamType::Int(256)];
+        assert_eq!(
+            decode(&types, &data),
+            Ok(vec![format!("0x{}", "11".repeat(20)), "1000".to_string(), "true".to_string(), "-1".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_decode_dynamic() {
+        // f(uint256[] ids, bytes data) with ids = [1, 2] and data = 0xabcd
+        let mut data = word_of(64);
+        data.extend(word_of(160));
+        data.extend(word_of(2));
+        data.extend(word_of(1));
+        data.extend(word_of(2));
+        data.extend(word_of(2));
+        let mut bytes = vec![0xab, 0xcd];
+        bytes.resize(32, 0);
+        data.extend(bytes);
+
+        let types = [ParamType::parse("uint256[]").unwrap(), ParamType::Bytes];
+        assert_eq!(decode(&types, &data), Ok(vec!["[1, 2]".to_string(), "0xabcd".to_string()]));
+    }
+
+    #[test]
+    fn test_decode_malformed() {
+        assert!(decode(&[ParamType::Uint(256)], &[0u8; 31]).is_err());
+        assert!(decode(&[ParamType::Bool], &word_of(2)).is_err());
+        // an offset pointing past the data
+        assert!(decode(&[ParamType::Bytes], &word_of(1 << 40)).is_err());
+        // a huge array length
+        let mut data = word_of(32);
+        data.extend(word_of(u32::MAX as u64));
+        assert!(decode(&[ParamType::parse("uint256[]").unwrap()], &data).is_err());
+    }
+
+    #[test]
+    fn test_parse_abi() {
+        let json = r#"[
+            {"type": "constructor", "inputs": [{"name": "owner", "type": "address"}]},
+            {"type": "event", "name": "Deposited", "inputs": [{"name": "amount", "type": "uint256", "indexed": false}]},
+            {"type": "function", "name": "deposit", "inputs": [{"name": "", "type": "uint256"}], "outputs": []},
+            {"name": "fill", "inputs": [
+                {"name": "orders", "type": "tuple[]", "components": [
+                    {"name": "maker", "type": "address"},
+                    {"name": "amounts", "type": "uint256[2]"}
+                ]}
+            ]}
+        ]"#;
+        let abi = ContractAbi::parse("0x00".to_string(), "Vault".to_string(), json).unwrap();
+
+        assert_eq!(abi.functions.len(), 2);
+        assert_eq!(abi.functions[0].signature, "deposit(uint256)");
+        assert_eq!(abi.functions[0].selector, "b6b55f25");
+        assert_eq!(abi.functions[0].inputs[0].name, "arg0");
+        assert_eq!(abi.functions[1].signature, "fill((address,uint256[2])[])");
+    }
+
+    #[test]
+    fn test_parse_invalid_abi() {
+        let parse = |json: &str| ContractAbi::parse("0x00".to_string(), "Vault".to_string(), json);
+        assert!(parse("{}").is_err());
+        assert!(parse(r#"[{"name": "f", "inputs": [{"name": "x", "type": "uint7"}]}]"#).is_err());
+        assert!(parse(r#"[{"name": "f", "inputs": []}, {"name": "f", "inputs": []}]"#).is_err());
+
+        let padded = |length: usize| format!("[{}]", " ".repeat(length - 2));
+        assert!(parse(&padded(MAX_ABI_LENGTH)).is_ok());
+        assert_eq!(parse(&padded(MAX_ABI_LENGTH + 1)), Err(format!("ABI exceeds {} bytes", MAX_ABI_LENGTH)));
+    }
+}
@@ -0,0 +1,1090 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use candid::CandidType;
+use libsecp256k1::Signature;
+use ripemd::Ripemd160;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use crate::schnorr::tagged_hash;
+
+/// The magic bytes every BIP-174 PSBT starts with.
+const PSBT_MAGIC: &[u8] = b"psbt\xff";
+
+/// The global key type of the unsigned transaction.
+const PSBT_GLOBAL_UNSIGNED_TX: u8 = 0x00;
+
+/// The input key type of the full transaction the input spends from.
+const PSBT_IN_NON_WITNESS_UTXO: u8 = 0x00;
+
+/// The input key type of the output a segwit input spends.
+const PSBT_IN_WITNESS_UTXO: u8 = 0x01;
+
+/// The input key type of the sighash type the input must be signed with.
+const PSBT_IN_SIGHASH_TYPE: u8 = 0x03;
+
+/// The input key type of the finalized scriptSig.
+const PSBT_IN_FINAL_SCRIPTSIG: u8 = 0x07;
+
+/// The input key type of the finalized witness.
+const PSBT_IN_FINAL_SCRIPTWITNESS: u8 = 0x08;
+
+/// The input key type of the Taproot key path signature.
+const PSBT_IN_TAP_KEY_SIG: u8 = 0x13;
+
+/// The input key type of the Taproot merkle root, the last Taproot input field of BIP-371.
+const PSBT_IN_TAP_MERKLE_ROOT: u8 = 0x18;
+
+/// The sighash type the wallet signs ECDSA inputs with: commit to all inputs and outputs.
+const SIGHASH_ALL: u32 = 0x01;
+
+/// The sighash type the wallet signs Taproot inputs with: like `SIGHASH_ALL`, without a sighash byte.
+const SIGHASH_DEFAULT: u32 = 0x00;
+
+/// The characters of the bech32 alphabet, indexed by their 5 bit value.
+const BECH32_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// The checksum constant of BIP-173 bech32, used for segwit version 0.
+const BECH32_CONST: u32 = 1;
+
+/// The checksum constant of BIP-350 bech32m, used for segwit version 1 and higher.
+const BECH32M_CONST: u32 = 0x2bc830a3;
+
+/// The characters of the base58 alphabet, indexed by their value.
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// The Bitcoin network an address or transaction is for.
+#[derive(Clone, Copy, Debug, PartialEq, CandidType, Deserialize)]
+pub enum BtcNetwork {
+    Mainnet,
+    Testnet,
+    Regtest,
+}
+
+impl BtcNetwork {
+    /// The base58 version byte of P2PKH addresses.
+    fn p2pkh_version(&self) -> u8 {
+        match self {
+            BtcNetwork::Mainnet => 0x00,
+            BtcNetwork::Testnet | BtcNetwork::Regtest => 0x6f,
+        }
+    }
+
+    /// The base58 version byte of P2SH addresses.
+    fn p2sh_version(&self) -> u8 {
+        match self {
+            BtcNetwork::Mainnet => 0x05,
+            BtcNetwork::Testnet | BtcNetwork::Regtest => 0xc4,
+        }
+    }
+
+    /// The human readable part of segwit addresses.
+    fn hrp(&self) -> &'static str {
+        match self {
+            BtcNetwork::Mainnet => "bc",
+            BtcNetwork::Testnet => "tb",
+            BtcNetwork::Regtest => "bcrt",
+        }
+    }
+}
+
+/// The kind of address a public key is paid to.
+#[derive(Clone, Copy, Debug, PartialEq, CandidType, Deserialize)]
+pub enum BtcAddressKind {
+    /// Pay to public key hash, a legacy base58 address.
+    P2pkh,
+    /// Pay to witness public key hash, a native segwit bech32 address.
+    P2wpkh,
+}
+
+/// The key the wallet signs a Bitcoin transaction with, which decides the inputs it can spend.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BtcSigner<'a> {
+    /// The compressed threshold ECDSA public key, spending P2PKH and P2WPKH outputs.
+    Ecdsa(&'a [u8]),
+    /// The x-only Taproot output key, spending P2TR outputs through the key path.
+    Taproot(&'a [u8; 32]),
+}
+
+impl BtcSigner<'_> {
+    /// The output scripts that pay to the wallet.
+    fn scripts(&self) -> Vec<Vec<u8>> {
+        match self {
+            BtcSigner::Ecdsa(public_key) => {
+                let key_hash = hash160(public_key);
+                vec![p2pkh_script(&key_hash), p2wpkh_script(&key_hash)]
+            }
+            BtcSigner::Taproot(output_key) => vec![p2tr_script(output_key)],
+        }
+    }
+}
+
+/// An input of a proposed Bitcoin transaction, decoded for review.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct BtcInput {
+    /// The ID of the transaction the spent output belongs to, in the byte order block explorers display.
+    pub txid: String,
+    /// The index of the spent output in its transaction.
+    pub vout: u32,
+    /// The amount of the spent output in satoshis.
+    pub value: u64,
+    /// The address of the spent output, or `None` if its script is not a standard address.
+    pub address: Option<String>,
+    /// The hex encoded sighash the wallet signs, or `None` if the input does not belong to the wallet.
+    pub sighash: Option<String>,
+}
+
+/// An output of a proposed Bitcoin transaction, decoded for review.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct BtcOutput {
+    /// The amount in satoshis.
+    pub value: u64,
+    /// The address paid to, or `None` if the script is not a standard address.
+    pub address: Option<String>,
+    /// The hex encoded output script.
+    pub script_pubkey: String,
+    /// Whether the output pays back to the wallet.
+    pub is_change: bool,
+}
+
+/// An unsigned Bitcoin transaction proposed as a PSBT, together with the decoded inputs and outputs signers review.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct BtcTransaction {
+    /// The network the addresses are rendered for.
+    pub network: BtcNetwork,
+    /// The base64 encoded PSBT as it was proposed.
+    pub psbt: String,
+    /// The ID of the transaction, in the byte order block explorers display.
+    pub txid: String,
+    /// The inputs of the transaction.
+    pub inputs: Vec<BtcInput>,
+    /// The outputs of the transaction.
+    pub outputs: Vec<BtcOutput>,
+    /// The fee in satoshis: the inputs minus the outputs.
+    pub fee: u64,
+}
+
+impl BtcTransaction {
+    /// Parse an unsigned PSBT and compute the sighash of every input the wallet can sign.
+    ///
+    /// * `psbt` - The base64 encoded BIP-174 PSBT. Every input must carry the output it spends;
+    ///   inputs spending legacy P2PKH outputs must carry the full previous transaction.
+    /// * `network` - The network addresses are rendered for.
+    /// * `signer` - The wallet's key. Inputs spending its outputs are signed.
+    ///
+    /// Returns `Result<BtcTransaction, String>` containing the decoded transaction or an error message.
+    pub fn parse(psbt: &str, network: BtcNetwork, signer: BtcSigner) -> Result<BtcTransaction, String> {
+        let data = BASE64.decode(psbt.trim()).map_err(|_| "PSBT is not valid base64".to_string())?;
+        let psbt = Psbt::parse(&data)?;
+        let spent = (0..psbt.inputs.len())
+            .map(|index| psbt.spent_output(index))
+            .collect::<Result<Vec<_>, String>>()?;
+        let owned_scripts = signer.scripts();
+
+        let mut inputs = Vec::new();
+        let mut input_total: u64 = 0;
+        for (index, (txin, (utxo, _))) in psbt.tx.inputs.iter().zip(&spent).enumerate() {
+            let sighash = psbt.sighash(index, &spent, signer)?;
+            input_total = input_total.checked_add(utxo.value).ok_or("Input amounts overflow".to_string())?;
+            inputs.push(BtcInput {
+                txid: display_txid(&txin.previous_txid),
+                vout: txin.vout,
+                value: utxo.value,
+                address: script_to_address(&utxo.script_pubkey, network),
+                sighash: sighash.map(hex::encode),
+            });
+        }
+        if inputs.iter().all(|input| input.sighash.is_none()) {
+            return Err("The PSBT has no input the wallet can sign".to_string());
+        }
+
+        let mut output_total: u64 = 0;
+        let outputs: Vec<BtcOutput> = psbt.tx.outputs.iter()
+            .map(|txout| {
+                output_total = output_total.checked_add(txout.value).ok_or("Output amounts overflow".to_string())?;
+                Ok(BtcOutput {
+                    value: txout.value,
+                    address: script_to_address(&txout.script_pubkey, network),
+                    script_pubkey: hex::encode(&txout.script_pubkey),
+                    is_change: owned_scripts.contains(&txout.script_pubkey),
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        let fee = input_total.checked_sub(output_total).ok_or("The outputs spend more than the inputs".to_string())?;
+
+        Ok(BtcTransaction {
+            network,
+            psbt: BASE64.encode(&data),
+            txid: display_txid(&psbt.tx.txid()),
+            inputs,
+            outputs,
+            fee,
+        })
+    }
+
+    /// The sighashes the wallet signs, in input order.
+    pub fn sighashes(&self) -> Vec<Vec<u8>> {
+        self.inputs.iter()
+            .filter_map(|input| input.sighash.as_ref())
+            .filter_map(|sighash| hex::decode(sighash).ok())
+            .collect()
+    }
+
+    /// Finalize the inputs the wallet signs.
+    ///
+    /// P2WPKH inputs get a `<signature> <public key>` witness, P2PKH inputs the same as their scriptSig and
+    /// P2TR inputs a `<signature>` witness. Partial signatures and other data the finalizer no longer needs
+    /// are cleared, as BIP-174 prescribes.
+    ///
+    /// * `signer` - The wallet's key the transaction was parsed with.
+    /// * `signatures` - One signature per sighash, in input order: DER encoded with the sighash type appended
+    ///   for ECDSA, the 64 byte BIP-340 signature for Taproot.
+    ///
+    /// Returns `Result<String, String>` containing the base64 encoded finalized PSBT or an error message.
+    pub fn finalize(&self, signer: BtcSigner, signatures: &[Vec<u8>]) -> Result<String, String> {
+        let data = BASE64.decode(&self.psbt).map_err(|_| "PSBT is not valid base64".to_string())?;
+        let mut psbt = Psbt::parse(&data)?;
+        let scripts = (0..psbt.inputs.len())
+            .map(|index| psbt.spent_output(index).map(|(utxo, _)| utxo.script_pubkey))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let mut signatures = signatures.iter();
+        for ((input, map), script) in self.inputs.iter().zip(psbt.inputs.iter_mut()).zip(scripts) {
+            if input.sighash.is_none() {
+                continue;
+            }
+            let signature = signatures.next().ok_or("Missing signature".to_string())?;
+            map.retain(|(key, _)| !matches!(
+                key.first(),
+                Some(0x02..=PSBT_IN_FINAL_SCRIPTWITNESS | PSBT_IN_TAP_KEY_SIG..=PSBT_IN_TAP_MERKLE_ROOT)
+            ));
+            match signer {
+                BtcSigner::Ecdsa(public_key) if script.starts_with(&[0x00]) => {
+                    map.push((vec![PSBT_IN_FINAL_SCRIPTWITNESS], encode_witness(&[signature, public_key])));
+                }
+                BtcSigner::Ecdsa(public_key) => {
+                    let mut script_sig = push_data(signature);
+                    script_sig.extend(push_data(public_key));
+                    map.push((vec![PSBT_IN_FINAL_SCRIPTSIG], script_sig));
+                }
+                BtcSigner::Taproot(_) => {
+                    map.push((vec![PSBT_IN_FINAL_SCRIPTWITNESS], encode_witness(&[signature])));
+                }
+            }
+        }
+        if signatures.next().is_some() {
+            return Err("Too many signatures".to_string());
+        }
+        Ok(BASE64.encode(psbt.serialize()))
+    }
+}
+
+/// Encode a 64 byte `r || s` signature as a low-S DER signature with `SIGHASH_ALL` appended.
+///
+/// # Arguments
+///
+/// * `signature` - The signature as returned by `sign_with_ecdsa`. Any trailing recovery ID is ignored.
+///
+/// # Returns
+///
+/// * `Result<Vec<u8>, String>` - The signature as it appears in a scriptSig or witness, or an error message.
+pub fn der_signature(signature: &[u8]) -> Result<Vec<u8>, String> {
+    let rs = signature.get(..64).ok_or("Invalid signature length".to_string())?;
+    let mut signature = Signature::parse_standard_slice(rs).map_err(|_| "Invalid signature".to_string())?;
+    // BIP-62 only relays signatures with a low S value
+    signature.normalize_s();
+    let mut der = signature.serialize_der().as_ref().to_vec();
+    der.push(SIGHASH_ALL as u8);
+    Ok(der)
+}
+
+/// Derive the Bitcoin address of a public key.
+///
+/// # Arguments
+///
+/// * `public_key` - The 33 byte compressed public key.
+/// * `network` - The network the address is for.
+/// * `kind` - The kind of address.
+///
+/// # Returns
+///
+/// * `String` - The base58check encoded P2PKH address or the bech32 encoded P2WPKH address.
+///
+/// # Example
+///
+/// ```
+/// let address = btc_address(&wallet_public_key.compressed, BtcNetwork::Mainnet, BtcAddressKind::P2wpkh);
+/// ```
+pub fn btc_address(public_key: &[u8], network: BtcNetwork, kind: BtcAddressKind) -> String {
+    let key_hash = hash160(public_key);
+    match kind {
+        BtcAddressKind::P2pkh => base58check(network.p2pkh_version(), &key_hash),
+        BtcAddressKind::P2wpkh => segwit_address(network.hrp(), 0, &key_hash),
+    }
+}
+
+/// Derive the Taproot address of an output key.
+///
+/// # Arguments
+///
+/// * `output_key` - The x-only output key, e.g. from `schnorr::taproot_output_key`.
+/// * `network` - The network the address is for.
+///
+/// # Returns
+///
+/// * `String` - The bech32m encoded P2TR address.
+///
+/// # Example
+///
+/// ```
+/// let address = taproot_address(&taproot_output_key(&schnorr_public_key.public_key)?, BtcNetwork::Mainnet);
+/// ```
+pub fn taproot_address(output_key: &[u8; 32], network: BtcNetwork) -> String {
+    segwit_address(network.hrp(), 1, output_key)
+}
+
+/// Render an output script as an address, or `None` if it is not one of the standard address types.
+fn script_to_address(script: &[u8], network: BtcNetwork) -> Option<String> {
+    match script {
+        [0x76, 0xa9, 0x14, hash @ .., 0x88, 0xac] if hash.len() == 20 => Some(base58check(network.p2pkh_version(), hash)),
+        [0xa9, 0x14, hash @ .., 0x87] if hash.len() == 20 => Some(base58check(network.p2sh_version(), hash)),
+        [0x00, length, program @ ..] if (*length == 20 || *length == 32) && program.len() == *length as usize =>
+            Some(segwit_address(network.hrp(), 0, program)),
+        [version @ 0x51..=0x60, length, program @ ..] if (2..=40).contains(length) && program.len() == *length as usize =>
+            Some(segwit_address(network.hrp(), version - 0x50, program)),
+        _ => None,
+    }
+}
+
+/// The P2PKH output script of a public key hash.
+fn p2pkh_script(key_hash: &[u8; 20]) -> Vec<u8> {
+    let mut script = vec![0x76, 0xa9, 0x14];
+    script.extend_from_slice(key_hash);
+    script.extend_from_slice(&[0x88, 0xac]);
+    script
+}
+
+/// The P2WPKH output script of a public key hash.
+fn p2wpkh_script(key_hash: &[u8; 20]) -> Vec<u8> {
+    let mut script = vec![0x00, 0x14];
+    script.extend_from_slice(key_hash);
+    script
+}
+
+/// The P2TR output script of an output key.
+fn p2tr_script(output_key: &[u8; 32]) -> Vec<u8> {
+    let mut script = vec![0x51, 0x20];
+    script.extend_from_slice(output_key);
+    script
+}
+
+/// Serialize the items of a witness stack.
+fn encode_witness(items: &[&[u8]]) -> Vec<u8> {
+    let mut witness = Vec::new();
+    write_compact_size(&mut witness, items.len() as u64);
+    for item in items {
+        write_var_bytes(&mut witness, item);
+    }
+    witness
+}
+
+/// A script that pushes the given data, which must be shorter than 76 bytes.
+fn push_data(data: &[u8]) -> Vec<u8> {
+    let mut script = vec![data.len() as u8];
+    script.extend_from_slice(data);
+    script
+}
+
+/// Compute `RIPEMD160(SHA256(data))`, the hash public keys and scripts are paid to.
+fn hash160(data: &[u8]) -> [u8; 20] {
+    Ripemd160::digest(Sha256::digest(data)).into()
+}
+
+/// Compute `SHA256(SHA256(data))`, the hash transactions are identified and signed by.
+fn sha256d(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(Sha256::digest(data)).into()
+}
+
+/// Hex encode a transaction ID in the reversed byte order block explorers display.
+fn display_txid(txid: &[u8; 32]) -> String {
+    hex::encode(txid.iter().rev().copied().collect::<Vec<u8>>())
+}
+
+/// Encode a payload with a version byte as a base58check address.
+fn base58check(version: u8, payload: &[u8]) -> String {
+    let mut data = vec![version];
+    data.extend_from_slice(payload);
+    let checksum = sha256d(&data);
+    data.extend_from_slice(&checksum[..4]);
+    base58_encode(&data)
+}
+
+/// Encode bytes in base58, the encoding of Bitcoin's legacy addresses and of Solana's keys.
+///
+/// # Arguments
+///
+/// * `data` - The bytes to encode.
+///
+/// # Returns
+///
+/// * `String` - The base58 string, with a leading `1` for every leading zero byte.
+///
+/// # Example
+///
+/// ```
+/// let address = base58_encode(&ed25519_public_key);
+/// ```
+pub fn base58_encode(data: &[u8]) -> String {
+    // repeatedly divide the big-endian number by 58, keeping the digits least significant first
+    let mut digits: Vec<u8> = Vec::new();
+    for byte in data {
+        let mut carry = *byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let zeros = data.iter().take_while(|byte| **byte == 0).count();
+    std::iter::repeat_n('1', zeros)
+        .chain(digits.iter().rev().map(|digit| BASE58_ALPHABET[*digit as usize] as char))
+        .collect()
+}
+
+/// Encode a witness program as a BIP-173 bech32 (version 0) or BIP-350 bech32m (version 1+) address.
+fn segwit_address(hrp: &str, version: u8, program: &[u8]) -> String {
+    let mut data = vec![version];
+    // regroup the program into 5 bit values, padding the last one with zeros
+    let (mut accumulator, mut bits) = (0u32, 0u32);
+    for byte in program {
+        accumulator = (accumulator << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            data.push(((accumulator >> bits) & 31) as u8);
+        }
+    }
+    if bits > 0 {
+        data.push(((accumulator << (5 - bits)) & 31) as u8);
+    }
+
+    let constant = if version == 0 { BECH32_CONST } else { BECH32M_CONST };
+    let mut values: Vec<u8> = hrp.bytes().map(|c| c >> 5).collect();
+    values.push(0);
+    values.extend(hrp.bytes().map(|c| c & 31));
+    values.extend(&data);
+    values.extend([0u8; 6]);
+    let polymod = bech32_polymod(&values) ^ constant;
+    data.extend((0..6).map(|i| ((polymod >> (5 * (5 - i))) & 31) as u8));
+
+    let encoded: String = data.iter().map(|value| BECH32_CHARSET[*value as usize] as char).collect();
+    format!("{}1{}", hrp, encoded)
+}
+
+/// The BCH checksum polynomial of bech32.
+fn bech32_polymod(values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    let mut checksum: u32 = 1;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x1ffffff) << 5) ^ *value as u32;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+/// Write a Bitcoin `CompactSize` unsigned integer.
+fn write_compact_size(out: &mut Vec<u8>, value: u64) {
+    match value {
+        0..=0xfc => out.push(value as u8),
+        0xfd..=0xffff => {
+            out.push(0xfd);
+            out.extend_from_slice(&(value as u16).to_le_bytes());
+        }
+        0x10000..=0xffff_ffff => {
+            out.push(0xfe);
+            out.extend_from_slice(&(value as u32).to_le_bytes());
+        }
+        _ => {
+            out.push(0xff);
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+/// Write a byte string prefixed with its `CompactSize` length.
+fn write_var_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_compact_size(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// A cursor over serialized Bitcoin data.
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, position: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position == self.data.len()
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.position).copied()
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let end = self.position.checked_add(length)
+            .filter(|end| *end <= self.data.len())
+            .ok_or("Unexpected end of data".to_string())?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.bytes(N)?.try_into().expect("slice has the requested length"))
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn compact_size(&mut self) -> Result<u64, String> {
+        Ok(match self.u8()? {
+            0xfd => u16::from_le_bytes(self.array()?) as u64,
+            0xfe => self.u32()? as u64,
+            0xff => self.u64()?,
+            value => value as u64,
+        })
+    }
+
+    fn var_bytes(&mut self) -> Result<&'a [u8], String> {
+        let length = usize::try_from(self.compact_size()?).map_err(|_| "Length out of range".to_string())?;
+        self.bytes(length)
+    }
+}
+
+/// A transaction input.
+#[derive(Clone, Debug, PartialEq)]
+struct TxIn {
+    /// The ID of the spent transaction, in internal byte order.
+    previous_txid: [u8; 32],
+    vout: u32,
+    script_sig: Vec<u8>,
+    sequence: u32,
+}
+
+/// A transaction output.
+#[derive(Clone, Debug, PartialEq)]
+struct TxOut {
+    value: u64,
+    script_pubkey: Vec<u8>,
+}
+
+impl TxOut {
+    fn read(reader: &mut Reader) -> Result<TxOut, String> {
+        Ok(TxOut { value: reader.u64()?, script_pubkey: reader.var_bytes()?.to_vec() })
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.value.to_le_bytes());
+        write_var_bytes(out, &self.script_pubkey);
+    }
+}
+
+/// A Bitcoin transaction. Witnesses are skipped when parsing, as neither IDs nor sighashes commit to them.
+#[derive(Clone, Debug, PartialEq)]
+struct Tx {
+    version: u32,
+    inputs: Vec<TxIn>,
+    outputs: Vec<TxOut>,
+    lock_time: u32,
+}
+
+impl Tx {
+    /// Parse a transaction in network serialization, with or without witnesses.
+    fn parse(data: &[u8]) -> Result<Tx, String> {
+        let mut reader = Reader::new(data);
+        let version = reader.u32()?;
+        // a zero input count is the segwit marker, followed by the flag
+        let has_witness = reader.peek() == Some(0);
+        if has_witness && reader.bytes(2)? != [0x00, 0x01] {
+            return Err("Invalid segwit flag".to_string());
+        }
+        let inputs = (0..reader.compact_size()?)
+            .map(|_| Ok(TxIn {
+                previous_txid: reader.array()?,
+                vout: reader.u32()?,
+                script_sig: reader.var_bytes()?.to_vec(),
+                sequence: reader.u32()?,
+            }))
+            .collect::<Result<Vec<_>, String>>()?;
+        let outputs = (0..reader.compact_size()?)
+            .map(|_| TxOut::read(&mut reader))
+            .collect::<Result<Vec<_>, String>>()?;
+        if has_witness {
+            for _ in &inputs {
+                for _ in 0..reader.compact_size()? {
+                    reader.var_bytes()?;
+                }
+            }
+        }
+        let lock_time = reader.u32()?;
+        if !reader.is_empty() {
+            return Err("Trailing bytes after transaction".to_string());
+        }
+        Ok(Tx { version, inputs, outputs, lock_time })
+    }
+
+    /// Serialize the transaction without witnesses.
+    fn serialize(&self) -> Vec<u8> {
+        let mut out = self.version.to_le_bytes().to_vec();
+        write_compact_size(&mut out, self.inputs.len() as u64);
+        for input in &self.inputs {
+            out.extend_from_slice(&input.previous_txid);
+            out.extend_from_slice(&input.vout.to_le_bytes());
+            write_var_bytes(&mut out, &input.script_sig);
+            out.extend_from_slice(&input.sequence.to_le_bytes());
+        }
+        write_compact_size(&mut out, self.outputs.len() as u64);
+        for output in &self.outputs {
+            output.write(&mut out);
+        }
+        out.extend_from_slice(&self.lock_time.to_le_bytes());
+        out
+    }
+
+    /// The transaction ID in internal byte order.
+    fn txid(&self) -> [u8; 32] {
+        sha256d(&self.serialize())
+    }
+
+    /// Compute the BIP-143 `SIGHASH_ALL` sighash of a P2WPKH input.
+    fn segwit_sighash(&self, index: usize, key_hash: &[u8; 20], value: u64) -> [u8; 32] {
+        let input = &self.inputs[index];
+        let prevouts: Vec<u8> = self.inputs.iter()
+            .flat_map(|input| input.previous_txid.into_iter().chain(input.vout.to_le_bytes()))
+            .collect();
+        let sequences: Vec<u8> = self.inputs.iter().flat_map(|input| input.sequence.to_le_bytes()).collect();
+        let mut outputs = Vec::new();
+        for output in &self.outputs {
+            output.write(&mut outputs);
+        }
+
+        let mut preimage = self.version.to_le_bytes().to_vec();
+        preimage.extend_from_slice(&sha256d(&prevouts));
+        preimage.extend_from_slice(&sha256d(&sequences));
+        preimage.extend_from_slice(&input.previous_txid);
+        preimage.extend_from_slice(&input.vout.to_le_bytes());
+        // the script code of P2WPKH is the P2PKH script of the key hash
+        write_var_bytes(&mut preimage, &p2pkh_script(key_hash));
+        preimage.extend_from_slice(&value.to_le_bytes());
+        preimage.extend_from_slice(&input.sequence.to_le_bytes());
+        preimage.extend_from_slice(&sha256d(&outputs));
+        preimage.extend_from_slice(&self.lock_time.to_le_bytes());
+        preimage.extend_from_slice(&SIGHASH_ALL.to_le_bytes());
+        sha256d(&preimage)
+    }
+
+    /// Compute the BIP-341 `SIGHASH_DEFAULT` sighash of a Taproot key path input without annex.
+    ///
+    /// * `index` - The index of the signed input.
+    /// * `spent` - The outputs all inputs spend, in input order.
+    fn taproot_sighash(&self, index: usize, spent: &[TxOut]) -> [u8; 32] {
+        let prevouts: Vec<u8> = self.inputs.iter()
+            .flat_map(|input| input.previous_txid.into_iter().chain(input.vout.to_le_bytes()))
+            .collect();
+        let amounts: Vec<u8> = spent.iter().flat_map(|output| output.value.to_le_bytes()).collect();
+        let mut scripts = Vec::new();
+        for output in spent {
+            write_var_bytes(&mut scripts, &output.script_pubkey);
+        }
+        let sequences: Vec<u8> = self.inputs.iter().flat_map(|input| input.sequence.to_le_bytes()).collect();
+        let mut outputs = Vec::new();
+        for output in &self.outputs {
+            output.write(&mut outputs);
+        }
+
+        // the sighash epoch, followed by the SigMsg of BIP-341
+        let mut message = vec![0x00, SIGHASH_DEFAULT as u8];
+        message.extend_from_slice(&self.version.to_le_bytes());
+        message.extend_from_slice(&self.lock_time.to_le_bytes());
+        for data in [prevouts, amounts, scripts, sequences, outputs] {
+            message.extend_from_slice(&Sha256::digest(data));
+        }
+        // the spend type: key path, no annex
+        message.push(0x00);
+        message.extend_from_slice(&(index as u32).to_le_bytes());
+        tagged_hash("TapSighash", &message)
+    }
+
+    /// Compute the legacy `SIGHASH_ALL` sighash of an input: the transaction with every scriptSig emptied
+    /// except the signed input's, which is replaced with the script it spends.
+    fn legacy_sighash(&self, index: usize, script_pubkey: &[u8]) -> [u8; 32] {
+        let mut tx = self.clone();
+        for (i, input) in tx.inputs.iter_mut().enumerate() {
+            input.script_sig = if i == index { script_pubkey.to_vec() } else { Vec::new() };
+        }
+        let mut preimage = tx.serialize();
+        preimage.extend_from_slice(&SIGHASH_ALL.to_le_bytes());
+        sha256d(&preimage)
+    }
+}
+
+/// The key-value pairs of a PSBT map, in the order they were serialized.
+type PsbtMap = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// A BIP-174 partially signed Bitcoin transaction.
+#[derive(Clone, Debug, PartialEq)]
+struct Psbt {
+    global: PsbtMap,
+    tx: Tx,
+    inputs: Vec<PsbtMap>,
+    outputs: Vec<PsbtMap>,
+}
+
+impl Psbt {
+    fn parse(data: &[u8]) -> Result<Psbt, String> {
+        let mut reader = Reader::new(data);
+        if reader.bytes(PSBT_MAGIC.len()).ok() != Some(PSBT_MAGIC) {
+            return Err("Missing PSBT magic bytes".to_string());
+        }
+        let global = read_map(&mut reader)?;
+        let tx = value(&global, PSBT_GLOBAL_UNSIGNED_TX)
+            .ok_or("PSBT has no unsigned transaction".to_string())
+            .and_then(Tx::parse)?;
+        if tx.inputs.is_empty() {
+            return Err("PSBT transaction has no inputs".to_string());
+        }
+        if tx.inputs.iter().any(|input| !input.script_sig.is_empty()) {
+            return Err("PSBT transaction is not unsigned".to_string());
+        }
+        let inputs = tx.inputs.iter().map(|_| read_map(&mut reader)).collect::<Result<_, String>>()?;
+        let outputs = tx.outputs.iter().map(|_| read_map(&mut reader)).collect::<Result<_, String>>()?;
+        if !reader.is_empty() {
+            return Err("Trailing bytes after PSBT".to_string());
+        }
+        Ok(Psbt { global, tx, inputs, outputs })
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut out = PSBT_MAGIC.to_vec();
+        for map in std::iter::once(&self.global).chain(&self.inputs).chain(&self.outputs) {
+            for (key, value) in map {
+                write_var_bytes(&mut out, key);
+                write_var_bytes(&mut out, value);
+            }
+            out.push(0x00);
+        }
+        out
+    }
+
+    /// The output an input spends, and whether it was taken from the full previous transaction.
+    fn spent_output(&self, index: usize) -> Result<(TxOut, bool), String> {
+        let (input, map) = (&self.tx.inputs[index], &self.inputs[index]);
+        if let Some(previous_tx) = value(map, PSBT_IN_NON_WITNESS_UTXO) {
+            let previous_tx = Tx::parse(previous_tx)?;
+            if previous_tx.txid() != input.previous_txid {
+                return Err(format!("Input {} has a non-witness UTXO of a different transaction", index));
+            }
+            let output = previous_tx.outputs.get(input.vout as usize)
+                .ok_or(format!("Input {} spends an output its non-witness UTXO does not have", index))?;
+            return Ok((output.clone(), true));
+        }
+        let output = value(map, PSBT_IN_WITNESS_UTXO)
+            .ok_or(format!("Input {} has no UTXO", index))?;
+        let mut reader = Reader::new(output);
+        let output = TxOut::read(&mut reader)?;
+        if !reader.is_empty() {
+            return Err(format!("Input {} has a malformed witness UTXO", index));
+        }
+        Ok((output, false))
+    }
+
+    /// Compute the sighash of an input if it spends an output of the wallet.
+    ///
+    /// * `index` - The index of the input.
+    /// * `spent` - The outputs all inputs spend, in input order, and whether they were taken from the full
+    ///   previous transaction.
+    /// * `signer` - The wallet's key.
+    ///
+    /// Returns an error message if the wallet cannot sign the input as the PSBT requests.
+    fn sighash(&self, index: usize, spent: &[(TxOut, bool)], signer: BtcSigner) -> Result<Option<[u8; 32]>, String> {
+        let (utxo, has_previous_tx) = &spent[index];
+        match signer {
+            BtcSigner::Ecdsa(public_key) => {
+                let key_hash = hash160(public_key);
+                if utxo.script_pubkey == p2wpkh_script(&key_hash) {
+                    self.check_sighash_type(index, SIGHASH_ALL)?;
+                    Ok(Some(self.tx.segwit_sighash(index, &key_hash, utxo.value)))
+                } else if utxo.script_pubkey == p2pkh_script(&key_hash) {
+                    if !has_previous_tx {
+                        return Err(format!("Input {} spends a P2PKH output but has no non-witness UTXO", index));
+                    }
+                    self.check_sighash_type(index, SIGHASH_ALL)?;
+                    Ok(Some(self.tx.legacy_sighash(index, &utxo.script_pubkey)))
+                } else {
+                    Ok(None)
+                }
+            }
+            BtcSigner::Taproot(output_key) if utxo.script_pubkey == p2tr_script(output_key) => {
+                self.check_sighash_type(index, SIGHASH_DEFAULT)?;
+                let spent: Vec<TxOut> = spent.iter().map(|(output, _)| output.clone()).collect();
+                Ok(Some(self.tx.taproot_sighash(index, &spent)))
+            }
+            BtcSigner::Taproot(_) => Ok(None),
+        }
+    }
+
+    /// Reject inputs that ask to be signed with another sighash type than the one the wallet signs with.
+    fn check_sighash_type(&self, index: usize, expected: u32) -> Result<(), String> {
+        match value(&self.inputs[index], PSBT_IN_SIGHASH_TYPE) {
+            Some(sighash_type) if sighash_type != expected.to_le_bytes() =>
+                Err(format!("Input {} requests a sighash type the wallet does not sign with", index)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Read a PSBT map up to its `0x00` separator.
+fn read_map(reader: &mut Reader) -> Result<PsbtMap, String> {
+    let mut map: PsbtMap = Vec::new();
+    loop {
+        let key = reader.var_bytes()?;
+        if key.is_empty() {
+            return Ok(map);
+        }
+        if map.iter().any(|(existing, _)| existing == key) {
+            return Err("Duplicate key in PSBT".to_string());
+        }
+        map.push((key.to_vec(), reader.var_bytes()?.to_vec()));
+    }
+}
+
+/// The value of a key that consists of its key type only.
+fn value(map: &PsbtMap, key_type: u8) -> Option<&[u8]> {
+    map.iter()
+        .find(|(key, _)| key.as_slice() == [key_type])
+        .map(|(_, value)| value.as_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libsecp256k1::{sign, Message, PublicKey, SecretKey};
+
+    /// The unsigned transaction of the native P2WPKH example of BIP-143.
+    const BIP143_TX: &str = "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000";
+
+    /// The private key of the second input of the BIP-143 example.
+    const BIP143_KEY: &str = "619c335025c7f4012e556c2a58b2506e30b8511b53ade95ea316fd8c3286feb9";
+
+    fn compressed_key(secret: &str) -> Vec<u8> {
+        let secret = SecretKey::parse_slice(&hex::decode(secret).unwrap()).unwrap();
+        PublicKey::from_secret_key(&secret).serialize_compressed().to_vec()
+    }
+
+    fn witness_utxo(value: u64, script: &str) -> Vec<u8> {
+        let mut out = Vec::new();
+        TxOut { value, script_pubkey: hex::decode(script).unwrap() }.write(&mut out);
+        out
+    }
+
+    fn encode_psbt(tx: &str, inputs: Vec<PsbtMap>) -> String {
+        let tx = Tx::parse(&hex::decode(tx).unwrap()).unwrap();
+        let outputs = tx.outputs.iter().map(|_| Vec::new()).collect();
+        let global = vec![(vec![PSBT_GLOBAL_UNSIGNED_TX], tx.serialize())];
+        BASE64.encode(Psbt { global, tx, inputs, outputs }.serialize())
+    }
+
+    fn bip143_psbt(second_input: PsbtMap) -> String {
+        let first_input = vec![(
+            vec![PSBT_IN_WITNESS_UTXO],
+            witness_utxo(625_000_000, "2103c9f4836b9a4f77fc0d81f7bcb01b7f1b35916864b9476c241ce9fc198bd25432ac"),
+        )];
+        encode_psbt(BIP143_TX, vec![first_input, second_input])
+    }
+
+    fn bip143_witness_utxo() -> PsbtMap {
+        vec![(vec![PSBT_IN_WITNESS_UTXO], witness_utxo(600_000_000, "00141d0f172a0ecb48aee1be1f2687d2963ae33f71a1"))]
+    }
+
+    #[test]
+    fn test_btc_address() {
+        let generator = hex::decode("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798").unwrap();
+        assert_eq!(btc_address(&generator, BtcNetwork::Mainnet, BtcAddressKind::P2wpkh), "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4");
+        assert_eq!(btc_address(&generator, BtcNetwork::Testnet, BtcAddressKind::P2wpkh), "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx");
+        assert_eq!(btc_address(&generator, BtcNetwork::Regtest, BtcAddressKind::P2wpkh), "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080");
+        assert_eq!(btc_address(&generator, BtcNetwork::Mainnet, BtcAddressKind::P2pkh), "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH");
+        assert_eq!(btc_address(&generator, BtcNetwork::Testnet, BtcAddressKind::P2pkh), "mrCDrCybB6J1vRfbwM5hemdJz73FwDBC8r");
+    }
+
+    #[test]
+    fn test_script_to_address() {
+        let p2sh = hex::decode("a914b472a266d0bd89c13706a4132ccfb16f7c3b9fcb87").unwrap();
+        assert_eq!(script_to_address(&p2sh, BtcNetwork::Mainnet).unwrap(), "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy");
+        // BIP-350 test vector of a version 1 witness program
+        let p2tr = hex::decode("5128751e76e8199196d454941c45d1b3a323f1433bd6751e76e8199196d454941c45d1b3a323f1433bd6").unwrap();
+        assert_eq!(script_to_address(&p2tr, BtcNetwork::Mainnet).unwrap(), "bc1pw508d6qejxtdg4y5r3zarvary0c5xw7kw508d6qejxtdg4y5r3zarvary0c5xw7kt5nd6y");
+        assert_eq!(script_to_address(&[0x6a, 0x01, 0x00], BtcNetwork::Mainnet), None);
+    }
+
+    #[test]
+    fn test_segwit_sighash_and_finalize() {
+        let public_key = compressed_key(BIP143_KEY);
+        let tx = BtcTransaction::parse(&bip143_psbt(bip143_witness_utxo()), BtcNetwork::Mainnet, BtcSigner::Ecdsa(&public_key)).unwrap();
+
+        assert_eq!(tx.txid, "3335ffae0df20c5407e8de12b49405c8e912371f00fe4132bfaf95ad49c40243");
+        assert_eq!(tx.inputs[0].sighash, None);
+        assert_eq!(tx.inputs[1].sighash.as_deref(), Some("c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670"));
+        assert_eq!(tx.inputs[1].address.as_deref(), Some("bc1qr583w2swedy2acd7rung055k8t3n7udp7vyzyg"));
+        assert_eq!(tx.outputs[0].value, 112_340_000);
+        assert_eq!(tx.outputs[0].address.as_deref(), Some("1Cu32FVupVCgHkMMRJdYJugxwo2Aprgk7H"));
+        assert!(!tx.outputs[0].is_change);
+        assert_eq!(tx.fee, 625_000_000 + 600_000_000 - 112_340_000 - 223_450_000);
+
+        // RFC 6979 signatures are deterministic, so this reproduces the signature of the BIP-143 example
+        let secret = SecretKey::parse_slice(&hex::decode(BIP143_KEY).unwrap()).unwrap();
+        let sighash = tx.sighashes().remove(0);
+        let (signature, _) = sign(&Message::parse_slice(&sighash).unwrap(), &secret);
+        let signature = der_signature(&signature.serialize()).unwrap();
+        assert_eq!(hex::encode(&signature), "304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee01");
+
+        let finalized = tx.finalize(BtcSigner::Ecdsa(&public_key), std::slice::from_ref(&signature)).unwrap();
+        let psbt = Psbt::parse(&BASE64.decode(finalized).unwrap()).unwrap();
+        let mut witness = vec![2];
+        write_var_bytes(&mut witness, &signature);
+        write_var_bytes(&mut witness, &public_key);
+        assert_eq!(value(&psbt.inputs[1], PSBT_IN_FINAL_SCRIPTWITNESS), Some(witness.as_slice()));
+        assert_eq!(value(&psbt.inputs[1], PSBT_IN_WITNESS_UTXO), Some(witness_utxo(600_000_000, "00141d0f172a0ecb48aee1be1f2687d2963ae33f71a1").as_slice()));
+        assert_eq!(psbt.inputs[0], Psbt::parse(&BASE64.decode(&tx.psbt).unwrap()).unwrap().inputs[0]);
+
+        assert!(tx.finalize(BtcSigner::Ecdsa(&public_key), &[]).is_err());
+        assert!(tx.finalize(BtcSigner::Ecdsa(&public_key), &[signature.clone(), signature]).is_err());
+    }
+
+    #[test]
+    fn test_legacy_sighash_and_finalize() {
+        let previous_tx = "02000000010000000000000000000000000000000000000000000000000000000000000000ffffffff00ffffffff01a0860100000000001976a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac00000000";
+        let spending_tx = "020000000151900ad7d399f762a01b523e1e9ad3ac2215bf64fd81d501ba3a5f713b411d1a0000000000fdffffff01905f0100000000001600141d0f172a0ecb48aee1be1f2687d2963ae33f71a100000000";
+        let public_key = compressed_key(BIP143_KEY);
+
+        let psbt = encode_psbt(spending_tx, vec![vec![(vec![PSBT_IN_NON_WITNESS_UTXO], hex::decode(previous_tx).unwrap())]]);
+        let tx = BtcTransaction::parse(&psbt, BtcNetwork::Mainnet, BtcSigner::Ecdsa(&public_key)).unwrap();
+        assert_eq!(tx.inputs[0].txid, "1a1d413b715f3aba01d581fd64bf1522acd39a1e3e521ba062f799d3d70a9051");
+        assert_eq!(tx.inputs[0].address.as_deref(), Some("13eeg4y5wYGxNTxBEuWLPFauoMJQLxdoip"));
+        assert_eq!(tx.inputs[0].sighash.as_deref(), Some("547c212c9cf2f76afc47858b3d72cb5c6b09f6e59b51556f9ba7f8b5f0db11ac"));
+        assert!(tx.outputs[0].is_change);
+        assert_eq!(tx.fee, 10_000);
+
+        let secret = SecretKey::parse_slice(&hex::decode(BIP143_KEY).unwrap()).unwrap();
+        let (signature, _) = sign(&Message::parse_slice(&tx.sighashes()[0]).unwrap(), &secret);
+        let signature = der_signature(&signature.serialize()).unwrap();
+        let finalized = tx.finalize(BtcSigner::Ecdsa(&public_key), std::slice::from_ref(&signature)).unwrap();
+        let psbt = Psbt::parse(&BASE64.decode(finalized).unwrap()).unwrap();
+        let mut script_sig = push_data(&signature);
+        script_sig.extend(push_data(&public_key));
+        assert_eq!(value(&psbt.inputs[0], PSBT_IN_FINAL_SCRIPTSIG), Some(script_sig.as_slice()));
+
+        // a P2PKH input must carry the transaction it spends from
+        let witness_only = encode_psbt(spending_tx, vec![vec![(
+            vec![PSBT_IN_WITNESS_UTXO],
+            witness_utxo(100_000, "76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac"),
+        )]]);
+        assert!(BtcTransaction::parse(&witness_only, BtcNetwork::Mainnet, BtcSigner::Ecdsa(&public_key)).is_err());
+
+        // and that transaction must be the one the input spends
+        let other_tx = previous_tx.replace("a0860100", "a0860200");
+        let mismatched = encode_psbt(spending_tx, vec![vec![(vec![PSBT_IN_NON_WITNESS_UTXO], hex::decode(other_tx).unwrap())]]);
+        assert!(BtcTransaction::parse(&mismatched, BtcNetwork::Mainnet, BtcSigner::Ecdsa(&public_key)).is_err());
+    }
+
+    #[test]
+    fn test_taproot_sighash_and_finalize() {
+        // the output key of the first receiving address of the BIP-86 test vectors
+        let output_key: [u8; 32] = hex::decode("a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c").unwrap().try_into().unwrap();
+        assert_eq!(taproot_address(&output_key, BtcNetwork::Mainnet), "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr");
+
+        let spending_tx = "020000000201010101010101010101010101010101010101010101010101010101010101010100000000fdffffff02020202020202020202020202020202020202020202020202020202020202020000000000ffffffff0160ea000000000000160014333333333333333333333333333333333333333300350c00";
+        let p2tr = hex::encode(p2tr_script(&output_key));
+        let mut taproot_input = vec![(vec![PSBT_IN_WITNESS_UTXO], witness_utxo(50_000, &p2tr))];
+        taproot_input.push((vec![PSBT_IN_TAP_MERKLE_ROOT], vec![0; 32]));
+        let foreign_input = vec![(vec![PSBT_IN_WITNESS_UTXO], witness_utxo(20_000, "00142222222222222222222222222222222222222222"))];
+        let psbt = encode_psbt(spending_tx, vec![taproot_input, foreign_input]);
+
+        let signer = BtcSigner::Taproot(&output_key);
+        let tx = BtcTransaction::parse(&psbt, BtcNetwork::Mainnet, signer).unwrap();
+        assert_eq!(tx.inputs[0].address.as_deref(), Some("bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr"));
+        assert_eq!(tx.inputs[0].sighash.as_deref(), Some("edbea17ce1083056591c16057b58e7c27b69b951ce98fb7536df9cd1a94392db"));
+        assert_eq!(tx.inputs[1].sighash, None);
+        assert_eq!(tx.fee, 10_000);
+
+        let signature = vec![0x5a; 64];
+        let finalized = tx.finalize(signer, std::slice::from_ref(&signature)).unwrap();
+        let finalized = Psbt::parse(&BASE64.decode(finalized).unwrap()).unwrap();
+        assert_eq!(value(&finalized.inputs[0], PSBT_IN_FINAL_SCRIPTWITNESS), Some(encode_witness(&[&signature]).as_slice()));
+        assert_eq!(value(&finalized.inputs[0], PSBT_IN_TAP_MERKLE_ROOT), None);
+
+        // the same transaction has no input the ECDSA key can sign, and Taproot inputs are only signed with SIGHASH_DEFAULT
+        let public_key = compressed_key(BIP143_KEY);
+        assert!(BtcTransaction::parse(&psbt, BtcNetwork::Mainnet, BtcSigner::Ecdsa(&public_key)).is_err());
+        let all = encode_psbt(spending_tx, vec![
+            vec![(vec![PSBT_IN_WITNESS_UTXO], witness_utxo(50_000, &p2tr)), (vec![PSBT_IN_SIGHASH_TYPE], SIGHASH_ALL.to_le_bytes().to_vec())],
+            vec![(vec![PSBT_IN_WITNESS_UTXO], witness_utxo(20_000, "00142222222222222222222222222222222222222222"))],
+        ]);
+        assert!(BtcTransaction::parse(&all, BtcNetwork::Mainnet, signer).is_err());
+    }
+
+    #[test]
+    fn test_der_signature_is_low_s() {
+        // n - 1 is a high S value, which is negated to 1
+        let mut signature = [0u8; 64];
+        signature[31] = 1;
+        signature[32..].copy_from_slice(&hex::decode("fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364140").unwrap());
+        assert_eq!(hex::encode(der_signature(&signature).unwrap()), "300602010102010101");
+    }
+
+    #[test]
+    fn test_rejects_invalid_psbt() {
+        let public_key = compressed_key(BIP143_KEY);
+        let parse = |psbt: &str| BtcTransaction::parse(psbt, BtcNetwork::Mainnet, BtcSigner::Ecdsa(&public_key));
+
+        assert!(parse("not base64!").is_err());
+        assert!(parse(&BASE64.encode(b"psbt")).is_err());
+        assert!(parse(&BASE64.encode(hex::decode(BIP143_TX).unwrap())).is_err());
+        // the wallet owns none of the inputs
+        assert!(parse(&bip143_psbt(vec![(vec![PSBT_IN_WITNESS_UTXO], witness_utxo(600_000_000, "0014ffffffffffffffffffffffffffffffffffffffff"))])).is_err());
+        // an input without a UTXO
+        assert!(parse(&bip143_psbt(Vec::new())).is_err());
+        // the outputs spend more than the inputs
+        let dust = vec![(vec![PSBT_IN_WITNESS_UTXO], witness_utxo(1, "00141d0f172a0ecb48aee1be1f2687d2963ae33f71a1"))];
+        assert!(parse(&encode_psbt(BIP143_TX, vec![dust.clone(), dust])).is_err());
+        // only SIGHASH_ALL is signed
+        let mut single = bip143_witness_utxo();
+        single.push((vec![PSBT_IN_SIGHASH_TYPE], 3u32.to_le_bytes().to_vec()));
+        assert!(parse(&bip143_psbt(single)).is_err());
+        let mut all = bip143_witness_utxo();
+        all.push((vec![PSBT_IN_SIGHASH_TYPE], SIGHASH_ALL.to_le_bytes().to_vec()));
+        assert!(parse(&bip143_psbt(all)).is_ok());
+    }
+}
@@ -0,0 +1,300 @@
+use candid::{CandidType, Nat};
+use serde::Deserialize;
+use crate::abi::{self, decode, ContractAbi, ParamType};
+use crate::eth::{decode_hex, normalize_address};
+use crate::wallet::{MultiSignatureWallet, Proposal, ProposalKind, Wallet};
+
+/// A well-known function: the standard it belongs to, its signature and the names of its arguments.
+struct KnownFunction {
+    standard: &'static str,
+    signature: &'static str,
+    arguments: &'static [&'static str],
+}
+
+/// The functions every co-signer should be able to review without an ABI.
+const KNOWN_FUNCTIONS: [KnownFunction; 10] = [
+    KnownFunction { standard: "ERC-20", signature: "transfer(address,uint256)", arguments: &["to", "amount"] },
+    KnownFunction { standard: "ERC-20", signature: "approve(address,uint256)", arguments: &["spender", "amount"] },
+    // ERC-721 shares the selector, in which case the last argument is the token ID
+    KnownFunction { standard: "ERC-20/ERC-721", signature: "transferFrom(address,address,uint256)", arguments: &["from", "to", "amount"] },
+    KnownFunction { standard: "ERC-721", signature: "safeTransferFrom(address,address,uint256)", arguments: &["from", "to", "tokenId"] },
+    KnownFunction { standard: "ERC-721", signature: "safeTransferFrom(address,address,uint256,bytes)", arguments: &["from", "to", "tokenId", "data"] },
+    KnownFunction { standard: "ERC-721/ERC-1155", signature: "setApprovalForAll(address,bool)", arguments: &["operator", "approved"] },
+    KnownFunction { standard: "ERC-1155", signature: "safeTransferFrom(address,address,uint256,uint256,bytes)", arguments: &["from", "to", "id", "amount", "data"] },
+    KnownFunction { standard: "ERC-1155", signature: "safeBatchTransferFrom(address,address,uint256[],uint256[],bytes)", arguments: &["from", "to", "ids", "amounts", "data"] },
+    KnownFunction { standard: "WETH", signature: "deposit()", arguments: &[] },
+    KnownFunction { standard: "WETH", signature: "withdraw(uint256)", arguments: &["amount"] },
+];
+
+/// A decoded argument of a contract call.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct DecodedArgument {
+    /// The name of the argument, e.g. `to` or `amount`.
+    pub name: String,
+    /// The Solidity type of the argument.
+    pub type_name: String,
+    /// The human-readable value: addresses and bytes in hex, integers in decimal.
+    pub value: String,
+}
+
+/// The human-readable form of a transaction's calldata.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct DecodedCall {
+    /// The called contract, i.e. the token for token standards.
+    pub contract: String,
+    /// The standard the function belongs to, e.g. `ERC-20`, or the name of the contract's registered ABI.
+    pub standard: String,
+    /// The hex encoded 4 byte function selector.
+    pub selector: String,
+    /// The function signature, e.g. `transfer(address,uint256)`.
+    pub function: String,
+    /// The decoded arguments.
+    pub arguments: Vec<DecodedArgument>,
+    /// The wei sent along with the call, e.g. the amount wrapped by `deposit()`.
+    pub value: Nat,
+    /// Set if the calldata matches neither a registered nor a well-known function, so signers would sign it blind.
+    pub unknown_selector: bool,
+}
+
+/// A proposal together with its decoded calldata.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct ProposalWithCall {
+    /// The proposal.
+    pub proposal: Proposal,
+    /// The decoded call, `None` if the proposal is not a contract call.
+    pub decoded_call: Option<DecodedCall>,
+}
+
+impl ProposalWithCall {
+    /// Decode the calldata of a proposal with the ABIs registered in its wallet.
+    ///
+    /// * `wallet` - The wallet the proposal belongs to.
+    /// * `proposal` - The proposal.
+    pub fn new(wallet: &Wallet, proposal: Proposal) -> Self {
+        let decoded_call = decode_proposal(wallet, &proposal.kind);
+        ProposalWithCall { proposal, decoded_call }
+    }
+}
+
+/// Decode the calldata of a proposed transaction.
+///
+/// # Arguments
+///
+/// * `wallet` - The wallet whose registered ABIs are used to decode calls to its contracts.
+/// * `kind` - The kind of the proposal.
+///
+/// # Returns
+///
+/// * `Option<DecodedCall>` - The decoded call, or `None` if the proposal is not a contract call.
+pub fn decode_proposal(wallet: &Wallet, kind: &ProposalKind) -> Option<DecodedCall> {
+    let (to, data, value) = match kind {
+        ProposalKind::LegacyTransaction(tx) => (&tx.to, &tx.data, &tx.value),
+        ProposalKind::Eip2930Transaction(tx) => (&tx.to, &tx.data, &tx.value),
+        ProposalKind::Eip1559Transaction(tx) => (&tx.to, &tx.data, &tx.value),
+        _ => return None,
+    };
+    let contract = normalize_address(to.as_ref()?).ok()?;
+    decode_call(&contract, &decode_hex(data).ok()?, value, wallet.get_abi(&contract))
+}
+
+/// Decode a call with the well-known token functions, falling back to the contract's registered ABI.
+///
+/// A registered ABI never changes how a well-known function reads, so a signer cannot register an ABI that
+/// presents a token transfer as something else.
+///
+/// # Arguments
+///
+/// * `contract` - The normalized address of the called contract.
+/// * `data` - The calldata, starting with the function selector.
+/// * `value` - The wei sent along with the call.
+/// * `abi` - The ABI registered for the contract, if any.
+///
+/// # Returns
+///
+/// * `Option<DecodedCall>` - The decoded call, or `None` if there is no calldata. Calldata that matches no
+///   function is returned with `unknown_selector` set.
+pub fn decode_call(contract: &str, data: &[u8], value: &Nat, abi: Option<&ContractAbi>) -> Option<DecodedCall> {
+    if data.is_empty() {
+        return None;
+    }
+    let selector = hex::encode(&data[..data.len().min(4)]);
+    let call = |standard: &str, function: &str, arguments: Vec<DecodedArgument>| DecodedCall {
+        contract: contract.to_string(),
+        standard: standard.to_string(),
+        selector: selector.clone(),
+        function: function.to_string(),
+        arguments,
+        value: value.clone(),
+        unknown_selector: false,
+    };
+
+    let known = KNOWN_FUNCTIONS.iter()
+        .find(|function| hex::encode(abi::selector(function.signature)) == selector)
+        .and_then(|function| {
+            let names = function.arguments.iter().copied();
+            let arguments = decode_arguments(names.zip(parameter_type_names(function.signature)).collect(), &data[4..])?;
+            Some(call(function.standard, function.signature, arguments))
+        });
+    if known.is_some() {
+        return known;
+    }
+
+    let registered = abi.and_then(|abi| {
+        let function = abi.functions.iter().find(|function| function.selector == selector)?;
+        let names = function.inputs.iter().map(|input| input.name.as_str());
+        let type_names = function.inputs.iter().map(|input| input.type_name.as_str());
+        let arguments = decode_arguments(names.zip(type_names).collect(), &data[4..])?;
+        Some(call(&abi.name, &function.signature, arguments))
+    });
+    if registered.is_some() {
+        return registered;
+    }
+
+    Some(DecodedCall { unknown_selector: true, ..call("", "", Vec::new()) })
+}
+
+/// Decode the arguments of a call, given their names and canonical types.
+fn decode_arguments(parameters: Vec<(&str, &str)>, data: &[u8]) -> Option<Vec<DecodedArgument>> {
+    let types = parameters.iter()
+        .map(|(_, type_name)| ParamType::parse(type_name).ok())
+        .collect::<Option<Vec<_>>>()?;
+    let values = decode(&types, data).ok()?;
+    Some(parameters.into_iter()
+        .zip(values)
+        .map(|((name, type_name), value)| DecodedArgument {
+            name: name.to_string(),
+            type_name: type_name.to_string(),
+            value,
+        })
+        .collect())
+}
+
+/// The parameter types of a function signature, e.g. `["address", "uint256"]`.
+fn parameter_type_names(signature: &str) -> Vec<&str> {
+    let start = signature.find('(').map_or(0, |index| index + 1);
+    let parameters = signature[start..].trim_end_matches(')');
+    if parameters.is_empty() {
+        return Vec::new();
+    }
+    parameters.split(',').collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &str = "0x6B175474E89094C44Da98b954EedeAC495271d0F";
+    const RECIPIENT: &str = "3535353535353535353535353535353535353535";
+
+    fn call(selector: &str, words: &[&str]) -> Vec<u8> {
+        let mut data = hex::decode(selector).unwrap();
+        for word in words {
+            data.extend(hex::decode(format!("{:0>64}", word)).unwrap());
+        }
+        data
+    }
+
+    fn arguments(call: &DecodedCall) -> Vec<(&str, &str)> {
+        call.arguments.iter().map(|argument| (argument.name.as_str(), argument.value.as_str())).collect()
+    }
+
+    #[test]
+    fn test_known_selectors() {
+        let expected = [
+            "a9059cbb", "095ea7b3", "23b872dd", "42842e0e", "b88d4fde",
+            "a22cb465", "f242432a", "2eb2c2d6", "d0e30db0", "2e1a7d4d",
+        ];
+        for (function, selector) in KNOWN_FUNCTIONS.iter().zip(expected) {
+            assert_eq!(hex::encode(abi::selector(function.signature)), selector, "{}", function.signature);
+            assert_eq!(parameter_type_names(function.signature).len(), function.arguments.len());
+        }
+    }
+
+    #[test]
+    fn test_decode_erc20_transfer() {
+        // transfer 1 DAI
+        let data = call("a9059cbb", &[RECIPIENT, "de0b6b3a7640000"]);
+        let decoded = decode_call(TOKEN, &data, &Nat::from(0u8), None).unwrap();
+
+        assert_eq!(decoded.contract, TOKEN);
+        assert!(!decoded.unknown_selector);
+        assert_eq!(decoded.standard, "ERC-20");
+        assert_eq!(decoded.function, "transfer(address,uint256)");
+        assert_eq!(
+            arguments(&decoded),
+            vec![("to", format!("0x{}", RECIPIENT).as_str()), ("amount", "1000000000000000000")]
+        );
+        assert_eq!(decoded.arguments[1].type_name, "uint256");
+    }
+
+    #[test]
+    fn test_decode_erc1155_batch_transfer() {
+        let data = call("2eb2c2d6", &[
+            RECIPIENT, RECIPIENT, "a0", "100", "160",
+            "2", "1", "2",
+            "2", "a", "14",
+            "0",
+        ]);
+        let decoded = decode_call(TOKEN, &data, &Nat::from(0u8), None).unwrap();
+
+        assert_eq!(decoded.standard, "ERC-1155");
+        let arguments = arguments(&decoded);
+        assert_eq!(arguments[2], ("ids", "[1, 2]"));
+        assert_eq!(arguments[3], ("amounts", "[10, 20]"));
+        assert_eq!(arguments[4], ("data", "0x"));
+    }
+
+    #[test]
+    fn test_decode_weth() {
+        let value = Nat::from(5u8);
+        let deposit = decode_call(TOKEN, &call("d0e30db0", &[]), &value, None).unwrap();
+        assert_eq!(deposit.standard, "WETH");
+        assert!(deposit.arguments.is_empty());
+        assert_eq!(deposit.value, value);
+
+        let withdraw = decode_call(TOKEN, &call("2e1a7d4d", &["5"]), &Nat::from(0u8), None).unwrap();
+        assert_eq!(arguments(&withdraw), vec![("amount", "5")]);
+    }
+
+    #[test]
+    fn test_decode_unknown_or_malformed() {
+        let value = Nat::from(0u8);
+        assert_eq!(decode_call(TOKEN, &[], &value, None), None);
+
+        let unknown = decode_call(TOKEN, &call("deadbeef", &[RECIPIENT]), &value, None).unwrap();
+        assert!(unknown.unknown_selector);
+        assert_eq!(unknown.selector, "deadbeef");
+        assert!(unknown.arguments.is_empty());
+
+        // a transfer without an amount
+        let malformed = decode_call(TOKEN, &call("a9059cbb", &[RECIPIENT]), &value, None).unwrap();
+        assert!(malformed.unknown_selector);
+    }
+
+    #[test]
+    fn test_decode_registered_abi() {
+        let json = r#"[
+            {"type": "function", "name": "deposit", "inputs": [{"name": "assets", "type": "uint256"}]},
+            {"type": "function", "name": "transfer", "inputs": [{"name": "recipient", "type": "address"}, {"name": "shares", "type": "uint256"}]}
+        ]"#;
+        let abi = ContractAbi::parse(TOKEN.to_string(), "Vault".to_string(), json).unwrap();
+        let value = Nat::from(0u8);
+
+        let deposit = decode_call(TOKEN, &call("b6b55f25", &["64"]), &value, Some(&abi)).unwrap();
+        assert_eq!(deposit.standard, "Vault");
+        assert_eq!(deposit.function, "deposit(uint256)");
+        assert_eq!(arguments(&deposit), vec![("assets", "100")]);
+
+        // the built-in functions take precedence over the registered ABI
+        let transfer = decode_call(TOKEN, &call("a9059cbb", &[RECIPIENT, "1"]), &value, Some(&abi)).unwrap();
+        assert_eq!(transfer.standard, "ERC-20");
+        assert_eq!(transfer.arguments[0].name, "to");
+
+        // built-in functions are still decoded for registered contracts
+        let approve = decode_call(TOKEN, &call("095ea7b3", &[RECIPIENT, "1"]), &value, Some(&abi)).unwrap();
+        assert_eq!(approve.standard, "ERC-20");
+
+        let unknown = decode_call(TOKEN, &call("deadbeef", &[]), &value, Some(&abi)).unwrap();
+        assert!(unknown.unknown_selector);
+    }
+}
@@ -1,9 +1,14 @@
 use libsecp256k1::{Message, PublicKey, PublicKeyFormat, recover, RecoveryId, Signature};
-use candid::Principal;
+use candid::{CandidType, Principal};
+use serde::Deserialize;
 use ic_cdk::api::management_canister::ecdsa::{EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument, EcdsaPublicKeyResponse, SignWithEcdsaArgument, SignWithEcdsaResponse};
+use crate::wallet::{AccountIndex, DEFAULT_ACCOUNT};
 
 const DEFAULT_ECDSA_SIGN_CYCLES : u64 = 10_000_000_000;
 
+/// The offset Ethereum adds to the recovery ID to form `v` in `personal_sign` and EIP-712 signatures.
+pub const ETH_V_OFFSET: u8 = 27;
+
 /// Get the EcdsaKeyId from the environment.
 ///
 /// # Arguments
@@ -61,6 +66,86 @@ pub fn keccak256(bytes: &[u8]) -> [u8; 32] {
     output
 }
 
+/// Compute the EIP-191 `personal_sign` hash of a message.
+///
+/// # Arguments
+///
+/// * `message` - The message in bytes.
+///
+/// # Returns
+///
+/// * `[u8; 32]` - `keccak256("\x19Ethereum Signed Message:\n" + len(message) + message)`.
+///
+/// # Example
+///
+/// ```
+/// let hash = personal_message_hash(b"Hello World");
+/// ```
+pub fn personal_message_hash(message: &[u8]) -> [u8; 32] {
+    let mut data = format!("\x19Ethereum Signed Message:\n{}", message.len()).into_bytes();
+    data.extend_from_slice(message);
+    keccak256(&data)
+}
+
+/// Encode an Ethereum address with the EIP-55 mixed-case checksum.
+///
+/// # Arguments
+///
+/// * `address` - The 20 byte address.
+///
+/// # Returns
+///
+/// * `String` - The `0x` prefixed address, with each letter uppercased if the matching nibble of
+///   `keccak256(lowercase hex)` is at least 8.
+///
+/// # Example
+///
+/// ```
+/// let address = to_checksum_address(&[0x5a; 20]);
+/// ```
+pub fn to_checksum_address(address: &[u8; 20]) -> String {
+    let lowercase = hex::encode(address);
+    let hash = keccak256(lowercase.as_bytes());
+    let checksummed: String = lowercase.chars()
+        .enumerate()
+        .map(|(index, c)| {
+            let nibble = if index % 2 == 0 { hash[index / 2] >> 4 } else { hash[index / 2] & 0x0f };
+            if nibble >= 8 { c.to_ascii_uppercase() } else { c }
+        })
+        .collect();
+    format!("0x{}", checksummed)
+}
+
+/// Parse an Ethereum address and validate its EIP-55 checksum.
+///
+/// Addresses in a single case carry no checksum and are accepted as they are.
+///
+/// # Arguments
+///
+/// * `address` - The hex address with an optional `0x` prefix.
+///
+/// # Returns
+///
+/// * `Result<[u8; 20], String>` - The address bytes, or an error message if the address is malformed
+///   or its checksum does not match.
+///
+/// # Example
+///
+/// ```
+/// let address = parse_checksum_address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed")?;
+/// ```
+pub fn parse_checksum_address(address: &str) -> Result<[u8; 20], String> {
+    let digits = address.strip_prefix("0x").unwrap_or(address);
+    let bytes: [u8; 20] = hex::decode(digits).ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(format!("Invalid address: {}", address))?;
+    let mixed_case = digits.chars().any(|c| c.is_ascii_lowercase()) && digits.chars().any(|c| c.is_ascii_uppercase());
+    if mixed_case && to_checksum_address(&bytes)[2..] != *digits {
+        return Err(format!("Invalid address checksum: {}", address));
+    }
+    Ok(bytes)
+}
+
 /// Find the recovery ID for a given ECDSA signature.
 ///
 /// # Arguments
@@ -82,12 +167,12 @@ pub fn keccak256(bytes: &[u8]) -> [u8; 32] {
 /// let recovery_id = find_recovery_id(message, &signature, public_key);
 /// ```
 pub fn find_recovery_id(msg: &[u8], sig: &[u8], known_pub_key: [u8; 65]) -> Option<u8> {
-    let message = Message::parse_slice(msg).expect("Invalid message");
+    let message = Message::parse_slice(msg).ok()?;
+    let signature = Signature::parse_overflowing_slice(sig).ok()?;
 
     // Try both possible recovery IDs
     for rec_id in [0u8, 1u8].iter() {
-        let recovery_id = RecoveryId::parse(*rec_id).expect("Invalid recovery ID");
-        let signature = Signature::parse_overflowing_slice(sig).expect("Invalid signature");
+        let recovery_id = RecoveryId::parse(*rec_id).ok()?;
 
         // Attempt to recover the public key
         if let Ok(pubkey) = recover(&message, &signature, &recovery_id) {
@@ -100,44 +185,154 @@ pub fn find_recovery_id(msg: &[u8], sig: &[u8], known_pub_key: [u8; 65]) -> Opti
     None
 }
 
-/// Asynchronously get the uncompressed public key.
+/// The key a signature is verified against.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub enum SignerKey {
+    /// An Ethereum address, validated against its EIP-55 checksum if it is in mixed case.
+    Address(String),
+    /// A hex encoded compressed or uncompressed public key.
+    PublicKey(String),
+}
+
+/// The data a signature was made over.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub enum SignedData {
+    /// A hex encoded 32 byte hash that was signed as it is.
+    Hash(String),
+    /// A message signed with the EIP-191 `personal_sign` prefix.
+    PersonalMessage(String),
+    /// EIP-712 typed data JSON.
+    TypedData(String),
+}
+
+/// The public key of a wallet, derived once from the threshold key and cached on the wallet.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct WalletPublicKey {
+    /// The threshold key the public key was derived from.
+    pub key_id: EcdsaKeyId,
+    /// The 33 byte SEC1 compressed public key.
+    pub compressed: Vec<u8>,
+    /// The 65 byte SEC1 uncompressed public key.
+    pub uncompressed: Vec<u8>,
+    /// The EIP-55 checksummed Ethereum address of the public key.
+    pub eth_address: String,
+}
+
+impl WalletPublicKey {
+    /// Decode a compressed public key as returned by `ecdsa_public_key`.
+    ///
+    /// * `key_id` - The threshold key the public key was derived from.
+    /// * `compressed` - The 33 byte compressed public key.
+    ///
+    /// Returns `Result<WalletPublicKey, String>` containing the public key in all formats or an error message.
+    pub fn from_compressed(key_id: EcdsaKeyId, compressed: &[u8]) -> Result<WalletPublicKey, String> {
+        let key = PublicKey::parse_slice(compressed, Some(PublicKeyFormat::Compressed))
+            .map_err(|_| "decompression public key failed: ".to_string())?;
+        let uncompressed = key.serialize();
+        Ok(WalletPublicKey {
+            key_id,
+            compressed: key.serialize_compressed().to_vec(),
+            uncompressed: uncompressed.to_vec(),
+            eth_address: public_key_to_eth_address(&uncompressed),
+        })
+    }
+
+    /// The uncompressed public key as the fixed size array `libsecp256k1` serializes to.
+    pub fn uncompressed_key(&self) -> Result<[u8; 65], String> {
+        self.uncompressed.as_slice().try_into().map_err(|_| "Invalid public key length".to_string())
+    }
+}
+
+/// Derive the Ethereum address of a public key: the last 20 bytes of the Keccak-256 hash of its coordinates.
+///
+/// # Arguments
+///
+/// * `pub_key` - The uncompressed public key.
+///
+/// # Returns
+///
+/// * `String` - The EIP-55 checksummed Ethereum address.
+///
+/// # Example
+///
+/// ```
+/// let address = public_key_to_eth_address(&wallet_public_key.uncompressed_key()?);
+/// ```
+pub fn public_key_to_eth_address(pub_key: &[u8; 65]) -> String {
+    to_checksum_address(&public_key_to_address(pub_key))
+}
+
+/// The 20 address bytes of an uncompressed public key.
+fn public_key_to_address(pub_key: &[u8; 65]) -> [u8; 20] {
+    let hash = keccak256(&pub_key[1..65]);
+    let mut result = [0u8; 20];
+    result.copy_from_slice(&hash[12..]);
+    result
+}
+
+/// Get the derivation path of an account's keys.
+///
+/// The default account derives from the wallet ID alone, so its keys are the wallet's keys from before accounts
+/// existed. Every other account appends its big-endian index.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet ID.
+/// * `account` - The index of the account.
+///
+/// # Returns
+///
+/// * `Vec<Vec<u8>>` - The derivation path.
+///
+/// # Example
+///
+/// ```
+/// let derivation_path = derivation_path("my_wallet_id", 1);
+/// ```
+pub fn derivation_path(wallet_id: &str, account: AccountIndex) -> Vec<Vec<u8>> {
+    let mut path = vec![wallet_id.as_bytes().to_vec()];
+    if account != DEFAULT_ACCOUNT {
+        path.push(account.to_be_bytes().to_vec());
+    }
+    path
+}
+
+/// Asynchronously derive the public key of a wallet's account with the management canister.
 ///
 /// # Arguments
 ///
 /// * `wallet_id` - The wallet ID as a String.
+/// * `account` - The index of the account.
 /// * `key_id` - The EcdsaKeyId.
 ///
 /// # Returns
 ///
-/// * `Result<[u8; 65], String>` - The uncompressed public key or an error message.
+/// * `Result<WalletPublicKey, String>` - The public key or an error message.
 ///
 /// # Example
 ///
 /// ```
 /// let wallet_id = "my_wallet_id".to_string();
 /// let key_id = get_ecdsa_key_id_from_env("test");
-/// let public_key = get_public_key(wallet_id, key_id).await?;
+/// let public_key = get_public_key(wallet_id, DEFAULT_ACCOUNT, key_id).await?;
 /// ```
 pub async fn get_public_key(
     wallet_id: String,
+    account: AccountIndex,
     key_id: EcdsaKeyId
-) -> Result<[u8; 65], String> {
+) -> Result<WalletPublicKey, String> {
     let ic = Principal::management_canister();
 
     let request = EcdsaPublicKeyArgument {
         canister_id: None,
-        derivation_path: vec![wallet_id.as_bytes().to_vec()],
-        key_id
+        derivation_path: derivation_path(&wallet_id, account),
+        key_id: key_id.clone(),
     };
     let (res,): (EcdsaPublicKeyResponse,) = ic_cdk::call(ic, "ecdsa_public_key", (request,))
         .await
         .map_err(|e| format!("Failed to call ecdsa_public_key {}", e.1))?;
 
-    let uncompressed_pub_key = match PublicKey::parse_slice(&res.public_key, Some(PublicKeyFormat::Compressed)) {
-        Ok(key) => { key.serialize() },
-        Err(_) => { return Err("decompression public key failed: ".to_string()); },
-    };
-    Ok(uncompressed_pub_key)
+    WalletPublicKey::from_compressed(key_id, &res.public_key)
 }
 
 
@@ -146,8 +341,10 @@ pub async fn get_public_key(
 /// # Arguments
 ///
 /// * `wallet_id` - The wallet ID as a String.
+/// * `account` - The index of the account whose key signs.
 /// * `message_hash` - The hash of the message to be signed.
 /// * `key_id` - The EcdsaKeyId.
+/// * `pub_key` - The wallet's uncompressed public key, used to find the recovery ID.
 ///
 /// # Returns
 ///
@@ -160,15 +357,22 @@ pub async fn get_public_key(
 /// let message = b"example message";
 /// let message_hash = keccak256(message);
 /// let key_id = get_ecdsa_key_id_from_env("test");
-/// let signature = sign_message(wallet_id, message_hash.to_vec(), key_id).await?;
+/// let pub_key = get_public_key(wallet_id.clone(), DEFAULT_ACCOUNT, key_id.clone()).await?.uncompressed_key()?;
+/// let signature = sign_message(wallet_id, DEFAULT_ACCOUNT, message_hash.to_vec(), key_id, pub_key).await?;
 /// ```
-pub async fn sign_message(wallet_id: String, message_hash: Vec<u8>, key_id: EcdsaKeyId) -> Result<Vec<u8>, String> {
+pub async fn sign_message(
+    wallet_id: String,
+    account: AccountIndex,
+    message_hash: Vec<u8>,
+    key_id: EcdsaKeyId,
+    pub_key: [u8; 65],
+) -> Result<Vec<u8>, String> {
     let ic = Principal::management_canister();
-    let derivation_path = vec![wallet_id.as_bytes().to_vec()];
+    let derivation_path = derivation_path(&wallet_id, account);
     let request = SignWithEcdsaArgument {
         message_hash: message_hash.clone(),
-        derivation_path: derivation_path.clone(),
-        key_id: key_id.clone(),
+        derivation_path,
+        key_id,
     };
 
     let (res,): (SignWithEcdsaResponse,) =
@@ -177,74 +381,269 @@ pub async fn sign_message(wallet_id: String, message_hash: Vec<u8>, key_id: Ecds
             .map_err(|e| format!("Failed to call sign_with_ecdsa {}", e.1))?;
 
     let mut signature = res.signature;
-    let pub_key = get_public_key(
-        wallet_id,
-        key_id
-    ).await?;
-    let rec_id = find_recovery_id(&message_hash, &signature, pub_key).unwrap();
+    let rec_id = find_recovery_id(&message_hash, &signature, pub_key)
+        .ok_or("Failed to find the recovery ID of the signature".to_string())?;
     signature.push(rec_id);
     Ok(signature)
 }
 
-/// Asynchronously get the Ethereum address from a wallet ID and key ID.
+/// Check that a message hash and a signature have the lengths `verify_with_public_key` expects.
 ///
 /// # Arguments
 ///
-/// * `wallet_id` - The wallet ID as a String.
-/// * `key_id` - The EcdsaKeyId.
+/// * `message` - The message hash in bytes.
+/// * `signature` - The ECDSA signature followed by the recovery ID, in bytes.
 ///
 /// # Returns
 ///
-/// * `Result<String, String>` - The Ethereum address or an error message.
+/// * `Result<(), String>` - An error message naming the argument with the wrong length.
+pub fn check_signature_input(message: &[u8], signature: &[u8]) -> Result<(), String> {
+    if message.len() != 32 {
+        return Err(format!("Invalid message hash length: {}", message.len()));
+    }
+    if signature.len() != 65 {
+        return Err(format!("Invalid signature length: {}", signature.len()));
+    }
+    Ok(())
+}
+
+/// Validate an ECDSA signature against a known public key.
 ///
-/// # Example
+/// # Arguments
 ///
-/// ```
-/// let wallet_id = "my_wallet_id".to_string();
-/// let key_id = get_ecdsa_key_id_from_env("test");
-/// let eth_address = get_eth_address(wallet_id, key_id).await?;
-/// println!("Ethereum address: {}", eth_address);
-/// ```
-pub async fn get_eth_address(wallet_id: String, key_id: EcdsaKeyId) -> Result<String, String> {
-    let pub_key = get_public_key(wallet_id, key_id).await?.to_vec();
-    let hash = keccak256(&pub_key[1..65]);
-    let mut result = [0u8; 20];
-    result.copy_from_slice(&hash[12..]);
-    Ok(format!("0x{}", hex::encode(result)))
+/// * `message` - The message hash in bytes.
+/// * `signature` - The ECDSA signature followed by `v`, either the recovery ID or the recovery ID plus 27.
+/// * `pub_key` - The uncompressed public key the signature should recover to.
+///
+/// # Returns
+///
+/// * `Result<bool, String>` - `true` if the signature recovers to `pub_key`, else `false`,
+///   or an error message if the input has the wrong length.
+pub fn verify_with_public_key(message: &[u8], signature: &[u8], pub_key: [u8; 65]) -> Result<bool, String> {
+    Ok(recover_public_key(message, signature)? == Some(pub_key))
 }
 
-/// Asynchronously validate an ECDSA signature.
+/// Validate an ECDSA signature against an Ethereum address.
 ///
 /// # Arguments
 ///
-/// * `message` - The original message in bytes.
-/// * `signature` - The ECDSA signature in bytes.
-/// * `wallet_id` - The wallet ID as a String.
-/// * `key_id` - The EcdsaKeyId.
+/// * `message` - The message hash in bytes.
+/// * `signature` - The ECDSA signature followed by `v`, either the recovery ID or the recovery ID plus 27.
+/// * `address` - The address of the public key the signature should recover to.
 ///
 /// # Returns
 ///
-/// * `Result<bool, String>` - `true` if the signature is valid, else `false`.
+/// * `Result<bool, String>` - `true` if the signature recovers to `address`, else `false`,
+///   or an error message if the input has the wrong length.
+pub fn verify_with_address(message: &[u8], signature: &[u8], address: &[u8; 20]) -> Result<bool, String> {
+    Ok(recover_public_key(message, signature)?.map(|pub_key| public_key_to_address(&pub_key)) == Some(*address))
+}
+
+/// Parse a SEC1 encoded public key.
 ///
-/// # Example
+/// # Arguments
 ///
-/// ```
-/// let message = b"example message";
-/// let signature = sign_message(...); // Assume this is a valid signature
-/// let wallet_id = "my_wallet_id".to_string();
-/// let key_id = get_ecdsa_key_id_from_env("test");
-/// let is_valid = is_signature_valid(message.to_vec(), signature, wallet_id, key_id).await?;
-/// ```
-pub async fn is_signature_valid(message: Vec<u8>, signature: Vec<u8>, wallet_id: String, key_id: EcdsaKeyId) -> Result<bool, String> {
-     let pub_key = get_public_key(wallet_id, key_id).await?.to_vec();
+/// * `bytes` - The 33 byte compressed or 65 byte uncompressed public key.
+///
+/// # Returns
+///
+/// * `Result<[u8; 65], String>` - The uncompressed public key or an error message.
+pub fn parse_public_key(bytes: &[u8]) -> Result<[u8; 65], String> {
+    PublicKey::parse_slice(bytes, None)
+        .map(|key| key.serialize())
+        .map_err(|_| "Invalid public key".to_string())
+}
 
-    let recovery_id = signature[64];
-    let signature_without_rec_id = signature[..64].to_vec();
+/// Recover the public key of a signature, or `None` if the signature does not recover to any key.
+///
+/// Like Ethereum does for transactions (EIP-2), only accepts `r` and `s` below the curve order, `s` in its lower
+/// half, and `v` of 0, 1, 27 or 28, so no signature has a second encoding that also verifies.
+fn recover_public_key(message: &[u8], signature: &[u8]) -> Result<Option<[u8; 65]>, String> {
+    check_signature_input(message, signature)?;
 
-    let message_obj = Message::parse_slice(message.as_slice()).expect("Invalid message");
-    let recovery_obj = RecoveryId::parse(recovery_id).expect("Invalid recovery ID");
-    let signature_obj = Signature::parse_overflowing_slice(signature_without_rec_id.as_slice()).expect("Invalid signature");
-    let recovered_address = recover(&message_obj, &signature_obj, &recovery_obj).unwrap();
+    let message_obj = Message::parse_slice(message).map_err(|_| "Invalid message".to_string())?;
+    let (v, rs) = (signature[64], &signature[..64]);
+    let recovery_id = match v {
+        0 | 1 => v,
+        27 | 28 => v - ETH_V_OFFSET,
+        _ => return Ok(None),
+    };
+    let (Ok(recovery_obj), Ok(signature_obj)) = (RecoveryId::parse(recovery_id), Signature::parse_standard_slice(rs)) else {
+        return Ok(None);
+    };
+    if signature_obj.s.is_high() {
+        return Ok(None);
+    }
 
-    Ok(recovered_address.serialize().to_vec() == pub_key)
+    Ok(recover(&message_obj, &signature_obj, &recovery_obj).ok().map(|recovered| recovered.serialize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libsecp256k1::{sign, SecretKey};
+
+    fn sign_hash(hash: &[u8; 32]) -> (Vec<u8>, [u8; 65]) {
+        let secret_key = SecretKey::parse(&[7u8; 32]).unwrap();
+        let (signature, recovery_id) = sign(&Message::parse(hash), &secret_key);
+        let mut signature = signature.serialize().to_vec();
+        signature.push(recovery_id.serialize());
+        (signature, PublicKey::from_secret_key(&secret_key).serialize())
+    }
+
+    #[test]
+    fn test_derivation_path() {
+        // the default account keeps the keys wallets had before accounts
+        assert_eq!(derivation_path("wallet", DEFAULT_ACCOUNT), vec![b"wallet".to_vec()]);
+        assert_eq!(derivation_path("wallet", 1), vec![b"wallet".to_vec(), vec![0, 0, 0, 1]]);
+        assert_ne!(derivation_path("wallet", 1), derivation_path("wallet", 256));
+    }
+
+    #[test]
+    fn test_personal_message_hash() {
+        assert_eq!(
+            hex::encode(personal_message_hash(b"Hello World")),
+            "a1de988600a42c4b4ab089b619297c17d53cffae5d5120d82d8a92d0bb3b78f2"
+        );
+    }
+
+    #[test]
+    fn test_checksum_address() {
+        // the test vectors of EIP-55
+        for expected in [
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+            "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+            "0x52908400098527886E0F7030069857D2E4169EE7",
+            "0xde709f2102306220921060314715629080e2fb77",
+        ] {
+            let bytes: [u8; 20] = hex::decode(&expected[2..]).unwrap().try_into().unwrap();
+            assert_eq!(to_checksum_address(&bytes), expected);
+            assert_eq!(parse_checksum_address(expected), Ok(bytes));
+        }
+    }
+
+    #[test]
+    fn test_parse_checksum_address() {
+        let bytes = parse_checksum_address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed").unwrap();
+        // single case addresses carry no checksum
+        assert_eq!(parse_checksum_address("5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"), Ok(bytes));
+        assert_eq!(parse_checksum_address("0x5AAEB6053F3E94C9B9A09F33669435E7EF1BEAED"), Ok(bytes));
+
+        assert!(parse_checksum_address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD").is_err());
+        assert!(parse_checksum_address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeA").is_err());
+        assert!(parse_checksum_address("0xzzAeb6053F3E94C9b9A09f33669435E7Ef1BeAed").is_err());
+    }
+
+    #[test]
+    fn test_find_recovery_id() {
+        let hash = keccak256(b"hello");
+        let (signature, pub_key) = sign_hash(&hash);
+
+        assert_eq!(find_recovery_id(&hash, &signature[..64], pub_key), Some(signature[64]));
+        assert_eq!(find_recovery_id(&hash, &signature[..64], [4u8; 65]), None);
+    }
+
+    #[test]
+    fn test_find_recovery_id_malformed_input() {
+        let hash = keccak256(b"hello");
+        let (signature, pub_key) = sign_hash(&hash);
+
+        assert_eq!(find_recovery_id(&hash[..31], &signature[..64], pub_key), None);
+        assert_eq!(find_recovery_id(&hash, &signature[..10], pub_key), None);
+        assert_eq!(find_recovery_id(&[], &[], pub_key), None);
+    }
+
+    #[test]
+    fn test_wallet_public_key() {
+        // the generator point, i.e. the public key of the private key 1
+        let compressed = hex::decode("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798").unwrap();
+        let key_id = get_ecdsa_key_id_from_env("test");
+        let public_key = WalletPublicKey::from_compressed(key_id.clone(), &compressed).unwrap();
+
+        assert_eq!(public_key.key_id, key_id);
+        assert_eq!(public_key.compressed, compressed);
+        assert_eq!(public_key.uncompressed[0], 0x04);
+        assert_eq!(&public_key.uncompressed[1..33], &compressed[1..]);
+        assert_eq!(public_key.eth_address, "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf");
+        assert_eq!(public_key_to_eth_address(&public_key.uncompressed_key().unwrap()), public_key.eth_address);
+
+        assert!(WalletPublicKey::from_compressed(key_id, &compressed[1..]).is_err());
+    }
+
+    #[test]
+    fn test_verify_with_public_key() {
+        let hash = keccak256(b"hello");
+        let (signature, pub_key) = sign_hash(&hash);
+
+        assert_eq!(verify_with_public_key(&hash, &signature, pub_key), Ok(true));
+        assert_eq!(verify_with_public_key(&keccak256(b"world"), &signature, pub_key), Ok(false));
+
+        let mut wrong_recovery_id = signature.clone();
+        wrong_recovery_id[64] = 9;
+        assert_eq!(verify_with_public_key(&hash, &wrong_recovery_id, pub_key), Ok(false));
+
+        // recovery IDs 2 and 3 never occur, and neither does v of 29 or 30
+        for v in [2, 3, 29, 30] {
+            let mut out_of_range = signature.clone();
+            out_of_range[64] = v;
+            assert_eq!(verify_with_public_key(&hash, &out_of_range, pub_key), Ok(false));
+        }
+    }
+
+    #[test]
+    fn test_verify_rejects_malleable_signatures() {
+        let hash = keccak256(b"hello");
+        let (signature, pub_key) = sign_hash(&hash);
+
+        // negating s and flipping the recovery ID recovers the same key
+        let mut high_s = Signature::parse_standard_slice(&signature[..64]).unwrap();
+        high_s.s = -high_s.s;
+        let mut malleated = high_s.serialize().to_vec();
+        malleated.push(signature[64] ^ 1);
+        assert_eq!(verify_with_public_key(&hash, &malleated, pub_key), Ok(false));
+
+        // s above the curve order
+        let mut overflowing = signature.clone();
+        overflowing[32..64].copy_from_slice(&[0xff; 32]);
+        assert_eq!(verify_with_public_key(&hash, &overflowing, pub_key), Ok(false));
+    }
+
+    #[test]
+    fn test_verify_with_address() {
+        let hash = keccak256(b"hello");
+        let (mut signature, pub_key) = sign_hash(&hash);
+        let address = parse_checksum_address(&public_key_to_eth_address(&pub_key)).unwrap();
+
+        assert_eq!(verify_with_address(&hash, &signature, &address), Ok(true));
+        assert_eq!(verify_with_address(&hash, &signature, &[0u8; 20]), Ok(false));
+        assert_eq!(verify_with_address(&keccak256(b"world"), &signature, &address), Ok(false));
+
+        // v in the 27/28 convention
+        signature[64] += 27;
+        assert_eq!(verify_with_address(&hash, &signature, &address), Ok(true));
+        assert_eq!(verify_with_public_key(&hash, &signature, pub_key), Ok(true));
+    }
+
+    #[test]
+    fn test_parse_public_key() {
+        let (_, pub_key) = sign_hash(&keccak256(b"hello"));
+        let compressed = PublicKey::parse(&pub_key).unwrap().serialize_compressed();
+
+        assert_eq!(parse_public_key(&pub_key), Ok(pub_key));
+        assert_eq!(parse_public_key(&compressed), Ok(pub_key));
+        assert!(parse_public_key(&compressed[1..]).is_err());
+        assert!(parse_public_key(&[0u8; 33]).is_err());
+    }
+
+    #[test]
+    fn test_verify_with_public_key_malformed_input() {
+        let hash = keccak256(b"hello");
+        let (signature, pub_key) = sign_hash(&hash);
+
+        assert!(verify_with_public_key(&hash, &signature[..64], pub_key).is_err());
+        assert!(verify_with_public_key(&hash, &[], pub_key).is_err());
+        assert!(verify_with_public_key(&hash[..16], &signature, pub_key).is_err());
+    }
 }
@@ -0,0 +1,455 @@
+use std::collections::{BTreeMap, BTreeSet};
+use candid::CandidType;
+use num_bigint::BigUint;
+use serde::Deserialize;
+use serde_json::Value;
+use crate::ecdsa::keccak256;
+use crate::eth::{decode_hex, parse_address};
+
+/// The name of the type describing the signing domain.
+const DOMAIN_TYPE: &str = "EIP712Domain";
+
+/// The fields an `EIP712Domain` may have, in the order EIP-712 defines, with their types.
+const DOMAIN_FIELDS: [(&str, &str); 5] = [
+    ("name", "string"),
+    ("version", "string"),
+    ("chainId", "uint256"),
+    ("verifyingContract", "address"),
+    ("salt", "bytes32"),
+];
+
+/// A single leaf value of typed data, flattened for review.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct TypedDataField {
+    /// The path of the value, e.g. `to.wallet` or `items[0].amount`.
+    pub path: String,
+    /// The Solidity type of the value.
+    pub type_name: String,
+    /// The value as it was given in the JSON.
+    pub value: String,
+}
+
+/// EIP-712 typed data, together with the decoded fields signers review and the hashes that are signed.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct TypedData {
+    /// The typed data JSON as it was proposed.
+    pub json: String,
+    /// The type of the signed message.
+    pub primary_type: String,
+    /// The fields of the signing domain.
+    pub domain: Vec<TypedDataField>,
+    /// The fields of the message.
+    pub message: Vec<TypedDataField>,
+    /// The hex encoded domain separator.
+    pub domain_separator: String,
+    /// The hex encoded `hashStruct` of the message.
+    pub struct_hash: String,
+}
+
+/// A member of a struct type as it appears in the `types` of the JSON.
+#[derive(Deserialize)]
+struct Member {
+    name: String,
+    #[serde(rename = "type")]
+    type_name: String,
+}
+
+/// The typed data JSON accepted by `eth_signTypedData_v4`.
+#[derive(Deserialize)]
+struct TypedDataJson {
+    types: BTreeMap<String, Vec<Member>>,
+    #[serde(rename = "primaryType")]
+    primary_type: String,
+    domain: Value,
+    message: Value,
+}
+
+type Types = BTreeMap<String, Vec<Member>>;
+
+impl TypedData {
+    /// Parse typed data JSON and compute its domain separator and message hash.
+    ///
+    /// * `json` - The typed data as accepted by `eth_signTypedData_v4`. The `EIP712Domain` type
+    ///   is derived from the domain if it is missing.
+    ///
+    /// Returns `Result<TypedData, String>` containing the decoded typed data or an error message.
+    pub fn parse(json: &str) -> Result<TypedData, String> {
+        let TypedDataJson { mut types, primary_type, domain, message } = serde_json::from_str(json)
+            .map_err(|e| format!("Invalid typed data: {}", e))?;
+
+        if !types.contains_key(DOMAIN_TYPE) {
+            types.insert(DOMAIN_TYPE.to_string(), domain_members(&domain)?);
+        }
+        if !types.contains_key(&primary_type) {
+            return Err(format!("Unknown primary type: {}", primary_type));
+        }
+
+        let domain_separator = hash_struct(DOMAIN_TYPE, &domain, &types)?;
+        let struct_hash = hash_struct(&primary_type, &message, &types)?;
+
+        let mut domain_fields = Vec::new();
+        flatten(DOMAIN_TYPE, &domain, "", &types, &mut domain_fields)?;
+        let mut message_fields = Vec::new();
+        flatten(&primary_type, &message, "", &types, &mut message_fields)?;
+
+        Ok(TypedData {
+            json: json.to_string(),
+            primary_type,
+            domain: domain_fields,
+            message: message_fields,
+            domain_separator: hex::encode(domain_separator),
+            struct_hash: hex::encode(struct_hash),
+        })
+    }
+
+    /// The digest that is signed: `keccak256(0x19 || 0x01 || domainSeparator || hashStruct(message))`.
+    pub fn signing_hash(&self) -> Result<[u8; 32], String> {
+        let mut data = vec![0x19, 0x01];
+        data.extend(decode_hex(&self.domain_separator)?);
+        data.extend(decode_hex(&self.struct_hash)?);
+        Ok(keccak256(&data))
+    }
+}
+
+/// Derive the members of `EIP712Domain` from the fields present in the domain.
+fn domain_members(domain: &Value) -> Result<Vec<Member>, String> {
+    let domain = domain.as_object().ok_or("Domain is not an object".to_string())?;
+    Ok(DOMAIN_FIELDS.iter()
+        .filter(|(name, _)| domain.contains_key(*name))
+        .map(|(name, type_name)| Member { name: name.to_string(), type_name: type_name.to_string() })
+        .collect())
+}
+
+/// Strip one array suffix from a type, e.g. `Person[][2]` to `Person[]`.
+fn array_item_type(type_name: &str) -> Option<&str> {
+    if !type_name.ends_with(']') {
+        return None;
+    }
+    type_name.rfind('[').map(|index| &type_name[..index])
+}
+
+/// The struct type an array or struct type is made of, e.g. `Person` for `Person[][2]`.
+fn base_type(type_name: &str) -> &str {
+    type_name.find('[').map_or(type_name, |index| &type_name[..index])
+}
+
+/// Collect the struct types `type_name` references, including itself.
+fn collect_dependencies(type_name: &str, types: &Types, found: &mut BTreeSet<String>) {
+    let type_name = base_type(type_name);
+    if found.contains(type_name) {
+        return;
+    }
+    if let Some(members) = types.get(type_name) {
+        found.insert(type_name.to_string());
+        for member in members {
+            collect_dependencies(&member.type_name, types, found);
+        }
+    }
+}
+
+/// `encodeType`: the primary type followed by the types it references, sorted by name.
+fn encode_type(primary_type: &str, types: &Types) -> String {
+    let mut dependencies = BTreeSet::new();
+    collect_dependencies(primary_type, types, &mut dependencies);
+    dependencies.remove(primary_type);
+
+    std::iter::once(primary_type.to_string())
+        .chain(dependencies)
+        .map(|name| {
+            let members = types[&name].iter()
+                .map(|member| format!("{} {}", member.type_name, member.name))
+                .collect::<Vec<_>>()
+                .join(",");
+            format!("{}({})", name, members)
+        })
+        .collect()
+}
+
+/// `hashStruct`: `keccak256(typeHash || encodeData(value))`.
+fn hash_struct(type_name: &str, value: &Value, types: &Types) -> Result<[u8; 32], String> {
+    let object = value.as_object().ok_or(format!("Value of {} is not an object", type_name))?;
+    let mut data = keccak256(encode_type(type_name, types).as_bytes()).to_vec();
+    for member in &types[type_name] {
+        let field = object.get(&member.name)
+            .ok_or(format!("Missing field {} of {}", member.name, type_name))?;
+        data.extend(encode_value(&member.type_name, field, types)?);
+    }
+    Ok(keccak256(&data))
+}
+
+/// Encode a single value to the 32 bytes `encodeData` uses for it.
+fn encode_value(type_name: &str, value: &Value, types: &Types) -> Result<[u8; 32], String> {
+    if let Some(item_type) = array_item_type(type_name) {
+        let items = value.as_array().ok_or(format!("Value of {} is not an array", type_name))?;
+        let expected_len = &type_name[item_type.len() + 1..type_name.len() - 1];
+        if !expected_len.is_empty() && expected_len.parse::<usize>() != Ok(items.len()) {
+            return Err(format!("Expected {} items for {}", expected_len, type_name));
+        }
+        let mut data = Vec::new();
+        for item in items {
+            data.extend(encode_value(item_type, item, types)?);
+        }
+        return Ok(keccak256(&data));
+    }
+    if types.contains_key(type_name) {
+        return hash_struct(type_name, value, types);
+    }
+
+    match type_name {
+        "string" => Ok(keccak256(as_str(type_name, value)?.as_bytes())),
+        "bytes" => Ok(keccak256(&decode_hex(as_str(type_name, value)?)?)),
+        "bool" => {
+            let flag = value.as_bool().ok_or("Value of bool is not a boolean".to_string())?;
+            Ok(left_pad(&[flag as u8]))
+        }
+        "address" => Ok(left_pad(&parse_address(as_str(type_name, value)?)?)),
+        _ if type_name.starts_with("bytes") => {
+            let size = parse_size(type_name, "bytes", 32)?;
+            let bytes = decode_hex(as_str(type_name, value)?)?;
+            if bytes.len() != size {
+                return Err(format!("Expected {} bytes for {}", size, type_name));
+            }
+            let mut out = [0u8; 32];
+            out[..size].copy_from_slice(&bytes);
+            Ok(out)
+        }
+        _ if type_name.starts_with("uint") => {
+            let bits = parse_size(type_name, "uint", 256)?;
+            let (negative, magnitude) = parse_integer(value)?;
+            if negative || magnitude.bits() > bits as u64 {
+                return Err(format!("Value out of range for {}", type_name));
+            }
+            Ok(left_pad(&magnitude.to_bytes_be()))
+        }
+        _ if type_name.starts_with("int") => {
+            let bits = parse_size(type_name, "int", 256)?;
+            let (negative, magnitude) = parse_integer(value)?;
+            let limit: BigUint = BigUint::from(1u8) << (bits - 1);
+            if (!negative && magnitude >= limit) || (negative && magnitude > limit) {
+                return Err(format!("Value out of range for {}", type_name));
+            }
+            if negative && magnitude.bits() > 0 {
+                // two's complement in 256 bits
+                let twos_complement: BigUint = (BigUint::from(1u8) << 256usize) - magnitude;
+                return Ok(left_pad(&twos_complement.to_bytes_be()));
+            }
+            Ok(left_pad(&magnitude.to_bytes_be()))
+        }
+        _ => Err(format!("Unknown type: {}", type_name)),
+    }
+}
+
+/// Flatten a struct value into its leaf fields for review.
+fn flatten(type_name: &str, value: &Value, path: &str, types: &Types, out: &mut Vec<TypedDataField>) -> Result<(), String> {
+    if let Some(item_type) = array_item_type(type_name) {
+        let items = value.as_array().ok_or(format!("Value of {} is not an array", type_name))?;
+        for (index, item) in items.iter().enumerate() {
+            flatten(item_type, item, &format!("{}[{}]", path, index), types, out)?;
+        }
+        return Ok(());
+    }
+    if let Some(members) = types.get(type_name) {
+        for member in members {
+            let field_path = if path.is_empty() { member.name.clone() } else { format!("{}.{}", path, member.name) };
+            let field = value.get(&member.name)
+                .ok_or(format!("Missing field {} of {}", member.name, type_name))?;
+            flatten(&member.type_name, field, &field_path, types, out)?;
+        }
+        return Ok(());
+    }
+    out.push(TypedDataField {
+        path: path.to_string(),
+        type_name: type_name.to_string(),
+        value: match value {
+            Value::String(value) => value.clone(),
+            value => value.to_string(),
+        },
+    });
+    Ok(())
+}
+
+fn as_str<'a>(type_name: &str, value: &'a Value) -> Result<&'a str, String> {
+    value.as_str().ok_or(format!("Value of {} is not a string", type_name))
+}
+
+/// Parse the size suffix of `bytesN`, `uintN` or `intN`, defaulting to `default` if there is none.
+fn parse_size(type_name: &str, prefix: &str, default: usize) -> Result<usize, String> {
+    let suffix = &type_name[prefix.len()..];
+    if suffix.is_empty() {
+        return Ok(default);
+    }
+    let size = suffix.parse::<usize>().map_err(|_| format!("Unknown type: {}", type_name))?;
+    let valid = match prefix {
+        "bytes" => (1..=32).contains(&size),
+        _ => size % 8 == 0 && (8..=256).contains(&size),
+    };
+    if !valid {
+        return Err(format!("Unknown type: {}", type_name));
+    }
+    Ok(size)
+}
+
+/// Parse an integer given as a JSON number, a decimal string or a `0x` prefixed hex string.
+///
+/// Returns the sign and the magnitude of the integer.
+fn parse_integer(value: &Value) -> Result<(bool, BigUint), String> {
+    match value {
+        Value::Number(number) => {
+            if let Some(value) = number.as_u64() {
+                Ok((false, BigUint::from(value)))
+            } else if let Some(value) = number.as_i64() {
+                Ok((value < 0, BigUint::from(value.unsigned_abs())))
+            } else {
+                Err(format!("Not an integer: {}", number))
+            }
+        }
+        Value::String(value) => {
+            let (negative, digits) = match value.strip_prefix('-') {
+                Some(digits) => (true, digits),
+                None => (false, value.as_str()),
+            };
+            let magnitude = match digits.strip_prefix("0x") {
+                Some(hex_digits) => BigUint::parse_bytes(hex_digits.as_bytes(), 16),
+                None => BigUint::parse_bytes(digits.as_bytes(), 10),
+            };
+            magnitude
+                .map(|magnitude| (negative, magnitude))
+                .ok_or(format!("Not an integer: {}", value))
+        }
+        value => Err(format!("Not an integer: {}", value)),
+    }
+}
+
+/// Left-pad up to 32 bytes with zeros.
+fn left_pad(bytes: &[u8]) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    out[32 - bytes.len()..].copy_from_slice(bytes);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The example from EIP-712.
+    const MAIL: &str = r#"{
+        "types": {
+            "EIP712Domain": [
+                {"name": "name", "type": "string"},
+                {"name": "version", "type": "string"},
+                {"name": "chainId", "type": "uint256"},
+                {"name": "verifyingContract", "type": "address"}
+            ],
+            "Person": [
+                {"name": "name", "type": "string"},
+                {"name": "wallet", "type": "address"}
+            ],
+            "Mail": [
+                {"name": "from", "type": "Person"},
+                {"name": "to", "type": "Person"},
+                {"name": "contents", "type": "string"}
+            ]
+        },
+        "primaryType": "Mail",
+        "domain": {
+            "name": "Ether Mail",
+            "version": "1",
+            "chainId": 1,
+            "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+        },
+        "message": {
+            "from": {"name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826"},
+            "to": {"name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB"},
+            "contents": "Hello, Bob!"
+        }
+    }"#;
+
+    #[test]
+    fn test_eip712_example() {
+        let typed_data = TypedData::parse(MAIL).unwrap();
+
+        assert_eq!(
+            encode_type("Mail", &serde_json::from_str::<TypedDataJson>(MAIL).unwrap().types),
+            "Mail(Person from,Person to,string contents)Person(string name,address wallet)"
+        );
+        assert_eq!(typed_data.domain_separator, "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f");
+        assert_eq!(typed_data.struct_hash, "c52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e");
+        assert_eq!(
+            hex::encode(typed_data.signing_hash().unwrap()),
+            "be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2"
+        );
+    }
+
+    #[test]
+    fn test_review_fields() {
+        let typed_data = TypedData::parse(MAIL).unwrap();
+
+        assert_eq!(typed_data.primary_type, "Mail");
+        assert_eq!(typed_data.domain[2], TypedDataField {
+            path: "chainId".to_string(),
+            type_name: "uint256".to_string(),
+            value: "1".to_string(),
+        });
+        let paths: Vec<&str> = typed_data.message.iter().map(|field| field.path.as_str()).collect();
+        assert_eq!(paths, vec!["from.name", "from.wallet", "to.name", "to.wallet", "contents"]);
+        assert_eq!(typed_data.message[4].value, "Hello, Bob!");
+    }
+
+    #[test]
+    fn test_domain_type_is_derived() {
+        let mut json: Value = serde_json::from_str(MAIL).unwrap();
+        json["types"].as_object_mut().unwrap().remove(DOMAIN_TYPE);
+
+        let typed_data = TypedData::parse(&json.to_string()).unwrap();
+        assert_eq!(typed_data.domain_separator, "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f");
+    }
+
+    #[test]
+    fn test_atomic_values() {
+        let types = Types::new();
+        let value = |json: &str| serde_json::from_str::<Value>(json).unwrap();
+
+        assert_eq!(encode_value("bool", &value("true"), &types).unwrap()[31], 1);
+        assert_eq!(encode_value("uint8", &value("255"), &types).unwrap()[31], 255);
+        assert_eq!(encode_value("uint256", &value("\"0x0100\""), &types).unwrap()[30..], [1, 0]);
+        assert_eq!(encode_value("int8", &value("-1"), &types).unwrap(), [0xff; 32]);
+        assert_eq!(encode_value("int256", &value("\"-128\""), &types).unwrap()[31], 0x80);
+        assert_eq!(encode_value("bytes2", &value("\"0xabcd\""), &types).unwrap()[..3], [0xab, 0xcd, 0x00]);
+        assert_eq!(encode_value("string", &value("\"\""), &types).unwrap(), keccak256(b""));
+
+        assert!(encode_value("uint8", &value("256"), &types).is_err());
+        assert!(encode_value("int8", &value("128"), &types).is_err());
+        assert!(encode_value("int8", &value("-129"), &types).is_err());
+        assert!(encode_value("uint256", &value("-1"), &types).is_err());
+        assert!(encode_value("uint7", &value("1"), &types).is_err());
+        assert!(encode_value("bytes33", &value("\"0x00\""), &types).is_err());
+        assert!(encode_value("bytes2", &value("\"0x00\""), &types).is_err());
+        assert!(encode_value("address", &value("\"0x00\""), &types).is_err());
+        assert!(encode_value("float", &value("1"), &types).is_err());
+    }
+
+    #[test]
+    fn test_arrays() {
+        let types = Types::new();
+        let value = serde_json::from_str::<Value>("[1, 2]").unwrap();
+
+        let mut expected = left_pad(&[1]).to_vec();
+        expected.extend(left_pad(&[2]));
+        assert_eq!(encode_value("uint256[]", &value, &types).unwrap(), keccak256(&expected));
+        assert_eq!(encode_value("uint256[2]", &value, &types).unwrap(), keccak256(&expected));
+        assert!(encode_value("uint256[3]", &value, &types).is_err());
+    }
+
+    #[test]
+    fn test_malformed_typed_data() {
+        assert!(TypedData::parse("not json").is_err());
+
+        let mut json: Value = serde_json::from_str(MAIL).unwrap();
+        json["primaryType"] = Value::String("Letter".to_string());
+        assert!(TypedData::parse(&json.to_string()).is_err());
+
+        let mut json: Value = serde_json::from_str(MAIL).unwrap();
+        json["message"].as_object_mut().unwrap().remove("contents");
+        assert!(TypedData::parse(&json.to_string()).is_err());
+    }
+}
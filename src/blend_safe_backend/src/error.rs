@@ -0,0 +1,118 @@
+use candid::CandidType;
+use serde::Deserialize;
+use crate::wallet::WalletError;
+
+/// The error returned by every fallible endpoint of the canister.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub enum SafeError {
+    /// No wallet exists with the given ID.
+    WalletNotFound,
+    /// A wallet with the given ID already exists.
+    WalletAlreadyExists,
+    /// A wallet was created without signers.
+    NoSigners,
+    /// The caller is not a signer of the wallet.
+    NotASigner,
+    /// The caller is not the proposer of the proposal.
+    NotProposer,
+    /// No proposal exists with the given ID or payload.
+    ProposalNotFound,
+    /// The proposal has already been executed, rejected, expired or cancelled.
+    ProposalNotPending,
+    /// The proposal does not have enough approvals to be executed.
+    CannotExecute,
+    /// The caller has already approved the proposal.
+    AlreadyApproved,
+    /// The caller has already rejected the proposal.
+    AlreadyRejected,
+    /// The caller has not approved the proposal.
+    NotApproved,
+    /// The threshold is zero.
+    InvalidThreshold,
+    /// There are not enough signers to meet the threshold.
+    NotEnoughSigners,
+    /// The signer to add is already a signer of the wallet.
+    SignerAlreadyExists,
+    /// The signer to remove is not a signer of the wallet.
+    SignerNotFound,
+    /// A proposal that does not change the wallet configuration was applied as one.
+    NotAConfigChange,
+    /// The proposal has no metadata, or the caller may not read it.
+    MetadataNotFound,
+    /// The proposal already has metadata.
+    MetadataAlreadyExists,
+    /// An argument could not be decoded. Holds the name of the argument.
+    DecodeError(String),
+    /// A message to sign or verify is not a 32 byte hash.
+    InvalidHashLength,
+    /// A signature is not 64 bytes followed by a recovery ID.
+    InvalidSignatureLength,
+    /// A transaction could not be encoded. Holds the reason.
+    InvalidTransaction(String),
+    /// EIP-712 typed data could not be parsed or hashed. Holds the reason.
+    InvalidTypedData(String),
+    /// The proposal is not of the requested kind.
+    WrongProposalKind,
+    /// An Ethereum address is malformed. Holds the reason.
+    InvalidAddress(String),
+    /// A contract ABI could not be parsed. Holds the reason.
+    InvalidAbi(String),
+    /// No ABI is registered for the contract.
+    AbiNotFound,
+    /// An ABI is already registered for the contract. Replacing it takes a proposal.
+    AbiAlreadyRegistered,
+    /// The account index or name does not exist in the wallet.
+    AccountNotFound,
+    /// An account with the same name already exists in the wallet.
+    AccountAlreadyExists,
+    /// Account names must not be empty.
+    InvalidAccountName,
+    /// The wallet already signs with the key it is migrated to.
+    KeyAlreadyInUse,
+    /// The wallet has migrated away from the key and never signs with it again.
+    KeyRetired,
+    /// Key names must not be empty.
+    InvalidKeyName,
+    /// Only controllers of the canister may change its configuration.
+    NotAController,
+    /// The proposal is already being executed by another call.
+    ExecutionInProgress,
+    /// A PSBT could not be parsed, or the wallet cannot sign it. Holds the reason.
+    InvalidPsbt(String),
+    /// A Solana transaction message could not be parsed, or the wallet does not sign it. Holds the reason.
+    InvalidSolanaTransaction(String),
+    /// The wallet's public key has not been derived yet. Calling `eth_address` derives and caches it.
+    PublicKeyNotCached,
+    /// A call to the management canister's threshold ECDSA API failed, or returned unusable data.
+    EcdsaError(String),
+    /// A call to the management canister's threshold Schnorr API failed, or returned unusable data.
+    SchnorrError(String),
+}
+
+impl From<WalletError> for SafeError {
+    fn from(error: WalletError) -> Self {
+        match error {
+            WalletError::InvalidSignature => SafeError::NotASigner,
+            WalletError::ProposalNotFound => SafeError::ProposalNotFound,
+            WalletError::NotEnoughSigners => SafeError::NotEnoughSigners,
+            WalletError::InvalidThreshold => SafeError::InvalidThreshold,
+            WalletError::SignerAlreadyExists => SafeError::SignerAlreadyExists,
+            WalletError::SignerNotFound => SafeError::SignerNotFound,
+            WalletError::NotAConfigChange => SafeError::NotAConfigChange,
+            WalletError::ProposalNotPending => SafeError::ProposalNotPending,
+            WalletError::NotProposer => SafeError::NotProposer,
+            WalletError::AlreadyRejected => SafeError::AlreadyRejected,
+            WalletError::AlreadyApproved => SafeError::AlreadyApproved,
+            WalletError::NotApproved => SafeError::NotApproved,
+            WalletError::MetadataAlreadyExists => SafeError::MetadataAlreadyExists,
+            WalletError::AbiNotFound => SafeError::AbiNotFound,
+            WalletError::AbiAlreadyRegistered => SafeError::AbiAlreadyRegistered,
+            WalletError::AccountNotFound => SafeError::AccountNotFound,
+            WalletError::AccountAlreadyExists => SafeError::AccountAlreadyExists,
+            WalletError::InvalidAccountName => SafeError::InvalidAccountName,
+            WalletError::KeyAlreadyInUse => SafeError::KeyAlreadyInUse,
+            WalletError::KeyRetired => SafeError::KeyRetired,
+            WalletError::InvalidKeyName => SafeError::InvalidKeyName,
+        }
+    }
+}
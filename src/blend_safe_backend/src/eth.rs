@@ -0,0 +1,494 @@
+use candid::{CandidType, Nat};
+use serde::Deserialize;
+use crate::ecdsa::{keccak256, parse_checksum_address, to_checksum_address};
+use crate::rlp::{encode_bytes, encode_list, encode_u64, encode_uint};
+
+/// The EIP-2718 type byte of EIP-2930 transactions.
+const EIP2930_TX_TYPE: u8 = 0x01;
+
+/// The EIP-2718 type byte of EIP-1559 transactions.
+const EIP1559_TX_TYPE: u8 = 0x02;
+
+/// An address and the storage slots of it a transaction pre-declares it will access.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct AccessListItem {
+    /// The `0x` prefixed hex address of the contract.
+    pub address: String,
+    /// The `0x` prefixed hex encoded 32 byte storage keys.
+    pub storage_keys: Vec<String>,
+}
+
+/// An Ethereum transaction that can be hashed for signing and serialized once signed.
+pub trait EthTransaction {
+    /// The EIP-2718 type byte, or `None` for legacy transactions.
+    fn tx_type(&self) -> Option<u8>;
+
+    /// The RLP encoded fields that are signed, in the order the transaction type defines.
+    ///
+    /// Returns an error message if a field is malformed.
+    fn unsigned_fields(&self) -> Result<Vec<Vec<u8>>, String>;
+
+    /// The RLP encoded fields of the signed transaction.
+    ///
+    /// * `signature` - The 65 byte `r || s || recovery ID` signature of `signing_hash`.
+    ///
+    /// Returns an error message if a field or the signature is malformed.
+    fn signed_fields(&self, signature: &[u8]) -> Result<Vec<Vec<u8>>, String>;
+
+    /// Compute the hash the sender signs: `keccak256(type || rlp(unsigned_fields))`.
+    ///
+    /// Returns an error message if a field is malformed.
+    fn signing_hash(&self) -> Result<[u8; 32], String> {
+        Ok(keccak256(&self.envelope(&self.unsigned_fields()?)))
+    }
+
+    /// Serialize the transaction together with its signature, ready for `eth_sendRawTransaction`.
+    ///
+    /// * `signature` - The 65 byte `r || s || recovery ID` signature of `signing_hash`.
+    ///
+    /// Returns an error message if a field or the signature is malformed.
+    fn encode_signed(&self, signature: &[u8]) -> Result<Vec<u8>, String> {
+        Ok(self.envelope(&self.signed_fields(signature)?))
+    }
+
+    /// Prefix the RLP encoded list of fields with the type byte, if any.
+    fn envelope(&self, fields: &[Vec<u8>]) -> Vec<u8> {
+        let mut out: Vec<u8> = self.tx_type().into_iter().collect();
+        out.extend(encode_list(fields));
+        out
+    }
+}
+
+/// A legacy (type 0) Ethereum transaction with EIP-155 replay protection.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct EthTxLegacy {
+    /// The EIP-155 chain ID.
+    pub chain_id: u64,
+    /// The nonce of the sending account.
+    pub nonce: u64,
+    /// The gas price in wei.
+    pub gas_price: Nat,
+    /// The maximum amount of gas the transaction may use.
+    pub gas_limit: u64,
+    /// The `0x` prefixed hex recipient address, or `None` to deploy a contract.
+    pub to: Option<String>,
+    /// The amount of wei to transfer.
+    pub value: Nat,
+    /// The `0x` prefixed hex encoded call data.
+    pub data: String,
+}
+
+impl EthTxLegacy {
+    /// The fields shared by the signed and the unsigned transaction.
+    fn fields(&self) -> Result<Vec<Vec<u8>>, String> {
+        Ok(vec![
+            encode_u64(self.nonce),
+            encode_uint(&nat_to_bytes(&self.gas_price)?),
+            encode_u64(self.gas_limit),
+            encode_to(&self.to)?,
+            encode_uint(&nat_to_bytes(&self.value)?),
+            encode_bytes(&decode_hex(&self.data)?),
+        ])
+    }
+}
+
+impl EthTransaction for EthTxLegacy {
+    fn tx_type(&self) -> Option<u8> {
+        None
+    }
+
+    fn unsigned_fields(&self) -> Result<Vec<Vec<u8>>, String> {
+        // EIP-155 signs over the chain ID followed by two empty values
+        let mut fields = self.fields()?;
+        fields.extend([encode_u64(self.chain_id), encode_u64(0), encode_u64(0)]);
+        Ok(fields)
+    }
+
+    fn signed_fields(&self, signature: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+        let (r, s, recovery_id) = split_signature(signature)?;
+        let v = self.chain_id.checked_mul(2)
+            .and_then(|v| v.checked_add(35 + recovery_id as u64))
+            .ok_or(format!("Chain ID too large: {}", self.chain_id))?;
+        let mut fields = self.fields()?;
+        fields.extend([encode_u64(v), encode_uint(r), encode_uint(s)]);
+        Ok(fields)
+    }
+}
+
+/// An EIP-2930 (type 1) Ethereum transaction.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct EthTx2930 {
+    /// The EIP-155 chain ID.
+    pub chain_id: u64,
+    /// The nonce of the sending account.
+    pub nonce: u64,
+    /// The gas price in wei.
+    pub gas_price: Nat,
+    /// The maximum amount of gas the transaction may use.
+    pub gas_limit: u64,
+    /// The `0x` prefixed hex recipient address, or `None` to deploy a contract.
+    pub to: Option<String>,
+    /// The amount of wei to transfer.
+    pub value: Nat,
+    /// The `0x` prefixed hex encoded call data.
+    pub data: String,
+    /// The addresses and storage keys the transaction pre-declares.
+    pub access_list: Vec<AccessListItem>,
+}
+
+impl EthTransaction for EthTx2930 {
+    fn tx_type(&self) -> Option<u8> {
+        Some(EIP2930_TX_TYPE)
+    }
+
+    fn unsigned_fields(&self) -> Result<Vec<Vec<u8>>, String> {
+        Ok(vec![
+            encode_u64(self.chain_id),
+            encode_u64(self.nonce),
+            encode_uint(&nat_to_bytes(&self.gas_price)?),
+            encode_u64(self.gas_limit),
+            encode_to(&self.to)?,
+            encode_uint(&nat_to_bytes(&self.value)?),
+            encode_bytes(&decode_hex(&self.data)?),
+            encode_access_list(&self.access_list)?,
+        ])
+    }
+
+    fn signed_fields(&self, signature: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+        with_y_parity_signature(self.unsigned_fields()?, signature)
+    }
+}
+
+/// An EIP-1559 (type 2) Ethereum transaction.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct EthTx1559 {
+    /// The EIP-155 chain ID.
+    pub chain_id: u64,
+    /// The nonce of the sending account.
+    pub nonce: u64,
+    /// The `0x` prefixed hex recipient address, or `None` to deploy a contract.
+    pub to: Option<String>,
+    /// The amount of wei to transfer.
+    pub value: Nat,
+    /// The `0x` prefixed hex encoded call data.
+    pub data: String,
+    /// The maximum amount of gas the transaction may use.
+    pub gas_limit: u64,
+    /// The maximum total fee per gas in wei.
+    pub max_fee: Nat,
+    /// The maximum priority fee per gas in wei.
+    pub max_priority_fee: Nat,
+    /// The addresses and storage keys the transaction pre-declares.
+    pub access_list: Vec<AccessListItem>,
+}
+
+impl EthTransaction for EthTx1559 {
+    fn tx_type(&self) -> Option<u8> {
+        Some(EIP1559_TX_TYPE)
+    }
+
+    fn unsigned_fields(&self) -> Result<Vec<Vec<u8>>, String> {
+        Ok(vec![
+            encode_u64(self.chain_id),
+            encode_u64(self.nonce),
+            encode_uint(&nat_to_bytes(&self.max_priority_fee)?),
+            encode_uint(&nat_to_bytes(&self.max_fee)?),
+            encode_u64(self.gas_limit),
+            encode_to(&self.to)?,
+            encode_uint(&nat_to_bytes(&self.value)?),
+            encode_bytes(&decode_hex(&self.data)?),
+            encode_access_list(&self.access_list)?,
+        ])
+    }
+
+    fn signed_fields(&self, signature: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+        with_y_parity_signature(self.unsigned_fields()?, signature)
+    }
+}
+
+/// Decode a hex string with an optional `0x` prefix.
+///
+/// # Arguments
+///
+/// * `value` - The hex string.
+///
+/// # Returns
+///
+/// * `Result<Vec<u8>, String>` - The decoded bytes or an error message.
+pub fn decode_hex(value: &str) -> Result<Vec<u8>, String> {
+    let value = value.strip_prefix("0x").unwrap_or(value);
+    hex::decode(value).map_err(|_| format!("Invalid hex: {}", value))
+}
+
+/// Decode a 20 byte Ethereum address, validating its EIP-55 checksum if it is in mixed case.
+///
+/// # Arguments
+///
+/// * `address` - The hex address with an optional `0x` prefix.
+///
+/// # Returns
+///
+/// * `Result<[u8; 20], String>` - The address bytes or an error message.
+pub fn parse_address(address: &str) -> Result<[u8; 20], String> {
+    parse_checksum_address(address)
+}
+
+/// Normalize an Ethereum address to its EIP-55 checksummed form.
+///
+/// # Arguments
+///
+/// * `address` - The hex address with an optional `0x` prefix.
+///
+/// # Returns
+///
+/// * `Result<String, String>` - The normalized address or an error message.
+pub fn normalize_address(address: &str) -> Result<String, String> {
+    parse_address(address).map(|bytes| to_checksum_address(&bytes))
+}
+
+/// Convert a `Nat` to big-endian bytes, rejecting values that do not fit in 256 bits.
+fn nat_to_bytes(value: &Nat) -> Result<Vec<u8>, String> {
+    let bytes = value.0.to_bytes_be();
+    if bytes.len() > 32 {
+        return Err(format!("Value does not fit in 256 bits: {}", value));
+    }
+    Ok(bytes)
+}
+
+/// Encode the recipient, which is the empty string for contract deployments.
+fn encode_to(to: &Option<String>) -> Result<Vec<u8>, String> {
+    match to {
+        Some(address) => Ok(encode_bytes(&parse_address(address)?)),
+        None => Ok(encode_bytes(&[])),
+    }
+}
+
+/// Encode an access list as `[[address, [storage_key, ...]], ...]`.
+fn encode_access_list(access_list: &[AccessListItem]) -> Result<Vec<u8>, String> {
+    let items = access_list.iter()
+        .map(|item| {
+            let storage_keys = item.storage_keys.iter()
+                .map(|key| {
+                    let key = decode_hex(key)?;
+                    if key.len() != 32 {
+                        return Err(format!("Invalid storage key length: {}", key.len()));
+                    }
+                    Ok(encode_bytes(&key))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            Ok(encode_list(&[
+                encode_bytes(&parse_address(&item.address)?),
+                encode_list(&storage_keys),
+            ]))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(encode_list(&items))
+}
+
+/// Append the `y_parity`, `r` and `s` of a signature to the fields of a typed transaction.
+fn with_y_parity_signature(mut fields: Vec<Vec<u8>>, signature: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let (r, s, y_parity) = split_signature(signature)?;
+    fields.extend([encode_u64(y_parity as u64), encode_uint(r), encode_uint(s)]);
+    Ok(fields)
+}
+
+/// Split a 65 byte `r || s || recovery ID` signature into its parts.
+fn split_signature(signature: &[u8]) -> Result<(&[u8], &[u8], u8), String> {
+    if signature.len() != 65 {
+        return Err(format!("Invalid signature length: {}", signature.len()));
+    }
+    Ok((&signature[..32], &signature[32..64], signature[64]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libsecp256k1::{recover, sign, Message, PublicKey, RecoveryId, SecretKey, Signature};
+
+    fn transfer() -> EthTx1559 {
+        EthTx1559 {
+            chain_id: 1,
+            nonce: 0,
+            to: Some(format!("0x{}", "35".repeat(20))),
+            value: Nat::from(1u64),
+            data: String::new(),
+            gas_limit: 21000,
+            max_fee: Nat::from(2u64),
+            max_priority_fee: Nat::from(1u64),
+            access_list: vec![],
+        }
+    }
+
+    #[test]
+    fn test_fields_encoding() {
+        let mut expected = vec![0xdf, 0x01, 0x80, 0x01, 0x02, 0x82, 0x52, 0x08, 0x94];
+        expected.extend([0x35; 20]);
+        expected.extend([0x01, 0x80, 0xc0]);
+
+        assert_eq!(encode_list(&transfer().unsigned_fields().unwrap()), expected);
+
+        let mut payload = vec![EIP1559_TX_TYPE];
+        payload.extend(expected);
+        assert_eq!(transfer().signing_hash().unwrap(), keccak256(&payload));
+    }
+
+    #[test]
+    fn test_contract_deployment_and_access_list() {
+        let mut tx = transfer();
+        tx.to = None;
+        tx.data = "0x6080".to_string();
+        tx.access_list = vec![AccessListItem {
+            address: format!("0x{}", "11".repeat(20)),
+            storage_keys: vec![format!("0x{}", "00".repeat(32))],
+        }];
+        let fields = tx.unsigned_fields().unwrap();
+
+        assert_eq!(fields[5], vec![0x80]);
+        assert_eq!(fields[7], vec![0x82, 0x60, 0x80]);
+
+        let mut expected = vec![0xf8, 0x38, 0xf7, 0x94];
+        expected.extend([0x11; 20]);
+        expected.extend([0xe1, 0xa0]);
+        expected.extend([0x00; 32]);
+        assert_eq!(fields[8], expected);
+    }
+
+    #[test]
+    fn test_malformed_transaction() {
+        let mut tx = transfer();
+        tx.to = Some("0x1234".to_string());
+        assert!(tx.signing_hash().is_err());
+
+        // a mixed case address with a wrong EIP-55 checksum
+        let mut tx = transfer();
+        tx.to = Some("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD".to_string());
+        assert!(tx.signing_hash().is_err());
+        tx.to = Some("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".to_string());
+        assert!(tx.signing_hash().is_ok());
+
+        let mut tx = transfer();
+        tx.data = "zz".to_string();
+        assert!(tx.signing_hash().is_err());
+
+        let mut tx = transfer();
+        tx.value = Nat(Nat::from(2u64).0.pow(256));
+        assert!(tx.signing_hash().is_err());
+
+        let mut tx = transfer();
+        tx.access_list = vec![AccessListItem {
+            address: format!("0x{}", "11".repeat(20)),
+            storage_keys: vec!["0x01".to_string()],
+        }];
+        assert!(tx.signing_hash().is_err());
+
+        assert!(transfer().encode_signed(&[0u8; 64]).is_err());
+    }
+
+    #[test]
+    fn test_encode_signed() {
+        let tx = transfer();
+        let hash = tx.signing_hash().unwrap();
+        let secret_key = SecretKey::parse(&[7u8; 32]).unwrap();
+        let (signature, recovery_id) = sign(&Message::parse(&hash), &secret_key);
+        let mut raw_signature = signature.serialize().to_vec();
+        raw_signature.push(recovery_id.serialize());
+
+        let signed = tx.encode_signed(&raw_signature).unwrap();
+        assert_eq!(signed[0], EIP1559_TX_TYPE);
+
+        // the signed list is the unsigned one followed by y_parity, r and s
+        let mut fields = tx.unsigned_fields().unwrap();
+        fields.push(encode_u64(recovery_id.serialize() as u64));
+        fields.push(encode_uint(&raw_signature[..32]));
+        fields.push(encode_uint(&raw_signature[32..64]));
+        assert_eq!(&signed[1..], encode_list(&fields).as_slice());
+
+        let recovered = recover(
+            &Message::parse(&hash),
+            &Signature::parse_standard_slice(&raw_signature[..64]).unwrap(),
+            &RecoveryId::parse(raw_signature[64]).unwrap(),
+        ).unwrap();
+        assert_eq!(recovered, PublicKey::from_secret_key(&secret_key));
+    }
+
+    /// The example transaction from EIP-155.
+    fn eip155_example() -> EthTxLegacy {
+        EthTxLegacy {
+            chain_id: 1,
+            nonce: 9,
+            gas_price: Nat::from(20_000_000_000u64),
+            gas_limit: 21000,
+            to: Some(format!("0x{}", "35".repeat(20))),
+            value: Nat::from(1_000_000_000_000_000_000u64),
+            data: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_legacy_eip155_vector() {
+        let tx = eip155_example();
+
+        assert_eq!(
+            hex::encode(tx.envelope(&tx.unsigned_fields().unwrap())),
+            "ec098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a764000080018080"
+        );
+        assert_eq!(
+            hex::encode(tx.signing_hash().unwrap()),
+            "daf5a779ae972f972197303d7b574746c7ef83eadac0f2791ad23db92e4c8e53"
+        );
+
+        let signature = hex::decode(concat!(
+            "28ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276",
+            "67cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83",
+            "00",
+        )).unwrap();
+        assert_eq!(
+            hex::encode(tx.encode_signed(&signature).unwrap()),
+            concat!(
+                "f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a764000080",
+                "25a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276",
+                "a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83",
+            )
+        );
+    }
+
+    #[test]
+    fn test_legacy_v_uses_chain_id() {
+        let mut tx = eip155_example();
+        tx.chain_id = 137;
+        let mut signature = vec![1u8; 64];
+        signature.push(1);
+
+        // v = recovery ID + chain ID * 2 + 35
+        let fields = tx.signed_fields(&signature).unwrap();
+        assert_eq!(fields[6], encode_u64(1 + 137 * 2 + 35));
+
+        tx.chain_id = u64::MAX;
+        assert!(tx.encode_signed(&signature).is_err());
+    }
+
+    #[test]
+    fn test_eip2930_encoding() {
+        let tx = EthTx2930 {
+            chain_id: 1,
+            nonce: 0,
+            gas_price: Nat::from(2u64),
+            gas_limit: 21000,
+            to: Some(format!("0x{}", "35".repeat(20))),
+            value: Nat::from(1u64),
+            data: String::new(),
+            access_list: vec![],
+        };
+        let mut expected = vec![0x01, 0xde, 0x01, 0x80, 0x02, 0x82, 0x52, 0x08, 0x94];
+        expected.extend([0x35; 20]);
+        expected.extend([0x01, 0x80, 0xc0]);
+        assert_eq!(tx.envelope(&tx.unsigned_fields().unwrap()), expected);
+
+        let mut signature = vec![1u8; 64];
+        signature.push(1);
+        let signed = tx.encode_signed(&signature).unwrap();
+        assert_eq!(signed[0], EIP2930_TX_TYPE);
+        let fields = tx.signed_fields(&signature).unwrap();
+        assert_eq!(&fields[8..], &[vec![0x01], encode_uint(&[1u8; 32]), encode_uint(&[1u8; 32])]);
+    }
+}
@@ -1,312 +1,1641 @@
 mod wallet;
 mod ecdsa;
+mod state;
+mod error;
+mod rlp;
+mod eth;
+mod eip712;
+mod abi;
+mod calldata;
+mod btc;
+mod schnorr;
+mod sol;
 
 use std::cell::RefCell;
-use std::collections::BTreeMap;
-use std::str::FromStr;
+use std::collections::BTreeSet;
 use candid::Principal;
-use ic_cdk::{caller, init, query, update};
-use ic_cdk::api::management_canister::ecdsa::EcdsaKeyId;
-use crate::wallet::{MultiSignatureWallet, Wallet, WalletError};
+use ic_cdk::{caller, init, post_upgrade, pre_upgrade, query, update};
+use ic_cdk::api::{is_controller, time};
+use ic_cdk::api::management_canister::ecdsa::{EcdsaCurve, EcdsaKeyId};
+use ic_cdk::storage::stable_save;
+use crate::wallet::{AccountIndex, AccountInfo, AccountRef, KeyInfo, MultiSignatureWallet, NonceResync, Proposal,
+                    ProposalHistoryItem, ProposalHistoryPage, ProposalId, ProposalKind, Wallet, DEFAULT_ACCOUNT};
+use crate::state::{PrincipalWalletsMap, StableState, WalletStore};
+use crate::error::SafeError;
+use crate::eip712::TypedData;
+use crate::abi::ContractAbi;
+use crate::calldata::{decode_proposal, DecodedCall, ProposalWithCall};
+use crate::btc::{btc_address as encode_btc_address, der_signature, taproot_address as encode_taproot_address, BtcAddressKind,
+                 BtcNetwork, BtcSigner, BtcTransaction};
+use crate::schnorr::{get_schnorr_public_key, sign_with_schnorr, taproot_output_key, SchnorrAlgorithm, SchnorrKeyId, SchnorrPublicKey};
+use crate::sol::{sol_address as encode_sol_address, SolTransaction};
+use crate::eth::{decode_hex, normalize_address, parse_address, EthTransaction, EthTx1559, EthTx2930, EthTxLegacy};
 
-use crate::ecdsa::{get_eth_address, sign_message, get_ecdsa_key_id_from_env, is_signature_valid};
-
-type WalletStore = BTreeMap<String, Wallet>;
-type PrincipalWalletsMap = BTreeMap<Principal, Vec<String>>;
+use crate::ecdsa::{get_public_key, sign_message, get_ecdsa_key_id_from_env, personal_message_hash, parse_public_key,
+                   verify_with_address, verify_with_public_key, SignedData, SignerKey, WalletPublicKey, ETH_V_OFFSET};
 
 thread_local! {
     static PRINCIPAL_WALLETS_MAP: RefCell<PrincipalWalletsMap> = RefCell::default();
     static WALLETS: RefCell<WalletStore> = RefCell::default();
     static KEY_ID: RefCell<EcdsaKeyId> = RefCell::default();
+    static EXECUTING: RefCell<BTreeSet<(String, ProposalId)>> = RefCell::default();
+}
+
+const MAX_HISTORY_PAGE_SIZE: u64 = 100;
+
+/// The length of a message hash signed by the threshold ECDSA API, in bytes.
+const MESSAGE_HASH_LENGTH: usize = 32;
+
+/// The length of a signature including its recovery ID, in bytes.
+const SIGNATURE_LENGTH: usize = 65;
+
+/// Initializes the module with environment-specific configurations.
+///
+/// # Arguments
+///
+/// * `env` - A string representing the environment.
+///
+/// # Behavior
+///
+/// Initializes the KEY_ID, the key new wallets are created with, with an EcdsaKeyId based on the provided environment.
+#[init]
+fn init(env: String) {
+    KEY_ID.with(|key_id| {
+        key_id.borrow_mut().clone_from(&get_ecdsa_key_id_from_env(&env));
+    });
+}
+
+/// Saves the wallet store, the principal index and the default key to stable memory before an upgrade.
+///
+/// # Behavior
+///
+/// Writes a versioned `StableState` snapshot. Traps (and thereby aborts the upgrade)
+/// if the snapshot cannot be written.
+#[pre_upgrade]
+fn pre_upgrade() {
+    let state = WALLETS.with(|wallets| {
+        PRINCIPAL_WALLETS_MAP.with(|map| {
+            StableState::new(wallets.take(), map.take(), default_key_id())
+        })
+    });
+    stable_save((state,)).expect("Failed to save state to stable memory");
+}
+
+/// Restores the wallet store, the principal index and the default key from stable memory after an upgrade.
+///
+/// # Arguments
+///
+/// * `env` - A string representing the environment.
+///
+/// # Behavior
+///
+/// Restores the snapshot written by `pre_upgrade`, including the KEY_ID. The environment's key is only used for
+/// snapshots that predate per-wallet keys; afterwards `set_default_key_id` changes it. Empty stable memory, left by
+/// releases without `pre_upgrade`, starts a fresh state. Traps (and thereby aborts the upgrade) if a non-empty
+/// snapshot cannot be read.
+#[post_upgrade]
+fn post_upgrade(env: String) {
+    let state = StableState::decode(&ic_cdk::api::stable::stable_bytes(), time())
+        .unwrap_or_else(|error| ic_cdk::trap(&format!("Failed to restore state from stable memory: {}", error)));
+    let (wallets, principal_wallets_map, default_key_id) = state.into_parts(&get_ecdsa_key_id_from_env(&env))
+        .unwrap_or_else(|error| ic_cdk::trap(&error));
+
+    KEY_ID.with(|key_id| *key_id.borrow_mut() = default_key_id);
+    WALLETS.with(|store| *store.borrow_mut() = wallets);
+    PRINCIPAL_WALLETS_MAP.with(|map| *map.borrow_mut() = principal_wallets_map);
+}
+
+/// Creates a new wallet.
+///
+/// # Arguments
+///
+/// * `wallet_id` - Unique identifier for the wallet as a String.
+/// * `signers` - A list of Principals representing the signers of the wallet.
+/// * `threshold` - The threshold number of signers required for a transaction.
+///
+/// # Returns
+///
+/// * `Result<(), SafeError>` - Result indicating success or a `SafeError`.
+#[update]
+fn create_wallet(wallet_id: String, signers: Vec<Principal>, threshold: u8) -> Result<(), SafeError> {
+    if WALLETS.with(|wallets| wallets.borrow().contains_key(&wallet_id)) {
+        return Err(SafeError::WalletAlreadyExists);
+    }
+
+    if signers.is_empty() {
+        return Err(SafeError::NoSigners);
+    }
+
+    let mut wallet = Wallet::with_key_id(default_key_id());
+    signers.iter().for_each(|signer| {
+        wallet.add_signer(*signer);
+    });
+
+    wallet.set_default_threshold(threshold)?;
+
+    let wallet_id_clone = wallet_id.clone(); // Clone wallet_id
+    WALLETS.with(|wallets| {
+        wallets.borrow_mut().insert(wallet_id_clone, wallet.clone()); // Clone wallet
+    });
+
+    // Now, use the original wallet and wallet_id
+    for signer in wallet.get_signers() {
+        let wallet_id_clone = wallet_id.clone(); // Clone wallet_id for use in the closure
+        PRINCIPAL_WALLETS_MAP.with(|map| {
+            let mut map = map.borrow_mut();
+            map.entry(signer).or_insert_with(Vec::new).push(wallet_id_clone);
+        });
+    }
+    Ok(())
+}
+
+
+/// Retrieves a wallet by its ID.
+///
+/// Only the pending proposals are included; `get_proposal_history` pages through the finished ones.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The unique identifier for the wallet as a String.
+///
+/// # Returns
+///
+/// * `Option<Wallet>` - The wallet if found, otherwise None.
+#[query]
+fn get_wallet(wallet_id: String) -> Option<Wallet> {
+    WALLETS.with(|wallets| {
+        wallets.borrow().get(&wallet_id).map(|wallet| wallet.with_pending_proposals(time()))
+    })
+}
+
+
+/// Proposes a message to be signed by the wallet.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `msg` - The 32 byte message hash to be proposed, in hexadecimal format.
+/// * `account` - The account that signs the message, or `None` for the default account.
+///
+/// # Returns
+///
+/// * `Result<ProposalId, SafeError>` - The ID of the new proposal or a `SafeError`.
+#[update]
+fn propose(wallet_id: String, msg: String, account: Option<AccountRef>) -> Result<ProposalId, SafeError> {
+    let msg = hex::decode(msg).map_err(|_| SafeError::DecodeError("msg".to_string()))?;
+    if msg.len() != MESSAGE_HASH_LENGTH {
+        return Err(SafeError::InvalidHashLength);
+    }
+    let account = resolve_account(&wallet_id, account)?;
+    propose_for_account(&wallet_id, account, msg, ProposalKind::SignPayload)
+}
+
+/// Creates a proposal of the given kind in the wallet.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `payload` - The payload to sign. Empty for configuration changes.
+/// * `kind` - The action the proposal performs once executed.
+///
+/// # Returns
+///
+/// * `Result<ProposalId, SafeError>` - The ID of the new proposal or a `SafeError`.
+fn propose_kind(wallet_id: &str, payload: Vec<u8>, kind: ProposalKind) -> Result<ProposalId, SafeError> {
+    WALLETS.with(|wallets| {
+        wallets.borrow_mut().get_mut(wallet_id).ok_or(SafeError::WalletNotFound)?
+            .propose_with_kind(caller(), payload, kind, time()).map_err(SafeError::from)
+    })
+}
+
+/// Creates a proposal of the given kind, signed by one of the wallet's accounts.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `account` - The account whose key signs the payload.
+/// * `payload` - The payload to sign.
+/// * `kind` - The action the proposal performs once executed.
+///
+/// # Returns
+///
+/// * `Result<ProposalId, SafeError>` - The ID of the new proposal or a `SafeError`.
+fn propose_for_account(wallet_id: &str, account: AccountIndex, payload: Vec<u8>, kind: ProposalKind) -> Result<ProposalId, SafeError> {
+    WALLETS.with(|wallets| {
+        wallets.borrow_mut().get_mut(wallet_id).ok_or(SafeError::WalletNotFound)?
+            .propose_for_account(caller(), account, payload, kind, time()).map_err(SafeError::from)
+    })
+}
+
+/// Resolves an account of a wallet by its index or name.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `account` - The account to resolve, or `None` for the default account.
+///
+/// # Returns
+///
+/// * `Result<AccountIndex, SafeError>` - The index of the account or a `SafeError`.
+fn resolve_account(wallet_id: &str, account: Option<AccountRef>) -> Result<AccountIndex, SafeError> {
+    WALLETS.with(|wallets| {
+        let wallets = wallets.borrow();
+        let wallet = wallets.get(wallet_id).ok_or(SafeError::WalletNotFound)?;
+        match account {
+            Some(account) => wallet.resolve_account(&account).map_err(SafeError::from),
+            None => Ok(DEFAULT_ACCOUNT),
+        }
+    })
+}
+
+/// Checks that the caller is a signer of a wallet, so that endpoints which call the management canister or change
+/// state before creating a proposal refuse anyone else up front.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+///
+/// # Returns
+///
+/// * `Result<(), SafeError>` - Success, `SafeError::WalletNotFound` or `SafeError::NotASigner`.
+fn ensure_signer(wallet_id: &str) -> Result<(), SafeError> {
+    WALLETS.with(|wallets| {
+        let wallets = wallets.borrow();
+        let wallet = wallets.get(wallet_id).ok_or(SafeError::WalletNotFound)?;
+        if !wallet.has_signer(caller()) {
+            return Err(SafeError::NotASigner);
+        }
+        Ok(())
+    })
+}
+
+/// Resolves a hex encoded payload to the oldest pending proposal signing it.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `msg` - The proposed message, in hexadecimal format.
+///
+/// # Returns
+///
+/// * `Result<ProposalId, SafeError>` - The ID of the proposal or a `SafeError`.
+fn find_proposal_by_payload(wallet_id: &str, msg: String) -> Result<ProposalId, SafeError> {
+    let msg = hex::decode(msg).map_err(|_| SafeError::DecodeError("msg".to_string()))?;
+    WALLETS.with(|wallets| {
+        wallets.borrow().get(wallet_id)
+            .ok_or(SafeError::WalletNotFound)?
+            .find_proposal_by_payload(&msg, time())
+            .ok_or(SafeError::ProposalNotFound)
+    })
+}
+
+/// Retrieves a proposal by its ID.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `proposal_id` - The ID of the proposal.
+///
+/// # Returns
+///
+/// * `Result<Proposal, SafeError>` - The proposal or a `SafeError`.
+#[query]
+fn get_proposal(wallet_id: String, proposal_id: ProposalId) -> Result<Proposal, SafeError> {
+    WALLETS.with(|wallets| {
+        wallets.borrow().get(&wallet_id)
+            .ok_or(SafeError::WalletNotFound)?
+            .get_proposal(proposal_id)
+            .cloned()
+            .ok_or(SafeError::ProposalNotFound)
+    })
+}
+
+/// Retrieves all pending proposals of a wallet.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+///
+/// # Returns
+///
+/// * `Result<Vec<Proposal>, SafeError>` - The proposals ordered by their ID or a `SafeError`.
+#[query]
+fn get_proposals(wallet_id: String) -> Result<Vec<Proposal>, SafeError> {
+    WALLETS.with(|wallets| {
+        wallets.borrow().get(&wallet_id)
+            .ok_or(SafeError::WalletNotFound)
+            .map(|wallet| wallet.get_proposals(time()).into_iter().cloned().collect())
+    })
+}
+
+/// Checks if a proposal can be executed by the wallet.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `proposal_id` - The ID of the proposal.
+///
+/// # Returns
+///
+/// * `bool` - True if the proposal can be executed, otherwise false.
+#[query]
+fn can_sign_proposal(wallet_id: String, proposal_id: ProposalId) -> bool {
+    WALLETS.with(|wallets| {
+        wallets.borrow().get(&wallet_id)
+            .is_some_and(|wallet| wallet.can_sign(proposal_id, time()))
+    })
+}
+
+/// Checks if a message can be signed by the wallet.
+///
+/// Kept for compatibility, this resolves the message to its proposal and calls `can_sign_proposal`.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `msg` - The message to be checked, in hexadecimal format.
+///
+/// # Returns
+///
+/// * `bool` - True if the message can be signed, otherwise false.
+#[query]
+fn can_sign(wallet_id: String, msg: String) -> bool {
+    match find_proposal_by_payload(&wallet_id, msg) {
+        Ok(proposal_id) => can_sign_proposal(wallet_id, proposal_id),
+        Err(_) => false,
+    }
+}
+
+/// Approves a proposal in the wallet.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `proposal_id` - The ID of the proposal to approve.
+///
+/// # Returns
+///
+/// * `Result<u8, SafeError>` - The number of approvals or a `SafeError`.
+#[update]
+fn approve_proposal(wallet_id: String, proposal_id: ProposalId) -> Result<u8, SafeError> {
+    WALLETS.with(|wallets| {
+        wallets
+            .borrow_mut()
+            .get_mut(&wallet_id)
+            .ok_or(SafeError::WalletNotFound)?
+            .approve(proposal_id, caller(), time()).map_err(SafeError::from)
+    })
+}
+
+/// Approves a message for signing in the wallet.
+///
+/// Kept for compatibility, this resolves the message to its proposal and calls `approve_proposal`.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `msg` - The message to be approved, in hexadecimal format.
+///
+/// # Returns
+///
+/// * `Result<u8, SafeError>` - The number of signatures or a `SafeError`.
+#[update]
+fn approve(wallet_id: String, msg: String) -> Result<u8, SafeError> {
+    let proposal_id = find_proposal_by_payload(&wallet_id, msg)?;
+    approve_proposal(wallet_id, proposal_id)
+}
+
+/// Withdraws the caller's approval from a pending proposal.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `proposal_id` - The ID of the proposal.
+///
+/// # Returns
+///
+/// * `Result<u8, SafeError>` - The number of remaining approvals or a `SafeError`.
+#[update]
+fn revoke_approval(wallet_id: String, proposal_id: ProposalId) -> Result<u8, SafeError> {
+    WALLETS.with(|wallets| {
+        wallets
+            .borrow_mut()
+            .get_mut(&wallet_id)
+            .ok_or(SafeError::WalletNotFound)?
+            .revoke_approval(proposal_id, caller(), time()).map_err(SafeError::from)
+    })
+}
+
+/// Rejects a proposal in the wallet.
+///
+/// The proposal is marked as rejected once the remaining signers can no longer reach the threshold.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `proposal_id` - The ID of the proposal to reject.
+///
+/// # Returns
+///
+/// * `Result<u8, SafeError>` - The number of rejections or a `SafeError`.
+#[update]
+fn reject(wallet_id: String, proposal_id: ProposalId) -> Result<u8, SafeError> {
+    WALLETS.with(|wallets| {
+        wallets
+            .borrow_mut()
+            .get_mut(&wallet_id)
+            .ok_or(SafeError::WalletNotFound)?
+            .reject(proposal_id, caller(), time()).map_err(SafeError::from)
+    })
+}
+
+/// Executes an approved proposal.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `proposal_id` - The ID of the proposal to execute.
+///
+/// # Returns
+///
+/// * `Result<String, SafeError>` - The signature in hexadecimal format for payload proposals,
+///   the `0x` prefixed signed raw transaction for transaction proposals,
+///   the `0x` prefixed signature with `v` in 27/28 for personal messages and typed data,
+///   the base64 encoded finalized PSBT for Bitcoin and Taproot transactions,
+///   the base64 encoded signed transaction for Solana transactions,
+///   the Ethereum address of the default account under the new key for key migrations,
+///   an empty string for other configuration changes, or a `SafeError`.
+#[update]
+async fn execute(wallet_id: String, proposal_id: ProposalId) -> Result<String, SafeError> {
+    let proposal = executable_proposal(&wallet_id, proposal_id)?;
+    let _guard = ExecutionGuard::new(&wallet_id, proposal_id)?;
+
+    let account = proposal.signing_account();
+    let mut migrated_key = None;
+    let mut signed_transaction = None;
+    let (signature, output) = match proposal.kind.clone() {
+        ProposalKind::SignPayload => {
+            let signature = sign_payload(&wallet_id, account, proposal.payload).await?;
+            let output = hex::encode(&signature);
+            (Some(signature), output)
+        }
+        ProposalKind::LegacyTransaction(tx) => sign_transaction(&wallet_id, account, proposal.payload, &tx).await?,
+        ProposalKind::Eip2930Transaction(tx) => sign_transaction(&wallet_id, account, proposal.payload, &tx).await?,
+        ProposalKind::Eip1559Transaction(tx) => sign_transaction(&wallet_id, account, proposal.payload, &tx).await?,
+        ProposalKind::BtcTransaction(tx) => {
+            let psbt = sign_btc_transaction(&wallet_id, account, &tx).await?;
+            signed_transaction = Some(psbt.clone());
+            (None, psbt)
+        }
+        ProposalKind::TaprootTransaction(tx) => {
+            let psbt = sign_taproot_transaction(&wallet_id, account, &tx).await?;
+            signed_transaction = Some(psbt.clone());
+            (None, psbt)
+        }
+        ProposalKind::SolanaTransaction(tx) => {
+            let signed = sign_sol_transaction(&wallet_id, account, &tx).await?;
+            signed_transaction = Some(signed.clone());
+            (None, signed)
+        }
+        ProposalKind::MigrateKey(name) => {
+            let public_key = derive_migrated_key(&wallet_id, name).await?;
+            let output = public_key.eth_address.clone();
+            migrated_key = Some(public_key);
+            (None, output)
+        }
+        ProposalKind::PersonalMessage(_) | ProposalKind::TypedData(_) => {
+            let mut signature = sign_payload(&wallet_id, account, proposal.payload).await?;
+            if let Some(v) = signature.last_mut() {
+                *v += ETH_V_OFFSET;
+            }
+            let output = format!("0x{}", hex::encode(&signature));
+            (Some(signature), output)
+        }
+        _ => (None, String::new()),
+    };
+
+    // approvals may have been revoked or signers removed while the signature was computed
+    executable_proposal(&wallet_id, proposal_id)?;
+    if migrated_key.is_none() && proposal.kind.is_config_change() {
+        // configuration changes are recorded as executed as they are applied
+        execute_config_change(&wallet_id, proposal_id, &proposal.kind)?;
+        return Ok(output);
+    }
+    if let Some(public_key) = migrated_key {
+        WALLETS.with(|wallets| {
+            wallets.borrow_mut().get_mut(&wallet_id).ok_or(SafeError::WalletNotFound)?
+                .migrate_key(public_key, time()).map_err(SafeError::from)
+        })?;
+    }
+
+    WALLETS.with(|wallets| {
+        wallets.borrow_mut().get_mut(&wallet_id).ok_or(SafeError::WalletNotFound)?
+            .mark_executed(proposal_id, signature, signed_transaction, time())
+            .map_err(SafeError::from)
+    })?;
+
+    Ok(output)
+}
+
+/// Gets a proposal that has enough approvals to be executed.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `proposal_id` - The ID of the proposal.
+///
+/// # Returns
+///
+/// * `Result<Proposal, SafeError>` - The proposal or `SafeError::CannotExecute` if it cannot be executed.
+fn executable_proposal(wallet_id: &str, proposal_id: ProposalId) -> Result<Proposal, SafeError> {
+    WALLETS.with(|wallets| {
+        let wallets = wallets.borrow();
+        let wallet = wallets.get(wallet_id).ok_or(SafeError::WalletNotFound)?;
+        if !wallet.can_sign(proposal_id, time()) {
+            return Err(SafeError::CannotExecute);
+        }
+        wallet.get_proposal(proposal_id).cloned().ok_or(SafeError::ProposalNotFound)
+    })
+}
+
+/// Marks a proposal as being executed for as long as it lives, so a concurrent `execute` cannot sign it a second
+/// time while the first one awaits the management canister.
+struct ExecutionGuard {
+    key: (String, ProposalId),
+}
+
+impl ExecutionGuard {
+    /// Marks a proposal as being executed.
+    ///
+    /// # Arguments
+    ///
+    /// * `wallet_id` - The wallet's unique identifier.
+    /// * `proposal_id` - The ID of the proposal.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<ExecutionGuard, SafeError>` - The guard or `SafeError::ExecutionInProgress`.
+    fn new(wallet_id: &str, proposal_id: ProposalId) -> Result<Self, SafeError> {
+        let key = (wallet_id.to_string(), proposal_id);
+        if !EXECUTING.with(|executing| executing.borrow_mut().insert(key.clone())) {
+            return Err(SafeError::ExecutionInProgress);
+        }
+        Ok(ExecutionGuard { key })
+    }
+}
+
+impl Drop for ExecutionGuard {
+    fn drop(&mut self) {
+        EXECUTING.with(|executing| executing.borrow_mut().remove(&self.key));
+    }
+}
+
+/// Signs a payload with the threshold ECDSA key of a wallet's account.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `account` - The account whose key signs.
+/// * `payload` - The 32 byte hash to sign.
+///
+/// # Returns
+///
+/// * `Result<Vec<u8>, SafeError>` - The 65 byte `r || s || recovery ID` signature or a `SafeError`.
+async fn sign_payload(wallet_id: &str, account: AccountIndex, payload: Vec<u8>) -> Result<Vec<u8>, SafeError> {
+    let public_key = wallet_public_key(wallet_id, account).await?;
+    let pub_key = public_key.uncompressed_key().map_err(SafeError::EcdsaError)?;
+    sign_message(wallet_id.to_string(), account, payload, public_key.key_id, pub_key).await
+        .map_err(SafeError::EcdsaError)
+}
+
+/// Gets the threshold key new wallets are created with.
+///
+/// # Returns
+///
+/// * `EcdsaKeyId` - The key ID.
+fn default_key_id() -> EcdsaKeyId {
+    KEY_ID.with(|key_id| {
+        key_id.borrow().clone()
+    })
+}
+
+/// Gets the threshold ECDSA key a wallet signs with.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+///
+/// # Returns
+///
+/// * `Result<EcdsaKeyId, SafeError>` - The key ID or `SafeError::WalletNotFound`.
+fn wallet_key_id(wallet_id: &str) -> Result<EcdsaKeyId, SafeError> {
+    WALLETS.with(|wallets| {
+        wallets.borrow().get(wallet_id)
+            .ok_or(SafeError::WalletNotFound)
+            .map(|wallet| wallet.get_key_id().cloned().unwrap_or_else(default_key_id))
+    })
+}
+
+/// Gets the cached public key of a wallet's account if it was derived from the wallet's threshold key.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `account` - The account of the key.
+///
+/// # Returns
+///
+/// * `Result<Option<WalletPublicKey>, SafeError>` - The cached public key, `None` if it has not been derived yet,
+///   or `SafeError::WalletNotFound`.
+fn cached_public_key(wallet_id: &str, account: AccountIndex) -> Result<Option<WalletPublicKey>, SafeError> {
+    let key_id = wallet_key_id(wallet_id)?;
+    WALLETS.with(|wallets| {
+        wallets.borrow().get(wallet_id)
+            .ok_or(SafeError::WalletNotFound)
+            .map(|wallet| wallet.get_public_key(account, &key_id).cloned())
+    })
+}
+
+/// Gets the public key of a wallet's account, deriving it with the management canister and caching it on first use.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `account` - The account of the key.
+///
+/// # Returns
+///
+/// * `Result<WalletPublicKey, SafeError>` - The public key or a `SafeError`.
+async fn wallet_public_key(wallet_id: &str, account: AccountIndex) -> Result<WalletPublicKey, SafeError> {
+    if let Some(public_key) = cached_public_key(wallet_id, account)? {
+        return Ok(public_key);
+    }
+    let key_id = wallet_key_id(wallet_id)?;
+    let public_key = get_public_key(wallet_id.to_string(), account, key_id).await
+        .map_err(SafeError::EcdsaError)?;
+    WALLETS.with(|wallets| {
+        if let Some(wallet) = wallets.borrow_mut().get_mut(wallet_id) {
+            wallet.set_public_key(account, public_key.clone());
+        }
+    });
+    Ok(public_key)
+}
+
+/// Verifies a signature against the cached public key of a wallet's account.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `account` - The account of the key.
+/// * `message` - The 32 byte message hash.
+/// * `signature` - The 65 byte `r || s || recovery ID` signature.
+///
+/// # Returns
+///
+/// * `Result<bool, SafeError>` - Whether the signature was made by the account, or a `SafeError`.
+fn verify_with_cached_key(wallet_id: &str, account: AccountIndex, message: &[u8], signature: &[u8]) -> Result<bool, SafeError> {
+    let public_key = cached_public_key(wallet_id, account)?.ok_or(SafeError::PublicKeyNotCached)?;
+    let pub_key = public_key.uncompressed_key().map_err(SafeError::EcdsaError)?;
+    verify_with_public_key(message, signature, pub_key).map_err(SafeError::EcdsaError)
+}
+
+/// Signs the signing hash of a transaction and serializes the signed transaction.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `account` - The account whose key signs.
+/// * `payload` - The signing hash of the transaction.
+/// * `tx` - The transaction.
+///
+/// # Returns
+///
+/// * `Result<(Option<Vec<u8>>, String), SafeError>` - The signature and the `0x` prefixed signed raw transaction,
+///   or a `SafeError`.
+async fn sign_transaction(
+    wallet_id: &str,
+    account: AccountIndex,
+    payload: Vec<u8>,
+    tx: &impl EthTransaction,
+) -> Result<(Option<Vec<u8>>, String), SafeError> {
+    let signature = sign_payload(wallet_id, account, payload).await?;
+    let raw = tx.encode_signed(&signature).map_err(SafeError::InvalidTransaction)?;
+    Ok((Some(signature), format!("0x{}", hex::encode(raw))))
+}
+
+/// Proposes a legacy Ethereum transaction with EIP-155 replay protection to be signed by the wallet.
+///
+/// Executing the proposal returns the signed raw transaction, with `v = recovery ID + chain ID * 2 + 35`.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `tx` - The transaction to sign. Its nonce is replaced with the account's next free nonce on the chain.
+/// * `account` - The account that signs the transaction, or `None` for the default account.
+///
+/// # Returns
+///
+/// * `Result<ProposalId, SafeError>` - The ID of the new proposal or a `SafeError`.
+#[update]
+fn propose_legacy_transaction(wallet_id: String, mut tx: EthTxLegacy, account: Option<AccountRef>) -> Result<ProposalId, SafeError> {
+    let account = resolve_account(&wallet_id, account)?;
+    tx.nonce = next_nonce(&wallet_id, account, tx.chain_id)?;
+    let hash = tx.signing_hash().map_err(SafeError::InvalidTransaction)?;
+    propose_for_account(&wallet_id, account, hash.to_vec(), ProposalKind::LegacyTransaction(tx))
+}
+
+/// Proposes an EIP-2930 Ethereum transaction to be signed by the wallet.
+///
+/// Executing the proposal returns the signed raw transaction.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `tx` - The transaction to sign. Its nonce is replaced with the account's next free nonce on the chain.
+/// * `account` - The account that signs the transaction, or `None` for the default account.
+///
+/// # Returns
+///
+/// * `Result<ProposalId, SafeError>` - The ID of the new proposal or a `SafeError`.
+#[update]
+fn propose_eip2930_transaction(wallet_id: String, mut tx: EthTx2930, account: Option<AccountRef>) -> Result<ProposalId, SafeError> {
+    let account = resolve_account(&wallet_id, account)?;
+    tx.nonce = next_nonce(&wallet_id, account, tx.chain_id)?;
+    let hash = tx.signing_hash().map_err(SafeError::InvalidTransaction)?;
+    propose_for_account(&wallet_id, account, hash.to_vec(), ProposalKind::Eip2930Transaction(tx))
+}
+
+/// Proposes an EIP-1559 Ethereum transaction to be signed by the wallet.
+///
+/// The transaction is RLP encoded and hashed in the canister, so signers approve the
+/// transaction itself rather than an opaque hash. Executing the proposal returns the
+/// signed raw transaction.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `tx` - The transaction to sign. Its nonce is replaced with the account's next free nonce on the chain.
+/// * `account` - The account that signs the transaction, or `None` for the default account.
+///
+/// # Returns
+///
+/// * `Result<ProposalId, SafeError>` - The ID of the new proposal or a `SafeError`.
+#[update]
+fn propose_eth_transaction(wallet_id: String, mut tx: EthTx1559, account: Option<AccountRef>) -> Result<ProposalId, SafeError> {
+    let account = resolve_account(&wallet_id, account)?;
+    tx.nonce = next_nonce(&wallet_id, account, tx.chain_id)?;
+    let hash = tx.signing_hash().map_err(SafeError::InvalidTransaction)?;
+    propose_for_account(&wallet_id, account, hash.to_vec(), ProposalKind::Eip1559Transaction(tx))
+}
+
+/// Gets the nonce the next transaction an account proposes on a chain is assigned.
+///
+/// Pending transaction proposals reserve their nonces until they are executed, cancelled, rejected or expire.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `chain_id` - The EIP-155 chain ID.
+/// * `account` - The account, or `None` for the default account.
+///
+/// # Returns
+///
+/// * `Result<u64, SafeError>` - The next nonce or a `SafeError`.
+#[query]
+fn get_next_nonce(wallet_id: String, chain_id: u64, account: Option<AccountRef>) -> Result<u64, SafeError> {
+    let account = resolve_account(&wallet_id, account)?;
+    next_nonce(&wallet_id, account, chain_id)
+}
+
+/// Gets the next free nonce of a wallet's account on a chain.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `account` - The account.
+/// * `chain_id` - The EIP-155 chain ID.
+///
+/// # Returns
+///
+/// * `Result<u64, SafeError>` - The next nonce or a `SafeError`.
+fn next_nonce(wallet_id: &str, account: AccountIndex, chain_id: u64) -> Result<u64, SafeError> {
+    WALLETS.with(|wallets| {
+        wallets.borrow().get(wallet_id)
+            .ok_or(SafeError::WalletNotFound)
+            .map(|wallet| wallet.next_nonce(account, chain_id, time()))
+    })
+}
+
+/// Proposes resyncing an account's nonce on a chain with its on-chain transaction count, e.g. after a
+/// signed transaction was never broadcast. Like other configuration changes it needs the threshold of approvals.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `chain_id` - The EIP-155 chain ID.
+/// * `nonce` - The account's transaction count on the chain.
+/// * `account` - The account, or `None` for the default account.
+///
+/// # Returns
+///
+/// * `Result<ProposalId, SafeError>` - The ID of the new proposal or a `SafeError`.
+#[update]
+fn resync_nonce(wallet_id: String, chain_id: u64, nonce: u64, account: Option<AccountRef>) -> Result<ProposalId, SafeError> {
+    let account = resolve_account(&wallet_id, account)?;
+    propose_kind(&wallet_id, Vec::new(), ProposalKind::ResyncNonce(NonceResync { account, chain_id, nonce }))
+}
+
+/// Cancels a pending proposal. Only the proposer may cancel.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `proposal_id` - The ID of the proposal to cancel.
+///
+/// # Returns
+///
+/// * `Result<(), SafeError>` - Result indicating success or a `SafeError`.
+#[update]
+fn cancel_proposal(wallet_id: String, proposal_id: ProposalId) -> Result<(), SafeError> {
+    WALLETS.with(|wallets| {
+        wallets.borrow_mut().get_mut(&wallet_id).ok_or(SafeError::WalletNotFound)?
+            .cancel(proposal_id, caller(), time()).map_err(SafeError::from)
+    })
+}
+
+/// Retrieves a page of proposals that are no longer pending, newest first.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `cursor` - The `next_cursor` of the previous page, or `None` to start at the newest proposal.
+/// * `limit` - The maximum number of proposals to return, capped at `MAX_HISTORY_PAGE_SIZE`.
+///
+/// # Returns
+///
+/// * `Result<ProposalHistoryPage, SafeError>` - The page of proposals or a `SafeError`.
+#[query]
+fn get_proposal_history(wallet_id: String, cursor: Option<ProposalId>, limit: u64) -> Result<ProposalHistoryPage, SafeError> {
+    let limit = limit.min(MAX_HISTORY_PAGE_SIZE) as usize;
+    let now = time();
+    WALLETS.with(|wallets| {
+        let wallets = wallets.borrow();
+        let wallet = wallets.get(&wallet_id).ok_or(SafeError::WalletNotFound)?;
+        // queries don't persist state, so proposals that expired since the last update are reported as expired
+        let items: Vec<ProposalHistoryItem> = wallet.get_history(cursor, limit, now)
+            .into_iter()
+            .map(|proposal| ProposalHistoryItem::new(proposal, now))
+            .collect();
+        let next_cursor = match items.last() {
+            Some(item) if items.len() == limit => Some(item.id),
+            _ => None,
+        };
+        Ok(ProposalHistoryPage { items, next_cursor })
+    })
+}
+
+/// Signs a message using the wallet.
+///
+/// Kept for compatibility, this resolves the message to its proposal and calls `execute`.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `msg` - The message to be signed, in hexadecimal format.
+///
+/// # Returns
+///
+/// * `Result<String, SafeError>` - The signature in hexadecimal format or a `SafeError`.
+#[update]
+async fn sign(wallet_id: String, msg: String) -> Result<String, SafeError> {
+    let proposal_id = find_proposal_by_payload(&wallet_id, msg)?;
+    execute(wallet_id, proposal_id).await
+}
+
+/// Applies the configuration change of a proposal, records it as executed and keeps the principal index in sync.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `proposal_id` - The ID of the proposal.
+/// * `kind` - The configuration change of the proposal.
+///
+/// # Returns
+///
+/// * `Result<(), SafeError>` - Result indicating success or a `SafeError`.
+fn execute_config_change(wallet_id: &str, proposal_id: ProposalId, kind: &ProposalKind) -> Result<(), SafeError> {
+    WALLETS.with(|wallets| {
+        wallets.borrow_mut().get_mut(wallet_id).ok_or(SafeError::WalletNotFound)?
+            .execute_config_change(proposal_id, time()).map_err(SafeError::from)
+    })?;
+
+    PRINCIPAL_WALLETS_MAP.with(|map| {
+        let mut map = map.borrow_mut();
+        match kind {
+            ProposalKind::AddSigner(signer) => {
+                map.entry(*signer).or_default().push(wallet_id.to_string());
+            }
+            ProposalKind::RemoveSigner(signer) => {
+                if let Some(wallets) = map.get_mut(signer) {
+                    wallets.retain(|id| id != wallet_id);
+                }
+            }
+            _ => {}
+        }
+    });
+    Ok(())
+}
+
+/// Derives the default account's public key under the key a wallet migrates to. Nothing changes until `execute`
+/// applies it, so a key the management canister does not know leaves the wallet and the proposal as they were.
+/// The old key is derived on a best-effort basis; when that fails the migration records no old address.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `key_name` - The name of the new key.
+///
+/// # Returns
+///
+/// * `Result<WalletPublicKey, SafeError>` - The public key under the new key or a `SafeError`.
+async fn derive_migrated_key(wallet_id: &str, key_name: String) -> Result<WalletPublicKey, SafeError> {
+    // derive the old address first so the migration can record it; if the old key cannot be derived (for example
+    // because it was retired) the migration proceeds and records no old address
+    let _ = wallet_public_key(wallet_id, DEFAULT_ACCOUNT).await;
+
+    let key_id = EcdsaKeyId { curve: EcdsaCurve::Secp256k1, name: key_name };
+    get_public_key(wallet_id.to_string(), DEFAULT_ACCOUNT, key_id).await
+        .map_err(SafeError::EcdsaError)
+}
+
+/// Retrieves the Ethereum address of an account of the wallet.
+///
+/// The account's public key is derived on the first call and cached, so later calls return without an
+/// inter-canister call.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `account` - The account, or `None` for the default account.
+///
+/// # Returns
+///
+/// * `Result<String, SafeError>` - The EIP-55 checksummed Ethereum address or a `SafeError`.
+#[update]
+async fn eth_address(wallet_id: String, account: Option<AccountRef>) -> Result<String, SafeError> {
+    let account = resolve_account(&wallet_id, account)?;
+    Ok(wallet_public_key(&wallet_id, account).await?.eth_address)
+}
+
+/// Retrieves a Bitcoin address of the wallet, derived from the same threshold key as its Ethereum address.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `network` - The network the address is for.
+/// * `kind` - Whether to return the P2PKH or the P2WPKH address.
+/// * `account` - The account, or `None` for the default account.
+///
+/// # Returns
+///
+/// * `Result<String, SafeError>` - The address or a `SafeError`.
+#[update]
+async fn btc_address(
+    wallet_id: String,
+    network: BtcNetwork,
+    kind: BtcAddressKind,
+    account: Option<AccountRef>,
+) -> Result<String, SafeError> {
+    let account = resolve_account(&wallet_id, account)?;
+    let public_key = wallet_public_key(&wallet_id, account).await?;
+    Ok(encode_btc_address(&public_key.compressed, network, kind))
+}
+
+/// Proposes a Bitcoin transaction, given as an unsigned PSBT, to be signed by the wallet.
+///
+/// The inputs, outputs and fee are decoded for review via `get_btc_transaction`, and the sighash of every
+/// input spending the wallet's P2PKH or P2WPKH outputs is computed in the canister. Executing the proposal
+/// signs these inputs with `SIGHASH_ALL` and returns the finalized PSBT.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `network` - The network the addresses of the transaction are rendered for.
+/// * `psbt` - The base64 encoded BIP-174 PSBT. Every input must carry the output it spends.
+/// * `account` - The account whose outputs are spent, or `None` for the default account.
+///
+/// # Returns
+///
+/// * `Result<ProposalId, SafeError>` - The ID of the new proposal or a `SafeError`.
+#[update]
+async fn propose_btc_transaction(
+    wallet_id: String,
+    network: BtcNetwork,
+    psbt: String,
+    account: Option<AccountRef>,
+) -> Result<ProposalId, SafeError> {
+    ensure_signer(&wallet_id)?;
+    let account = resolve_account(&wallet_id, account)?;
+    let public_key = wallet_public_key(&wallet_id, account).await?;
+    let tx = BtcTransaction::parse(&psbt, network, BtcSigner::Ecdsa(&public_key.compressed)).map_err(SafeError::InvalidPsbt)?;
+    let txid = decode_hex(&tx.txid).map_err(|_| SafeError::DecodeError("txid".to_string()))?;
+    propose_for_account(&wallet_id, account, txid, ProposalKind::BtcTransaction(tx))
+}
+
+/// Get the decoded Bitcoin transaction of a proposal for review.
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `proposal_id` - The ID of the proposal.
+///
+/// Returns `Result<BtcTransaction, SafeError>` containing the transaction, or a `SafeError` if the proposal does not
+/// sign a Bitcoin transaction.
+#[query]
+fn get_btc_transaction(wallet_id: String, proposal_id: ProposalId) -> Result<BtcTransaction, SafeError> {
+    let proposal = get_proposal(wallet_id, proposal_id)?;
+    match proposal.kind {
+        ProposalKind::BtcTransaction(tx) | ProposalKind::TaprootTransaction(tx) => Ok(tx),
+        _ => Err(SafeError::WrongProposalKind),
+    }
+}
+
+/// Signs an account's inputs of a Bitcoin transaction and finalizes them.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `account` - The account whose key signs.
+/// * `tx` - The proposed transaction.
+///
+/// # Returns
+///
+/// * `Result<String, SafeError>` - The base64 encoded finalized PSBT or a `SafeError`.
+async fn sign_btc_transaction(wallet_id: &str, account: AccountIndex, tx: &BtcTransaction) -> Result<String, SafeError> {
+    let public_key = wallet_public_key(wallet_id, account).await?;
+    let mut signatures = Vec::new();
+    for sighash in tx.sighashes() {
+        let signature = sign_payload(wallet_id, account, sighash).await?;
+        signatures.push(der_signature(&signature).map_err(SafeError::EcdsaError)?);
+    }
+    tx.finalize(BtcSigner::Ecdsa(&public_key.compressed), &signatures).map_err(SafeError::InvalidPsbt)
+}
+
+/// Gets the threshold Schnorr key ID a wallet signs with. Schnorr keys are named like the wallet's ECDSA key.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `algorithm` - The signature algorithm.
+///
+/// # Returns
+///
+/// * `Result<SchnorrKeyId, SafeError>` - The key ID or `SafeError::WalletNotFound`.
+fn schnorr_key_id(wallet_id: &str, algorithm: SchnorrAlgorithm) -> Result<SchnorrKeyId, SafeError> {
+    Ok(SchnorrKeyId { algorithm, name: wallet_key_id(wallet_id)?.name })
+}
+
+/// Gets the Schnorr public key of a wallet's account, deriving it with the management canister and caching it on
+/// first use.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `account` - The account of the key.
+/// * `algorithm` - The signature algorithm.
+///
+/// # Returns
+///
+/// * `Result<SchnorrPublicKey, SafeError>` - The public key or a `SafeError`.
+async fn wallet_schnorr_public_key(
+    wallet_id: &str,
+    account: AccountIndex,
+    algorithm: SchnorrAlgorithm,
+) -> Result<SchnorrPublicKey, SafeError> {
+    let key_id = schnorr_key_id(wallet_id, algorithm)?;
+    let cached = WALLETS.with(|wallets| {
+        wallets.borrow().get(wallet_id)
+            .ok_or(SafeError::WalletNotFound)
+            .map(|wallet| wallet.get_schnorr_public_key(account, &key_id).cloned())
+    })?;
+    if let Some(public_key) = cached {
+        return Ok(public_key);
+    }
+    let public_key = get_schnorr_public_key(wallet_id.to_string(), account, key_id).await
+        .map_err(SafeError::SchnorrError)?;
+    WALLETS.with(|wallets| {
+        if let Some(wallet) = wallets.borrow_mut().get_mut(wallet_id) {
+            wallet.set_schnorr_public_key(account, public_key.clone());
+        }
+    });
+    Ok(public_key)
+}
+
+/// Gets the Taproot output key of a wallet's account: its BIP-340 key, tweaked for an output without script paths.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `account` - The account of the key.
+///
+/// # Returns
+///
+/// * `Result<[u8; 32], SafeError>` - The x-only output key or a `SafeError`.
+async fn wallet_taproot_output_key(wallet_id: &str, account: AccountIndex) -> Result<[u8; 32], SafeError> {
+    let public_key = wallet_schnorr_public_key(wallet_id, account, SchnorrAlgorithm::Bip340Secp256k1).await?;
+    taproot_output_key(&public_key.public_key).map_err(SafeError::SchnorrError)
+}
+
+/// Retrieves the Taproot address of the wallet, derived from its threshold Schnorr key.
+///
+/// The output has no script paths, so it can only be spent through the key path with the wallet's approval flow.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `network` - The network the address is for.
+/// * `account` - The account, or `None` for the default account.
+///
+/// # Returns
+///
+/// * `Result<String, SafeError>` - The P2TR address or a `SafeError`.
+#[update]
+async fn taproot_address(wallet_id: String, network: BtcNetwork, account: Option<AccountRef>) -> Result<String, SafeError> {
+    let account = resolve_account(&wallet_id, account)?;
+    let output_key = wallet_taproot_output_key(&wallet_id, account).await?;
+    Ok(encode_taproot_address(&output_key, network))
+}
+
+/// Proposes a Bitcoin transaction, given as an unsigned PSBT, that spends the wallet's Taproot outputs.
+///
+/// The transaction is decoded for review via `get_btc_transaction` like for `propose_btc_transaction`, with the
+/// BIP-341 sighash of every input spending the wallet's P2TR output. Executing the proposal signs these inputs
+/// through the key path with `SIGHASH_DEFAULT` and returns the finalized PSBT.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `network` - The network the addresses of the transaction are rendered for.
+/// * `psbt` - The base64 encoded BIP-174 PSBT. Every input must carry the output it spends.
+/// * `account` - The account whose outputs are spent, or `None` for the default account.
+///
+/// # Returns
+///
+/// * `Result<ProposalId, SafeError>` - The ID of the new proposal or a `SafeError`.
+#[update]
+async fn propose_taproot_transaction(
+    wallet_id: String,
+    network: BtcNetwork,
+    psbt: String,
+    account: Option<AccountRef>,
+) -> Result<ProposalId, SafeError> {
+    ensure_signer(&wallet_id)?;
+    let account = resolve_account(&wallet_id, account)?;
+    let output_key = wallet_taproot_output_key(&wallet_id, account).await?;
+    let tx = BtcTransaction::parse(&psbt, network, BtcSigner::Taproot(&output_key)).map_err(SafeError::InvalidPsbt)?;
+    let txid = decode_hex(&tx.txid).map_err(|_| SafeError::DecodeError("txid".to_string()))?;
+    propose_for_account(&wallet_id, account, txid, ProposalKind::TaprootTransaction(tx))
+}
+
+/// Signs an account's Taproot inputs of a Bitcoin transaction through the key path and finalizes them.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `account` - The account whose key signs.
+/// * `tx` - The proposed transaction.
+///
+/// # Returns
+///
+/// * `Result<String, SafeError>` - The base64 encoded finalized PSBT or a `SafeError`.
+async fn sign_taproot_transaction(wallet_id: &str, account: AccountIndex, tx: &BtcTransaction) -> Result<String, SafeError> {
+    let output_key = wallet_taproot_output_key(wallet_id, account).await?;
+    let key_id = schnorr_key_id(wallet_id, SchnorrAlgorithm::Bip340Secp256k1)?;
+    let mut signatures = Vec::new();
+    for sighash in tx.sighashes() {
+        // the management canister applies the same tweak as `taproot_output_key` for an empty merkle root
+        let signature = sign_with_schnorr(wallet_id.to_string(), account, sighash, key_id.clone(), Some(Vec::new())).await
+            .map_err(SafeError::SchnorrError)?;
+        signatures.push(signature);
+    }
+    tx.finalize(BtcSigner::Taproot(&output_key), &signatures).map_err(SafeError::InvalidPsbt)
+}
+
+/// Gets the Ed25519 public key of a wallet's account, which Solana uses as the account's address.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `account` - The account of the key.
+///
+/// # Returns
+///
+/// * `Result<Vec<u8>, SafeError>` - The 32 byte public key or a `SafeError`.
+async fn wallet_ed25519_public_key(wallet_id: &str, account: AccountIndex) -> Result<Vec<u8>, SafeError> {
+    let public_key = wallet_schnorr_public_key(wallet_id, account, SchnorrAlgorithm::Ed25519).await?;
+    Ok(public_key.public_key)
+}
+
+/// Retrieves the Solana address of an account of the wallet, derived from its threshold Ed25519 key.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `account` - The account, or `None` for the default account.
+///
+/// # Returns
+///
+/// * `Result<String, SafeError>` - The base58 address or a `SafeError`.
+#[update]
+async fn sol_address(wallet_id: String, account: Option<AccountRef>) -> Result<String, SafeError> {
+    let account = resolve_account(&wallet_id, account)?;
+    let public_key = wallet_ed25519_public_key(&wallet_id, account).await?;
+    encode_sol_address(&public_key).map_err(SafeError::SchnorrError)
+}
+
+/// Proposes a Solana transaction, given as its serialized message, to be signed by the wallet.
+///
+/// The instructions are decoded for review via `get_sol_transaction`: the accounts of every instruction, and the
+/// arguments of the System, SPL Token, Associated Token Account, Compute Budget and Memo programs. Executing the
+/// proposal signs the message with the wallet's Ed25519 key and returns the signed transaction.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `message` - The base64 encoded legacy or version 0 message. The account must be one of its signers.
+/// * `account` - The account that signs the transaction, or `None` for the default account.
+///
+/// # Returns
+///
+/// * `Result<ProposalId, SafeError>` - The ID of the new proposal or a `SafeError`.
+#[update]
+async fn propose_sol_transaction(wallet_id: String, message: String, account: Option<AccountRef>) -> Result<ProposalId, SafeError> {
+    ensure_signer(&wallet_id)?;
+    let account = resolve_account(&wallet_id, account)?;
+    let public_key = wallet_ed25519_public_key(&wallet_id, account).await?;
+    let tx = SolTransaction::parse(&message, &public_key).map_err(SafeError::InvalidSolanaTransaction)?;
+    let payload = tx.message_bytes().map_err(SafeError::InvalidSolanaTransaction)?;
+    propose_for_account(&wallet_id, account, payload, ProposalKind::SolanaTransaction(tx))
+}
+
+/// Get the decoded Solana transaction of a proposal for review.
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `proposal_id` - The ID of the proposal.
+///
+/// Returns `Result<SolTransaction, SafeError>` containing the transaction, or a `SafeError` if the proposal does not
+/// sign a Solana transaction.
+#[query]
+fn get_sol_transaction(wallet_id: String, proposal_id: ProposalId) -> Result<SolTransaction, SafeError> {
+    let proposal = get_proposal(wallet_id, proposal_id)?;
+    match proposal.kind {
+        ProposalKind::SolanaTransaction(tx) => Ok(tx),
+        _ => Err(SafeError::WrongProposalKind),
+    }
+}
+
+/// Signs the message of a Solana transaction with an account's Ed25519 key.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `account` - The account whose key signs.
+/// * `tx` - The proposed transaction.
+///
+/// # Returns
+///
+/// * `Result<String, SafeError>` - The base64 encoded signed transaction or a `SafeError`.
+async fn sign_sol_transaction(wallet_id: &str, account: AccountIndex, tx: &SolTransaction) -> Result<String, SafeError> {
+    let message = tx.message_bytes().map_err(SafeError::InvalidSolanaTransaction)?;
+    let key_id = schnorr_key_id(wallet_id, SchnorrAlgorithm::Ed25519)?;
+    let signature = sign_with_schnorr(wallet_id.to_string(), account, message, key_id, None).await
+        .map_err(SafeError::SchnorrError)?;
+    tx.encode_signed(&signature).map_err(SafeError::SchnorrError)
 }
 
-const WALLET_NOT_FOUND_ERROR: &str = "WalletNotFound";
-const WALLET_ALREADY_EXISTS_ERROR: &str = "WalletAlreadyExists";
-const WALLET_MSG_ALREADY_QUEUED_ERROR: &str = "WalletMsgAlreadyQueued";
-const WALLET_INVALID_SIGNATURE_ERROR: &str = "WalletInvalidSignature";
-const WALLET_CANNOT_SIGN_ERROR: &str = "WalletCannotSign";
-const WALLET_SIGNERS_NOT_MATCH_THRESHOLD: &str = "WalletSignersNotMatchThreshold";
-const METADATA_NOT_FOUND: &str = "MetadataNotFound";
-
-
-/// Initializes the module with environment-specific configurations.
+/// Verifies a signature for a given message and wallet.
+///
+/// Runs against the account's cached public key, which `eth_address` or the first signature derives.
 ///
 /// # Arguments
 ///
-/// * `env` - A string representing the environment.
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `message` - The message associated with the signature, in hexadecimal format.
+/// * `signature` - The signature to be verified, in hexadecimal format.
+/// * `account` - The account that should have signed, or `None` for the default account.
 ///
-/// # Behavior
+/// # Returns
 ///
-/// Initializes the KEY_ID with an EcdsaKeyId based on the provided environment.
-#[init]
-fn init(env: String) {
-    KEY_ID.with(|key_id| {
-        key_id.borrow_mut().clone_from(&get_ecdsa_key_id_from_env(&env));
-    });
+/// * `Result<bool, SafeError>` - Whether the signature was made by the account, or a `SafeError`.
+#[query]
+fn verify_signature(wallet_id: String, message: String, signature: String, account: Option<AccountRef>) -> Result<bool, SafeError> {
+    let message = hex::decode(message).map_err(|_| SafeError::DecodeError("message".to_string()))?;
+    let signature = hex::decode(signature).map_err(|_| SafeError::DecodeError("signature".to_string()))?;
+    if message.len() != MESSAGE_HASH_LENGTH {
+        return Err(SafeError::InvalidHashLength);
+    }
+    if signature.len() != SIGNATURE_LENGTH {
+        return Err(SafeError::InvalidSignatureLength);
+    }
+    let account = resolve_account(&wallet_id, account)?;
+    verify_with_cached_key(&wallet_id, account, &message, &signature)
 }
 
-/// Creates a new wallet.
+/// Proposes a message to be signed with the EIP-191 `personal_sign` prefix.
+///
+/// The readable message is stored with the proposal so signers can review it. Executing the
+/// proposal returns a 65 byte signature with `v` in the 27/28 convention.
 ///
 /// # Arguments
 ///
-/// * `wallet_id` - Unique identifier for the wallet as a String.
-/// * `signers` - A list of Principals representing the signers of the wallet.
-/// * `threshold` - The threshold number of signers required for a transaction.
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `message` - The message to sign.
+/// * `account` - The account that signs the message, or `None` for the default account.
 ///
 /// # Returns
 ///
-/// * `Result<(), String>` - Result indicating success or an error message.
+/// * `Result<ProposalId, SafeError>` - The ID of the new proposal or a `SafeError`.
 #[update]
-fn create_wallet(wallet_id: String, signers: Vec<Principal>, threshold: u8) -> Result<(), String> {
-    if WALLETS.with(|wallets| wallets.borrow().contains_key(&wallet_id)) {
-        return Err(WALLET_ALREADY_EXISTS_ERROR.to_string());
-    }
+fn propose_personal_message(wallet_id: String, message: String, account: Option<AccountRef>) -> Result<ProposalId, SafeError> {
+    let account = resolve_account(&wallet_id, account)?;
+    let hash = personal_message_hash(message.as_bytes());
+    propose_for_account(&wallet_id, account, hash.to_vec(), ProposalKind::PersonalMessage(message))
+}
 
-    let mut wallet = Wallet::default();
-    signers.iter().for_each(|signer| {
-        wallet.add_signer(signer.clone());
-    });
+/// Verifies a `personal_sign` signature for a given message and wallet.
+///
+/// Runs against the account's cached public key, which `eth_address` or the first signature derives.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `message` - The signed message.
+/// * `signature` - The signature in hexadecimal format, with `v` in either the 27/28 or the 0/1 convention.
+/// * `account` - The account that should have signed, or `None` for the default account.
+///
+/// # Returns
+///
+/// * `Result<bool, SafeError>` - Whether the signature was made by the account, or a `SafeError`.
+#[query]
+fn verify_personal_signature(
+    wallet_id: String,
+    message: String,
+    signature: String,
+    account: Option<AccountRef>,
+) -> Result<bool, SafeError> {
+    let signature = decode_hex(&signature).map_err(|_| SafeError::DecodeError("signature".to_string()))?;
+    if signature.len() != SIGNATURE_LENGTH {
+        return Err(SafeError::InvalidSignatureLength);
+    }
+    let account = resolve_account(&wallet_id, account)?;
+    verify_with_cached_key(&wallet_id, account, &personal_message_hash(message.as_bytes()), &signature)
+}
 
-    if wallet.set_default_threshold(threshold).is_err() {
-        return Err(WALLET_SIGNERS_NOT_MATCH_THRESHOLD.to_string());
+/// Verifies an Ethereum signature against an address or public key, without a call to the management canister.
+///
+/// # Arguments
+///
+/// * `signer` - The address or public key the signature should recover to.
+/// * `data` - The signed hash, `personal_sign` message or EIP-712 typed data.
+/// * `signature` - The signature in hexadecimal format, with `v` in either the 27/28 or the 0/1 convention.
+///
+/// # Returns
+///
+/// * `Result<bool, SafeError>` - Whether the signature was made by the signer, or a `SafeError`.
+#[query]
+fn verify_eth_signature(signer: SignerKey, data: SignedData, signature: String) -> Result<bool, SafeError> {
+    let signature = decode_hex(&signature).map_err(|_| SafeError::DecodeError("signature".to_string()))?;
+    if signature.len() != SIGNATURE_LENGTH {
+        return Err(SafeError::InvalidSignatureLength);
     }
+    let digest = match data {
+        SignedData::Hash(hash) => {
+            let hash = decode_hex(&hash).map_err(|_| SafeError::DecodeError("hash".to_string()))?;
+            if hash.len() != MESSAGE_HASH_LENGTH {
+                return Err(SafeError::InvalidHashLength);
+            }
+            hash
+        }
+        SignedData::PersonalMessage(message) => personal_message_hash(message.as_bytes()).to_vec(),
+        SignedData::TypedData(json) => TypedData::parse(&json)
+            .and_then(|typed_data| typed_data.signing_hash())
+            .map_err(SafeError::InvalidTypedData)?
+            .to_vec(),
+    };
+    match signer {
+        SignerKey::Address(address) => {
+            let address = parse_address(&address).map_err(SafeError::InvalidAddress)?;
+            verify_with_address(&digest, &signature, &address)
+        }
+        SignerKey::PublicKey(public_key) => {
+            let public_key = decode_hex(&public_key)
+                .and_then(|bytes| parse_public_key(&bytes))
+                .map_err(|_| SafeError::DecodeError("public_key".to_string()))?;
+            verify_with_public_key(&digest, &signature, public_key)
+        }
+    }.map_err(SafeError::EcdsaError)
+}
 
-    let wallet_id_clone = wallet_id.clone(); // Clone wallet_id
-    WALLETS.with(|wallets| {
-        wallets.borrow_mut().insert(wallet_id_clone, wallet.clone()); // Clone wallet
-    });
+/// Proposes EIP-712 typed structured data to be signed by the wallet.
+///
+/// The domain separator and message hash are computed in the canister, and the decoded
+/// fields are stored with the proposal for review via `get_typed_data`. Executing the
+/// proposal returns a 65 byte signature with `v` in the 27/28 convention.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `json` - The typed data JSON with `types`, `primaryType`, `domain` and `message`, as accepted by `eth_signTypedData_v4`.
+/// * `account` - The account that signs the typed data, or `None` for the default account.
+///
+/// # Returns
+///
+/// * `Result<ProposalId, SafeError>` - The ID of the new proposal or a `SafeError`.
+#[update]
+fn propose_typed_data(wallet_id: String, json: String, account: Option<AccountRef>) -> Result<ProposalId, SafeError> {
+    let typed_data = TypedData::parse(&json).map_err(SafeError::InvalidTypedData)?;
+    let hash = typed_data.signing_hash().map_err(SafeError::InvalidTypedData)?;
+    let account = resolve_account(&wallet_id, account)?;
+    propose_for_account(&wallet_id, account, hash.to_vec(), ProposalKind::TypedData(typed_data))
+}
 
-    // Now, use the original wallet and wallet_id
-    for signer in wallet.get_signers() {
-        let wallet_id_clone = wallet_id.clone(); // Clone wallet_id for use in the closure
-        PRINCIPAL_WALLETS_MAP.with(|map| {
-            let mut map = map.borrow_mut();
-            map.entry(signer.clone()).or_insert_with(Vec::new).push(wallet_id_clone);
-        });
+/// Get the decoded EIP-712 typed data of a proposal for review.
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `proposal_id` - The ID of the proposal.
+///
+/// Returns `Result<TypedData, SafeError>` containing the typed data, or a `SafeError` if the proposal does not sign typed data.
+#[query]
+fn get_typed_data(wallet_id: String, proposal_id: ProposalId) -> Result<TypedData, SafeError> {
+    let proposal = get_proposal(wallet_id, proposal_id)?;
+    match proposal.kind {
+        ProposalKind::TypedData(typed_data) => Ok(typed_data),
+        _ => Err(SafeError::WrongProposalKind),
     }
-    Ok(())
 }
 
+/// Get the decoded calldata of a proposed transaction for review.
+///
+/// Calls to contracts with a registered ABI are decoded with it, other calls only if they are well-known token
+/// functions. Calldata that cannot be decoded is returned with `unknown_selector` set.
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `proposal_id` - The ID of the proposal.
+///
+/// Returns `Result<Option<DecodedCall>, SafeError>` containing the decoded call, which is `None` if the proposal
+/// is not a contract call, or a `SafeError`.
+#[query]
+fn get_decoded_call(wallet_id: String, proposal_id: ProposalId) -> Result<Option<DecodedCall>, SafeError> {
+    WALLETS.with(|wallets| {
+        let wallets = wallets.borrow();
+        let wallet = wallets.get(&wallet_id).ok_or(SafeError::WalletNotFound)?;
+        let proposal = wallet.get_proposal(proposal_id).ok_or(SafeError::ProposalNotFound)?;
+        Ok(decode_proposal(wallet, &proposal.kind))
+    })
+}
 
-/// Retrieves a wallet by its ID.
+/// Retrieves all pending proposals of a wallet, each with its decoded calldata.
 ///
 /// # Arguments
 ///
-/// * `wallet_id` - The unique identifier for the wallet as a String.
+/// * `wallet_id` - The wallet's unique identifier.
 ///
 /// # Returns
 ///
-/// * `Option<Wallet>` - The wallet if found, otherwise None.
+/// * `Result<Vec<ProposalWithCall>, SafeError>` - The proposals ordered by their ID or a `SafeError`.
 #[query]
-fn get_wallet(wallet_id: String) -> Option<Wallet> {
+fn get_proposals_with_calls(wallet_id: String) -> Result<Vec<ProposalWithCall>, SafeError> {
     WALLETS.with(|wallets| {
-        wallets.borrow().get(&wallet_id).cloned()
+        let wallets = wallets.borrow();
+        let wallet = wallets.get(&wallet_id).ok_or(SafeError::WalletNotFound)?;
+        Ok(wallet.get_proposals(time())
+            .into_iter()
+            .map(|proposal| ProposalWithCall::new(wallet, proposal.clone()))
+            .collect())
     })
 }
 
-
-/// Proposes a message to be signed by the wallet.
+/// Proposes registering the JSON ABI of a contract so that proposed calls to it are decoded.
+///
+/// Only contracts without an ABI can be registered; `replace_abi` proposes replacing one.
 ///
 /// # Arguments
 ///
 /// * `wallet_id` - The wallet's unique identifier.
-/// * `msg` - The message to be proposed, in hexadecimal format.
+/// * `address` - The address of the contract.
+/// * `name` - A label for the contract, shown in place of a token standard.
+/// * `abi` - The JSON ABI of the contract, at most `MAX_ABI_LENGTH` bytes.
 ///
 /// # Returns
 ///
-/// * `Result<(), String>` - Result indicating success or an error message.
+/// * `Result<ProposalId, SafeError>` - The ID of the new proposal or a `SafeError`,
+///   `SafeError::AbiAlreadyRegistered` if the contract has an ABI.
 #[update]
-fn propose(wallet_id: String, msg: String) -> Result<(), String> {
-    let msg = hex::decode(msg).map_err(|_| "InvalidMessage".to_string())?;
+fn register_abi(wallet_id: String, address: String, name: String, abi: String) -> Result<ProposalId, SafeError> {
+    let address = normalize_address(&address).map_err(SafeError::InvalidAddress)?;
+    let abi = ContractAbi::parse(address, name, &abi).map_err(SafeError::InvalidAbi)?;
     WALLETS.with(|wallets| {
-        wallets.borrow_mut().get_mut(&wallet_id).ok_or(WALLET_NOT_FOUND_ERROR.to_string()).unwrap()
-            .propose_message(caller(), msg).map_err(|error| {
-            match error {
-                WalletError::MsgAlreadyQueued => WALLET_MSG_ALREADY_QUEUED_ERROR.to_string(),
-                WalletError::InvalidSignature => WALLET_INVALID_SIGNATURE_ERROR.to_string(),
-                _ => "UnknownError".to_string(),
-            }
-        })
+        wallets.borrow_mut().get_mut(&wallet_id)
+            .ok_or(SafeError::WalletNotFound)?
+            .register_abi(abi, caller(), time())
+            .map_err(SafeError::from)
     })
 }
 
-/// Checks if a message can be signed by the wallet.
+/// Proposes registering the JSON ABI of a contract, replacing the ABI registered for it.
 ///
 /// # Arguments
 ///
 /// * `wallet_id` - The wallet's unique identifier.
-/// * `msg` - The message to be checked, in hexadecimal format.
+/// * `address` - The address of the contract.
+/// * `name` - A label for the contract, shown in place of a token standard.
+/// * `abi` - The JSON ABI of the contract, at most `MAX_ABI_LENGTH` bytes.
 ///
 /// # Returns
 ///
-/// * `bool` - True if the message can be signed, otherwise false.
-#[query]
-fn can_sign(wallet_id: String, msg: String) -> bool {
-    match hex::decode(&msg) {
-        Ok(decoded_msg) => {
-            WALLETS.with(|wallets| {
-                wallets.borrow().get(&wallet_id).ok_or(WALLET_NOT_FOUND_ERROR.to_string()).unwrap()
-                    .can_sign(&decoded_msg)
-            })
-        }
-        Err(_) => false,
-    }
+/// * `Result<ProposalId, SafeError>` - The ID of the new proposal or a `SafeError`.
+#[update]
+fn replace_abi(wallet_id: String, address: String, name: String, abi: String) -> Result<ProposalId, SafeError> {
+    let address = normalize_address(&address).map_err(SafeError::InvalidAddress)?;
+    let abi = ContractAbi::parse(address, name, &abi).map_err(SafeError::InvalidAbi)?;
+    propose_kind(&wallet_id, Vec::new(), ProposalKind::RegisterAbi(abi))
 }
 
-/// Approves a message for signing in the wallet.
+/// Proposes removing the ABI registered for a contract.
 ///
 /// # Arguments
 ///
 /// * `wallet_id` - The wallet's unique identifier.
-/// * `msg` - The message to be approved, in hexadecimal format.
+/// * `address` - The address of the contract.
 ///
 /// # Returns
 ///
-/// * `Result<u8, String>` - The number of signatures or an error message.
+/// * `Result<ProposalId, SafeError>` - The ID of the new proposal or a `SafeError`.
 #[update]
-fn approve(wallet_id: String, msg: String) -> Result<u8, String> {
-    let msg = hex::decode(msg).map_err(|_| "InvalidMessage".to_string())?;
-
-    WALLETS.with(|wallets| {
-        wallets
-            .borrow_mut()
-            .get_mut(&wallet_id)
-            .ok_or(WALLET_NOT_FOUND_ERROR.to_string())?
-            .approve(msg, caller()).map_err(|error| {
-                match error {
-                    WalletError::MsgNotQueued => "WalletMsgNotQueued".to_string(),
-                    WalletError::InvalidSignature => WALLET_INVALID_SIGNATURE_ERROR.to_string(),
-                    _ => "UnknownError".to_string(),
-                }
-            })
-    })
+fn remove_abi(wallet_id: String, address: String) -> Result<ProposalId, SafeError> {
+    let address = normalize_address(&address).map_err(SafeError::InvalidAddress)?;
+    propose_kind(&wallet_id, Vec::new(), ProposalKind::RemoveAbi(address))
 }
 
-/// Signs a message using the wallet.
+/// Retrieves the ABIs registered in a wallet.
 ///
 /// # Arguments
 ///
 /// * `wallet_id` - The wallet's unique identifier.
-/// * `msg` - The message to be signed, in hexadecimal format.
 ///
 /// # Returns
 ///
-/// * `Result<String, String>` - The signature in hexadecimal format or an error message.
-#[update]
-async fn sign(wallet_id: String, msg: String) -> Result<String, String> {
-    let msg = hex::decode(msg).map_err(|_| "InvalidMessage".to_string())?;
-
-    let can_sign = WALLETS.with(|wallets| {
-        wallets.borrow().get(&wallet_id).ok_or(WALLET_NOT_FOUND_ERROR.to_string()).unwrap()
-            .can_sign(&msg)
-    });
-
-    let key_id = KEY_ID.with(|key_id| {
-        key_id.borrow().clone()
-    });
-
-    if !can_sign {
-        return Err(WALLET_CANNOT_SIGN_ERROR.to_string());
-    }
-    let mut is_special_message = false;
-    if let Ok(message_str) = String::from_utf8(msg.clone()) {
-        WALLETS.with(|wallets| {
-            let mut wallets = wallets.borrow_mut();
-
-            // it is safe to unwrap here, as we checked that the wallet exists before
-            let wallet = wallets.get_mut(&wallet_id)
-                            .ok_or(WALLET_NOT_FOUND_ERROR.to_string()).unwrap();
-
-             // Handle special add/remove signer commands
-             if message_str.starts_with("ADD_SIGNER::") {
-                let new_signer_str = &message_str["ADD_SIGNER::".len()..];
-                if let Ok(new_signer) = Principal::from_str(new_signer_str) {
-                    wallet.add_signer(new_signer);
-                    PRINCIPAL_WALLETS_MAP.with(|map| {
-                        let mut map = map.borrow_mut();
-                        map.entry(new_signer.clone()).or_insert_with(Vec::new).push(wallet_id.clone());
-                    });
-                }
-                is_special_message = true;
-             } else if message_str.starts_with("REMOVE_SIGNER::") {
-                let signer_to_remove_str = &message_str["REMOVE_SIGNER::".len()..];
-                if let Ok(signer_to_remove) = Principal::from_str(signer_to_remove_str) {
-                    wallet.remove_signer(signer_to_remove);
-                    PRINCIPAL_WALLETS_MAP.with(|map| {
-                        let mut map = map.borrow_mut();
-                        if let Some(wallets) = map.get_mut(&signer_to_remove) {
-                            wallets.retain(|id| id != &wallet_id);
-                        }
-                    });
-                }
-                is_special_message = true;
-            }
-            // Handle special set threshold command
-            else if message_str.starts_with("SET_THRESHOLD::") {
-                let new_threshold_str = &message_str["SET_THRESHOLD::".len()..];
-                if let Ok(new_threshold) = u8::from_str(new_threshold_str) {
-                    wallet.set_default_threshold(new_threshold).unwrap();
-                }
-                is_special_message = true;
-            }
-        });
-    }
-
-    let signature = match is_special_message {
-        true => "".to_string(),
-        false => hex::encode(
-            sign_message(wallet_id.clone(), msg.clone(), key_id).await?
-        )
-    };
-
-    let _ = WALLETS.with(|wallets| {
-        wallets.borrow_mut().get_mut(&wallet_id).ok_or(WALLET_NOT_FOUND_ERROR.to_string())?
-            .remove_message_and_metadata(msg.clone(), caller())
-    });
-
-    Ok(signature)
+/// * `Result<Vec<ContractAbi>, SafeError>` - The ABIs ordered by contract address or a `SafeError`.
+#[query]
+fn get_abis(wallet_id: String) -> Result<Vec<ContractAbi>, SafeError> {
+    WALLETS.with(|wallets| {
+        wallets.borrow().get(&wallet_id)
+            .ok_or(SafeError::WalletNotFound)
+            .map(|wallet| wallet.get_abis().into_iter().cloned().collect())
+    })
 }
 
-/// Retrieves the Ethereum address associated with the wallet.
+/// Adds a named account to the wallet, with keys derived from its own derivation path. Only signers may add
+/// accounts. The account is governed by the wallet's signers and threshold like all other accounts.
 ///
 /// # Arguments
 ///
 /// * `wallet_id` - The wallet's unique identifier.
+/// * `name` - The name of the account, unique within the wallet.
 ///
 /// # Returns
 ///
-/// * `Result<String, String>` - The Ethereum address or an error message.
+/// * `Result<AccountIndex, SafeError>` - The index of the new account or a `SafeError`.
 #[update]
-async fn eth_address(wallet_id: String) -> Result<String, String> {
-    let key_id = KEY_ID.with(|key_id| {
-        key_id.borrow().clone()
-    });
-    get_eth_address(wallet_id, key_id).await
+fn add_account(wallet_id: String, name: String) -> Result<AccountIndex, SafeError> {
+    WALLETS.with(|wallets| {
+        wallets.borrow_mut().get_mut(&wallet_id)
+            .ok_or(SafeError::WalletNotFound)?
+            .add_account(name, caller())
+            .map_err(SafeError::from)
+    })
 }
 
-/// Verifies a signature for a given message and wallet.
+/// Retrieves the accounts of a wallet.
 ///
 /// # Arguments
 ///
 /// * `wallet_id` - The wallet's unique identifier.
-/// * `message` - The message associated with the signature, in hexadecimal format.
-/// * `signature` - The signature to be verified, in hexadecimal format.
 ///
 /// # Returns
 ///
-/// * `Result<bool, String>` - True if the signature is valid, otherwise an error message.
-#[update]
-async fn verify_signature(wallet_id: String, message: String, signature: String) -> Result<bool, String> {
-    let message = hex::decode(message).map_err(|_| "Invalid message".to_string())?;
-    let signature = hex::decode(signature).map_err(|_| "Invalid signature".to_string())?;
-    let key_id = KEY_ID.with(|key_id| {
-        key_id.borrow().clone()
-    });
-    Ok(is_signature_valid(message, signature, wallet_id, key_id).await?)
+/// * `Result<Vec<AccountInfo>, SafeError>` - The accounts ordered by index or a `SafeError`.
+#[query]
+fn get_accounts(wallet_id: String) -> Result<Vec<AccountInfo>, SafeError> {
+    WALLETS.with(|wallets| {
+        wallets.borrow().get(&wallet_id)
+            .ok_or(SafeError::WalletNotFound)
+            .map(|wallet| wallet.get_accounts())
+    })
 }
 
 /// Retrieves all messages that can be signed for a given wallet.
 ///
+/// Kept for compatibility, this only lists payload proposals. Use `get_proposals` to see all proposals.
+///
 /// # Arguments
 ///
 /// * `wallet_id` - The wallet's unique identifier.
@@ -315,14 +1644,15 @@ async fn verify_signature(wallet_id: String, message: String, signature: String)
 ///
 /// * `Vec<Vec<u8>>` - A list of messages that can be signed.
 #[query]
-fn get_messages_to_sign(wallet_id: String) -> Result<Vec<String>, String> {
+fn get_messages_to_sign(wallet_id: String) -> Result<Vec<String>, SafeError> {
     WALLETS.with(|wallets| {
         wallets.borrow().get(&wallet_id)
-            .ok_or(WALLET_NOT_FOUND_ERROR.to_string())
+            .ok_or(SafeError::WalletNotFound)
             .map(|wallet| {
-                wallet.get_messages_to_sign()
+                wallet.get_proposals_to_sign(time())
                     .into_iter()
-                    .map(|msg| hex::encode(msg))
+                    .filter(|proposal| proposal.kind == ProposalKind::SignPayload)
+                    .map(|proposal| hex::encode(&proposal.payload))
                     .collect()
             })
     })
@@ -330,6 +1660,8 @@ fn get_messages_to_sign(wallet_id: String) -> Result<Vec<String>, String> {
 
 /// Retrieves all messages that have been proposed for a given wallet.
 ///
+/// Kept for compatibility, this only lists payload proposals. Use `get_proposals` to see all proposals.
+///
 /// # Arguments
 ///
 /// * `wallet_id` - The wallet's unique identifier.
@@ -338,14 +1670,15 @@ fn get_messages_to_sign(wallet_id: String) -> Result<Vec<String>, String> {
 ///
 /// * `Vec<Vec<u8>>` - A list of messages that have been proposed.
 #[query]
-fn get_proposed_messages(wallet_id: String) -> Result<Vec<String>, String> {
+fn get_proposed_messages(wallet_id: String) -> Result<Vec<String>, SafeError> {
     WALLETS.with(|wallets| {
         wallets.borrow().get(&wallet_id)
-            .ok_or(WALLET_NOT_FOUND_ERROR.to_string())
+            .ok_or(SafeError::WalletNotFound)
             .map(|wallet| {
-                wallet.get_proposed_messages()
+                wallet.get_proposals(time())
                     .into_iter()
-                    .map(|msg| hex::encode(msg))
+                    .filter(|proposal| proposal.kind == ProposalKind::SignPayload)
+                    .map(|proposal| hex::encode(&proposal.payload))
                     .collect()
             })
     })
@@ -353,6 +1686,8 @@ fn get_proposed_messages(wallet_id: String) -> Result<Vec<String>, String> {
 
 /// Retrieves all messages that have been proposed along with their signers for a given wallet.
 ///
+/// Kept for compatibility, this only lists payload proposals. Use `get_proposals` to see all proposals.
+///
 /// # Arguments
 ///
 /// * `wallet_id` - The wallet's unique identifier.
@@ -361,14 +1696,42 @@ fn get_proposed_messages(wallet_id: String) -> Result<Vec<String>, String> {
 ///
 /// * `Vec<(Vec<u8>, Vec<Principal>)>` - A list of tuples containing messages and their signers.
 #[query]
-fn get_messages_with_signers(wallet_id: String) -> Result<Vec<(String, Vec<Principal>)>, String> {
+fn get_messages_with_signers(wallet_id: String) -> Result<Vec<(String, Vec<Principal>)>, SafeError> {
+    WALLETS.with(|wallets| {
+        wallets.borrow().get(&wallet_id)
+            .ok_or(SafeError::WalletNotFound)
+            .map(|wallet| {
+                wallet.get_proposals(time())
+                    .into_iter()
+                    .filter(|proposal| proposal.kind == ProposalKind::SignPayload)
+                    .map(|proposal| (hex::encode(&proposal.payload), proposal.approvals.iter().cloned().collect()))
+                    .collect()
+            })
+    })
+}
+
+/// Retrieves all messages that have been proposed along with the signers that rejected them for a given wallet.
+///
+/// Kept for parity with `get_messages_with_signers`, this only lists payload proposals.
+/// Use `get_proposals` to see the votes on all proposals.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+///
+/// # Returns
+///
+/// * `Vec<(Vec<u8>, Vec<Principal>)>` - A list of tuples containing messages and the signers that rejected them.
+#[query]
+fn get_messages_with_rejections(wallet_id: String) -> Result<Vec<(String, Vec<Principal>)>, SafeError> {
     WALLETS.with(|wallets| {
         wallets.borrow().get(&wallet_id)
-            .ok_or(WALLET_NOT_FOUND_ERROR.to_string())
+            .ok_or(SafeError::WalletNotFound)
             .map(|wallet| {
-                wallet.get_messages_with_signers()
+                wallet.get_proposals(time())
                     .into_iter()
-                    .map(|(msg, signers)| (hex::encode(msg), signers))
+                    .filter(|proposal| proposal.kind == ProposalKind::SignPayload)
+                    .map(|proposal| (hex::encode(&proposal.payload), proposal.rejections.iter().cloned().collect()))
                     .collect()
             })
     })
@@ -383,12 +1746,10 @@ fn get_messages_with_signers(wallet_id: String) -> Result<Vec<(String, Vec<Princ
 ///
 /// # Returns
 ///
-/// * `Result<(), String>` - Result indicating success or an error message.
+/// * `Result<ProposalId, SafeError>` - The ID of the new proposal or a `SafeError`.
 #[update]
-fn add_signer(wallet_id: String, new_signer: Principal) -> Result<String, String> {
-    let special_message = hex::encode(format!("ADD_SIGNER::{}", new_signer));
-    let _ = propose(wallet_id, special_message.clone());
-    Ok(special_message)
+fn add_signer(wallet_id: String, new_signer: Principal) -> Result<ProposalId, SafeError> {
+    propose_kind(&wallet_id, Vec::new(), ProposalKind::AddSigner(new_signer))
 }
 
 /// Proposes removing a signer from the wallet.
@@ -400,12 +1761,10 @@ fn add_signer(wallet_id: String, new_signer: Principal) -> Result<String, String
 ///
 /// # Returns
 ///
-/// * `Result<(), String>` - Result indicating success or an error message.
+/// * `Result<ProposalId, SafeError>` - The ID of the new proposal or a `SafeError`.
 #[update]
-fn remove_signer(wallet_id: String, signer_to_remove: Principal) -> Result<String, String> {
-    let special_message = hex::encode(format!("REMOVE_SIGNER::{}", signer_to_remove));
-    let _ = propose(wallet_id, special_message.clone());
-    Ok(special_message)
+fn remove_signer(wallet_id: String, signer_to_remove: Principal) -> Result<ProposalId, SafeError> {
+    propose_kind(&wallet_id, Vec::new(), ProposalKind::RemoveSigner(signer_to_remove))
 }
 
 /// Proposes setting a new threshold for the wallet.
@@ -417,12 +1776,82 @@ fn remove_signer(wallet_id: String, signer_to_remove: Principal) -> Result<Strin
 ///
 /// # Returns
 ///
-/// * `Result<String, String>` - Result indicating success or an error message.
+/// * `Result<ProposalId, SafeError>` - The ID of the new proposal or a `SafeError`.
+#[update]
+fn set_threshold(wallet_id: String, new_threshold: u8) -> Result<ProposalId, SafeError> {
+    propose_kind(&wallet_id, Vec::new(), ProposalKind::SetThreshold(new_threshold))
+}
+
+/// Proposes moving the wallet to another threshold key, e.g. from `test_key_1` to `key_1`.
+///
+/// Once executed, all accounts derive their ECDSA and Schnorr keys from the new key and the wallet never signs
+/// with the old one again. Every address changes, so pending proposals that sign payloads are rejected.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `key_name` - The name of the new key.
+///
+/// # Returns
+///
+/// * `Result<ProposalId, SafeError>` - The ID of the new proposal or a `SafeError`.
+#[update]
+fn propose_key_migration(wallet_id: String, key_name: String) -> Result<ProposalId, SafeError> {
+    propose_kind(&wallet_id, Vec::new(), ProposalKind::MigrateKey(key_name))
+}
+
+/// Retrieves the threshold key a wallet signs with and its completed key migrations.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet's unique identifier.
+///
+/// # Returns
+///
+/// * `Result<KeyInfo, SafeError>` - The key information or a `SafeError`.
+#[query]
+fn get_key_info(wallet_id: String) -> Result<KeyInfo, SafeError> {
+    WALLETS.with(|wallets| {
+        wallets.borrow().get(&wallet_id)
+            .ok_or(SafeError::WalletNotFound)
+            .map(|wallet| wallet.get_key_info()
+                .unwrap_or_else(|| KeyInfo { key_name: default_key_id().name, migrations: Vec::new() }))
+    })
+}
+
+/// Sets the threshold key new wallets are created with. Only controllers of the canister may change it.
+///
+/// Existing wallets keep their key until a `MigrateKey` proposal moves them.
+///
+/// # Arguments
+///
+/// * `key_name` - The name of the key.
+///
+/// # Returns
+///
+/// * `Result<(), SafeError>` - Result indicating success or a `SafeError`.
 #[update]
-fn set_threshold(wallet_id: String, new_threshold: u8) -> Result<String, String> {
-    let special_message = hex::encode(format!("SET_THRESHOLD::{}", new_threshold));
-    let _ = propose(wallet_id, special_message.clone());
-    Ok(special_message)
+fn set_default_key_id(key_name: String) -> Result<(), SafeError> {
+    if !is_controller(&caller()) {
+        return Err(SafeError::NotAController);
+    }
+    if key_name.trim().is_empty() {
+        return Err(SafeError::InvalidKeyName);
+    }
+    KEY_ID.with(|key_id| {
+        *key_id.borrow_mut() = EcdsaKeyId { curve: EcdsaCurve::Secp256k1, name: key_name };
+    });
+    Ok(())
+}
+
+/// Retrieves the name of the threshold key new wallets are created with.
+///
+/// # Returns
+///
+/// * `String` - The name of the key.
+#[query]
+fn get_default_key_id() -> String {
+    default_key_id().name
 }
 
 /// Retrieves all wallets associated with a given principal.
@@ -444,41 +1873,74 @@ fn get_wallets_for_principal(principal: Principal) -> Vec<String> {
     })
 }
 
-/// Add metadata to a message in the wallet.
+/// Add metadata to a proposal in the wallet.
 ///
-/// * `message` - The message as a `Vec<u8>`.
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `proposal_id` - The ID of the proposal.
 /// * `metadata` - The metadata as a `String`.
-/// * `caller` - The `Principal` of the caller.
 ///
-/// Returns `Result<(), String>` indicating success or the type of failure.
+/// Returns `Result<(), SafeError>` indicating success or the type of failure.
 #[update]
-fn add_metadata(wallet_id: String, msg: String, metadata: String) -> Result<(), String> {
-    let msg = hex::decode(msg).map_err(|_| "InvalidMessage".to_string())?;
+fn add_proposal_metadata(wallet_id: String, proposal_id: ProposalId, metadata: String) -> Result<(), SafeError> {
     WALLETS.with(|wallets| {
         wallets.borrow_mut().get_mut(&wallet_id)
-            .ok_or(WALLET_NOT_FOUND_ERROR.to_string())?
-            .add_metadata(msg, metadata, caller())
+            .ok_or(SafeError::WalletNotFound)?
+            .add_metadata(proposal_id, metadata, caller())
+            .map_err(SafeError::from)
     })
 }
 
-/// Get the metadata associated with a message in the wallet.
+/// Add metadata to a message in the wallet.
+///
+/// Kept for compatibility, this resolves the message to its proposal and calls `add_proposal_metadata`.
 ///
 /// * `message` - The message as a `Vec<u8>`.
+/// * `metadata` - The metadata as a `String`.
+/// * `caller` - The `Principal` of the caller.
 ///
-/// Returns `Option<&String>` containing the metadata if it exists.
+/// Returns `Result<(), SafeError>` indicating success or the type of failure.
+#[update]
+fn add_metadata(wallet_id: String, msg: String, metadata: String) -> Result<(), SafeError> {
+    let proposal_id = find_proposal_by_payload(&wallet_id, msg)?;
+    add_proposal_metadata(wallet_id, proposal_id, metadata)
+}
+
+/// Get the metadata associated with a proposal in the wallet.
+///
+/// * `wallet_id` - The wallet's unique identifier.
+/// * `proposal_id` - The ID of the proposal.
+///
+/// Returns `Result<String, SafeError>` containing the metadata if it exists.
 #[query]
-fn get_metadata(wallet_id: String, msg: String) -> Result<String, String> {
-    let msg = hex::decode(msg).map_err(|_| "InvalidMessage".to_string())?;
+fn get_proposal_metadata(wallet_id: String, proposal_id: ProposalId) -> Result<String, SafeError> {
     WALLETS.with(|wallets| {
         wallets.borrow().get(&wallet_id)
-            .ok_or(WALLET_NOT_FOUND_ERROR.to_string())?
-            .get_metadata(msg, caller())
+            .ok_or(SafeError::WalletNotFound)?
+            .get_metadata(proposal_id, caller())
             .cloned()
-            .ok_or(METADATA_NOT_FOUND.to_string())
+            .ok_or(SafeError::MetadataNotFound)
     })
 }
 
-/// Proposes a message and adds metadata in one call.
+/// Get the metadata associated with a message in the wallet.
+///
+/// Kept for compatibility, this resolves the message to its proposal and calls `get_proposal_metadata`.
+///
+/// * `message` - The message as a `Vec<u8>`.
+///
+/// Returns `Option<&String>` containing the metadata if it exists.
+#[query]
+fn get_metadata(wallet_id: String, msg: String) -> Result<String, SafeError> {
+    // a message without a pending proposal has no metadata, but a missing wallet or a malformed message is reported
+    let proposal_id = find_proposal_by_payload(&wallet_id, msg).map_err(|error| match error {
+        SafeError::ProposalNotFound => SafeError::MetadataNotFound,
+        error => error,
+    })?;
+    get_proposal_metadata(wallet_id, proposal_id)
+}
+
+/// Proposes a message and adds metadata in one call. The metadata is checked before the proposal is created, so
+/// a call that fails leaves no proposal behind.
 ///
 /// # Arguments
 ///
@@ -488,9 +1950,12 @@ fn get_metadata(wallet_id: String, msg: String) -> Result<String, String> {
 ///
 /// # Returns
 ///
-/// * `Result<(), String>` - Result indicating success or an error message.
+/// * `Result<ProposalId, SafeError>` - The ID of the new proposal or a `SafeError`.
 #[update]
-fn propose_with_metadata(wallet_id: String, msg: String, metadata: String) -> Result<(), String> {
-    propose(wallet_id.clone(), msg.clone())?;
-    add_metadata(wallet_id, msg, metadata)
-}
\ No newline at end of file
+fn propose_with_metadata(wallet_id: String, msg: String, metadata: String) -> Result<ProposalId, SafeError> {
+    // only signers may add metadata, and a new proposal has none yet, so attaching it cannot fail once proposed
+    ensure_signer(&wallet_id)?;
+    let proposal_id = propose(wallet_id.clone(), msg, None)?;
+    add_proposal_metadata(wallet_id, proposal_id, metadata)?;
+    Ok(proposal_id)
+}
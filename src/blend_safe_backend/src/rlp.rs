@@ -0,0 +1,125 @@
+/// Encode a byte string with Ethereum's Recursive Length Prefix encoding.
+///
+/// # Arguments
+///
+/// * `bytes` - The byte string to encode.
+///
+/// # Returns
+///
+/// * `Vec<u8>` - The RLP encoded byte string.
+pub fn encode_bytes(bytes: &[u8]) -> Vec<u8> {
+    if bytes.len() == 1 && bytes[0] < 0x80 {
+        return bytes.to_vec();
+    }
+    let mut out = encode_length(bytes.len(), 0x80);
+    out.extend_from_slice(bytes);
+    out
+}
+
+/// Encode a big-endian unsigned integer as an RLP byte string without leading zeros.
+///
+/// # Arguments
+///
+/// * `value` - The integer as big-endian bytes. Leading zeros are stripped, so zero encodes as the empty string.
+///
+/// # Returns
+///
+/// * `Vec<u8>` - The RLP encoded integer.
+pub fn encode_uint(value: &[u8]) -> Vec<u8> {
+    let start = value.iter().position(|byte| *byte != 0).unwrap_or(value.len());
+    encode_bytes(&value[start..])
+}
+
+/// Encode a `u64` as an RLP integer.
+///
+/// # Arguments
+///
+/// * `value` - The integer to encode.
+///
+/// # Returns
+///
+/// * `Vec<u8>` - The RLP encoded integer.
+pub fn encode_u64(value: u64) -> Vec<u8> {
+    encode_uint(&value.to_be_bytes())
+}
+
+/// Encode a list of already encoded RLP items.
+///
+/// # Arguments
+///
+/// * `items` - The RLP encoded items of the list.
+///
+/// # Returns
+///
+/// * `Vec<u8>` - The RLP encoded list.
+pub fn encode_list(items: &[Vec<u8>]) -> Vec<u8> {
+    let payload = items.concat();
+    let mut out = encode_length(payload.len(), 0xc0);
+    out.extend(payload);
+    out
+}
+
+/// Encode the prefix of a string (`offset` 0x80) or list (`offset` 0xc0) of the given length.
+fn encode_length(len: usize, offset: u8) -> Vec<u8> {
+    if len < 56 {
+        return vec![offset + len as u8];
+    }
+    let len_bytes = (len as u64).to_be_bytes();
+    let start = len_bytes.iter().position(|byte| *byte != 0).unwrap_or(len_bytes.len());
+    let mut out = vec![offset + 55 + (len_bytes.len() - start) as u8];
+    out.extend_from_slice(&len_bytes[start..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_bytes() {
+        assert_eq!(encode_bytes(b""), vec![0x80]);
+        assert_eq!(encode_bytes(&[0x00]), vec![0x00]);
+        assert_eq!(encode_bytes(&[0x7f]), vec![0x7f]);
+        assert_eq!(encode_bytes(&[0x80]), vec![0x81, 0x80]);
+        assert_eq!(encode_bytes(b"dog"), vec![0x83, b'd', b'o', b'g']);
+
+        let lorem = b"Lorem ipsum dolor sit amet, consectetur adipisicing elit";
+        let mut expected = vec![0xb8, 0x38];
+        expected.extend_from_slice(lorem);
+        assert_eq!(encode_bytes(lorem), expected);
+    }
+
+    #[test]
+    fn test_encode_uint() {
+        assert_eq!(encode_u64(0), vec![0x80]);
+        assert_eq!(encode_u64(15), vec![0x0f]);
+        assert_eq!(encode_u64(1024), vec![0x82, 0x04, 0x00]);
+        assert_eq!(encode_uint(&[0x00, 0x00, 0x04, 0x00]), vec![0x82, 0x04, 0x00]);
+        assert_eq!(encode_uint(&[]), vec![0x80]);
+    }
+
+    #[test]
+    fn test_encode_list() {
+        assert_eq!(encode_list(&[]), vec![0xc0]);
+        assert_eq!(
+            encode_list(&[encode_bytes(b"cat"), encode_bytes(b"dog")]),
+            vec![0xc8, 0x83, b'c', b'a', b't', 0x83, b'd', b'o', b'g']
+        );
+        // the set theoretical representation of three: [ [], [[]], [ [], [[]] ] ]
+        let empty = encode_list(&[]);
+        let one = encode_list(std::slice::from_ref(&empty));
+        let two = encode_list(&[empty.clone(), one.clone()]);
+        assert_eq!(
+            encode_list(&[empty, one, two]),
+            vec![0xc7, 0xc0, 0xc1, 0xc0, 0xc3, 0xc0, 0xc1, 0xc0]
+        );
+    }
+
+    #[test]
+    fn test_encode_long_list() {
+        let items = vec![encode_bytes(&[0xaa; 20]); 3];
+        let encoded = encode_list(&items);
+        assert_eq!(&encoded[..2], &[0xf8, 63]);
+        assert_eq!(encoded.len(), 65);
+    }
+}
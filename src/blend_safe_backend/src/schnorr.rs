@@ -0,0 +1,254 @@
+use candid::{CandidType, Principal};
+use libsecp256k1::{PublicKey, PublicKeyFormat, SecretKey};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use crate::ecdsa::derivation_path;
+use crate::wallet::AccountIndex;
+
+/// The cycles attached to `sign_with_schnorr`, enough for the production key. Unused cycles are refunded.
+const DEFAULT_SCHNORR_SIGN_CYCLES: u64 = 26_153_846_153;
+
+/// A signature algorithm of the management canister's threshold Schnorr API.
+#[derive(Clone, Copy, Debug, Default, PartialEq, CandidType, Deserialize)]
+pub enum SchnorrAlgorithm {
+    /// BIP-340 Schnorr signatures over secp256k1, as used by Taproot.
+    #[default]
+    #[serde(rename = "bip340secp256k1")]
+    Bip340Secp256k1,
+    /// Ed25519 signatures, as used by Solana and other EdDSA chains.
+    #[serde(rename = "ed25519")]
+    Ed25519,
+}
+
+/// The ID of a threshold Schnorr key, as the management canister expects it.
+#[derive(Clone, Debug, Default, PartialEq, CandidType, Deserialize)]
+pub struct SchnorrKeyId {
+    pub algorithm: SchnorrAlgorithm,
+    pub name: String,
+}
+
+/// The public key of a wallet under a threshold Schnorr key, derived once and cached on the wallet.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct SchnorrPublicKey {
+    /// The threshold key the public key was derived from.
+    pub key_id: SchnorrKeyId,
+    /// The public key: 33 byte SEC1 compressed for BIP-340, 32 bytes for Ed25519.
+    pub public_key: Vec<u8>,
+}
+
+#[derive(CandidType)]
+struct SchnorrPublicKeyArgument {
+    canister_id: Option<Principal>,
+    derivation_path: Vec<Vec<u8>>,
+    key_id: SchnorrKeyId,
+}
+
+#[derive(Deserialize, CandidType)]
+struct SchnorrPublicKeyResponse {
+    public_key: Vec<u8>,
+    chain_code: Vec<u8>,
+}
+
+/// The BIP-341 tweak the management canister applies to the key before signing.
+#[derive(CandidType, Deserialize)]
+struct SignWithBip341Aux {
+    /// The merkle root of the script tree, or empty for outputs without script paths.
+    merkle_root_hash: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize)]
+enum SignWithSchnorrAux {
+    #[serde(rename = "bip341")]
+    Bip341(SignWithBip341Aux),
+}
+
+#[derive(CandidType)]
+struct SignWithSchnorrArgument {
+    message: Vec<u8>,
+    derivation_path: Vec<Vec<u8>>,
+    key_id: SchnorrKeyId,
+    aux: Option<SignWithSchnorrAux>,
+}
+
+#[derive(Deserialize, CandidType)]
+struct SignWithSchnorrResponse {
+    signature: Vec<u8>,
+}
+
+/// Compute a BIP-340 tagged hash: `SHA256(SHA256(tag) || SHA256(tag) || data)`.
+///
+/// # Arguments
+///
+/// * `tag` - The tag that separates the hash from hashes of other purposes.
+/// * `data` - The data to hash.
+///
+/// # Returns
+///
+/// * `[u8; 32]` - The tagged hash.
+///
+/// # Example
+///
+/// ```
+/// let tweak = tagged_hash("TapTweak", &internal_key);
+/// ```
+pub fn tagged_hash(tag: &str, data: &[u8]) -> [u8; 32] {
+    let tag = Sha256::digest(tag.as_bytes());
+    Sha256::new()
+        .chain_update(tag)
+        .chain_update(tag)
+        .chain_update(data)
+        .finalize()
+        .into()
+}
+
+/// Compute the BIP-341 output key of a Taproot output without script paths, as BIP-86 wallets use.
+///
+/// # Arguments
+///
+/// * `internal_key` - The 33 byte compressed or 32 byte x-only internal key.
+///
+/// # Returns
+///
+/// * `Result<[u8; 32], String>` - The x-only output key `P + hashTapTweak(P)G`, or an error message.
+///
+/// # Example
+///
+/// ```
+/// let output_key = taproot_output_key(&schnorr_public_key.public_key)?;
+/// ```
+pub fn taproot_output_key(internal_key: &[u8]) -> Result<[u8; 32], String> {
+    let x_only = match internal_key.len() {
+        33 => &internal_key[1..],
+        32 => internal_key,
+        length => return Err(format!("Invalid public key length: {}", length)),
+    };
+    // BIP-340 keys are identified by their x coordinate, lifted to the point with an even y
+    let mut lifted = [0x02; 33];
+    lifted[1..].copy_from_slice(x_only);
+    let mut key = PublicKey::parse_slice(&lifted, Some(PublicKeyFormat::Compressed))
+        .map_err(|_| "Invalid public key".to_string())?;
+    let tweak = SecretKey::parse(&tagged_hash("TapTweak", x_only))
+        .map_err(|_| "Invalid Taproot tweak".to_string())?;
+    key.tweak_add_assign(&tweak).map_err(|_| "Invalid Taproot tweak".to_string())?;
+    let mut output_key = [0u8; 32];
+    output_key.copy_from_slice(&key.serialize_compressed()[1..]);
+    Ok(output_key)
+}
+
+/// Asynchronously derive the Schnorr public key of a wallet's account with the management canister.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet ID as a String.
+/// * `account` - The index of the account. It selects the derivation path, like for `ecdsa::get_public_key`.
+/// * `key_id` - The SchnorrKeyId.
+///
+/// # Returns
+///
+/// * `Result<SchnorrPublicKey, String>` - The public key or an error message.
+///
+/// # Example
+///
+/// ```
+/// let public_key = get_schnorr_public_key(wallet_id, DEFAULT_ACCOUNT, key_id).await?;
+/// ```
+pub async fn get_schnorr_public_key(
+    wallet_id: String,
+    account: AccountIndex,
+    key_id: SchnorrKeyId,
+) -> Result<SchnorrPublicKey, String> {
+    let ic = Principal::management_canister();
+
+    let request = SchnorrPublicKeyArgument {
+        canister_id: None,
+        derivation_path: derivation_path(&wallet_id, account),
+        key_id: key_id.clone(),
+    };
+    let (res,): (SchnorrPublicKeyResponse,) = ic_cdk::call(ic, "schnorr_public_key", (request,))
+        .await
+        .map_err(|e| format!("Failed to call schnorr_public_key {}", e.1))?;
+
+    Ok(SchnorrPublicKey { key_id, public_key: res.public_key })
+}
+
+/// Asynchronously sign a message with threshold Schnorr.
+///
+/// # Arguments
+///
+/// * `wallet_id` - The wallet ID as a String.
+/// * `account` - The index of the account whose key signs.
+/// * `message` - The message to sign. BIP-340 signs 32 byte sighashes, Ed25519 signs messages of any length.
+/// * `key_id` - The SchnorrKeyId.
+/// * `taproot_merkle_root` - For Taproot key path spends, the merkle root of the script tree (empty if there is
+///   none) the key is tweaked with before signing; `None` to sign with the untweaked key.
+///
+/// # Returns
+///
+/// * `Result<Vec<u8>, String>` - The 64 byte signature or an error message.
+///
+/// # Example
+///
+/// ```
+/// let signature = sign_with_schnorr(wallet_id, DEFAULT_ACCOUNT, sighash.to_vec(), key_id, Some(Vec::new())).await?;
+/// ```
+pub async fn sign_with_schnorr(
+    wallet_id: String,
+    account: AccountIndex,
+    message: Vec<u8>,
+    key_id: SchnorrKeyId,
+    taproot_merkle_root: Option<Vec<u8>>,
+) -> Result<Vec<u8>, String> {
+    let ic = Principal::management_canister();
+    let request = SignWithSchnorrArgument {
+        message,
+        derivation_path: derivation_path(&wallet_id, account),
+        key_id,
+        aux: taproot_merkle_root.map(|merkle_root_hash| SignWithSchnorrAux::Bip341(SignWithBip341Aux { merkle_root_hash })),
+    };
+
+    let (res,): (SignWithSchnorrResponse,) =
+        ic_cdk::api::call::call_with_payment(ic, "sign_with_schnorr", (request,), DEFAULT_SCHNORR_SIGN_CYCLES)
+            .await
+            .map_err(|e| format!("Failed to call sign_with_schnorr {}", e.1))?;
+    Ok(res.signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_taproot_output_key() {
+        // the first receiving address of the BIP-86 test vectors
+        let internal_key = hex::decode("cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115").unwrap();
+        let output_key = "a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c";
+        assert_eq!(hex::encode(taproot_output_key(&internal_key).unwrap()), output_key);
+
+        // the parity of a compressed key does not matter, as BIP-340 only uses the x coordinate
+        let mut compressed = vec![0x03];
+        compressed.extend(&internal_key);
+        assert_eq!(hex::encode(taproot_output_key(&compressed).unwrap()), output_key);
+
+        assert!(taproot_output_key(&internal_key[1..]).is_err());
+        assert!(taproot_output_key(&[0xff; 32]).is_err());
+    }
+
+    #[test]
+    fn test_tagged_hash() {
+        let tag = Sha256::digest(b"TapTweak");
+        let expected: [u8; 32] = Sha256::digest([tag.as_slice(), tag.as_slice(), b"data"].concat()).into();
+        assert_eq!(tagged_hash("TapTweak", b"data"), expected);
+    }
+
+    #[test]
+    fn test_candid_names() {
+        use candid::types::TypeInner;
+        // the management canister only accepts the algorithm and aux names of its interface
+        let TypeInner::Variant(algorithms) = SchnorrAlgorithm::ty().as_ref().clone() else { panic!() };
+        let mut names: Vec<String> = algorithms.iter().map(|field| field.id.to_string()).collect();
+        names.sort();
+        assert_eq!(names, ["bip340secp256k1", "ed25519"]);
+        let TypeInner::Variant(aux) = SignWithSchnorrAux::ty().as_ref().clone() else { panic!() };
+        assert_eq!(aux[0].id.to_string(), "bip341");
+    }
+}
@@ -0,0 +1,586 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use candid::CandidType;
+use serde::Deserialize;
+use crate::btc::base58_encode;
+use crate::calldata::DecodedArgument;
+
+/// The flag of the first message byte that marks a versioned message; the other bits hold the version.
+const VERSION_PREFIX: u8 = 0x80;
+
+/// The length of Ed25519 public keys, Solana's addresses.
+const PUBLIC_KEY_LENGTH: usize = 32;
+
+/// The length of Ed25519 signatures.
+const SIGNATURE_LENGTH: usize = 64;
+
+/// The instructions of the System program, indexed by their `u32` discriminant.
+const SYSTEM_INSTRUCTIONS: &[&str] = &[
+    "CreateAccount", "Assign", "Transfer", "CreateAccountWithSeed", "AdvanceNonceAccount", "WithdrawNonceAccount",
+    "InitializeNonceAccount", "AuthorizeNonceAccount", "Allocate", "AllocateWithSeed", "AssignWithSeed",
+    "TransferWithSeed", "UpgradeNonceAccount",
+];
+
+/// The instructions of the SPL Token program, indexed by their `u8` discriminant. Token-2022 shares them.
+const TOKEN_INSTRUCTIONS: &[&str] = &[
+    "InitializeMint", "InitializeAccount", "InitializeMultisig", "Transfer", "Approve", "Revoke", "SetAuthority",
+    "MintTo", "Burn", "CloseAccount", "FreezeAccount", "ThawAccount", "TransferChecked", "ApproveChecked",
+    "MintToChecked", "BurnChecked",
+];
+
+/// The name of a decoded instruction and its arguments.
+type DecodedInstruction = (String, Vec<DecodedArgument>);
+
+/// A well-known program whose instructions are decoded for review.
+struct KnownProgram {
+    /// The base58 address of the program.
+    address: &'static str,
+    /// The name of the program.
+    name: &'static str,
+    /// Decodes the instruction data into the instruction name and its arguments.
+    decode: fn(&[u8]) -> Option<DecodedInstruction>,
+}
+
+const KNOWN_PROGRAMS: &[KnownProgram] = &[
+    KnownProgram { address: "11111111111111111111111111111111", name: "System", decode: decode_system },
+    KnownProgram { address: "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA", name: "SPL Token", decode: decode_token },
+    KnownProgram { address: "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb", name: "SPL Token-2022", decode: decode_token },
+    KnownProgram { address: "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL", name: "Associated Token Account", decode: decode_associated_token },
+    KnownProgram { address: "ComputeBudget111111111111111111111111111111", name: "Compute Budget", decode: decode_compute_budget },
+    KnownProgram { address: "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr", name: "Memo", decode: decode_memo },
+];
+
+/// An account an instruction of a proposed Solana transaction passes to its program.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct SolAccount {
+    /// The base58 address, or `<table>#<index>` for accounts loaded from an address lookup table.
+    pub address: String,
+    /// Whether the account signs the transaction.
+    pub is_signer: bool,
+    /// Whether the instruction may modify the account.
+    pub is_writable: bool,
+}
+
+/// An instruction of a proposed Solana transaction, decoded for review.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct SolInstruction {
+    /// The base58 address of the invoked program.
+    pub program_id: String,
+    /// The name of the program, or `None` if it is not a well-known program.
+    pub program: Option<String>,
+    /// The name of the instruction, or `None` if its data could not be decoded.
+    pub instruction: Option<String>,
+    /// The decoded arguments: amounts in decimal, addresses in base58.
+    pub arguments: Vec<DecodedArgument>,
+    /// The accounts passed to the program.
+    pub accounts: Vec<SolAccount>,
+    /// The hex encoded instruction data.
+    pub data: String,
+}
+
+/// An unsigned Solana transaction message, together with the decoded instructions signers review.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct SolTransaction {
+    /// The base64 encoded message as it was proposed. It is what the wallet signs.
+    pub message: String,
+    /// The version of a versioned message, or `None` for a legacy message.
+    pub version: Option<u8>,
+    /// The base58 address of the account paying the fees, the first signer.
+    pub fee_payer: String,
+    /// The base58 encoded blockhash the transaction expires with.
+    pub recent_blockhash: String,
+    /// The number of signatures the transaction requires.
+    pub required_signatures: u8,
+    /// The position of the wallet's signature among the required signatures.
+    pub signer_index: u8,
+    /// The decoded instructions.
+    pub instructions: Vec<SolInstruction>,
+}
+
+impl SolTransaction {
+    /// Parse a serialized legacy or version 0 message the wallet has to sign.
+    ///
+    /// * `message` - The base64 encoded message, as `Transaction.serializeMessage()` of `@solana/web3.js` returns it.
+    /// * `signer` - The wallet's 32 byte Ed25519 public key. It must be one of the required signers.
+    ///
+    /// Returns `Result<SolTransaction, String>` containing the decoded transaction or an error message.
+    pub fn parse(message: &str, signer: &[u8]) -> Result<SolTransaction, String> {
+        let data = BASE64.decode(message.trim()).map_err(|_| "Message is not valid base64".to_string())?;
+        let mut reader = Reader { data: &data, position: 0 };
+
+        let mut version = None;
+        let mut required_signatures = reader.byte()?;
+        if required_signatures & VERSION_PREFIX != 0 {
+            let number = required_signatures & !VERSION_PREFIX;
+            if number != 0 {
+                return Err(format!("Unsupported message version: {}", number));
+            }
+            version = Some(number);
+            required_signatures = reader.byte()?;
+        }
+        let readonly_signed = reader.byte()?;
+        let readonly_unsigned = reader.byte()?;
+
+        let static_count = reader.compact_u16()?;
+        let static_keys = (0..static_count)
+            .map(|_| reader.bytes(PUBLIC_KEY_LENGTH))
+            .collect::<Result<Vec<_>, String>>()?;
+        if required_signatures == 0 || readonly_signed >= required_signatures
+            || required_signatures as usize + readonly_unsigned as usize > static_keys.len() {
+            return Err("Invalid message header".to_string());
+        }
+        let recent_blockhash = reader.bytes(PUBLIC_KEY_LENGTH)?;
+
+        let mut compiled = Vec::new();
+        for _ in 0..reader.compact_u16()? {
+            let program_index = reader.byte()?;
+            let accounts = reader.compact_bytes()?;
+            let data = reader.compact_bytes()?;
+            compiled.push((program_index, accounts, data));
+        }
+
+        // accounts loaded from lookup tables follow the static ones: all writable accounts, then all readonly ones
+        let mut loaded_writable = Vec::new();
+        let mut loaded_readonly = Vec::new();
+        if version.is_some() {
+            for _ in 0..reader.compact_u16()? {
+                let table = base58_encode(reader.bytes(PUBLIC_KEY_LENGTH)?);
+                for index in reader.compact_bytes()? {
+                    loaded_writable.push(format!("{}#{}", table, index));
+                }
+                for index in reader.compact_bytes()? {
+                    loaded_readonly.push(format!("{}#{}", table, index));
+                }
+            }
+        }
+        if reader.position != data.len() {
+            return Err("Trailing bytes after the message".to_string());
+        }
+
+        let required = required_signatures as usize;
+        let writable_signed = required - readonly_signed as usize;
+        let writable_unsigned = static_keys.len() - readonly_unsigned as usize;
+        let mut accounts: Vec<SolAccount> = static_keys.iter().enumerate()
+            .map(|(index, key)| SolAccount {
+                address: base58_encode(key),
+                is_signer: index < required,
+                is_writable: if index < required { index < writable_signed } else { index < writable_unsigned },
+            })
+            .collect();
+        let loaded = loaded_writable.into_iter().map(|address| (address, true))
+            .chain(loaded_readonly.into_iter().map(|address| (address, false)));
+        for (address, is_writable) in loaded {
+            accounts.push(SolAccount { address, is_signer: false, is_writable });
+        }
+
+        let signer_index = static_keys[..required].iter()
+            .position(|key| *key == signer)
+            .ok_or("The wallet is not a signer of the message".to_string())?;
+
+        let mut instructions = Vec::new();
+        for (program_index, account_indexes, data) in compiled {
+            // programs are always static accounts, even in versioned messages
+            let program_id = static_keys.get(program_index as usize)
+                .map(|key| base58_encode(key))
+                .ok_or(format!("Program index {} is out of range", program_index))?;
+            let accounts = account_indexes.iter()
+                .map(|index| accounts.get(*index as usize).cloned()
+                    .ok_or(format!("Account index {} is out of range", index)))
+                .collect::<Result<Vec<_>, String>>()?;
+            let program = KNOWN_PROGRAMS.iter().find(|program| program.address == program_id);
+            let decoded = program.and_then(|program| (program.decode)(&data));
+            let (instruction, arguments) = match decoded {
+                Some((instruction, arguments)) => (Some(instruction), arguments),
+                None => (None, Vec::new()),
+            };
+            instructions.push(SolInstruction {
+                program_id,
+                program: program.map(|program| program.name.to_string()),
+                instruction,
+                arguments,
+                accounts,
+                data: hex::encode(&data),
+            });
+        }
+
+        Ok(SolTransaction {
+            message: BASE64.encode(&data),
+            version,
+            fee_payer: base58_encode(static_keys[0]),
+            recent_blockhash: base58_encode(recent_blockhash),
+            required_signatures,
+            signer_index: signer_index as u8,
+            instructions,
+        })
+    }
+
+    /// The serialized message the wallet signs.
+    pub fn message_bytes(&self) -> Result<Vec<u8>, String> {
+        BASE64.decode(&self.message).map_err(|_| "Message is not valid base64".to_string())
+    }
+
+    /// Serialize the transaction with the wallet's signature, leaving the slots of other signers zeroed.
+    ///
+    /// * `signature` - The 64 byte Ed25519 signature of the message.
+    ///
+    /// Returns `Result<String, String>` containing the base64 encoded transaction or an error message.
+    pub fn encode_signed(&self, signature: &[u8]) -> Result<String, String> {
+        if signature.len() != SIGNATURE_LENGTH {
+            return Err(format!("Invalid signature length: {}", signature.len()));
+        }
+        let mut tx = Vec::new();
+        write_compact_u16(&mut tx, self.required_signatures as u16);
+        for index in 0..self.required_signatures {
+            if index == self.signer_index {
+                tx.extend_from_slice(signature);
+            } else {
+                tx.extend_from_slice(&[0u8; SIGNATURE_LENGTH]);
+            }
+        }
+        tx.extend(self.message_bytes()?);
+        Ok(BASE64.encode(tx))
+    }
+}
+
+/// Encode an Ed25519 public key as a Solana address.
+///
+/// # Arguments
+///
+/// * `public_key` - The 32 byte Ed25519 public key.
+///
+/// # Returns
+///
+/// * `Result<String, String>` - The base58 address or an error message.
+///
+/// # Example
+///
+/// ```
+/// let address = sol_address(&schnorr_public_key.public_key)?;
+/// ```
+pub fn sol_address(public_key: &[u8]) -> Result<String, String> {
+    if public_key.len() != PUBLIC_KEY_LENGTH {
+        return Err(format!("Invalid public key length: {}", public_key.len()));
+    }
+    Ok(base58_encode(public_key))
+}
+
+/// Write a Solana compact-u16: 7 bits per byte, least significant first, with the high bit marking continuation.
+fn write_compact_u16(out: &mut Vec<u8>, mut value: u16) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// A cursor over a serialized message.
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let end = self.position.checked_add(length).filter(|end| *end <= self.data.len())
+            .ok_or("Unexpected end of message".to_string())?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn compact_u16(&mut self) -> Result<usize, String> {
+        let mut value = 0usize;
+        for shift in [0, 7, 14] {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return if value > u16::MAX as usize { Err("Invalid compact-u16".to_string()) } else { Ok(value) };
+            }
+        }
+        Err("Invalid compact-u16".to_string())
+    }
+
+    fn compact_bytes(&mut self) -> Result<Vec<u8>, String> {
+        let length = self.compact_u16()?;
+        Ok(self.bytes(length)?.to_vec())
+    }
+}
+
+/// Build a decoded argument.
+fn argument(name: &str, type_name: &str, value: String) -> DecodedArgument {
+    DecodedArgument { name: name.to_string(), type_name: type_name.to_string(), value }
+}
+
+/// Read a little-endian `u64` argument at `offset`.
+fn u64_at(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+/// Read a little-endian `u32` argument at `offset`.
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+/// Read a public key argument at `offset` as a base58 address.
+fn pubkey_at(data: &[u8], offset: usize) -> Option<String> {
+    Some(base58_encode(data.get(offset..offset + PUBLIC_KEY_LENGTH)?))
+}
+
+fn decode_system(data: &[u8]) -> Option<DecodedInstruction> {
+    let discriminant = u32_at(data, 0)?;
+    let name = SYSTEM_INSTRUCTIONS.get(discriminant as usize)?;
+    let arguments = match discriminant {
+        0 => vec![
+            argument("lamports", "u64", u64_at(data, 4)?.to_string()),
+            argument("space", "u64", u64_at(data, 12)?.to_string()),
+            argument("owner", "pubkey", pubkey_at(data, 20)?),
+        ],
+        1 => vec![argument("owner", "pubkey", pubkey_at(data, 4)?)],
+        2 => vec![argument("lamports", "u64", u64_at(data, 4)?.to_string())],
+        8 => vec![argument("space", "u64", u64_at(data, 4)?.to_string())],
+        _ => Vec::new(),
+    };
+    Some((name.to_string(), arguments))
+}
+
+fn decode_token(data: &[u8]) -> Option<DecodedInstruction> {
+    let discriminant = *data.first()?;
+    let name = TOKEN_INSTRUCTIONS.get(discriminant as usize)?;
+    let arguments = match discriminant {
+        3 | 4 | 7 | 8 => vec![argument("amount", "u64", u64_at(data, 1)?.to_string())],
+        12..=15 => vec![
+            argument("amount", "u64", u64_at(data, 1)?.to_string()),
+            argument("decimals", "u8", data.get(9)?.to_string()),
+        ],
+        _ => Vec::new(),
+    };
+    Some((name.to_string(), arguments))
+}
+
+fn decode_associated_token(data: &[u8]) -> Option<DecodedInstruction> {
+    // the original instruction has no data at all
+    let name = match data.first() {
+        None | Some(0) => "Create",
+        Some(1) => "CreateIdempotent",
+        Some(2) => "RecoverNested",
+        _ => return None,
+    };
+    Some((name.to_string(), Vec::new()))
+}
+
+fn decode_compute_budget(data: &[u8]) -> Option<DecodedInstruction> {
+    let (name, arguments) = match *data.first()? {
+        1 => ("RequestHeapFrame", vec![argument("bytes", "u32", u32_at(data, 1)?.to_string())]),
+        2 => ("SetComputeUnitLimit", vec![argument("units", "u32", u32_at(data, 1)?.to_string())]),
+        3 => ("SetComputeUnitPrice", vec![argument("microLamports", "u64", u64_at(data, 1)?.to_string())]),
+        4 => ("SetLoadedAccountsDataSizeLimit", vec![argument("bytes", "u32", u32_at(data, 1)?.to_string())]),
+        _ => return None,
+    };
+    Some((name.to_string(), arguments))
+}
+
+fn decode_memo(data: &[u8]) -> Option<DecodedInstruction> {
+    let memo = String::from_utf8(data.to_vec()).ok()?;
+    Some(("Memo".to_string(), vec![argument("memo", "string", memo)]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WALLET: [u8; 32] = [0x11; 32];
+    const RECIPIENT: [u8; 32] = [0x22; 32];
+    const BLOCKHASH: [u8; 32] = [0x33; 32];
+
+    /// A legacy message transferring 1 SOL from the wallet, with a compute unit price and a memo.
+    fn transfer_message() -> Vec<u8> {
+        let compute_budget = hex::decode("0306466fe5211732ffecadba72c39be7bc8ce5bbc5f7126b2c439b3a40000000").unwrap();
+        let memo = hex::decode("054a535a992921064d24e87160da387c7c35b5ddbc92bb81e41fa8404105448d").unwrap();
+        // one signer, no readonly signer, the three programs are readonly
+        let mut message = vec![1, 0, 3, 5];
+        message.extend(WALLET);
+        message.extend(RECIPIENT);
+        message.extend([0u8; 32]);
+        message.extend(&compute_budget);
+        message.extend(&memo);
+        message.extend(BLOCKHASH);
+        message.push(3);
+        message.extend([3, 0, 9, 3]);
+        message.extend(1000u64.to_le_bytes());
+        message.extend([2, 2, 0, 1, 12]);
+        message.extend(2u32.to_le_bytes());
+        message.extend(1_000_000_000u64.to_le_bytes());
+        message.extend([4, 1, 0, 5]);
+        message.extend(b"hello");
+        message
+    }
+
+    #[test]
+    fn test_parse_legacy_message() {
+        let message = BASE64.encode(transfer_message());
+        let tx = SolTransaction::parse(&message, &WALLET).unwrap();
+        assert_eq!(tx.version, None);
+        assert_eq!(tx.fee_payer, base58_encode(&WALLET));
+        assert_eq!(tx.recent_blockhash, base58_encode(&BLOCKHASH));
+        assert_eq!((tx.required_signatures, tx.signer_index), (1, 0));
+        assert_eq!(tx.instructions.len(), 3);
+
+        let price = &tx.instructions[0];
+        assert_eq!(price.program.as_deref(), Some("Compute Budget"));
+        assert_eq!(price.instruction.as_deref(), Some("SetComputeUnitPrice"));
+        assert_eq!(price.arguments, vec![argument("microLamports", "u64", "1000".to_string())]);
+        assert!(price.accounts.is_empty());
+
+        let transfer = &tx.instructions[1];
+        assert_eq!(transfer.program_id, "11111111111111111111111111111111");
+        assert_eq!(transfer.instruction.as_deref(), Some("Transfer"));
+        assert_eq!(transfer.arguments, vec![argument("lamports", "u64", "1000000000".to_string())]);
+        assert_eq!(transfer.accounts, vec![
+            SolAccount { address: base58_encode(&WALLET), is_signer: true, is_writable: true },
+            SolAccount { address: base58_encode(&RECIPIENT), is_signer: false, is_writable: true },
+        ]);
+        assert_eq!(transfer.data, "0200000000ca9a3b00000000");
+
+        let memo = &tx.instructions[2];
+        assert_eq!(memo.program_id, "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr");
+        assert_eq!(memo.arguments, vec![argument("memo", "string", "hello".to_string())]);
+        assert!(memo.accounts[0].is_signer);
+    }
+
+    #[test]
+    fn test_parse_v0_message() {
+        let token = hex::decode("06ddf6e1d765a193d9cbe146ceeb79ac1cb485ed5f5b37913a8cf5857eff00a9").unwrap();
+        let table = [0x44; 32];
+        // the fee payer signs, the wallet is a readonly second signer
+        let mut message = vec![0x80, 2, 1, 1, 3];
+        message.extend(RECIPIENT);
+        message.extend(WALLET);
+        message.extend(&token);
+        message.extend(BLOCKHASH);
+        // TransferChecked from a loaded account to another, with a loaded mint and the wallet as owner
+        message.extend([1, 2, 4, 3, 5, 4, 1, 10, 12]);
+        message.extend(5_000_000u64.to_le_bytes());
+        message.push(6);
+        message.push(1);
+        message.extend(table);
+        message.extend([2, 7, 8, 1, 9]);
+
+        let tx = SolTransaction::parse(&BASE64.encode(&message), &WALLET).unwrap();
+        assert_eq!(tx.version, Some(0));
+        assert_eq!(tx.fee_payer, base58_encode(&RECIPIENT));
+        assert_eq!((tx.required_signatures, tx.signer_index), (2, 1));
+
+        let transfer = &tx.instructions[0];
+        assert_eq!(transfer.program.as_deref(), Some("SPL Token"));
+        assert_eq!(transfer.instruction.as_deref(), Some("TransferChecked"));
+        assert_eq!(transfer.arguments, vec![
+            argument("amount", "u64", "5000000".to_string()),
+            argument("decimals", "u8", "6".to_string()),
+        ]);
+        let table = base58_encode(&table);
+        assert_eq!(transfer.accounts, vec![
+            SolAccount { address: format!("{}#7", table), is_signer: false, is_writable: true },
+            SolAccount { address: format!("{}#9", table), is_signer: false, is_writable: false },
+            SolAccount { address: format!("{}#8", table), is_signer: false, is_writable: true },
+            SolAccount { address: base58_encode(&WALLET), is_signer: true, is_writable: false },
+        ]);
+    }
+
+    #[test]
+    fn test_unknown_instructions() {
+        let mut message = transfer_message();
+        // an unknown compute budget instruction and an unknown program
+        message[4 + 5 * 32 + 32 + 4] = 0xff;
+        message[4 + 2 * 32 + 31] = 0x01;
+        let tx = SolTransaction::parse(&BASE64.encode(&message), &WALLET).unwrap();
+        assert_eq!(tx.instructions[0].program.as_deref(), Some("Compute Budget"));
+        assert_eq!(tx.instructions[0].instruction, None);
+        assert!(tx.instructions[0].arguments.is_empty());
+        assert_eq!(tx.instructions[1].program, None);
+        assert_eq!(tx.instructions[1].instruction, None);
+    }
+
+    #[test]
+    fn test_invalid_messages() {
+        let message = transfer_message();
+        let parse = |data: &[u8]| SolTransaction::parse(&BASE64.encode(data), &WALLET);
+        assert!(SolTransaction::parse("not base64!", &WALLET).is_err());
+        assert!(SolTransaction::parse(&BASE64.encode(&message), &RECIPIENT).is_err());
+        assert!(parse(&message[..message.len() - 1]).is_err());
+        assert!(parse(&[message.as_slice(), &[0]].concat()).is_err());
+        assert!(parse(&[&[0x81], message.as_slice()].concat()).is_err());
+
+        let mut header = message.clone();
+        header[2] = 5;
+        assert!(parse(&header).is_err());
+        let mut program = message.clone();
+        program[4 + 5 * 32 + 32 + 1] = 5;
+        assert!(parse(&program).is_err());
+        let mut account = message;
+        account[4 + 5 * 32 + 32 + 1 + 12 + 3] = 5;
+        assert!(parse(&account).is_err());
+    }
+
+    #[test]
+    fn test_encode_signed() {
+        let message = transfer_message();
+        let tx = SolTransaction::parse(&BASE64.encode(&message), &WALLET).unwrap();
+        let signed = BASE64.decode(tx.encode_signed(&[0xaa; 64]).unwrap()).unwrap();
+        assert_eq!(signed[0], 1);
+        assert_eq!(&signed[1..65], &[0xaa; 64]);
+        assert_eq!(&signed[65..], message.as_slice());
+        assert!(tx.encode_signed(&[0xaa; 63]).is_err());
+
+        let mut v0 = vec![0x80, 2, 0, 0, 2];
+        v0.extend(RECIPIENT);
+        v0.extend(WALLET);
+        v0.extend(BLOCKHASH);
+        v0.extend([0, 0]);
+        let tx = SolTransaction::parse(&BASE64.encode(&v0), &WALLET).unwrap();
+        let signed = BASE64.decode(tx.encode_signed(&[0xaa; 64]).unwrap()).unwrap();
+        assert_eq!(&signed[..65], &[[2].as_slice(), &[0; 64]].concat());
+        assert_eq!(&signed[65..129], &[0xaa; 64]);
+        assert_eq!(&signed[129..], v0.as_slice());
+    }
+
+    #[test]
+    fn test_compact_u16() {
+        for (value, encoded) in [(0u16, vec![0x00]), (0x7f, vec![0x7f]), (0x80, vec![0x80, 0x01]),
+                                 (0x3fff, vec![0xff, 0x7f]), (0xffff, vec![0xff, 0xff, 0x03])] {
+            let mut out = Vec::new();
+            write_compact_u16(&mut out, value);
+            assert_eq!(out, encoded);
+            assert_eq!(Reader { data: &encoded, position: 0 }.compact_u16().unwrap(), value as usize);
+        }
+        assert!(Reader { data: &[0xff, 0xff, 0x04], position: 0 }.compact_u16().is_err());
+        assert!(Reader { data: &[0xff, 0xff, 0xff, 0x01], position: 0 }.compact_u16().is_err());
+    }
+
+    #[test]
+    fn test_sol_address() {
+        assert_eq!(sol_address(&[0u8; 32]).unwrap(), "11111111111111111111111111111111");
+        let token = hex::decode("06ddf6e1d765a193d9cbe146ceeb79ac1cb485ed5f5b37913a8cf5857eff00a9").unwrap();
+        assert_eq!(sol_address(&token).unwrap(), "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+        assert!(sol_address(&token[1..]).is_err());
+        // the addresses of the known programs encode their keys
+        let keys = [
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "06ddf6e1d765a193d9cbe146ceeb79ac1cb485ed5f5b37913a8cf5857eff00a9",
+            "06ddf6e1ee758fde18425dbce46ccddab61afc4d83b90d27febdf928d8a18bfc",
+            "8c97258f4e2489f1bb3d1029148e0d830b5a1399daff1084048e7bd8dbe9f859",
+            "0306466fe5211732ffecadba72c39be7bc8ce5bbc5f7126b2c439b3a40000000",
+            "054a535a992921064d24e87160da387c7c35b5ddbc92bb81e41fa8404105448d",
+        ];
+        for (program, key) in KNOWN_PROGRAMS.iter().zip(keys) {
+            assert_eq!(sol_address(&hex::decode(key).unwrap()).unwrap(), program.address);
+        }
+    }
+}
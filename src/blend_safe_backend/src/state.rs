@@ -0,0 +1,514 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::str::FromStr;
+use candid::de::IDLDeserialize;
+use candid::{CandidType, Principal};
+use ic_cdk::api::management_canister::ecdsa::EcdsaKeyId;
+use serde::Deserialize;
+use crate::wallet::{Proposal, ProposalId, ProposalKind, ProposalStatus, Wallet, DEFAULT_PROPOSAL_TTL};
+
+pub type WalletStore = BTreeMap<String, Wallet>;
+pub type PrincipalWalletsMap = BTreeMap<Principal, Vec<String>>;
+
+/// The version of the stable memory layout written by this build.
+///
+/// Bump this whenever the shape of `StableState` (or anything it contains)
+/// changes, and teach `StableState::into_parts` how to read the old layout.
+///
+/// * 1 - Wallets with a message queue, or with proposals that may lack rejections and expiry.
+///   Both were written as version 1, so `StableState::decode` tells them apart by their shape.
+/// * 2 - The ABI registry.
+/// * 3 - Ethereum nonces.
+/// * 4 - The cached public key.
+/// * 5 - The cached Schnorr public keys.
+/// * 6 - Accounts.
+/// * 7 - The key ID per wallet and the default key ID.
+/// * 8 - The signed transaction of executed proposals.
+pub const STATE_VERSION: u8 = 8;
+
+/// A snapshot of the canister state that is written to stable memory in
+/// `pre_upgrade` and read back in `post_upgrade`.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct StableState {
+    /// The layout version the snapshot was written with.
+    version: u8,
+    /// All wallets, keyed by their wallet ID.
+    wallets: WalletStore,
+    /// The wallet IDs each principal is a signer of.
+    principal_wallets_map: PrincipalWalletsMap,
+    /// The threshold key new wallets are created with. `None` in snapshots that predate it.
+    default_key_id: Option<EcdsaKeyId>,
+}
+
+/// The version every snapshot starts with, decoded first to pick the layout of the rest.
+#[derive(CandidType, Deserialize)]
+struct StateHeader {
+    version: u8,
+}
+
+/// A version 1 snapshot, with wallets of one of the layouts written as version 1.
+#[derive(CandidType, Deserialize)]
+struct StableStateV1<W> {
+    wallets: BTreeMap<String, W>,
+    principal_wallets_map: PrincipalWalletsMap,
+}
+
+/// A version 1 wallet that queues messages by payload, with the kind of each message once kinds were added.
+#[derive(CandidType, Deserialize)]
+struct QueueWalletV1 {
+    signers: HashSet<Principal>,
+    threshold: u8,
+    message_queue: HashMap<Vec<u8>, Vec<Principal>>,
+    metadata: HashMap<Vec<u8>, String>,
+    proposal_kinds: Option<HashMap<Vec<u8>, ProposalKind>>,
+}
+
+/// A version 1 wallet that keys proposals by ID.
+#[derive(CandidType, Deserialize)]
+struct ProposalWalletV1 {
+    signers: HashSet<Principal>,
+    threshold: u8,
+    proposals: BTreeMap<ProposalId, ProposalV1>,
+    next_proposal_id: ProposalId,
+}
+
+/// A version 1 proposal, written before or after rejections and expiry were added.
+#[derive(CandidType, Deserialize)]
+struct ProposalV1 {
+    id: ProposalId,
+    proposer: Principal,
+    created_at: u64,
+    payload: Vec<u8>,
+    kind: ProposalKind,
+    approvals: HashSet<Principal>,
+    rejections: Option<HashSet<Principal>>,
+    status: ProposalStatus,
+    metadata: Option<String>,
+    expires_at: Option<u64>,
+    closed_at: Option<u64>,
+    signature: Option<Vec<u8>>,
+}
+
+impl From<ProposalWalletV1> for Wallet {
+    fn from(wallet: ProposalWalletV1) -> Self {
+        let proposals = wallet.proposals.into_iter()
+            .map(|(id, proposal)| (id, Proposal {
+                id: proposal.id,
+                proposer: proposal.proposer,
+                created_at: proposal.created_at,
+                payload: proposal.payload,
+                kind: proposal.kind,
+                account: None,
+                approvals: proposal.approvals,
+                rejections: proposal.rejections.unwrap_or_default(),
+                status: proposal.status,
+                metadata: proposal.metadata,
+                expires_at: proposal.expires_at.unwrap_or(proposal.created_at.saturating_add(DEFAULT_PROPOSAL_TTL)),
+                closed_at: proposal.closed_at,
+                signature: proposal.signature,
+                signed_transaction: None,
+            }))
+            .collect();
+        Wallet::restore(wallet.signers, wallet.threshold, proposals, wallet.next_proposal_id)
+    }
+}
+
+impl QueueWalletV1 {
+    /// Turn the queued messages into proposals, ordered by payload.
+    ///
+    /// The proposer of a queued message was not recorded, so it reads as the anonymous principal.
+    ///
+    /// * `now` - The current time in nanoseconds since the epoch, used as the creation time.
+    fn into_wallet(self, now: u64) -> Wallet {
+        let mut queue: Vec<_> = self.message_queue.into_iter().collect();
+        queue.sort();
+        let kinds = self.proposal_kinds.unwrap_or_default();
+        let mut metadata = self.metadata;
+        let proposals: BTreeMap<ProposalId, Proposal> = queue.into_iter()
+            .enumerate()
+            .map(|(id, (payload, approvals))| {
+                let id = id as ProposalId;
+                let kind = kinds.get(&payload).cloned().unwrap_or_else(|| special_message_kind(&payload));
+                let proposal = Proposal {
+                    id,
+                    proposer: Principal::anonymous(),
+                    created_at: now,
+                    payload: if kind.is_config_change() { Vec::new() } else { payload.clone() },
+                    kind,
+                    account: None,
+                    approvals: approvals.into_iter().collect(),
+                    rejections: HashSet::new(),
+                    status: ProposalStatus::Open,
+                    metadata: metadata.remove(&payload),
+                    expires_at: now.saturating_add(DEFAULT_PROPOSAL_TTL),
+                    closed_at: None,
+                    signature: None,
+                    signed_transaction: None,
+                };
+                (id, proposal)
+            })
+            .collect();
+        let next_proposal_id = proposals.len() as ProposalId;
+        Wallet::restore(self.signers, self.threshold, proposals, next_proposal_id)
+    }
+}
+
+/// Read the kind of a queued message that predates kinds. Configuration changes were queued as
+/// `ADD_SIGNER::<principal>`, `REMOVE_SIGNER::<principal>` and `SET_THRESHOLD::<threshold>` messages.
+///
+/// * `payload` - The queued message.
+fn special_message_kind(payload: &[u8]) -> ProposalKind {
+    let message = std::str::from_utf8(payload).unwrap_or_default();
+    let kind = if let Some(signer) = message.strip_prefix("ADD_SIGNER::") {
+        Principal::from_str(signer).ok().map(ProposalKind::AddSigner)
+    } else if let Some(signer) = message.strip_prefix("REMOVE_SIGNER::") {
+        Principal::from_str(signer).ok().map(ProposalKind::RemoveSigner)
+    } else if let Some(threshold) = message.strip_prefix("SET_THRESHOLD::") {
+        threshold.parse().ok().map(ProposalKind::SetThreshold)
+    } else {
+        None
+    };
+    kind.unwrap_or(ProposalKind::SignPayload)
+}
+
+/// Decode the first value of a candid message, ignoring what follows it, as `stable_restore` does for the
+/// unused tail of stable memory.
+///
+/// * `bytes` - The candid message.
+fn decode_first<T: for<'de> Deserialize<'de> + CandidType>(bytes: &[u8]) -> Result<T, String> {
+    let mut de = IDLDeserialize::new(bytes).map_err(|error| error.to_string())?;
+    de.get_value().map_err(|error| error.to_string())
+}
+
+impl StableState {
+    /// Decode a snapshot from the bytes `pre_upgrade` wrote to stable memory, converting the wallets of a version 1
+    /// snapshot to the current layout. The snapshot keeps its version, so `into_parts` still migrates it.
+    ///
+    /// * `bytes` - The contents of stable memory.
+    /// * `now` - The current time in nanoseconds since the epoch, the creation time of queued messages.
+    ///
+    /// Empty stable memory, as left by a release without `pre_upgrade`, decodes to a snapshot without wallets.
+    ///
+    /// Returns an error if the bytes hold no snapshot of a known layout.
+    pub fn decode(bytes: &[u8], now: u64) -> Result<StableState, String> {
+        if bytes.is_empty() {
+            return Ok(StableState {
+                version: STATE_VERSION,
+                wallets: WalletStore::new(),
+                principal_wallets_map: PrincipalWalletsMap::new(),
+                default_key_id: None,
+            });
+        }
+        let header: StateHeader = decode_first(bytes)?;
+        if header.version != 1 {
+            return decode_first(bytes);
+        }
+        let (wallets, principal_wallets_map) = match decode_first::<StableStateV1<ProposalWalletV1>>(bytes) {
+            Ok(state) => (state.wallets.into_iter().map(|(id, wallet)| (id, wallet.into())).collect(), state.principal_wallets_map),
+            Err(_) => {
+                let state: StableStateV1<QueueWalletV1> = decode_first(bytes)?;
+                let wallets = state.wallets.into_iter().map(|(id, wallet)| (id, wallet.into_wallet(now))).collect();
+                (wallets, state.principal_wallets_map)
+            }
+        };
+        Ok(StableState { version: 1, wallets, principal_wallets_map, default_key_id: None })
+    }
+
+    /// Create a snapshot tagged with the current `STATE_VERSION`.
+    ///
+    /// * `wallets` - The wallet store to persist.
+    /// * `principal_wallets_map` - The principal to wallet IDs index to persist.
+    /// * `default_key_id` - The threshold key new wallets are created with.
+    pub fn new(wallets: WalletStore, principal_wallets_map: PrincipalWalletsMap, default_key_id: EcdsaKeyId) -> Self {
+        StableState {
+            version: STATE_VERSION,
+            wallets,
+            principal_wallets_map,
+            default_key_id: Some(default_key_id),
+        }
+    }
+
+    /// Consume the snapshot and return the stores and the default key it holds.
+    ///
+    /// * `key_id` - The key of the environment, used as the default key and as the key of wallets
+    ///   without one when the snapshot predates them.
+    ///
+    /// Returns an error if the snapshot was written by a newer, unknown layout.
+    pub fn into_parts(self, key_id: &EcdsaKeyId) -> Result<(WalletStore, PrincipalWalletsMap, EcdsaKeyId), String> {
+        let mut wallets = self.wallets;
+        match self.version {
+            // older wallets decode without an ABI registry, nonces or public keys, which read as empty,
+            // keep their keys and nonces on the wallet until they move to the default account,
+            // and sign with the key of the environment unless a cached public key tells otherwise
+            1..=6 => wallets.values_mut().for_each(|wallet| {
+                wallet.migrate_accounts();
+                wallet.migrate_key_id(key_id);
+            }),
+            // proposals executed before decode without a signed transaction
+            7 => {}
+            STATE_VERSION => {}
+            version => return Err(format!("UnsupportedStateVersion: {}", version)),
+        }
+        let default_key_id = self.default_key_id.unwrap_or_else(|| key_id.clone());
+        Ok((wallets, self.principal_wallets_map, default_key_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecdsa::get_ecdsa_key_id_from_env;
+    use crate::wallet::{MultiSignatureWallet, ProposalKind};
+    use candid::{Decode, Encode};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_state_roundtrip() {
+        let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+
+        let key_id = get_ecdsa_key_id_from_env("test");
+
+        let mut wallet = Wallet::with_key_id(key_id.clone());
+        wallet.add_signer(signer1);
+        wallet.add_signer(signer2);
+        wallet.set_default_threshold(2).unwrap();
+        let id = wallet.propose_with_kind(signer1, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
+        wallet.approve(id, signer2, 0).unwrap();
+
+        let mut wallets = WalletStore::new();
+        wallets.insert("wallet".to_string(), wallet.clone());
+        let mut principal_wallets_map = PrincipalWalletsMap::new();
+        principal_wallets_map.insert(signer1, vec!["wallet".to_string()]);
+        principal_wallets_map.insert(signer2, vec!["wallet".to_string()]);
+
+        let bytes = Encode!(&StableState::new(wallets.clone(), principal_wallets_map.clone(), key_id.clone())).unwrap();
+        let restored = Decode!(&bytes, StableState).unwrap();
+
+        // the default key survives an upgrade to another environment
+        let env_key_id = get_ecdsa_key_id_from_env("production");
+        assert_eq!(restored.into_parts(&env_key_id), Ok((wallets, principal_wallets_map, key_id)));
+    }
+
+    #[test]
+    fn test_restore_version_1() {
+        use crate::wallet::DEFAULT_ACCOUNT;
+        use std::collections::{HashMap, HashSet};
+
+        #[derive(CandidType)]
+        struct WalletV1 {
+            signers: HashSet<Principal>,
+            threshold: u8,
+            message_queue: HashMap<Vec<u8>, Vec<Principal>>,
+            metadata: HashMap<Vec<u8>, String>,
+        }
+
+        #[derive(CandidType)]
+        struct StableStateV1 {
+            version: u8,
+            wallets: BTreeMap<String, WalletV1>,
+            principal_wallets_map: PrincipalWalletsMap,
+        }
+
+        let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        let add_signer = format!("ADD_SIGNER::{}", signer2).into_bytes();
+        let wallet = WalletV1 {
+            signers: HashSet::from([signer1]),
+            threshold: 1,
+            message_queue: HashMap::from([(add_signer, vec![signer1]), (vec![1, 2, 3], vec![])]),
+            metadata: HashMap::from([(vec![1, 2, 3], "transfer".to_string())]),
+        };
+        let state = StableStateV1 {
+            version: 1,
+            wallets: BTreeMap::from([("wallet".to_string(), wallet)]),
+            principal_wallets_map: PrincipalWalletsMap::from([(signer1, vec!["wallet".to_string()])]),
+        };
+
+        let bytes = Encode!(&state).unwrap();
+        let key_id = get_ecdsa_key_id_from_env("test");
+        let (wallets, principal_wallets_map, default_key_id) = StableState::decode(&bytes, 10).unwrap()
+            .into_parts(&key_id).unwrap();
+        assert_eq!(default_key_id, key_id);
+        assert_eq!(principal_wallets_map[&signer1], vec!["wallet".to_string()]);
+        let wallet = &wallets["wallet"];
+        assert_eq!(wallet.get_key_id(), Some(&key_id));
+        assert_eq!(wallet.get_signers(), vec![signer1]);
+        assert!(wallet.get_abis().is_empty());
+        assert_eq!(wallet.next_nonce(DEFAULT_ACCOUNT, 1, 0), 0);
+        assert_eq!(wallet.get_accounts().len(), 1);
+
+        // queued messages become proposals ordered by payload, with their approvals and metadata
+        let payload = wallet.get_proposal(0).unwrap();
+        assert_eq!(payload.payload, vec![1, 2, 3]);
+        assert_eq!(payload.kind, ProposalKind::SignPayload);
+        assert!(payload.approvals.is_empty());
+        assert_eq!(payload.metadata, Some("transfer".to_string()));
+        assert_eq!(payload.status, ProposalStatus::Open);
+        assert_eq!(wallet.get_metadata(0, signer1), Some(&"transfer".to_string()));
+
+        // special messages become configuration changes
+        let config = wallet.get_proposal(1).unwrap();
+        assert_eq!(config.kind, ProposalKind::AddSigner(signer2));
+        assert!(config.payload.is_empty());
+        assert_eq!(config.approvals, HashSet::from([signer1]));
+        assert_eq!(config.metadata, None);
+        assert_eq!(config.status, ProposalStatus::Approved);
+        assert!(wallet.can_sign(1, 10));
+        assert_eq!(wallet.get_proposal(2), None);
+    }
+
+    #[test]
+    fn test_restore_version_1_message_queue_with_kinds() {
+        use std::collections::{HashMap, HashSet};
+
+        #[derive(CandidType)]
+        struct WalletV1 {
+            signers: HashSet<Principal>,
+            threshold: u8,
+            message_queue: HashMap<Vec<u8>, Vec<Principal>>,
+            metadata: HashMap<Vec<u8>, String>,
+            proposal_kinds: HashMap<Vec<u8>, ProposalKind>,
+        }
+
+        #[derive(CandidType)]
+        struct StableStateV1 {
+            version: u8,
+            wallets: BTreeMap<String, WalletV1>,
+            principal_wallets_map: PrincipalWalletsMap,
+        }
+
+        let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        let add_signer = format!("ADD_SIGNER::{}", signer2).into_bytes();
+        let wallet = WalletV1 {
+            signers: HashSet::from([signer1]),
+            threshold: 1,
+            message_queue: HashMap::from([(vec![1, 2, 3], vec![signer1]), (add_signer.clone(), vec![])]),
+            metadata: HashMap::from([(vec![1, 2, 3], "transfer".to_string())]),
+            proposal_kinds: HashMap::from([
+                (vec![1, 2, 3], ProposalKind::SignPayload),
+                (add_signer, ProposalKind::AddSigner(signer2)),
+            ]),
+        };
+        let state = StableStateV1 {
+            version: 1,
+            wallets: BTreeMap::from([("wallet".to_string(), wallet)]),
+            principal_wallets_map: PrincipalWalletsMap::new(),
+        };
+
+        let state = StableState::decode(&Encode!(&state).unwrap(), 10).unwrap();
+        assert_eq!(state.version, 1);
+        let (mut wallets, _, _) = state.into_parts(&get_ecdsa_key_id_from_env("test")).unwrap();
+        let wallet = wallets.get_mut("wallet").unwrap();
+        assert_eq!(wallet.get_proposals(10).len(), 2);
+
+        let payload = wallet.get_proposal(0).unwrap();
+        assert_eq!(payload.payload, vec![1, 2, 3]);
+        assert_eq!(payload.kind, ProposalKind::SignPayload);
+        assert_eq!(payload.approvals, HashSet::from([signer1]));
+        assert_eq!(payload.metadata, Some("transfer".to_string()));
+        assert_eq!(payload.status, ProposalStatus::Approved);
+        assert_eq!(payload.expires_at, 10 + DEFAULT_PROPOSAL_TTL);
+
+        let config = wallet.get_proposal(1).unwrap();
+        assert!(config.payload.is_empty());
+        assert_eq!(config.kind, ProposalKind::AddSigner(signer2));
+        assert_eq!(config.status, ProposalStatus::Open);
+        assert_eq!(wallet.propose_with_kind(signer1, vec![4], ProposalKind::SignPayload, 10), Ok(2));
+    }
+
+    #[test]
+    fn test_restore_version_1_proposals_without_expiry() {
+        use std::collections::HashSet;
+
+        #[derive(CandidType)]
+        struct ProposalV1 {
+            id: ProposalId,
+            proposer: Principal,
+            created_at: u64,
+            payload: Vec<u8>,
+            kind: ProposalKind,
+            approvals: HashSet<Principal>,
+            status: ProposalStatus,
+            metadata: Option<String>,
+        }
+
+        #[derive(CandidType)]
+        struct WalletV1 {
+            signers: HashSet<Principal>,
+            threshold: u8,
+            proposals: BTreeMap<ProposalId, ProposalV1>,
+            next_proposal_id: ProposalId,
+        }
+
+        #[derive(CandidType)]
+        struct StableStateV1 {
+            version: u8,
+            wallets: BTreeMap<String, WalletV1>,
+            principal_wallets_map: PrincipalWalletsMap,
+        }
+
+        let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let proposal = ProposalV1 {
+            id: 3,
+            proposer: signer,
+            created_at: 5,
+            payload: vec![1, 2, 3],
+            kind: ProposalKind::SignPayload,
+            approvals: HashSet::from([signer]),
+            status: ProposalStatus::Approved,
+            metadata: Some("transfer".to_string()),
+        };
+        let wallet = WalletV1 {
+            signers: HashSet::from([signer]),
+            threshold: 1,
+            proposals: BTreeMap::from([(3, proposal)]),
+            next_proposal_id: 4,
+        };
+        let state = StableStateV1 {
+            version: 1,
+            wallets: BTreeMap::from([("wallet".to_string(), wallet)]),
+            principal_wallets_map: PrincipalWalletsMap::new(),
+        };
+
+        let state = StableState::decode(&Encode!(&state).unwrap(), 10).unwrap();
+        let (wallets, _, _) = state.into_parts(&get_ecdsa_key_id_from_env("test")).unwrap();
+        let wallet = &wallets["wallet"];
+        let proposal = wallet.get_proposal(3).unwrap();
+        assert_eq!(proposal.proposer, signer);
+        assert_eq!(proposal.payload, vec![1, 2, 3]);
+        assert!(proposal.rejections.is_empty());
+        assert_eq!(proposal.metadata, Some("transfer".to_string()));
+        assert_eq!(proposal.expires_at, 5 + DEFAULT_PROPOSAL_TTL);
+        assert!(wallet.can_sign(3, 10));
+    }
+
+    #[test]
+    fn test_decode_ignores_trailing_bytes() {
+        let key_id = get_ecdsa_key_id_from_env("test");
+        let state = StableState::new(WalletStore::new(), PrincipalWalletsMap::new(), key_id.clone());
+        let mut bytes = Encode!(&state).unwrap();
+        bytes.extend([0; 64]);
+
+        let restored = StableState::decode(&bytes, 0).unwrap();
+        assert_eq!(restored.into_parts(&key_id), Ok((WalletStore::new(), PrincipalWalletsMap::new(), key_id)));
+    }
+
+    #[test]
+    fn test_decode_empty_stable_memory() {
+        let key_id = get_ecdsa_key_id_from_env("test");
+        let restored = StableState::decode(&[], 0).unwrap();
+        assert_eq!(restored.into_parts(&key_id), Ok((WalletStore::new(), PrincipalWalletsMap::new(), key_id)));
+
+        assert!(StableState::decode(&[0; 8], 0).is_err());
+    }
+
+    #[test]
+    fn test_unsupported_state_version() {
+        let key_id = get_ecdsa_key_id_from_env("test");
+        let mut state = StableState::new(WalletStore::new(), PrincipalWalletsMap::new(), key_id.clone());
+        state.version = STATE_VERSION + 1;
+
+        assert!(state.into_parts(&key_id).is_err());
+    }
+}
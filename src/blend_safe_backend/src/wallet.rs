@@ -1,17 +1,362 @@
 use candid::{CandidType, Principal};
 use serde::Deserialize;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use ic_cdk::api::management_canister::ecdsa::EcdsaKeyId;
+use crate::abi::ContractAbi;
+use crate::btc::BtcTransaction;
+use crate::sol::SolTransaction;
+use crate::ecdsa::WalletPublicKey;
+use crate::schnorr::{SchnorrKeyId, SchnorrPublicKey};
+use crate::eip712::TypedData;
+use crate::eth::{EthTx1559, EthTx2930, EthTxLegacy};
+
+/// The per-wallet identifier of a proposal.
+pub type ProposalId = u64;
+
+/// How long a proposal stays pending before it expires, in nanoseconds.
+pub const DEFAULT_PROPOSAL_TTL: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+/// How many proposals that are no longer pending a wallet keeps. The oldest are dropped as new proposals are queued.
+pub const MAX_RETAINED_HISTORY: usize = 1_000;
+
+/// The per-wallet index of an account. Accounts are never removed, so an index always selects the same keys.
+pub type AccountIndex = u32;
+
+/// The account every wallet starts with. Its keys are derived from the wallet ID alone.
+pub const DEFAULT_ACCOUNT: AccountIndex = 0;
+
+/// The name of the account every wallet starts with.
+pub const DEFAULT_ACCOUNT_NAME: &str = "default";
 
 #[derive(Debug, PartialEq)]
 pub enum WalletError {
     /// Represents an error when the signature provided is invalid.
     InvalidSignature,
-    /// Error when a message is already queued for signing.
-    MsgAlreadyQueued,
-    /// Error when a message is not found in the queue.
-    MsgNotQueued,
+    /// Error when a proposal is not found in the wallet.
+    ProposalNotFound,
     /// Error when there are not enough signers to meet the threshold.
     NotEnoughSigners,
+    /// Error when the threshold is set to zero.
+    InvalidThreshold,
+    /// Error when a signer to be added is already a signer of the wallet.
+    SignerAlreadyExists,
+    /// Error when a signer to be removed is not a signer of the wallet.
+    SignerNotFound,
+    /// Error when a proposal that does not change the wallet configuration is applied as one.
+    NotAConfigChange,
+    /// Error when a proposal has already been executed, rejected, expired or cancelled.
+    ProposalNotPending,
+    /// Error when someone other than the proposer tries to cancel a proposal.
+    NotProposer,
+    /// Error when a signer rejects a proposal they have already rejected.
+    AlreadyRejected,
+    /// Error when a signer approves a proposal they have already approved.
+    AlreadyApproved,
+    /// Error when a signer revokes an approval they have not given.
+    NotApproved,
+    /// Error when metadata is added to a proposal that already has metadata.
+    MetadataAlreadyExists,
+    /// Error when no ABI is registered for a contract.
+    AbiNotFound,
+    /// Error when an ABI is registered for a contract that already has one, outside of a proposal.
+    AbiAlreadyRegistered,
+    /// Error when an account index or name does not exist in the wallet.
+    AccountNotFound,
+    /// Error when an account is added with the name of an existing account.
+    AccountAlreadyExists,
+    /// Error when an account is added with an empty name.
+    InvalidAccountName,
+    /// Error when a wallet is migrated to the key it already uses.
+    KeyAlreadyInUse,
+    /// Error when a wallet is migrated back to a key it has migrated away from.
+    KeyRetired,
+    /// Error when a wallet is migrated to a key with an empty name.
+    InvalidKeyName,
+}
+
+/// Selects an account of a wallet, either by its index or by its name.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub enum AccountRef {
+    /// The account with the given index.
+    Index(AccountIndex),
+    /// The account with the given name.
+    Name(String),
+}
+
+/// A named account of a wallet, with keys derived from its own derivation path.
+///
+/// All accounts of a wallet are governed by the same signers and threshold.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct Account {
+    /// The name of the account, unique within the wallet.
+    name: String,
+    /// The public key of the account, cached the first time it is derived.
+    public_key: Option<WalletPublicKey>,
+    /// The threshold Schnorr public keys of the account, at most one per algorithm, cached the first time they are derived.
+    schnorr_public_keys: Vec<SchnorrPublicKey>,
+    /// The account's next nonce per chain ID, as of the last executed transaction or resync.
+    nonces: BTreeMap<u64, u64>,
+}
+
+impl Account {
+    /// Create an account without cached keys.
+    ///
+    /// * `name` - The name of the account.
+    pub fn new(name: String) -> Self {
+        Account { name, public_key: None, schnorr_public_keys: Vec::new(), nonces: BTreeMap::new() }
+    }
+}
+
+/// An account of a wallet, as returned by `get_accounts`.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct AccountInfo {
+    /// The index of the account, which selects its derivation path.
+    pub index: AccountIndex,
+    /// The name of the account.
+    pub name: String,
+}
+
+/// A completed move of a wallet from one threshold ECDSA key to another.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct KeyMigration {
+    /// The name of the key the wallet used before.
+    pub from_key: String,
+    /// The name of the key the wallet uses since.
+    pub to_key: String,
+    /// The Ethereum address of the default account under the old key, if it had been derived.
+    pub from_address: Option<String>,
+    /// The Ethereum address of the default account under the new key.
+    pub to_address: String,
+    /// The time of the migration, in nanoseconds since the epoch.
+    pub migrated_at: u64,
+}
+
+/// The threshold key of a wallet and the keys it has migrated away from, as returned by `get_key_info`.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct KeyInfo {
+    /// The name of the key the wallet signs with.
+    pub key_name: String,
+    /// The completed key migrations, oldest first.
+    pub migrations: Vec<KeyMigration>,
+}
+
+/// A reset of an account's next nonce on a chain, performed by a `ProposalKind::ResyncNonce` proposal.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct NonceResync {
+    /// The account whose nonce is reset.
+    pub account: AccountIndex,
+    /// The EIP-155 chain ID.
+    pub chain_id: u64,
+    /// The account's transaction count on the chain.
+    pub nonce: u64,
+}
+
+/// The action a proposal performs once it is executed.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub enum ProposalKind {
+    /// Sign the proposed payload with the wallet's threshold key.
+    SignPayload,
+    /// Add a new signer to the wallet.
+    AddSigner(Principal),
+    /// Remove an existing signer from the wallet.
+    RemoveSigner(Principal),
+    /// Change the number of approvals required for signing.
+    SetThreshold(u8),
+    /// Sign a legacy Ethereum transaction with EIP-155 replay protection. The payload is its signing hash.
+    LegacyTransaction(EthTxLegacy),
+    /// Sign an EIP-2930 Ethereum transaction. The payload is its signing hash.
+    Eip2930Transaction(EthTx2930),
+    /// Sign an EIP-1559 Ethereum transaction. The payload is its signing hash.
+    Eip1559Transaction(EthTx1559),
+    /// Sign a message with the EIP-191 `personal_sign` prefix. The payload is its hash.
+    PersonalMessage(String),
+    /// Sign EIP-712 typed structured data. The payload is its digest.
+    TypedData(TypedData),
+    /// Sign the inputs of a Bitcoin transaction that spend the wallet's outputs. The payload is its transaction ID.
+    BtcTransaction(BtcTransaction),
+    /// Sign the key path of the inputs of a Bitcoin transaction that spend the wallet's Taproot outputs.
+    /// The payload is its transaction ID.
+    TaprootTransaction(BtcTransaction),
+    /// Sign a Solana transaction with the wallet's Ed25519 key. The payload is its serialized message.
+    SolanaTransaction(SolTransaction),
+    /// Move all accounts of the wallet to the threshold ECDSA and Schnorr keys with the given name.
+    MigrateKey(String),
+    /// Register the ABI of a contract, replacing any ABI registered for the same address.
+    RegisterAbi(ContractAbi),
+    /// Remove the ABI registered for the contract with the given address.
+    RemoveAbi(String),
+    /// Set an account's next nonce on a chain, e.g. after a signed transaction was never broadcast.
+    ResyncNonce(NonceResync),
+}
+
+impl ProposalKind {
+    /// Returns whether executing a proposal of this kind changes the wallet configuration.
+    pub fn is_config_change(&self) -> bool {
+        matches!(
+            self,
+            ProposalKind::AddSigner(_)
+                | ProposalKind::RemoveSigner(_)
+                | ProposalKind::SetThreshold(_)
+                | ProposalKind::MigrateKey(_)
+                | ProposalKind::RegisterAbi(_)
+                | ProposalKind::RemoveAbi(_)
+                | ProposalKind::ResyncNonce(_)
+        )
+    }
+
+    /// Returns the chain ID and nonce of an Ethereum transaction, or `None` for other kinds.
+    pub fn eth_nonce(&self) -> Option<(u64, u64)> {
+        match self {
+            ProposalKind::LegacyTransaction(tx) => Some((tx.chain_id, tx.nonce)),
+            ProposalKind::Eip2930Transaction(tx) => Some((tx.chain_id, tx.nonce)),
+            ProposalKind::Eip1559Transaction(tx) => Some((tx.chain_id, tx.nonce)),
+            _ => None,
+        }
+    }
+}
+
+/// The state of a proposal.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub enum ProposalStatus {
+    /// The proposal is collecting approvals.
+    Open,
+    /// The proposal has enough approvals to be executed.
+    Approved,
+    /// The proposal has been executed.
+    Executed,
+    /// The proposal can no longer reach the threshold, or the wallet migrated away from the key it was proposed for.
+    Rejected,
+    /// The proposal was not executed before it expired.
+    Expired,
+    /// The proposal was withdrawn by its proposer.
+    Cancelled,
+}
+
+impl ProposalStatus {
+    /// Returns whether a proposal in this state can still be approved or executed.
+    pub fn is_pending(&self) -> bool {
+        matches!(self, ProposalStatus::Open | ProposalStatus::Approved)
+    }
+}
+
+/// A proposal queued in a wallet.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct Proposal {
+    /// The per-wallet identifier of the proposal.
+    pub id: ProposalId,
+    /// The signer who created the proposal.
+    pub proposer: Principal,
+    /// The time the proposal was created at, in nanoseconds since the epoch.
+    pub created_at: u64,
+    /// The payload to sign. Empty for configuration changes.
+    pub payload: Vec<u8>,
+    /// The action the proposal performs once executed.
+    pub kind: ProposalKind,
+    /// The account whose key signs the proposal. `None` for configuration changes and for proposals
+    /// restored from a snapshot that predates accounts, which the default account signs.
+    pub account: Option<AccountIndex>,
+    /// The signers who have approved the proposal.
+    pub approvals: HashSet<Principal>,
+    /// The signers who have rejected the proposal.
+    pub rejections: HashSet<Principal>,
+    /// The state of the proposal.
+    pub status: ProposalStatus,
+    /// Free-form metadata describing the proposal.
+    pub metadata: Option<String>,
+    /// The time the proposal expires at if it is still pending, in nanoseconds since the epoch.
+    pub expires_at: u64,
+    /// The time the proposal left the pending states, in nanoseconds since the epoch.
+    pub closed_at: Option<u64>,
+    /// The signature produced when a payload proposal was executed.
+    pub signature: Option<Vec<u8>>,
+    /// The base64 encoded finalized PSBT or signed transaction produced when a Bitcoin, Taproot or Solana transaction
+    /// proposal was executed.
+    pub signed_transaction: Option<String>,
+}
+
+/// A proposal that is no longer pending, as returned by the proposal history.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct ProposalHistoryItem {
+    /// The per-wallet identifier of the proposal.
+    pub id: ProposalId,
+    /// The signer who created the proposal.
+    pub proposer: Principal,
+    /// The time the proposal was created at, in nanoseconds since the epoch.
+    pub created_at: u64,
+    /// The time the proposal left the pending states, in nanoseconds since the epoch.
+    pub closed_at: Option<u64>,
+    /// The payload that was proposed, in hexadecimal format.
+    pub payload: String,
+    /// The action the proposal performs once executed.
+    pub kind: ProposalKind,
+    /// The account whose key signs the proposal, `None` for configuration changes.
+    pub account: Option<AccountIndex>,
+    /// The final state of the proposal.
+    pub status: ProposalStatus,
+    /// The signers who approved the proposal.
+    pub approvals: Vec<Principal>,
+    /// The signers who rejected the proposal.
+    pub rejections: Vec<Principal>,
+    /// The signature produced on execution, in hexadecimal format.
+    pub signature: Option<String>,
+    /// The base64 encoded finalized PSBT or signed transaction produced on execution of a Bitcoin, Taproot or Solana
+    /// transaction.
+    pub signed_transaction: Option<String>,
+    /// Free-form metadata describing the proposal.
+    pub metadata: Option<String>,
+}
+
+impl ProposalHistoryItem {
+    /// Create the history entry of a proposal, reporting a pending proposal past its expiry as expired.
+    ///
+    /// * `proposal` - The proposal.
+    /// * `now` - The current time in nanoseconds since the epoch.
+    pub fn new(proposal: &Proposal, now: u64) -> Self {
+        let expired = proposal.status.is_pending() && !proposal.is_pending(now);
+        let (status, closed_at) = if expired {
+            (ProposalStatus::Expired, Some(proposal.expires_at))
+        } else {
+            (proposal.status.clone(), proposal.closed_at)
+        };
+        ProposalHistoryItem {
+            id: proposal.id,
+            proposer: proposal.proposer,
+            created_at: proposal.created_at,
+            closed_at,
+            payload: hex::encode(&proposal.payload),
+            kind: proposal.kind.clone(),
+            account: proposal.account,
+            status,
+            approvals: proposal.approvals.iter().cloned().collect(),
+            rejections: proposal.rejections.iter().cloned().collect(),
+            signature: proposal.signature.as_ref().map(hex::encode),
+            signed_transaction: proposal.signed_transaction.clone(),
+            metadata: proposal.metadata.clone(),
+        }
+    }
+}
+
+/// A page of the proposal history.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct ProposalHistoryPage {
+    /// The proposals on this page, newest first.
+    pub items: Vec<ProposalHistoryItem>,
+    /// The cursor to pass to fetch the next page, `None` if this is the last page.
+    pub next_cursor: Option<ProposalId>,
+}
+
+impl Proposal {
+    /// Returns whether the proposal is still pending at the given time.
+    ///
+    /// * `now` - The current time in nanoseconds since the epoch.
+    pub fn is_pending(&self, now: u64) -> bool {
+        self.status.is_pending() && now < self.expires_at
+    }
+
+    /// Returns the account whose key signs the proposal.
+    pub fn signing_account(&self) -> AccountIndex {
+        self.account.unwrap_or(DEFAULT_ACCOUNT)
+    }
 }
 
 /// A trait defining the behaviors of a MultiSignature Wallet.
@@ -23,8 +368,12 @@ pub trait MultiSignatureWallet {
 
     /// Remove an existing signer from the wallet.
     ///
+    /// Fails if the remaining signers could no longer meet the threshold.
+    ///
     /// * `signer` - The `Principal` of the signer to remove.
-    fn remove_signer(&mut self, signer: Principal);
+    ///
+    /// Returns `Result<(), WalletError>` indicating success or the type of failure.
+    fn remove_signer(&mut self, signer: Principal) -> Result<(), WalletError>;
 
     /// Get a list of all current signers of the wallet.
     ///
@@ -33,7 +382,7 @@ pub trait MultiSignatureWallet {
 
     /// Set the default threshold for signing.
     ///
-    /// * `threshold` - The threshold as a `u8` value.
+    /// * `threshold` - The threshold as a `u8` value. Must be at least one and at most the number of signers.
     ///
     /// Returns `Result<(), WalletError>` indicating success or the type of failure.
     fn set_default_threshold(&mut self, threshold: u8) -> Result<(), WalletError>;
@@ -45,91 +394,319 @@ pub trait MultiSignatureWallet {
     /// Returns `bool` indicating whether the signer is present.
     fn has_signer(&self, signer: Principal) -> bool;
 
-    /// Get the current default threshold for signing.
+    /// Propose a new action for the wallet. Payloads are signed by the default account.
+    ///
+    /// * `caller` - The `Principal` proposing the action.
+    /// * `payload` - The payload to sign as a `Vec<u8>`. Empty for configuration changes.
+    /// * `kind` - The `ProposalKind` to perform on execution.
+    /// * `now` - The current time in nanoseconds since the epoch.
     ///
-    /// Returns the threshold as a `u8` value.
-    fn get_default_threshold(&self) -> u8;
+    /// Returns `Result<ProposalId, WalletError>` containing the ID of the new proposal or the type of failure.
+    fn propose_with_kind(
+        &mut self,
+        caller: Principal,
+        payload: Vec<u8>,
+        kind: ProposalKind,
+        now: u64,
+    ) -> Result<ProposalId, WalletError>;
 
-    /// Propose a new message for signing.
+    /// Propose a new payload to be signed by one of the wallet's accounts.
     ///
-    /// * `caller` - The `Principal` proposing the message.
-    /// * `msg` - The message as a `Vec<u8>`.
+    /// * `caller` - The `Principal` proposing the action.
+    /// * `account` - The `AccountIndex` of the account whose key signs the payload.
+    /// * `payload` - The payload to sign as a `Vec<u8>`.
+    /// * `kind` - The `ProposalKind` to perform on execution.
+    /// * `now` - The current time in nanoseconds since the epoch.
     ///
-    /// Returns `Result<(), WalletError>` indicating success or the type of failure.
-    fn propose_message(&mut self, caller: Principal, msg: Vec<u8>) -> Result<(), WalletError>;
+    /// Returns `Result<ProposalId, WalletError>` containing the ID of the new proposal or the type of failure.
+    fn propose_for_account(
+        &mut self,
+        caller: Principal,
+        account: AccountIndex,
+        payload: Vec<u8>,
+        kind: ProposalKind,
+        now: u64,
+    ) -> Result<ProposalId, WalletError>;
+
+    /// Get a proposal by its ID.
+    ///
+    /// * `id` - The `ProposalId` of the proposal.
+    ///
+    /// Returns `Option<&Proposal>` containing the proposal if it exists.
+    fn get_proposal(&self, id: ProposalId) -> Option<&Proposal>;
+
+    /// Find the oldest pending proposal to sign the given payload.
+    ///
+    /// * `payload` - The payload as a byte slice.
+    /// * `now` - The current time in nanoseconds since the epoch.
+    ///
+    /// Returns `Option<ProposalId>` containing the ID of the proposal if one exists.
+    fn find_proposal_by_payload(&self, payload: &[u8], now: u64) -> Option<ProposalId>;
 
-    /// Check if a message can be signed according to the current rules.
+    /// Check if a proposal can be executed according to the current rules.
     ///
-    /// * `msg` - A reference to the message as a `Vec<u8>`.
+    /// * `id` - The `ProposalId` of the proposal.
+    /// * `now` - The current time in nanoseconds since the epoch.
     ///
-    /// Returns `bool` indicating whether the message can be signed.
-    fn can_sign(&self, msg: &Vec<u8>) -> bool;
+    /// Returns `bool` indicating whether the proposal can be executed.
+    fn can_sign(&self, id: ProposalId, now: u64) -> bool;
 
-    /// Approve a message with a signer's consent.
+    /// Approve a proposal with a signer's consent.
     ///
-    /// * `msg` - The message as a `Vec<u8>`.
-    /// * `signer` - The `Principal` of the signer approving the message.
+    /// * `id` - The `ProposalId` of the proposal.
+    /// * `signer` - The `Principal` of the signer approving the proposal.
+    /// * `now` - The current time in nanoseconds since the epoch.
     ///
     /// Returns `Result<u8, WalletError>` indicating the number of approvals or the type of failure.
-    fn approve(&mut self, msg: Vec<u8>, signer: Principal) -> Result<u8, WalletError>;
+    fn approve(&mut self, id: ProposalId, signer: Principal, now: u64) -> Result<u8, WalletError>;
+
+    /// Withdraw a signer's approval from a pending proposal.
+    ///
+    /// * `id` - The `ProposalId` of the proposal.
+    /// * `signer` - The `Principal` of the signer revoking the approval.
+    /// * `now` - The current time in nanoseconds since the epoch.
+    ///
+    /// Returns `Result<u8, WalletError>` indicating the number of remaining approvals or the type of failure.
+    fn revoke_approval(&mut self, id: ProposalId, signer: Principal, now: u64) -> Result<u8, WalletError>;
 
-    /// Returns all messages that can be signed.
+    /// Reject a proposal on behalf of a signer.
+    ///
+    /// The proposal moves to `ProposalStatus::Rejected` once the signers that have not
+    /// rejected it can no longer reach the threshold.
+    ///
+    /// * `id` - The `ProposalId` of the proposal.
+    /// * `signer` - The `Principal` of the signer rejecting the proposal.
+    /// * `now` - The current time in nanoseconds since the epoch.
+    ///
+    /// Returns `Result<u8, WalletError>` indicating the number of rejections or the type of failure.
+    fn reject(&mut self, id: ProposalId, signer: Principal, now: u64) -> Result<u8, WalletError>;
+
+    /// Returns all proposals that can be executed.
+    ///
+    /// * `now` - The current time in nanoseconds since the epoch.
+    ///
+    /// Returns a `Vec<&Proposal>` containing the proposals that can be executed.
+    fn get_proposals_to_sign(&self, now: u64) -> Vec<&Proposal>;
+
+    /// Returns all pending proposals.
+    ///
+    /// * `now` - The current time in nanoseconds since the epoch.
     ///
-    /// Returns a `Vec<Vec<u8>>` containing the messages that can be signed.
-    fn get_messages_to_sign(&self) -> Vec<Vec<u8>>;
+    /// Returns a `Vec<&Proposal>` containing the proposals ordered by their ID.
+    fn get_proposals(&self, now: u64) -> Vec<&Proposal>;
 
-    /// Returns all messages that have been proposed.
+    /// Returns a page of proposals that are no longer pending, newest first. Proposals that have expired by `now`
+    /// are included even if `expire_proposals` has not recorded it yet.
     ///
-    /// Returns a `Vec<Vec<u8>>` containing the messages that have been proposed.
-    fn get_proposed_messages(&self) -> Vec<Vec<u8>>;
+    /// * `cursor` - Only proposals with an ID lower than the cursor are returned. `None` starts at the newest.
+    /// * `limit` - The maximum number of proposals to return.
+    /// * `now` - The current time in nanoseconds since the epoch.
+    ///
+    /// Returns a `Vec<&Proposal>` containing the proposals ordered by descending ID.
+    fn get_history(&self, cursor: Option<ProposalId>, limit: usize, now: u64) -> Vec<&Proposal>;
 
-    /// Returns all messages that have been proposed with their signers.
+    /// Move all pending proposals that have expired to `ProposalStatus::Expired`.
     ///
-    /// Returns a `Vec<(Vec<u8>, Vec<Principal>)>` containing the messages that have been proposed with their signers.
-    fn get_messages_with_signers(&self) -> Vec<(Vec<u8>, Vec<Principal>)>;
+    /// * `now` - The current time in nanoseconds since the epoch.
+    fn expire_proposals(&mut self, now: u64);
 
-    /// Add metadata to a message in the wallet.
+    /// Add metadata to a proposal in the wallet.
     ///
-    /// * `message` - The message as a `Vec<u8>`.
+    /// * `id` - The `ProposalId` of the proposal.
     /// * `metadata` - The metadata as a `String`.
     /// * `caller` - The `Principal` of the caller.
     ///
-    /// Returns `Result<(), String>` indicating success or the type of failure.
+    /// Returns `Result<(), WalletError>` indicating success or the type of failure.
     fn add_metadata(
         &mut self,
-        message: Vec<u8>,
+        id: ProposalId,
         metadata: String,
         caller: Principal,
-    ) -> Result<(), String>;
+    ) -> Result<(), WalletError>;
 
-    /// Get the metadata associated with a message in the wallet.
+    /// Get the metadata associated with a proposal in the wallet.
     ///
-    /// * `message` - The message as a `Vec<u8>`.
+    /// * `id` - The `ProposalId` of the proposal.
     /// * `caller` - The `Principal` of the caller.
     ///
     /// Returns `Option<&String>` containing the metadata if it exists.
-    fn get_metadata(&self, message: Vec<u8>, caller: Principal) -> Option<&String>;
+    fn get_metadata(&self, id: ProposalId, caller: Principal) -> Option<&String>;
+
+    /// Propose registering the ABI of a contract that has none. Like replacing or removing a registered ABI, the
+    /// registration takes effect once the `RegisterAbi` proposal is executed.
+    ///
+    /// * `abi` - The parsed `ContractAbi`.
+    /// * `caller` - The `Principal` of the caller.
+    /// * `now` - The current time in nanoseconds since the epoch.
+    ///
+    /// Returns `Result<ProposalId, WalletError>` containing the ID of the new proposal or the type of failure.
+    fn register_abi(&mut self, abi: ContractAbi, caller: Principal, now: u64) -> Result<ProposalId, WalletError>;
+
+    /// Get the ABI registered for a contract.
+    ///
+    /// * `address` - The address of the contract in any case.
+    ///
+    /// Returns `Option<&ContractAbi>` containing the ABI if one is registered.
+    fn get_abi(&self, address: &str) -> Option<&ContractAbi>;
+
+    /// Get all registered ABIs.
+    ///
+    /// Returns a `Vec<&ContractAbi>` ordered by contract address.
+    fn get_abis(&self) -> Vec<&ContractAbi>;
+
+    /// Add a named account with its own derived keys. Only signers may add accounts.
+    ///
+    /// * `name` - The name of the account, unique within the wallet.
+    /// * `caller` - The `Principal` of the caller.
+    ///
+    /// Returns `Result<AccountIndex, WalletError>` containing the index of the new account or the type of failure.
+    fn add_account(&mut self, name: String, caller: Principal) -> Result<AccountIndex, WalletError>;
+
+    /// Get all accounts of the wallet.
+    ///
+    /// Returns a `Vec<AccountInfo>` ordered by account index.
+    fn get_accounts(&self) -> Vec<AccountInfo>;
+
+    /// Resolve an account by its index or name.
+    ///
+    /// * `account` - The `AccountRef` selecting the account.
+    ///
+    /// Returns `Result<AccountIndex, WalletError>` containing the index of the account or the type of failure.
+    fn resolve_account(&self, account: &AccountRef) -> Result<AccountIndex, WalletError>;
+
+    /// Get the nonce to assign to the next transaction an account proposes on a chain.
+    ///
+    /// This is the lowest nonce at or above the account's next nonce that is not reserved by a pending
+    /// transaction proposal, so nonces freed by cancelled, rejected or expired proposals are reused.
+    ///
+    /// * `account` - The `AccountIndex` of the account.
+    /// * `chain_id` - The EIP-155 chain ID.
+    /// * `now` - The current time in nanoseconds since the epoch.
+    ///
+    /// Returns the nonce as a `u64`.
+    fn next_nonce(&self, account: AccountIndex, chain_id: u64, now: u64) -> u64;
+
+    /// Set the account's next nonce on a chain, e.g. after transactions were sent outside the wallet or dropped.
+    ///
+    /// * `account` - The `AccountIndex` of the account.
+    /// * `chain_id` - The EIP-155 chain ID.
+    /// * `nonce` - The account's transaction count on the chain.
+    ///
+    /// Returns `Result<(), WalletError>` indicating success or the type of failure.
+    fn resync_nonce(&mut self, account: AccountIndex, chain_id: u64, nonce: u64) -> Result<(), WalletError>;
+
+    /// Get the cached public key of an account.
+    ///
+    /// * `account` - The `AccountIndex` of the account.
+    /// * `key_id` - The threshold key currently in use. A key cached for another key ID is stale and not returned.
+    ///
+    /// Returns `Option<&WalletPublicKey>` containing the public key if it is cached.
+    fn get_public_key(&self, account: AccountIndex, key_id: &EcdsaKeyId) -> Option<&WalletPublicKey>;
+
+    /// Cache the public key of an account, replacing a key derived from another key ID.
+    ///
+    /// * `account` - The `AccountIndex` of the account.
+    /// * `public_key` - The `WalletPublicKey` derived from the threshold key.
+    fn set_public_key(&mut self, account: AccountIndex, public_key: WalletPublicKey);
+
+    /// Get the cached Schnorr public key of an account.
+    ///
+    /// * `account` - The `AccountIndex` of the account.
+    /// * `key_id` - The threshold Schnorr key currently in use for the algorithm.
+    ///
+    /// Returns `Option<&SchnorrPublicKey>` containing the public key if it is cached.
+    fn get_schnorr_public_key(&self, account: AccountIndex, key_id: &SchnorrKeyId) -> Option<&SchnorrPublicKey>;
+
+    /// Cache a Schnorr public key of an account, replacing a key derived for the same algorithm.
+    ///
+    /// * `account` - The `AccountIndex` of the account.
+    /// * `public_key` - The `SchnorrPublicKey` derived from the threshold key.
+    fn set_schnorr_public_key(&mut self, account: AccountIndex, public_key: SchnorrPublicKey);
+
+    /// Get the threshold ECDSA key the wallet signs with.
+    ///
+    /// Returns `Option<&EcdsaKeyId>` containing the key, `None` if the wallet was created without one.
+    fn get_key_id(&self) -> Option<&EcdsaKeyId>;
 
+    /// Get the key the wallet signs with and the keys it has migrated away from.
+    ///
+    /// Returns `Option<KeyInfo>` containing the key information, `None` if the wallet was created without a key.
+    fn get_key_info(&self) -> Option<KeyInfo>;
+
+    /// Move the wallet to another threshold key, as a `ProposalKind::MigrateKey` proposal does once executed.
+    ///
+    /// Every address changes with the key, so the cached keys and nonces of all accounts are dropped and pending
+    /// proposals that sign payloads are rejected. The wallet never signs with the old key again.
+    ///
+    /// * `public_key` - The public key of the default account, derived from the new key.
+    /// * `now` - The current time in nanoseconds since the epoch.
+    ///
+    /// Returns `Result<(), WalletError>` indicating success or the type of failure.
+    fn migrate_key(&mut self, public_key: WalletPublicKey, now: u64) -> Result<(), WalletError>;
+
+    /// Record that a proposal has been executed. Executing a transaction advances the account's next nonce on its chain.
+    ///
+    /// * `id` - The `ProposalId` of the proposal.
+    /// * `signature` - The signature produced for payload proposals.
+    /// * `signed_transaction` - The base64 encoded finalized PSBT produced for Bitcoin and Taproot transactions, or the
+    ///   signed transaction produced for Solana transactions.
+    /// * `now` - The current time in nanoseconds since the epoch.
+    ///
+    /// Returns `Result<(), WalletError>` indicating success or the type of failure.
+    fn mark_executed(
+        &mut self,
+        id: ProposalId,
+        signature: Option<Vec<u8>>,
+        signed_transaction: Option<String>,
+        now: u64,
+    ) -> Result<(), WalletError>;
 
-    /// Remove a message and its metadata from the wallet.
+    /// Cancel a pending proposal. Only the proposer may cancel.
     ///
-    /// * `msg` - The message as a `Vec<u8>`.
+    /// * `id` - The `ProposalId` of the proposal.
     /// * `caller` - The `Principal` of the caller.
+    /// * `now` - The current time in nanoseconds since the epoch.
+    ///
+    /// Returns `Result<(), WalletError>` indicating success or the type of failure.
+    fn cancel(&mut self, id: ProposalId, caller: Principal, now: u64) -> Result<(), WalletError>;
+
+    /// Apply the configuration change of a pending proposal and record the proposal as executed.
     ///
-    /// Returns `Result<(), String>` indicating success or the type of failure.
-    fn remove_message_and_metadata(&mut self, msg: Vec<u8>, caller: Principal) -> Result<(), String>;
+    /// * `id` - The `ProposalId` of the proposal. Proposals that sign payloads are rejected, and so are
+    ///   `ProposalKind::MigrateKey` proposals, which need the derived key and are applied with `migrate_key`.
+    /// * `now` - The current time in nanoseconds since the epoch.
+    ///
+    /// Returns `Result<(), WalletError>` indicating success or the type of failure.
+    fn execute_config_change(&mut self, id: ProposalId, now: u64) -> Result<(), WalletError>;
 }
 
-#[derive(Clone, Debug, CandidType, Deserialize)]
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
 pub struct Wallet {
     /// A set of signers for the wallet, represented by their `Principal`.
     signers: HashSet<Principal>,
     /// The threshold number of signers required for certain actions.
     threshold: u8,
-    /// A map tracking messages and the list of signers who have already signed them.
-    message_queue: HashMap<Vec<u8>, Vec<Principal>>,
-    /// A map tracking messages and their metadata.
-    metadata: HashMap<Vec<u8>, String>,
+    /// A map tracking pending and past proposals by their ID.
+    proposals: BTreeMap<ProposalId, Proposal>,
+    /// The ID assigned to the next proposal.
+    next_proposal_id: ProposalId,
+    /// The contract ABIs used to decode proposed calls, keyed by lowercase contract address.
+    /// `None` for wallets restored from a snapshot that predates the registry.
+    abis: Option<BTreeMap<String, ContractAbi>>,
+    /// The accounts of the wallet, indexed by their `AccountIndex`.
+    /// `None` for wallets restored from a snapshot that predates accounts, until `migrate_accounts` runs.
+    accounts: Option<Vec<Account>>,
+    /// The nonces of a snapshot that predates accounts, moved to the default account by `migrate_accounts`.
+    nonces: Option<BTreeMap<u64, u64>>,
+    /// The public key of a snapshot that predates accounts, moved to the default account by `migrate_accounts`.
+    public_key: Option<WalletPublicKey>,
+    /// The Schnorr public keys of a snapshot that predates accounts, moved to the default account by `migrate_accounts`.
+    schnorr_public_keys: Option<Vec<SchnorrPublicKey>>,
+    /// The threshold ECDSA key the wallet signs with. Its name also selects the threshold Schnorr keys.
+    /// `None` for wallets restored from a snapshot that predates per-wallet keys, until `migrate_key_id` runs.
+    key_id: Option<EcdsaKeyId>,
+    /// The completed key migrations, oldest first. `None` for wallets restored from a snapshot that predates them.
+    key_migrations: Option<Vec<KeyMigration>>,
 }
 
 impl Default for Wallet {
@@ -137,8 +714,305 @@ impl Default for Wallet {
         Wallet {
             signers: HashSet::new(),
             threshold: 0,
-            message_queue: HashMap::new(),
-            metadata: HashMap::new(),
+            proposals: BTreeMap::new(),
+            next_proposal_id: 0,
+            abis: Some(BTreeMap::new()),
+            accounts: Some(vec![Account::new(DEFAULT_ACCOUNT_NAME.to_string())]),
+            nonces: None,
+            public_key: None,
+            schnorr_public_keys: None,
+            key_id: None,
+            key_migrations: Some(Vec::new()),
+        }
+    }
+}
+
+impl Wallet {
+    /// Rebuild a wallet from the parts of a snapshot that predates the current layout.
+    ///
+    /// The statuses of pending proposals are re-evaluated against the signers and threshold.
+    ///
+    /// * `signers` - The signers of the wallet.
+    /// * `threshold` - The number of approvals required for signing.
+    /// * `proposals` - The proposals of the wallet, keyed by their ID.
+    /// * `next_proposal_id` - The ID assigned to the next proposal.
+    pub fn restore(
+        signers: HashSet<Principal>,
+        threshold: u8,
+        proposals: BTreeMap<ProposalId, Proposal>,
+        next_proposal_id: ProposalId,
+    ) -> Self {
+        let mut wallet = Wallet {
+            signers,
+            threshold,
+            proposals,
+            next_proposal_id,
+            abis: None,
+            accounts: None,
+            key_migrations: None,
+            ..Wallet::default()
+        };
+        wallet.refresh_statuses();
+        wallet
+    }
+
+    /// Returns a copy of the wallet that only holds its pending proposals. Finished proposals are paged by
+    /// `get_history` instead.
+    ///
+    /// * `now` - The current time in nanoseconds since the epoch.
+    pub fn with_pending_proposals(&self, now: u64) -> Wallet {
+        Wallet {
+            signers: self.signers.clone(),
+            threshold: self.threshold,
+            proposals: self.proposals
+                .iter()
+                .filter(|(_, proposal)| proposal.is_pending(now))
+                .map(|(id, proposal)| (*id, proposal.clone()))
+                .collect(),
+            next_proposal_id: self.next_proposal_id,
+            abis: self.abis.clone(),
+            accounts: self.accounts.clone(),
+            nonces: self.nonces.clone(),
+            public_key: self.public_key.clone(),
+            schnorr_public_keys: self.schnorr_public_keys.clone(),
+            key_id: self.key_id.clone(),
+            key_migrations: self.key_migrations.clone(),
+        }
+    }
+
+    /// Create an empty wallet that signs with the given threshold key.
+    ///
+    /// * `key_id` - The threshold ECDSA key of the wallet.
+    pub fn with_key_id(key_id: EcdsaKeyId) -> Self {
+        Wallet { key_id: Some(key_id), ..Wallet::default() }
+    }
+
+    /// Record the threshold key of a wallet restored from a snapshot that predates per-wallet keys.
+    ///
+    /// The key the default account's public key was derived from is kept if it is cached, as it is the key the
+    /// wallet has been signing with. Does nothing if the wallet already has a key.
+    ///
+    /// * `key_id` - The key to record if no public key is cached.
+    pub fn migrate_key_id(&mut self, key_id: &EcdsaKeyId) {
+        if self.key_id.is_some() {
+            return;
+        }
+        let cached = self.account(DEFAULT_ACCOUNT).and_then(|account| account.public_key.as_ref());
+        self.key_id = Some(cached.map_or(key_id, |public_key| &public_key.key_id).clone());
+    }
+
+    /// Move the keys and nonces of a wallet restored from a snapshot that predates accounts to its default account.
+    ///
+    /// Does nothing if the wallet already has accounts.
+    pub fn migrate_accounts(&mut self) {
+        if self.accounts.is_some() {
+            return;
+        }
+        let mut account = Account::new(DEFAULT_ACCOUNT_NAME.to_string());
+        account.public_key = self.public_key.take();
+        account.schnorr_public_keys = self.schnorr_public_keys.take().unwrap_or_default();
+        account.nonces = self.nonces.take().unwrap_or_default();
+        self.accounts = Some(vec![account]);
+    }
+
+    /// Get an account by its index.
+    ///
+    /// * `account` - The `AccountIndex` of the account.
+    fn account(&self, account: AccountIndex) -> Option<&Account> {
+        self.accounts.as_ref()?.get(account as usize)
+    }
+
+    /// Get an account by its index for modification.
+    ///
+    /// * `account` - The `AccountIndex` of the account.
+    fn account_mut(&mut self, account: AccountIndex) -> Option<&mut Account> {
+        self.accounts.as_mut()?.get_mut(account as usize)
+    }
+
+    /// Queue a new proposal.
+    ///
+    /// * `caller` - The `Principal` proposing the action.
+    /// * `account` - The account whose key signs the proposal, `None` for configuration changes.
+    /// * `payload` - The payload to sign.
+    /// * `kind` - The `ProposalKind` to perform on execution.
+    /// * `now` - The current time in nanoseconds since the epoch.
+    fn insert_proposal(
+        &mut self,
+        caller: Principal,
+        account: Option<AccountIndex>,
+        payload: Vec<u8>,
+        kind: ProposalKind,
+        now: u64,
+    ) -> ProposalId {
+        let id = self.next_proposal_id;
+        self.next_proposal_id += 1;
+
+        let status = if self.threshold == 0 {
+            ProposalStatus::Approved
+        } else {
+            ProposalStatus::Open
+        };
+        self.proposals.insert(id, Proposal {
+            id,
+            proposer: caller,
+            created_at: now,
+            payload,
+            kind,
+            account,
+            approvals: HashSet::new(),
+            rejections: HashSet::new(),
+            status,
+            metadata: None,
+            expires_at: now.saturating_add(DEFAULT_PROPOSAL_TTL),
+            closed_at: None,
+            signature: None,
+            signed_transaction: None,
+        });
+        self.prune_history();
+        id
+    }
+
+    /// Drop the oldest proposals that are no longer pending until at most `MAX_RETAINED_HISTORY` of them remain.
+    fn prune_history(&mut self) {
+        let closed: Vec<ProposalId> = self.proposals
+            .values()
+            .filter(|proposal| !proposal.status.is_pending())
+            .map(|proposal| proposal.id)
+            .collect();
+        for id in closed.iter().take(closed.len().saturating_sub(MAX_RETAINED_HISTORY)) {
+            self.proposals.remove(id);
+        }
+    }
+
+    /// Count the approvals of a proposal that were given by current signers.
+    ///
+    /// * `signers` - The current signers of the wallet.
+    /// * `proposal` - The proposal to count the approvals of.
+    fn live_approvals(signers: &HashSet<Principal>, proposal: &Proposal) -> usize {
+        proposal.approvals.iter().filter(|approver| signers.contains(approver)).count()
+    }
+
+    /// Drop the votes of a former signer from all pending proposals.
+    ///
+    /// * `signer` - The `Principal` of the removed signer.
+    fn purge_votes(&mut self, signer: &Principal) {
+        for proposal in self.proposals.values_mut().filter(|proposal| proposal.status.is_pending()) {
+            proposal.approvals.remove(signer);
+            proposal.rejections.remove(signer);
+        }
+    }
+
+    /// Check that a configuration change can be applied to the wallet as it is now.
+    ///
+    /// * `kind` - The `ProposalKind` to check.
+    ///
+    /// Returns `Result<(), WalletError>` indicating success or the reason the change would fail.
+    fn validate_config_change(&self, kind: &ProposalKind) -> Result<(), WalletError> {
+        match kind {
+            ProposalKind::AddSigner(signer) => {
+                if self.has_signer(*signer) {
+                    return Err(WalletError::SignerAlreadyExists);
+                }
+                Ok(())
+            }
+            ProposalKind::RemoveSigner(signer) => {
+                if !self.has_signer(*signer) {
+                    return Err(WalletError::SignerNotFound);
+                }
+                if self.signers.len() - 1 < self.threshold as usize {
+                    return Err(WalletError::NotEnoughSigners);
+                }
+                Ok(())
+            }
+            ProposalKind::SetThreshold(threshold) => {
+                if *threshold == 0 {
+                    return Err(WalletError::InvalidThreshold);
+                }
+                if self.signers.len() < *threshold as usize {
+                    return Err(WalletError::NotEnoughSigners);
+                }
+                Ok(())
+            }
+            ProposalKind::MigrateKey(name) => {
+                if name.trim().is_empty() {
+                    return Err(WalletError::InvalidKeyName);
+                }
+                if self.key_id.as_ref().is_some_and(|key_id| key_id.name == *name) {
+                    return Err(WalletError::KeyAlreadyInUse);
+                }
+                if self.key_migrations.iter().flatten().any(|migration| migration.from_key == *name) {
+                    return Err(WalletError::KeyRetired);
+                }
+                Ok(())
+            }
+            ProposalKind::RegisterAbi(_) => Ok(()),
+            ProposalKind::ResyncNonce(resync) => {
+                self.account(resync.account).ok_or(WalletError::AccountNotFound)?;
+                Ok(())
+            }
+            ProposalKind::RemoveAbi(address) => {
+                if self.get_abi(address).is_none() {
+                    return Err(WalletError::AbiNotFound);
+                }
+                Ok(())
+            }
+            _ => Err(WalletError::NotAConfigChange),
+        }
+    }
+
+    /// Re-evaluate the status of all pending proposals against the current signers and threshold.
+    fn refresh_statuses(&mut self) {
+        let threshold = self.threshold as usize;
+        for proposal in self.proposals.values_mut().filter(|proposal| proposal.status.is_pending()) {
+            proposal.status = if Self::live_approvals(&self.signers, proposal) >= threshold {
+                ProposalStatus::Approved
+            } else {
+                ProposalStatus::Open
+            };
+        }
+    }
+
+    /// Apply a configuration change to the wallet and reject the pending proposals it makes unreachable.
+    ///
+    /// * `kind` - The `ProposalKind` to apply. `ProposalKind::SignPayload` is rejected, and so is
+    ///   `ProposalKind::MigrateKey`, which needs the derived key and is applied with `migrate_key`.
+    /// * `now` - The current time in nanoseconds since the epoch.
+    ///
+    /// Returns `Result<(), WalletError>` indicating success or the type of failure.
+    fn apply_config_change(&mut self, kind: &ProposalKind, now: u64) -> Result<(), WalletError> {
+        self.validate_config_change(kind)?;
+        match kind {
+            ProposalKind::AddSigner(signer) => self.add_signer(*signer),
+            ProposalKind::RemoveSigner(signer) => self.remove_signer(*signer)?,
+            ProposalKind::SetThreshold(threshold) => self.set_default_threshold(*threshold)?,
+            ProposalKind::RegisterAbi(abi) => {
+                self.abis.get_or_insert_with(BTreeMap::new).insert(abi.address.to_lowercase(), abi.clone());
+            }
+            ProposalKind::RemoveAbi(address) => {
+                self.abis.as_mut().and_then(|abis| abis.remove(&address.to_lowercase()));
+            }
+            ProposalKind::ResyncNonce(resync) => self.resync_nonce(resync.account, resync.chain_id, resync.nonce)?,
+            _ => return Err(WalletError::NotAConfigChange),
+        }
+        // a smaller signer set or a higher threshold can make pending proposals unreachable
+        self.reject_unreachable(now);
+        Ok(())
+    }
+
+    /// Reject all pending proposals that can no longer reach the threshold.
+    ///
+    /// * `now` - The current time in nanoseconds since the epoch.
+    fn reject_unreachable(&mut self, now: u64) {
+        let threshold = self.threshold as usize;
+        for proposal in self.proposals.values_mut().filter(|proposal| proposal.status.is_pending()) {
+            let reachable = self.signers
+                .iter()
+                .filter(|signer| !proposal.rejections.contains(signer))
+                .count();
+            if reachable < threshold {
+                proposal.status = ProposalStatus::Rejected;
+                proposal.closed_at = Some(now);
+            }
         }
     }
 }
@@ -148,8 +1022,17 @@ impl MultiSignatureWallet for Wallet {
         self.signers.insert(signer);
     }
 
-    fn remove_signer(&mut self, signer: Principal) {
+    fn remove_signer(&mut self, signer: Principal) -> Result<(), WalletError> {
+        if !self.has_signer(signer) {
+            return Err(WalletError::SignerNotFound);
+        }
+        if self.signers.len() - 1 < self.threshold as usize {
+            return Err(WalletError::NotEnoughSigners);
+        }
         self.signers.remove(&signer);
+        self.purge_votes(&signer);
+        self.refresh_statuses();
+        Ok(())
     }
 
     fn get_signers(&self) -> Vec<Principal> {
@@ -157,10 +1040,14 @@ impl MultiSignatureWallet for Wallet {
     }
 
     fn set_default_threshold(&mut self, threshold: u8) -> Result<(), WalletError> {
+        if threshold == 0 {
+            return Err(WalletError::InvalidThreshold);
+        }
         if self.signers.len() < threshold as usize {
             return Err(WalletError::NotEnoughSigners);
         }
         self.threshold = threshold;
+        self.refresh_statuses();
         Ok(())
     }
 
@@ -168,119 +1055,405 @@ impl MultiSignatureWallet for Wallet {
         self.signers.contains(&signer)
     }
 
-    fn get_default_threshold(&self) -> u8 {
-        self.threshold
+    fn propose_with_kind(
+        &mut self,
+        caller: Principal,
+        payload: Vec<u8>,
+        kind: ProposalKind,
+        now: u64,
+    ) -> Result<ProposalId, WalletError> {
+        if !self.has_signer(caller) {
+            return Err(WalletError::InvalidSignature);
+        }
+
+        if kind.is_config_change() {
+            // refuse configuration changes that could never be executed
+            self.validate_config_change(&kind)?;
+            return Ok(self.insert_proposal(caller, None, payload, kind, now));
+        }
+
+        Ok(self.insert_proposal(caller, Some(DEFAULT_ACCOUNT), payload, kind, now))
+    }
+
+    fn propose_for_account(
+        &mut self,
+        caller: Principal,
+        account: AccountIndex,
+        payload: Vec<u8>,
+        kind: ProposalKind,
+        now: u64,
+    ) -> Result<ProposalId, WalletError> {
+        if !self.has_signer(caller) {
+            return Err(WalletError::InvalidSignature);
+        }
+        if kind.is_config_change() {
+            return Err(WalletError::NotAConfigChange);
+        }
+        if self.account(account).is_none() {
+            return Err(WalletError::AccountNotFound);
+        }
+
+        Ok(self.insert_proposal(caller, Some(account), payload, kind, now))
+    }
+
+    fn get_proposal(&self, id: ProposalId) -> Option<&Proposal> {
+        self.proposals.get(&id)
+    }
+
+    fn find_proposal_by_payload(&self, payload: &[u8], now: u64) -> Option<ProposalId> {
+        self.proposals
+            .values()
+            .filter(|proposal| proposal.is_pending(now))
+            .find(|proposal| proposal.kind == ProposalKind::SignPayload && proposal.payload == payload)
+            .map(|proposal| proposal.id)
     }
 
-    fn propose_message(&mut self, caller: Principal, msg: Vec<u8>) -> Result<(), WalletError> {
-        if !self.signers.contains(&caller) {
+    fn can_sign(&self, id: ProposalId, now: u64) -> bool {
+        self.proposals
+            .get(&id)
+            .is_some_and(|proposal| {
+                proposal.is_pending(now) && proposal.status == ProposalStatus::Approved
+            })
+    }
+
+    fn approve(&mut self, id: ProposalId, signer: Principal, now: u64) -> Result<u8, WalletError> {
+        if !self.proposals.contains_key(&id) {
+            return Err(WalletError::ProposalNotFound);
+        }
+
+        if !self.has_signer(signer) {
             return Err(WalletError::InvalidSignature);
         }
 
-        if self.message_queue.contains_key(&msg) {
-            return Err(WalletError::MsgAlreadyQueued);
+        self.expire_proposals(now);
+
+        let proposal = self.proposals.get_mut(&id).ok_or(WalletError::ProposalNotFound)?;
+        if !proposal.status.is_pending() {
+            return Err(WalletError::ProposalNotPending);
+        }
+        if !proposal.approvals.insert(signer) {
+            return Err(WalletError::AlreadyApproved);
         }
+        proposal.rejections.remove(&signer);
+        let approvals = Self::live_approvals(&self.signers, proposal);
 
-        self.message_queue.insert(msg.clone(), Vec::new());
+        self.refresh_statuses();
 
-        Ok(())
+        Ok(approvals as u8)
     }
 
-    fn can_sign(&self, msg: &Vec<u8>) -> bool {
-        if !self.message_queue.contains_key(msg) {
-            return false;
+    fn revoke_approval(&mut self, id: ProposalId, signer: Principal, now: u64) -> Result<u8, WalletError> {
+        self.expire_proposals(now);
+
+        let proposal = self.proposals.get_mut(&id).ok_or(WalletError::ProposalNotFound)?;
+        if !proposal.status.is_pending() {
+            return Err(WalletError::ProposalNotPending);
         }
-        self.message_queue[msg].len() >= self.threshold as usize
+        if !proposal.approvals.remove(&signer) {
+            return Err(WalletError::NotApproved);
+        }
+        let approvals = Self::live_approvals(&self.signers, proposal);
+
+        self.refresh_statuses();
+
+        Ok(approvals as u8)
     }
 
-    fn approve(&mut self, msg: Vec<u8>, signer: Principal) -> Result<u8, WalletError> {
-        if !self.message_queue.contains_key(&msg) {
-            return Err(WalletError::MsgNotQueued);
+    fn reject(&mut self, id: ProposalId, signer: Principal, now: u64) -> Result<u8, WalletError> {
+        if !self.proposals.contains_key(&id) {
+            return Err(WalletError::ProposalNotFound);
         }
 
-        if !self.signers.contains(&signer) {
+        if !self.has_signer(signer) {
             return Err(WalletError::InvalidSignature);
         }
 
-        let queue = self.message_queue.get_mut(&msg).unwrap();
-        queue.push(signer);
+        self.expire_proposals(now);
+
+        let proposal = self.proposals.get_mut(&id).ok_or(WalletError::ProposalNotFound)?;
+        if !proposal.status.is_pending() {
+            return Err(WalletError::ProposalNotPending);
+        }
+        if !proposal.rejections.insert(signer) {
+            return Err(WalletError::AlreadyRejected);
+        }
+        proposal.approvals.remove(&signer);
+        let rejections = proposal.rejections.len() as u8;
+
+        self.refresh_statuses();
+        self.reject_unreachable(now);
 
-        Ok(queue.len() as u8)
+        Ok(rejections)
     }
 
-    fn get_messages_to_sign(&self) -> Vec<Vec<u8>> {
-        self.message_queue
-            .iter()
-            .filter(|(msg, _)| self.can_sign(msg))
-            .map(|(msg, _)| msg.clone())
+    fn get_proposals_to_sign(&self, now: u64) -> Vec<&Proposal> {
+        self.proposals
+            .values()
+            .filter(|proposal| self.can_sign(proposal.id, now))
             .collect()
     }
 
-    fn get_proposed_messages(&self) -> Vec<Vec<u8>> {
-        self.message_queue
-            .iter()
-            .map(|(msg, _)| msg.clone())
+    fn get_proposals(&self, now: u64) -> Vec<&Proposal> {
+        self.proposals
+            .values()
+            .filter(|proposal| proposal.is_pending(now))
             .collect()
     }
 
-    fn get_messages_with_signers(&self) -> Vec<(Vec<u8>, Vec<Principal>)> {
-        self.message_queue
-            .iter()
-            .map(|(msg, signers)| (msg.clone(), signers.clone()))
+    fn get_history(&self, cursor: Option<ProposalId>, limit: usize, now: u64) -> Vec<&Proposal> {
+        let upper = cursor.unwrap_or(ProposalId::MAX);
+        self.proposals
+            .range(..upper)
+            .rev()
+            .map(|(_, proposal)| proposal)
+            .filter(|proposal| !proposal.is_pending(now))
+            .take(limit)
             .collect()
     }
 
+    fn expire_proposals(&mut self, now: u64) {
+        for proposal in self.proposals.values_mut() {
+            if proposal.status.is_pending() && now >= proposal.expires_at {
+                proposal.status = ProposalStatus::Expired;
+                proposal.closed_at = Some(proposal.expires_at);
+            }
+        }
+    }
+
     fn add_metadata(
         &mut self,
-        message: Vec<u8>,
+        id: ProposalId,
         metadata: String,
         caller: Principal,
-    ) -> Result<(), String> {
-        if !self.signers.contains(&caller) {
-            return Err("Cannot add metadata: No signer.".to_string());
-        }
-        if !self.message_queue.contains_key(&message) {
-            return Err("Cannot add metadata: Message not found.".to_string());
+    ) -> Result<(), WalletError> {
+        if !self.has_signer(caller) {
+            return Err(WalletError::InvalidSignature);
         }
-        if self.metadata.contains_key(&message) {
-            return Err("Metadata already exists for this message".to_string())
+        let proposal = self.proposals
+            .get_mut(&id)
+            .ok_or(WalletError::ProposalNotFound)?;
+        if proposal.metadata.is_some() {
+            return Err(WalletError::MetadataAlreadyExists)
         }
-        self.metadata.insert(message, metadata);
+        proposal.metadata = Some(metadata);
         Ok(())
     }
 
-    fn get_metadata(&self, message: Vec<u8>, caller: Principal) -> Option<&String> {
-        if !self.signers.contains(&caller) {
+    fn get_metadata(&self, id: ProposalId, caller: Principal) -> Option<&String> {
+        if !self.has_signer(caller) {
             return None;
         }
-        self.metadata.get(&message)
+        self.proposals.get(&id)?.metadata.as_ref()
     }
 
-    fn remove_message_and_metadata(&mut self, msg: Vec<u8>, caller: Principal) -> Result<(), String> {
-        // Check if the caller is a signer
-        if !self.signers.contains(&caller) {
-            return Err("CallerNotSigner".to_string());
+    fn register_abi(&mut self, abi: ContractAbi, caller: Principal, now: u64) -> Result<ProposalId, WalletError> {
+        if !self.has_signer(caller) {
+            return Err(WalletError::InvalidSignature);
         }
+        if self.get_abi(&abi.address).is_some() {
+            return Err(WalletError::AbiAlreadyRegistered);
+        }
+        self.propose_with_kind(caller, Vec::new(), ProposalKind::RegisterAbi(abi), now)
+    }
 
-        // Remove the message and its metadata
-        self.message_queue.remove(&msg);
-        self.metadata.remove(&msg);
-
-        Ok(())
+    fn get_abi(&self, address: &str) -> Option<&ContractAbi> {
+        self.abis.as_ref()?.get(&address.to_lowercase())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use candid::Principal;
-    use std::str::FromStr;
+    fn get_abis(&self) -> Vec<&ContractAbi> {
+        self.abis.iter().flat_map(|abis| abis.values()).collect()
+    }
+
+    fn add_account(&mut self, name: String, caller: Principal) -> Result<AccountIndex, WalletError> {
+        if !self.has_signer(caller) {
+            return Err(WalletError::InvalidSignature);
+        }
+        if name.trim().is_empty() {
+            return Err(WalletError::InvalidAccountName);
+        }
+        let accounts = self.accounts.get_or_insert_with(Vec::new);
+        if accounts.iter().any(|account| account.name == name) {
+            return Err(WalletError::AccountAlreadyExists);
+        }
+        accounts.push(Account::new(name));
+        Ok((accounts.len() - 1) as AccountIndex)
+    }
+
+    fn get_accounts(&self) -> Vec<AccountInfo> {
+        self.accounts.iter()
+            .flatten()
+            .enumerate()
+            .map(|(index, account)| AccountInfo { index: index as AccountIndex, name: account.name.clone() })
+            .collect()
+    }
+
+    fn resolve_account(&self, account: &AccountRef) -> Result<AccountIndex, WalletError> {
+        let accounts = self.accounts.as_deref().unwrap_or_default();
+        match account {
+            AccountRef::Index(index) if (*index as usize) < accounts.len() => Ok(*index),
+            AccountRef::Index(_) => Err(WalletError::AccountNotFound),
+            AccountRef::Name(name) => accounts.iter()
+                .position(|account| account.name == *name)
+                .map(|index| index as AccountIndex)
+                .ok_or(WalletError::AccountNotFound),
+        }
+    }
+
+    fn next_nonce(&self, account: AccountIndex, chain_id: u64, now: u64) -> u64 {
+        let reserved: BTreeSet<u64> = self.proposals.values()
+            .filter(|proposal| proposal.is_pending(now) && proposal.signing_account() == account)
+            .filter_map(|proposal| proposal.kind.eth_nonce())
+            .filter(|(chain, _)| *chain == chain_id)
+            .map(|(_, nonce)| nonce)
+            .collect();
+        let mut nonce = self.account(account).and_then(|account| account.nonces.get(&chain_id)).copied().unwrap_or(0);
+        while reserved.contains(&nonce) {
+            nonce += 1;
+        }
+        nonce
+    }
+
+    fn resync_nonce(&mut self, account: AccountIndex, chain_id: u64, nonce: u64) -> Result<(), WalletError> {
+        self.account_mut(account).ok_or(WalletError::AccountNotFound)?.nonces.insert(chain_id, nonce);
+        Ok(())
+    }
+
+    fn get_public_key(&self, account: AccountIndex, key_id: &EcdsaKeyId) -> Option<&WalletPublicKey> {
+        self.account(account)?.public_key.as_ref().filter(|public_key| public_key.key_id == *key_id)
+    }
+
+    fn set_public_key(&mut self, account: AccountIndex, public_key: WalletPublicKey) {
+        if let Some(account) = self.account_mut(account) {
+            account.public_key = Some(public_key);
+        }
+    }
+
+    fn get_schnorr_public_key(&self, account: AccountIndex, key_id: &SchnorrKeyId) -> Option<&SchnorrPublicKey> {
+        self.account(account)?.schnorr_public_keys.iter().find(|public_key| public_key.key_id == *key_id)
+    }
+
+    fn set_schnorr_public_key(&mut self, account: AccountIndex, public_key: SchnorrPublicKey) {
+        if let Some(account) = self.account_mut(account) {
+            account.schnorr_public_keys.retain(|cached| cached.key_id.algorithm != public_key.key_id.algorithm);
+            account.schnorr_public_keys.push(public_key);
+        }
+    }
+
+    fn get_key_id(&self) -> Option<&EcdsaKeyId> {
+        self.key_id.as_ref()
+    }
+
+    fn get_key_info(&self) -> Option<KeyInfo> {
+        Some(KeyInfo {
+            key_name: self.key_id.as_ref()?.name.clone(),
+            migrations: self.key_migrations.clone().unwrap_or_default(),
+        })
+    }
+
+    fn migrate_key(&mut self, public_key: WalletPublicKey, now: u64) -> Result<(), WalletError> {
+        self.validate_config_change(&ProposalKind::MigrateKey(public_key.key_id.name.clone()))?;
+
+        let from_key = self.key_id.take().map(|key_id| key_id.name).unwrap_or_default();
+        let from_address = self.account(DEFAULT_ACCOUNT)
+            .and_then(|account| account.public_key.as_ref())
+            .map(|public_key| public_key.eth_address.clone());
+        for account in self.accounts.iter_mut().flatten() {
+            account.public_key = None;
+            account.schnorr_public_keys.clear();
+            account.nonces.clear();
+        }
+        for proposal in self.proposals.values_mut() {
+            if proposal.status.is_pending() && !proposal.kind.is_config_change() {
+                proposal.status = ProposalStatus::Rejected;
+                proposal.closed_at = Some(now);
+            }
+        }
+
+        self.key_migrations.get_or_insert_with(Vec::new).push(KeyMigration {
+            from_key,
+            to_key: public_key.key_id.name.clone(),
+            from_address,
+            to_address: public_key.eth_address.clone(),
+            migrated_at: now,
+        });
+        self.key_id = Some(public_key.key_id.clone());
+        self.set_public_key(DEFAULT_ACCOUNT, public_key);
+        Ok(())
+    }
+
+    fn mark_executed(
+        &mut self,
+        id: ProposalId,
+        signature: Option<Vec<u8>>,
+        signed_transaction: Option<String>,
+        now: u64,
+    ) -> Result<(), WalletError> {
+        let proposal = self.proposals.get_mut(&id).ok_or(WalletError::ProposalNotFound)?;
+        if !proposal.status.is_pending() {
+            return Err(WalletError::ProposalNotPending);
+        }
+        proposal.status = ProposalStatus::Executed;
+        proposal.closed_at = Some(now);
+        proposal.signature = signature;
+        proposal.signed_transaction = signed_transaction;
+        let account = proposal.signing_account();
+        if let Some((chain_id, nonce)) = proposal.kind.eth_nonce() {
+            if let Some(account) = self.account_mut(account) {
+                let next = account.nonces.entry(chain_id).or_insert(0);
+                *next = (*next).max(nonce.saturating_add(1));
+            }
+        }
+        Ok(())
+    }
+
+    fn cancel(&mut self, id: ProposalId, caller: Principal, now: u64) -> Result<(), WalletError> {
+        self.expire_proposals(now);
+
+        let proposal = self.proposals.get_mut(&id).ok_or(WalletError::ProposalNotFound)?;
+        if proposal.proposer != caller {
+            return Err(WalletError::NotProposer);
+        }
+        if !proposal.status.is_pending() {
+            return Err(WalletError::ProposalNotPending);
+        }
+        proposal.status = ProposalStatus::Cancelled;
+        proposal.closed_at = Some(now);
+        Ok(())
+    }
+
+    fn execute_config_change(&mut self, id: ProposalId, now: u64) -> Result<(), WalletError> {
+        let proposal = self.proposals.get_mut(&id).ok_or(WalletError::ProposalNotFound)?;
+        if !proposal.status.is_pending() {
+            return Err(WalletError::ProposalNotPending);
+        }
+        let kind = proposal.kind.clone();
+        // close the proposal first, so that re-checking the pending proposals after the change never rejects it
+        let status = std::mem::replace(&mut proposal.status, ProposalStatus::Executed);
+        proposal.closed_at = Some(now);
+
+        let result = self.apply_config_change(&kind, now);
+        if result.is_err() {
+            if let Some(proposal) = self.proposals.get_mut(&id) {
+                proposal.status = status;
+                proposal.closed_at = None;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eth::EthTransaction;
+    use candid::Principal;
+    use std::str::FromStr;
 
     #[test]
     fn test_default_wallet() {
         let wallet = Wallet::default();
         assert_eq!(wallet.get_signers().len(), 0);
-        assert_eq!(wallet.get_default_threshold(), 0);
+        assert_eq!(wallet.threshold, 0);
     }
 
     #[test]
@@ -290,15 +1463,15 @@ mod tests {
         let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
         let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
 
-        wallet.add_signer(signer1.clone());
-        wallet.add_signer(signer2.clone());
+        wallet.add_signer(signer1);
+        wallet.add_signer(signer2);
 
         let signers = wallet.get_signers();
         assert_eq!(signers.len(), 2);
         assert!(signers.contains(&signer1));
         assert!(signers.contains(&signer2));
 
-        wallet.remove_signer(signer1.clone());
+        wallet.remove_signer(signer1).unwrap();
         let signers = wallet.get_signers();
         assert_eq!(signers.len(), 1);
         assert!(!signers.contains(&signer1));
@@ -309,55 +1482,82 @@ mod tests {
     fn test_set_get_default_threshold() {
         let mut wallet = Wallet::default();
 
-        assert_eq!(wallet.get_default_threshold(), 0);
+        assert_eq!(wallet.threshold, 0);
 
         wallet.add_signer(Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap());
         let _ = wallet.set_default_threshold(1);
-        assert_eq!(wallet.get_default_threshold(), 1);
+        assert_eq!(wallet.threshold, 1);
         assert_eq!(
             wallet.set_default_threshold(2),
             Err(WalletError::NotEnoughSigners)
         );
+        assert_eq!(
+            wallet.set_default_threshold(0),
+            Err(WalletError::InvalidThreshold)
+        );
+        assert_eq!(wallet.threshold, 1);
     }
 
     #[test]
-    fn test_propose_message_valid_signature() {
+    fn test_propose_payload_valid_signature() {
         let mut wallet = Wallet::default();
         let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
-        wallet.add_signer(signer.clone());
+        wallet.add_signer(signer);
 
         let msg = vec![1, 2, 3];
-        let result = wallet.propose_message(signer, msg);
-
-        assert!(result.is_ok());
+        let id = wallet.propose_with_kind(signer, msg.clone(), ProposalKind::SignPayload, 42).unwrap();
+
+        let proposal = wallet.get_proposal(id).unwrap();
+        assert_eq!(proposal.id, id);
+        assert_eq!(proposal.proposer, signer);
+        assert_eq!(proposal.created_at, 42);
+        assert_eq!(proposal.payload, msg);
+        assert_eq!(proposal.kind, ProposalKind::SignPayload);
+        assert!(proposal.approvals.is_empty());
+        assert_eq!(proposal.metadata, None);
     }
 
     #[test]
-    fn test_propose_message_invalid_signature() {
+    fn test_propose_payload_invalid_signature() {
         let mut wallet = Wallet::default();
         let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
         // Don't add the signer to the wallet.
-        // wallet.add_signer(signer.clone());
+        // wallet.add_signer(signer);
 
         let msg = vec![1, 2, 3];
-        let result = wallet.propose_message(signer, msg.clone());
+        let result = wallet.propose_with_kind(signer, msg, ProposalKind::SignPayload, 0);
 
         assert_eq!(result.err(), Some(WalletError::InvalidSignature));
     }
 
     #[test]
-    fn test_propose_message_duplicate_message() {
+    fn test_propose_payload_duplicate_message() {
         let mut wallet = Wallet::default();
         let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
-        wallet.add_signer(signer.clone());
+        wallet.add_signer(signer);
 
         let msg = vec![1, 2, 3];
-        let _ = wallet.propose_message(signer.clone(), msg.clone());
+        let first = wallet.propose_with_kind(signer, msg.clone(), ProposalKind::SignPayload, 0).unwrap();
 
-        // Try proposing the same message again.
-        let result = wallet.propose_message(signer, msg);
+        // The same payload can be proposed again and gets its own ID.
+        let second = wallet.propose_with_kind(signer, msg.clone(), ProposalKind::SignPayload, 0).unwrap();
 
-        assert_eq!(result.err(), Some(WalletError::MsgAlreadyQueued));
+        assert_ne!(first, second);
+        assert_eq!(wallet.get_proposals(0).len(), 2);
+        assert_eq!(wallet.find_proposal_by_payload(&msg, 0), Some(first));
+    }
+
+    #[test]
+    fn test_proposal_ids_are_not_reused() {
+        let mut wallet = Wallet::default();
+        let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        wallet.add_signer(signer);
+
+        let first = wallet.propose_with_kind(signer, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
+        wallet.cancel(first, signer, 0).unwrap();
+        let second = wallet.propose_with_kind(signer, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
+
+        assert!(second > first);
     }
 
     #[test]
@@ -365,60 +1565,53 @@ mod tests {
         let mut wallet = Wallet::default();
 
         let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
-        wallet.add_signer(signer.clone());
+        wallet.add_signer(signer);
         let _ = wallet.set_default_threshold(1);
 
-        let msg = vec![1, 2, 3];
-        let _ = wallet.propose_message(signer.clone(), msg.clone());
+        let id = wallet.propose_with_kind(signer, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
 
         // Threshold is not met, so cannot sign.
-        let can_sign = wallet.can_sign(&msg.clone());
-
-        assert_eq!(can_sign, false);
+        assert!(!wallet.can_sign(id, 0));
+        assert_eq!(wallet.get_proposal(id).unwrap().status, ProposalStatus::Open);
     }
 
     #[test]
     fn test_can_sign_message_not_queued() {
         let mut wallet = Wallet::default();
         let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
-        wallet.add_signer(signer.clone());
-
-        let msg = vec![1, 2, 3];
+        wallet.add_signer(signer);
 
-        // Message is not in the queue, so cannot sign.
-        let can_sign = wallet.can_sign(&msg.clone());
-
-        assert_eq!(can_sign, false);
+        // Proposal does not exist, so cannot sign.
+        assert!(!wallet.can_sign(0, 0));
     }
 
     #[test]
     fn test_approve_valid_message() {
         let mut wallet = Wallet::default();
         let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
-        wallet.add_signer(signer.clone());
+        wallet.add_signer(signer);
         let _ = wallet.set_default_threshold(1);
 
-        let msg = vec![1, 2, 3];
-        wallet.propose_message(signer.clone(), msg.clone()).unwrap();
+        let id = wallet.propose_with_kind(signer, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
 
-        assert_eq!(wallet.can_sign(&msg), false);
-        let result = wallet.approve(msg.clone(), signer);
+        assert!(!wallet.can_sign(id, 0));
+        let result = wallet.approve(id, signer, 0);
 
-        assert!(result.is_ok());
-        assert_eq!(wallet.can_sign(&msg), true);
+        assert_eq!(result, Ok(1));
+        assert!(wallet.can_sign(id, 0));
+        assert_eq!(wallet.get_proposal(id).unwrap().status, ProposalStatus::Approved);
+        assert_eq!(wallet.get_proposal(id).unwrap().approvals, HashSet::from([signer]));
     }
 
     #[test]
     fn test_approve_invalid_message() {
         let mut wallet = Wallet::default();
         let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
-        wallet.add_signer(signer.clone());
-
-        let msg = vec![1, 2, 3];
+        wallet.add_signer(signer);
 
-        let result = wallet.approve(msg.clone(), signer);
+        let result = wallet.approve(0, signer, 0);
 
-        assert_eq!(result.err(), Some(WalletError::MsgNotQueued));
+        assert_eq!(result.err(), Some(WalletError::ProposalNotFound));
     }
 
     #[test]
@@ -427,86 +1620,84 @@ mod tests {
         let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
         let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
         let _ = wallet.set_default_threshold(1);
-        wallet.add_signer(signer1.clone());
+        wallet.add_signer(signer1);
 
-        let msg = vec![1, 2, 3];
-        wallet
-            .propose_message(signer1.clone(), msg.clone())
-            .unwrap();
+        let id = wallet.propose_with_kind(signer1, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
 
-        let result = wallet.approve(msg.clone(), signer2);
+        let result = wallet.approve(id, signer2, 0);
 
         assert_eq!(result.err(), Some(WalletError::InvalidSignature));
     }
 
     #[test]
-    fn test_has_signer() {
+    fn test_threshold_change_updates_status() {
         let mut wallet = Wallet::default();
         let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
         let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
-        wallet.add_signer(signer1.clone());
+        wallet.add_signer(signer1);
+        wallet.add_signer(signer2);
+        wallet.set_default_threshold(1).unwrap();
+
+        let id = wallet.propose_with_kind(signer1, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
+        wallet.approve(id, signer1, 0).unwrap();
+        assert!(wallet.can_sign(id, 0));
 
-        assert_eq!(wallet.has_signer(signer1.clone()), true);
-        assert_eq!(wallet.has_signer(signer2.clone()), false);
+        wallet.set_default_threshold(2).unwrap();
+        assert!(!wallet.can_sign(id, 0));
+        assert_eq!(wallet.get_proposal(id).unwrap().status, ProposalStatus::Open);
     }
 
     #[test]
-    fn test_get_messages() {
+    fn test_has_signer() {
         let mut wallet = Wallet::default();
         let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
         let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
-        wallet.add_signer(signer1.clone());
-        wallet.add_signer(signer2.clone());
-        let _ = wallet.set_default_threshold(1);
+        wallet.add_signer(signer1);
 
-        let msg1 = vec![1, 2, 3];
-        let msg2 = vec![4, 5, 6];
-        let msg3 = vec![7, 8, 9];
-        wallet
-            .propose_message(signer1.clone(), msg1.clone())
-            .unwrap();
-        wallet
-            .propose_message(signer2.clone(), msg2.clone())
-            .unwrap();
-        wallet
-            .propose_message(signer2.clone(), msg3.clone())
-            .unwrap();
-
-        wallet.approve(msg1.clone(), signer1).unwrap();
-        wallet.approve(msg2.clone(), signer2).unwrap();
-
-        let messages_to_sign = wallet.get_messages_to_sign();
+        assert!(wallet.has_signer(signer1));
+        assert!(!wallet.has_signer(signer2));
+    }
 
-        assert_eq!(messages_to_sign.len(), 2);
-        assert!(messages_to_sign.contains(&msg1));
-        assert!(messages_to_sign.contains(&msg2));
+    #[test]
+    fn test_get_proposals() {
+        let mut wallet = Wallet::default();
+        let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        wallet.add_signer(signer1);
+        wallet.add_signer(signer2);
+        let _ = wallet.set_default_threshold(1);
 
-        let proposed_messages = wallet.get_proposed_messages();
+        let id1 = wallet.propose_with_kind(signer1, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
+        let id2 = wallet.propose_with_kind(signer2, vec![4, 5, 6], ProposalKind::SignPayload, 0).unwrap();
+        let id3 = wallet.propose_with_kind(signer2, vec![7, 8, 9], ProposalKind::SignPayload, 0).unwrap();
 
-        assert_eq!(proposed_messages.len(), 3);
+        wallet.approve(id1, signer1, 0).unwrap();
+        wallet.approve(id2, signer2, 0).unwrap();
 
-        let all_messages_with_signers = wallet.get_messages_with_signers();
+        let to_sign: Vec<ProposalId> = wallet.get_proposals_to_sign(0).iter().map(|p| p.id).collect();
+        assert_eq!(to_sign, vec![id1, id2]);
 
-        assert_eq!(all_messages_with_signers.len(), 3);
-        assert!(all_messages_with_signers.contains(&(msg1.clone(), vec![signer1.clone()])));
-        assert!(all_messages_with_signers.contains(&(msg2.clone(), vec![signer2.clone()])));
-        assert!(all_messages_with_signers.contains(&(msg3.clone(), vec![])));
+        let proposals = wallet.get_proposals(0);
+        assert_eq!(proposals.len(), 3);
+        assert_eq!(proposals[0].approvals, HashSet::from([signer1]));
+        assert_eq!(proposals[1].approvals, HashSet::from([signer2]));
+        assert_eq!(proposals[2].id, id3);
+        assert!(proposals[2].approvals.is_empty());
     }
 
     #[test]
     fn test_add_metadata_valid() {
         let mut wallet = Wallet::default();
         let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
-        wallet.add_signer(signer.clone());
+        wallet.add_signer(signer);
 
-        let msg = vec![1, 2, 3];
-        let _ = wallet.propose_message(signer.clone(), msg.clone());
+        let id = wallet.propose_with_kind(signer, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
 
-        let result = wallet.add_metadata(msg.clone(), "metadata".to_string(), signer.clone());
+        let result = wallet.add_metadata(id, "metadata".to_string(), signer);
 
         assert!(result.is_ok());
         assert_eq!(
-            wallet.get_metadata(msg.clone(), signer),
+            wallet.get_metadata(id, signer),
             Some(&"metadata".to_string())
         );
     }
@@ -515,13 +1706,11 @@ mod tests {
     fn test_add_metadata_invalid_message() {
         let mut wallet = Wallet::default();
         let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
-        wallet.add_signer(signer.clone());
+        wallet.add_signer(signer);
 
-        let msg = vec![1, 2, 3];
-
-        let result = wallet.add_metadata(msg.clone(), "metadata".to_string(), signer.clone());
+        let result = wallet.add_metadata(0, "metadata".to_string(), signer);
 
-        assert_eq!(result.err(), Some("Cannot add metadata: Message not found.".to_string()));
+        assert_eq!(result.err(), Some(WalletError::ProposalNotFound));
     }
 
     #[test]
@@ -529,28 +1718,80 @@ mod tests {
         let mut wallet = Wallet::default();
         let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
         let invalid_signer = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
-        wallet.add_signer(signer.clone());
+        wallet.add_signer(signer);
 
-        let msg = vec![1, 2, 3];
-        let _ = wallet.propose_message(signer.clone(), msg.clone());
+        let id = wallet.propose_with_kind(signer, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
 
-        let result =
-            wallet.add_metadata(msg.clone(), "metadata".to_string(), invalid_signer.clone());
+        let result = wallet.add_metadata(id, "metadata".to_string(), invalid_signer);
 
-        assert_eq!(result.err(), Some("Cannot add metadata: No signer.".to_string()));
+        assert_eq!(result.err(), Some(WalletError::InvalidSignature));
+    }
+
+    #[test]
+    fn test_register_abi() {
+        let mut wallet = Wallet::default();
+        let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let invalid_signer = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        wallet.add_signer(signer);
+
+        let address = "0x6B175474E89094C44Da98b954EedeAC495271d0F".to_string();
+        let abi = ContractAbi::parse(address.clone(), "Vault".to_string(), "[]").unwrap();
+
+        assert_eq!(wallet.register_abi(abi.clone(), invalid_signer, 0), Err(WalletError::InvalidSignature));
+        let id = wallet.register_abi(abi.clone(), signer, 0).unwrap();
+        // the ABI is only registered once the proposal is executed
+        assert_eq!(wallet.get_proposal(id).unwrap().kind, ProposalKind::RegisterAbi(abi.clone()));
+        assert_eq!(wallet.get_abi(&address), None);
+        assert_eq!(wallet.execute_config_change(id, 0), Ok(()));
+        assert_eq!(wallet.get_abi(&address), Some(&abi));
+        assert_eq!(wallet.get_abi(&address.to_lowercase()), Some(&abi));
+        assert_eq!(wallet.get_abis(), vec![&abi]);
+
+        // a registered ABI is only replaced or removed by a proposal
+        let replacement = ContractAbi::parse(address.clone(), "Token".to_string(), "[]").unwrap();
+        assert_eq!(wallet.register_abi(replacement.clone(), signer, 0), Err(WalletError::AbiAlreadyRegistered));
+        assert_eq!(wallet.get_abi(&address), Some(&abi));
+    }
+
+    #[test]
+    fn test_abi_proposals() {
+        let mut wallet = Wallet::default();
+        let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        wallet.add_signer(signer);
+
+        let address = "0x6B175474E89094C44Da98b954EedeAC495271d0F".to_string();
+        let abi = ContractAbi::parse(address.clone(), "Vault".to_string(), "[]").unwrap();
+        let id = wallet.register_abi(abi, signer, 0).unwrap();
+        wallet.execute_config_change(id, 0).unwrap();
+
+        let replacement = ContractAbi::parse(address.clone(), "Token".to_string(), "[]").unwrap();
+        let kind = ProposalKind::RegisterAbi(replacement.clone());
+        assert!(kind.is_config_change());
+        let id = wallet.propose_with_kind(signer, Vec::new(), kind.clone(), 0).unwrap();
+        assert_eq!(wallet.get_abi(&address).map(|abi| abi.name.as_str()), Some("Vault"));
+        wallet.approve(id, signer, 0).unwrap();
+        assert_eq!(wallet.apply_config_change(&kind, 0), Ok(()));
+        assert_eq!(wallet.get_abi(&address), Some(&replacement));
+
+        let kind = ProposalKind::RemoveAbi(address.to_lowercase());
+        wallet.propose_with_kind(signer, Vec::new(), kind.clone(), 0).unwrap();
+        assert_eq!(wallet.apply_config_change(&kind, 0), Ok(()));
+        assert_eq!(wallet.get_abi(&address), None);
+
+        // removing an ABI that is not registered could never be executed
+        assert_eq!(wallet.propose_with_kind(signer, Vec::new(), kind, 0), Err(WalletError::AbiNotFound));
     }
 
     #[test]
     fn test_get_metadata_exists() {
         let mut wallet = Wallet::default();
         let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
-        wallet.add_signer(signer.clone());
+        wallet.add_signer(signer);
 
-        let msg = vec![1, 2, 3];
-        let _ = wallet.propose_message(signer.clone(), msg.clone());
-        let _ = wallet.add_metadata(msg.clone(), "metadata".to_string(), signer.clone());
+        let id = wallet.propose_with_kind(signer, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
+        let _ = wallet.add_metadata(id, "metadata".to_string(), signer);
 
-        let result = wallet.get_metadata(msg.clone(), signer);
+        let result = wallet.get_metadata(id, signer);
 
         assert_eq!(result, Some(&"metadata".to_string()));
     }
@@ -559,12 +1800,11 @@ mod tests {
     fn test_get_metadata_not_exists() {
         let mut wallet = Wallet::default();
         let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
-        wallet.add_signer(signer.clone());
+        wallet.add_signer(signer);
 
-        let msg = vec![1, 2, 3];
-        let _ = wallet.propose_message(signer.clone(), msg.clone());
+        let id = wallet.propose_with_kind(signer, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
 
-        let result = wallet.get_metadata(msg.clone(), signer);
+        let result = wallet.get_metadata(id, signer);
 
         assert_eq!(result, None);
     }
@@ -574,13 +1814,12 @@ mod tests {
         let mut wallet = Wallet::default();
         let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
         let invalid_signer = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
-        wallet.add_signer(signer.clone());
+        wallet.add_signer(signer);
 
-        let msg = vec![1, 2, 3];
-        let _ = wallet.propose_message(signer.clone(), msg.clone());
-        let _ = wallet.add_metadata(msg.clone(), "metadata".to_string(), signer.clone());
+        let id = wallet.propose_with_kind(signer, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
+        let _ = wallet.add_metadata(id, "metadata".to_string(), signer);
 
-        let result = wallet.get_metadata(msg.clone(), invalid_signer.clone());
+        let result = wallet.get_metadata(id, invalid_signer);
 
         assert_eq!(result, None);
     }
@@ -589,45 +1828,904 @@ mod tests {
     fn test_add_metadata_only_once() {
         let mut wallet = Wallet::default();
         let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
-        wallet.add_signer(signer.clone());
+        wallet.add_signer(signer);
 
-        let msg = vec![1, 2, 3];
-        let _ = wallet.propose_message(signer.clone(), msg.clone());
+        let id = wallet.propose_with_kind(signer, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
 
-        let result = wallet.add_metadata(msg.clone(), "metadata".to_string(), signer.clone());
+        let result = wallet.add_metadata(id, "metadata".to_string(), signer);
         assert!(result.is_ok());
         assert_eq!(
-            wallet.get_metadata(msg.clone(), signer),
+            wallet.get_metadata(id, signer),
             Some(&"metadata".to_string())
         );
 
-        // Try to add metadata again to the same message
-        let result = wallet.add_metadata(msg.clone(), "new metadata".to_string(), signer.clone());
+        // Try to add metadata again to the same proposal
+        let result = wallet.add_metadata(id, "new metadata".to_string(), signer);
+        assert_eq!(result.err(), Some(WalletError::MetadataAlreadyExists));
+    }
+
+    #[test]
+    fn test_mark_executed() {
+        let mut wallet = Wallet::default();
+        let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        wallet.add_signer(signer);
+        wallet.set_default_threshold(1).unwrap();
+
+        let id = wallet.propose_with_kind(signer, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
+        let _ = wallet.add_metadata(id, "metadata".to_string(), signer);
+        wallet.approve(id, signer, 0).unwrap();
+
+        assert_eq!(wallet.mark_executed(id, Some(vec![9; 65]), None, 5), Ok(()));
+
+        // the proposal is no longer pending, but kept with its signature
+        assert!(!wallet.can_sign(id, 5));
+        assert!(wallet.get_proposals(5).is_empty());
+        assert_eq!(wallet.find_proposal_by_payload(&[1, 2, 3], 5), None);
+        let proposal = wallet.get_proposal(id).unwrap();
+        assert_eq!(proposal.status, ProposalStatus::Executed);
+        assert_eq!(proposal.closed_at, Some(5));
+        assert_eq!(proposal.signature, Some(vec![9; 65]));
+        assert_eq!(proposal.signed_transaction, None);
+        assert_eq!(proposal.approvals, HashSet::from([signer]));
+        assert_eq!(wallet.get_metadata(id, signer), Some(&"metadata".to_string()));
+
+        // a proposal can only be executed once
+        assert_eq!(wallet.mark_executed(id, None, None, 6), Err(WalletError::ProposalNotPending));
+        assert_eq!(wallet.approve(id, signer, 6), Err(WalletError::ProposalNotPending));
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut wallet = Wallet::default();
+        let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        wallet.add_signer(signer1);
+        wallet.add_signer(signer2);
+        wallet.set_default_threshold(1).unwrap();
+
+        let id = wallet.propose_with_kind(signer1, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
+
+        assert_eq!(wallet.cancel(id, signer2, 1), Err(WalletError::NotProposer));
+        assert_eq!(wallet.cancel(id, signer1, 1), Ok(()));
+        assert_eq!(wallet.get_proposal(id).unwrap().status, ProposalStatus::Cancelled);
+        assert_eq!(wallet.cancel(id, signer1, 2), Err(WalletError::ProposalNotPending));
+        assert_eq!(wallet.approve(id, signer1, 2), Err(WalletError::ProposalNotPending));
+        assert_eq!(wallet.cancel(42, signer1, 2), Err(WalletError::ProposalNotFound));
+    }
+
+    #[test]
+    fn test_expire_proposals() {
+        let mut wallet = Wallet::default();
+        let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        wallet.add_signer(signer);
+        wallet.set_default_threshold(1).unwrap();
+
+        let id = wallet.propose_with_kind(signer, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
+        wallet.approve(id, signer, 0).unwrap();
+
+        assert!(wallet.can_sign(id, DEFAULT_PROPOSAL_TTL - 1));
+        assert!(!wallet.can_sign(id, DEFAULT_PROPOSAL_TTL));
+        assert!(wallet.get_proposals(DEFAULT_PROPOSAL_TTL).is_empty());
+
+        wallet.expire_proposals(DEFAULT_PROPOSAL_TTL);
+        let proposal = wallet.get_proposal(id).unwrap();
+        assert_eq!(proposal.status, ProposalStatus::Expired);
+        assert_eq!(proposal.closed_at, Some(DEFAULT_PROPOSAL_TTL));
         assert_eq!(
-            result.err(),
-            Some("Metadata already exists for this message".to_string())
+            wallet.mark_executed(id, None, None, DEFAULT_PROPOSAL_TTL),
+            Err(WalletError::ProposalNotPending)
         );
     }
 
     #[test]
-    fn test_remove_message_and_metadata() {
+    fn test_get_history() {
         let mut wallet = Wallet::default();
         let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
-        wallet.add_signer(signer.clone());
+        wallet.add_signer(signer);
+        wallet.set_default_threshold(1).unwrap();
+
+        let ids: Vec<ProposalId> = (0..5)
+            .map(|i| wallet.propose_with_kind(signer, vec![i], ProposalKind::SignPayload, 0).unwrap())
+            .collect();
+        wallet.approve(ids[0], signer, 0).unwrap();
+        wallet.mark_executed(ids[0], Some(vec![1; 65]), None, 1).unwrap();
+        wallet.cancel(ids[1], signer, 1).unwrap();
+        wallet.cancel(ids[3], signer, 1).unwrap();
+        wallet.cancel(ids[4], signer, 1).unwrap();
+
+        let page: Vec<ProposalId> = wallet.get_history(None, 2, 1).iter().map(|p| p.id).collect();
+        assert_eq!(page, vec![ids[4], ids[3]]);
+
+        // the pending proposal is skipped
+        let page: Vec<ProposalId> = wallet.get_history(Some(ids[3]), 2, 1).iter().map(|p| p.id).collect();
+        assert_eq!(page, vec![ids[1], ids[0]]);
+
+        assert!(wallet.get_history(Some(ids[0]), 2, 1).is_empty());
+
+        // once it expires it shows up as expired, without expire_proposals having run
+        let page = wallet.get_history(Some(ids[3]), 1, DEFAULT_PROPOSAL_TTL);
+        assert_eq!(page.iter().map(|p| p.id).collect::<Vec<_>>(), vec![ids[2]]);
+        assert!(wallet.get_proposal(ids[2]).unwrap().status.is_pending());
+        let item = ProposalHistoryItem::new(page[0], DEFAULT_PROPOSAL_TTL);
+        assert_eq!(item.status, ProposalStatus::Expired);
+        assert_eq!(item.closed_at, Some(DEFAULT_PROPOSAL_TTL));
+
+        let item = ProposalHistoryItem::new(wallet.get_proposal(ids[0]).unwrap(), DEFAULT_PROPOSAL_TTL);
+        assert_eq!(item.status, ProposalStatus::Executed);
+        assert_eq!(item.closed_at, Some(1));
+        assert_eq!(item.signature, Some(hex::encode([1; 65])));
+    }
 
-        let msg = vec![1, 2, 3];
-        let _ = wallet.propose_message(signer.clone(), msg.clone());
-        let _ = wallet.add_metadata(msg.clone(), "metadata".to_string(), signer.clone());
+    #[test]
+    fn test_prune_history() {
+        let mut wallet = Wallet::default();
+        let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        wallet.add_signer(signer);
+        wallet.set_default_threshold(1).unwrap();
+
+        let pending = wallet.propose_with_kind(signer, vec![0], ProposalKind::SignPayload, 0).unwrap();
+        for i in 0..MAX_RETAINED_HISTORY as u32 + 1 {
+            let id = wallet.propose_with_kind(signer, i.to_be_bytes().to_vec(), ProposalKind::SignPayload, 0).unwrap();
+            wallet.cancel(id, signer, 1).unwrap();
+        }
+        assert_eq!(wallet.get_history(None, usize::MAX, 1).len(), MAX_RETAINED_HISTORY + 1);
+
+        // queueing the next proposal drops the oldest finished proposal, but keeps the pending one
+        let id = wallet.propose_with_kind(signer, vec![1], ProposalKind::SignPayload, 1).unwrap();
+        let history = wallet.get_history(None, usize::MAX, 1);
+        assert_eq!(history.len(), MAX_RETAINED_HISTORY);
+        assert_eq!(history.last().unwrap().id, pending + 2);
+        assert!(wallet.get_proposal(pending + 1).is_none());
+        assert!(wallet.get_proposal(pending).unwrap().status.is_pending());
+        assert!(wallet.get_proposal(id).unwrap().status.is_pending());
+    }
+
+    #[test]
+    fn test_with_pending_proposals() {
+        let mut wallet = Wallet::default();
+        let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        wallet.add_signer(signer);
+        wallet.set_default_threshold(1).unwrap();
+
+        let cancelled = wallet.propose_with_kind(signer, vec![1], ProposalKind::SignPayload, 0).unwrap();
+        let pending = wallet.propose_with_kind(signer, vec![2], ProposalKind::SignPayload, 0).unwrap();
+        wallet.cancel(cancelled, signer, 1).unwrap();
+
+        let view = wallet.with_pending_proposals(1);
+        assert_eq!(view.proposals.keys().copied().collect::<Vec<_>>(), vec![pending]);
+        assert_eq!(view.get_signers(), wallet.get_signers());
+        assert_eq!(view.next_proposal_id, wallet.next_proposal_id);
+        assert!(wallet.with_pending_proposals(DEFAULT_PROPOSAL_TTL).proposals.is_empty());
+    }
+
+    #[test]
+    fn test_propose_with_kind() {
+        let mut wallet = Wallet::default();
+        let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let new_signer = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        wallet.add_signer(signer);
+
+        let payload = b"ADD_SIGNER::rrkah-fqaaa-aaaaa-aaaaq-cai".to_vec();
+        let payload_id = wallet.propose_with_kind(signer, payload.clone(), ProposalKind::SignPayload, 0).unwrap();
+        let add_id = wallet
+            .propose_with_kind(signer, Vec::new(), ProposalKind::AddSigner(new_signer), 0)
+            .unwrap();
+
+        // a payload that looks like an admin command is still just a payload
+        assert_eq!(wallet.get_proposal(payload_id).unwrap().kind, ProposalKind::SignPayload);
+        assert_eq!(wallet.get_proposal(add_id).unwrap().kind, ProposalKind::AddSigner(new_signer));
 
+        // configuration changes are not found by payload
+        assert_eq!(wallet.find_proposal_by_payload(&payload, 0), Some(payload_id));
+        assert_eq!(wallet.find_proposal_by_payload(&[], 0), None);
+    }
+
+    #[test]
+    fn test_propose_transaction() {
+        let mut wallet = Wallet::default();
+        let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        wallet.add_signer(signer);
+
+        let tx = EthTx1559 {
+            chain_id: 1,
+            nonce: 0,
+            to: None,
+            value: 0u64.into(),
+            data: "0x".to_string(),
+            gas_limit: 100_000,
+            max_fee: 2u64.into(),
+            max_priority_fee: 1u64.into(),
+            access_list: vec![],
+        };
+        let hash = tx.signing_hash().unwrap().to_vec();
+        let kind = ProposalKind::Eip1559Transaction(tx);
+        let id = wallet.propose_with_kind(signer, hash.clone(), kind.clone(), 0).unwrap();
+
+        assert!(!kind.is_config_change());
+        assert_eq!(wallet.get_proposal(id).unwrap().payload, hash);
+        assert!(wallet.can_sign(id, 0));
+        // transactions are not found by payload and are not configuration changes
+        assert_eq!(wallet.find_proposal_by_payload(&hash, 0), None);
+        assert_eq!(wallet.apply_config_change(&kind, 0), Err(WalletError::NotAConfigChange));
+    }
+
+    #[test]
+    fn test_nonce_tracking() {
+        let mut wallet = Wallet::default();
+        let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let invalid_signer = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        wallet.add_signer(signer);
+
+        let propose = |wallet: &mut Wallet, chain_id: u64| {
+            let nonce = wallet.next_nonce(DEFAULT_ACCOUNT, chain_id, 0);
+            let tx = EthTx1559 {
+                chain_id,
+                nonce,
+                to: None,
+                value: 0u64.into(),
+                data: "0x".to_string(),
+                gas_limit: 100_000,
+                max_fee: 2u64.into(),
+                max_priority_fee: 1u64.into(),
+                access_list: vec![],
+            };
+            let hash = tx.signing_hash().unwrap().to_vec();
+            wallet.propose_with_kind(signer, hash, ProposalKind::Eip1559Transaction(tx), 0).unwrap()
+        };
+
+        // pending proposals reserve consecutive nonces per chain
+        let first = propose(&mut wallet, 1);
+        let second = propose(&mut wallet, 1);
+        propose(&mut wallet, 5);
+        assert_eq!(wallet.get_proposal(second).unwrap().kind.eth_nonce(), Some((1, 1)));
+        assert_eq!(wallet.next_nonce(DEFAULT_ACCOUNT, 1, 0), 2);
+        assert_eq!(wallet.next_nonce(DEFAULT_ACCOUNT, 5, 0), 1);
+
+        // a cancelled proposal releases its nonce
+        wallet.cancel(first, signer, 0).unwrap();
+        assert_eq!(wallet.next_nonce(DEFAULT_ACCOUNT, 1, 0), 0);
+        let third = propose(&mut wallet, 1);
+        assert_eq!(wallet.get_proposal(third).unwrap().kind.eth_nonce(), Some((1, 0)));
+
+        // executing a transaction advances the account's nonce
+        wallet.mark_executed(second, None, None, 0).unwrap();
+        wallet.mark_executed(third, None, None, 0).unwrap();
+        assert_eq!(wallet.next_nonce(DEFAULT_ACCOUNT, 1, 0), 2);
+
+        // resyncing is a configuration change that takes effect once executed
+        let resync = ProposalKind::ResyncNonce(NonceResync { account: DEFAULT_ACCOUNT, chain_id: 1, nonce: 7 });
+        assert_eq!(wallet.propose_with_kind(invalid_signer, vec![], resync.clone(), 0), Err(WalletError::InvalidSignature));
+        let id = wallet.propose_with_kind(signer, vec![], resync, 0).unwrap();
+        assert_eq!(wallet.next_nonce(DEFAULT_ACCOUNT, 1, 0), 2);
+        assert_eq!(wallet.execute_config_change(id, 0), Ok(()));
+        assert_eq!(wallet.next_nonce(DEFAULT_ACCOUNT, 1, 0), 7);
+        // expired proposals release their nonces too
+        assert_eq!(wallet.next_nonce(DEFAULT_ACCOUNT, 5, DEFAULT_PROPOSAL_TTL), 0);
+    }
+
+    #[test]
+    fn test_public_key_cache() {
+        let mut wallet = Wallet::default();
+        let key_id = EcdsaKeyId { name: "test_key_1".to_string(), ..Default::default() };
+        let other_key_id = EcdsaKeyId { name: "key_1".to_string(), ..Default::default() };
+        let public_key = WalletPublicKey {
+            key_id: key_id.clone(),
+            compressed: vec![2; 33],
+            uncompressed: vec![4; 65],
+            eth_address: "0x0000000000000000000000000000000000000000".to_string(),
+        };
+
+        assert_eq!(wallet.get_public_key(DEFAULT_ACCOUNT, &key_id), None);
+        wallet.set_public_key(DEFAULT_ACCOUNT, public_key.clone());
+        assert_eq!(wallet.get_public_key(DEFAULT_ACCOUNT, &key_id), Some(&public_key));
+        // a key derived from another key ID is stale
+        assert_eq!(wallet.get_public_key(DEFAULT_ACCOUNT, &other_key_id), None);
+    }
+
+    #[test]
+    fn test_schnorr_public_key_cache() {
+        let mut wallet = Wallet::default();
+        let key_id = SchnorrKeyId { name: "test_key_1".to_string(), ..Default::default() };
+        let other_key_id = SchnorrKeyId { name: "key_1".to_string(), ..Default::default() };
+        let public_key = SchnorrPublicKey { key_id: key_id.clone(), public_key: vec![2; 33] };
+
+        assert_eq!(wallet.get_schnorr_public_key(DEFAULT_ACCOUNT, &key_id), None);
+        wallet.set_schnorr_public_key(DEFAULT_ACCOUNT, public_key.clone());
+        assert_eq!(wallet.get_schnorr_public_key(DEFAULT_ACCOUNT, &key_id), Some(&public_key));
+        assert_eq!(wallet.get_schnorr_public_key(DEFAULT_ACCOUNT, &other_key_id), None);
+
+        // a key derived for the same algorithm from another key ID replaces the stale one
+        let other_public_key = SchnorrPublicKey { key_id: other_key_id.clone(), public_key: vec![3; 33] };
+        wallet.set_schnorr_public_key(DEFAULT_ACCOUNT, other_public_key.clone());
+        assert_eq!(wallet.get_schnorr_public_key(DEFAULT_ACCOUNT, &key_id), None);
+        assert_eq!(wallet.get_schnorr_public_key(DEFAULT_ACCOUNT, &other_key_id), Some(&other_public_key));
+    }
+
+    #[test]
+    fn test_accounts() {
+        let mut wallet = Wallet::default();
+        let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let invalid_signer = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        wallet.add_signer(signer);
+
+        assert_eq!(wallet.get_accounts(), vec![AccountInfo { index: DEFAULT_ACCOUNT, name: "default".to_string() }]);
+        assert_eq!(wallet.add_account("ops".to_string(), signer), Ok(1));
+        assert_eq!(wallet.add_account("payroll".to_string(), signer), Ok(2));
+        assert_eq!(wallet.add_account("ops".to_string(), signer), Err(WalletError::AccountAlreadyExists));
+        assert_eq!(wallet.add_account(" ".to_string(), signer), Err(WalletError::InvalidAccountName));
+        assert_eq!(wallet.add_account("cold".to_string(), invalid_signer), Err(WalletError::InvalidSignature));
+        assert_eq!(wallet.get_accounts().len(), 3);
+
+        assert_eq!(wallet.resolve_account(&AccountRef::Name("payroll".to_string())), Ok(2));
+        assert_eq!(wallet.resolve_account(&AccountRef::Name("default".to_string())), Ok(DEFAULT_ACCOUNT));
+        assert_eq!(wallet.resolve_account(&AccountRef::Index(1)), Ok(1));
+        assert_eq!(wallet.resolve_account(&AccountRef::Index(3)), Err(WalletError::AccountNotFound));
+        assert_eq!(wallet.resolve_account(&AccountRef::Name("cold".to_string())), Err(WalletError::AccountNotFound));
+
+        let id = wallet.propose_for_account(signer, 1, vec![1; 32], ProposalKind::SignPayload, 0).unwrap();
+        assert_eq!(wallet.get_proposal(id).unwrap().signing_account(), 1);
+        assert_eq!(wallet.propose_for_account(signer, 3, vec![1; 32], ProposalKind::SignPayload, 0),
+                   Err(WalletError::AccountNotFound));
+        assert_eq!(wallet.propose_for_account(invalid_signer, 1, vec![1; 32], ProposalKind::SignPayload, 0),
+                   Err(WalletError::InvalidSignature));
+        assert_eq!(wallet.propose_for_account(signer, 1, vec![], ProposalKind::SetThreshold(1), 0),
+                   Err(WalletError::NotAConfigChange));
+
+        // configuration changes are not signed by any account
+        let id = wallet.propose_with_kind(signer, vec![], ProposalKind::SetThreshold(1), 0).unwrap();
+        assert_eq!(wallet.get_proposal(id).unwrap().account, None);
+    }
+
+    #[test]
+    fn test_account_nonces_and_keys() {
+        let mut wallet = Wallet::default();
+        let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        wallet.add_signer(signer);
+        let ops = wallet.add_account("ops".to_string(), signer).unwrap();
+
+        let tx = EthTx1559 {
+            chain_id: 1,
+            nonce: 0,
+            to: None,
+            value: 0u64.into(),
+            data: "0x".to_string(),
+            gas_limit: 100_000,
+            max_fee: 2u64.into(),
+            max_priority_fee: 1u64.into(),
+            access_list: vec![],
+        };
+        let hash = tx.signing_hash().unwrap().to_vec();
+        let id = wallet.propose_for_account(signer, ops, hash, ProposalKind::Eip1559Transaction(tx), 0).unwrap();
+
+        // every account has its own nonces
+        assert_eq!(wallet.next_nonce(ops, 1, 0), 1);
+        assert_eq!(wallet.next_nonce(DEFAULT_ACCOUNT, 1, 0), 0);
+        wallet.mark_executed(id, None, None, 0).unwrap();
+        assert_eq!(wallet.next_nonce(ops, 1, 0), 1);
+        assert_eq!(wallet.next_nonce(DEFAULT_ACCOUNT, 1, 0), 0);
+        assert_eq!(wallet.resync_nonce(ops, 1, 5), Ok(()));
+        assert_eq!(wallet.next_nonce(ops, 1, 0), 5);
+        assert_eq!(wallet.resync_nonce(2, 1, 5), Err(WalletError::AccountNotFound));
+        let resync = ProposalKind::ResyncNonce(NonceResync { account: 2, chain_id: 1, nonce: 5 });
+        assert_eq!(wallet.propose_with_kind(signer, vec![], resync, 0), Err(WalletError::AccountNotFound));
+
+        // and its own keys
+        let key_id = EcdsaKeyId { name: "test_key_1".to_string(), ..Default::default() };
+        let public_key = WalletPublicKey {
+            key_id: key_id.clone(),
+            compressed: vec![2; 33],
+            uncompressed: vec![4; 65],
+            eth_address: "0x0000000000000000000000000000000000000000".to_string(),
+        };
+        wallet.set_public_key(ops, public_key.clone());
+        assert_eq!(wallet.get_public_key(ops, &key_id), Some(&public_key));
+        assert_eq!(wallet.get_public_key(DEFAULT_ACCOUNT, &key_id), None);
+        wallet.set_public_key(2, public_key);
+        assert_eq!(wallet.get_public_key(2, &key_id), None);
+    }
+
+    #[test]
+    fn test_migrate_accounts() {
+        let key_id = EcdsaKeyId { name: "test_key_1".to_string(), ..Default::default() };
+        let public_key = WalletPublicKey {
+            key_id: key_id.clone(),
+            compressed: vec![2; 33],
+            uncompressed: vec![4; 65],
+            eth_address: "0x0000000000000000000000000000000000000000".to_string(),
+        };
+        let mut wallet = Wallet {
+            accounts: None,
+            nonces: Some(BTreeMap::from([(1, 3)])),
+            public_key: Some(public_key.clone()),
+            ..Wallet::default()
+        };
+
+        wallet.migrate_accounts();
+        assert_eq!(wallet.get_accounts(), vec![AccountInfo { index: DEFAULT_ACCOUNT, name: "default".to_string() }]);
+        assert_eq!(wallet.next_nonce(DEFAULT_ACCOUNT, 1, 0), 3);
+        assert_eq!(wallet.get_public_key(DEFAULT_ACCOUNT, &key_id), Some(&public_key));
+        assert_eq!((wallet.nonces.clone(), wallet.public_key.clone()), (None, None));
+
+        // migrating again keeps the accounts
+        let migrated = wallet.clone();
+        wallet.migrate_accounts();
+        assert_eq!(wallet, migrated);
+    }
+
+    #[test]
+    fn test_migrate_key() {
+        let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let old_key = EcdsaKeyId { name: "test_key_1".to_string(), ..Default::default() };
+        let new_key = EcdsaKeyId { name: "key_1".to_string(), ..Default::default() };
+        let public_key = |key_id: &EcdsaKeyId, byte: u8| WalletPublicKey {
+            key_id: key_id.clone(),
+            compressed: vec![byte; 33],
+            uncompressed: vec![byte; 65],
+            eth_address: format!("0x{}", hex::encode([byte; 20])),
+        };
+        let mut wallet = Wallet::with_key_id(old_key.clone());
+        wallet.add_signer(signer);
+        wallet.set_default_threshold(1).unwrap();
+        wallet.set_public_key(DEFAULT_ACCOUNT, public_key(&old_key, 1));
+        wallet.resync_nonce(DEFAULT_ACCOUNT, 1, 7).unwrap();
+        let payload_id = wallet.propose_with_kind(signer, vec![1; 32], ProposalKind::SignPayload, 0).unwrap();
+        let threshold_id = wallet.propose_with_kind(signer, vec![], ProposalKind::SetThreshold(1), 0).unwrap();
+
+        assert_eq!(wallet.propose_with_kind(signer, vec![], ProposalKind::MigrateKey(" ".to_string()), 0),
+                   Err(WalletError::InvalidKeyName));
+        assert_eq!(wallet.propose_with_kind(signer, vec![], ProposalKind::MigrateKey("test_key_1".to_string()), 0),
+                   Err(WalletError::KeyAlreadyInUse));
+        let id = wallet.propose_with_kind(signer, vec![], ProposalKind::MigrateKey("key_1".to_string()), 0).unwrap();
+        assert_eq!(wallet.get_proposal(id).unwrap().account, None);
+        assert_eq!(wallet.apply_config_change(&ProposalKind::MigrateKey("key_1".to_string()), 0),
+                   Err(WalletError::NotAConfigChange));
+
+        assert_eq!(wallet.migrate_key(public_key(&new_key, 2), 5), Ok(()));
+        assert_eq!(wallet.get_key_id(), Some(&new_key));
+        assert_eq!(wallet.get_public_key(DEFAULT_ACCOUNT, &new_key), Some(&public_key(&new_key, 2)));
+        assert_eq!(wallet.get_public_key(DEFAULT_ACCOUNT, &old_key), None);
+        assert_eq!(wallet.next_nonce(DEFAULT_ACCOUNT, 1, 0), 0);
+        assert_eq!(wallet.get_key_info(), Some(KeyInfo {
+            key_name: "key_1".to_string(),
+            migrations: vec![KeyMigration {
+                from_key: "test_key_1".to_string(),
+                to_key: "key_1".to_string(),
+                from_address: Some(public_key(&old_key, 1).eth_address),
+                to_address: public_key(&new_key, 2).eth_address,
+                migrated_at: 5,
+            }],
+        }));
+
+        // payloads proposed for the old key are never signed, configuration changes stay pending
+        assert_eq!(wallet.get_proposal(payload_id).unwrap().status, ProposalStatus::Rejected);
+        assert!(wallet.get_proposal(threshold_id).unwrap().status.is_pending());
+        assert!(wallet.get_proposal(id).unwrap().status.is_pending());
+
+        // the old key is retired
+        assert_eq!(wallet.propose_with_kind(signer, vec![], ProposalKind::MigrateKey("test_key_1".to_string()), 0),
+                   Err(WalletError::KeyRetired));
+        assert_eq!(wallet.migrate_key(public_key(&old_key, 3), 6), Err(WalletError::KeyRetired));
+        assert_eq!(wallet.get_key_id(), Some(&new_key));
+    }
+
+    #[test]
+    fn test_migrate_key_id() {
+        let cached_key = EcdsaKeyId { name: "test_key_1".to_string(), ..Default::default() };
+        let env_key = EcdsaKeyId { name: "key_1".to_string(), ..Default::default() };
+
+        let mut wallet = Wallet::default();
+        wallet.migrate_key_id(&env_key);
+        assert_eq!(wallet.get_key_id(), Some(&env_key));
+
+        // the key of a cached public key is the key the wallet has been signing with
+        let mut wallet = Wallet::default();
+        wallet.set_public_key(DEFAULT_ACCOUNT, WalletPublicKey {
+            key_id: cached_key.clone(),
+            compressed: vec![2; 33],
+            uncompressed: vec![4; 65],
+            eth_address: "0x0000000000000000000000000000000000000000".to_string(),
+        });
+        wallet.migrate_key_id(&env_key);
+        assert_eq!(wallet.get_key_id(), Some(&cached_key));
+        wallet.migrate_key_id(&env_key);
+        assert_eq!(wallet.get_key_id(), Some(&cached_key));
+    }
+
+    #[test]
+    fn test_apply_config_change() {
+        let mut wallet = Wallet::default();
+        let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        wallet.add_signer(signer1);
+
+        assert_eq!(wallet.apply_config_change(&ProposalKind::AddSigner(signer2), 0), Ok(()));
+        assert!(wallet.has_signer(signer2));
         assert_eq!(
-            wallet.get_metadata(msg.clone(), signer.clone()),
-            Some(&"metadata".to_string())
+            wallet.apply_config_change(&ProposalKind::AddSigner(signer2), 0),
+            Err(WalletError::SignerAlreadyExists)
         );
 
-        let result = wallet.remove_message_and_metadata(msg.clone(), signer.clone());
+        assert_eq!(wallet.apply_config_change(&ProposalKind::SetThreshold(2), 0), Ok(()));
+        assert_eq!(wallet.threshold, 2);
+        assert_eq!(
+            wallet.apply_config_change(&ProposalKind::SetThreshold(3), 0),
+            Err(WalletError::NotEnoughSigners)
+        );
 
-        assert!(result.is_ok());
-        assert_eq!(wallet.get_metadata(msg.clone(), signer), None);
-        assert!(!wallet.message_queue.contains_key(&msg));
+        assert_eq!(
+            wallet.apply_config_change(&ProposalKind::RemoveSigner(signer2), 0),
+            Err(WalletError::NotEnoughSigners)
+        );
+        assert_eq!(wallet.apply_config_change(&ProposalKind::SetThreshold(1), 0), Ok(()));
+        assert_eq!(wallet.apply_config_change(&ProposalKind::RemoveSigner(signer2), 0), Ok(()));
+        assert!(!wallet.has_signer(signer2));
+        assert_eq!(
+            wallet.apply_config_change(&ProposalKind::RemoveSigner(signer2), 0),
+            Err(WalletError::SignerNotFound)
+        );
+
+        assert_eq!(
+            wallet.apply_config_change(&ProposalKind::SignPayload, 0),
+            Err(WalletError::NotAConfigChange)
+        );
+    }
+
+    #[test]
+    fn test_reject() {
+        let mut wallet = Wallet::default();
+        let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        let signer3 = Principal::from_str("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+        wallet.add_signer(signer1);
+        wallet.add_signer(signer2);
+        wallet.add_signer(signer3);
+        wallet.set_default_threshold(2).unwrap();
+
+        let id = wallet.propose_with_kind(signer1, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
+
+        // two of three signers can still approve
+        assert_eq!(wallet.reject(id, signer2, 1), Ok(1));
+        assert_eq!(wallet.reject(id, signer2, 1), Err(WalletError::AlreadyRejected));
+        assert_eq!(wallet.get_proposal(id).unwrap().status, ProposalStatus::Open);
+        assert_eq!(wallet.get_proposal(id).unwrap().rejections, HashSet::from([signer2]));
+
+        // an approval turned into a rejection no longer counts
+        wallet.approve(id, signer3, 1).unwrap();
+        assert_eq!(wallet.reject(id, signer3, 2), Ok(2));
+
+        let proposal = wallet.get_proposal(id).unwrap();
+        assert_eq!(proposal.status, ProposalStatus::Rejected);
+        assert_eq!(proposal.closed_at, Some(2));
+        assert!(proposal.approvals.is_empty());
+        assert_eq!(wallet.approve(id, signer1, 3), Err(WalletError::ProposalNotPending));
+        assert_eq!(wallet.reject(id, signer1, 3), Err(WalletError::ProposalNotPending));
+    }
+
+    #[test]
+    fn test_reject_not_signer() {
+        let mut wallet = Wallet::default();
+        let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        wallet.add_signer(signer1);
+
+        let id = wallet.propose_with_kind(signer1, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
+
+        assert_eq!(wallet.reject(id, signer2, 0), Err(WalletError::InvalidSignature));
+        assert_eq!(wallet.reject(42, signer1, 0), Err(WalletError::ProposalNotFound));
+    }
+
+    #[test]
+    fn test_approve_after_reject() {
+        let mut wallet = Wallet::default();
+        let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        wallet.add_signer(signer1);
+        wallet.add_signer(signer2);
+        wallet.set_default_threshold(1).unwrap();
+
+        let id = wallet.propose_with_kind(signer1, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
+        wallet.reject(id, signer2, 0).unwrap();
+        wallet.approve(id, signer2, 0).unwrap();
+
+        let proposal = wallet.get_proposal(id).unwrap();
+        assert!(proposal.rejections.is_empty());
+        assert_eq!(proposal.approvals, HashSet::from([signer2]));
+        assert!(wallet.can_sign(id, 0));
+    }
+
+    #[test]
+    fn test_execute_config_change_rejected_by_a_signer() {
+        let mut wallet = Wallet::default();
+        let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        let signer3 = Principal::from_str("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+        wallet.add_signer(signer1);
+        wallet.add_signer(signer2);
+        wallet.add_signer(signer3);
+        wallet.set_default_threshold(1).unwrap();
+
+        let id = wallet.propose_with_kind(signer1, Vec::new(), ProposalKind::SetThreshold(3), 0).unwrap();
+        wallet.approve(id, signer1, 0).unwrap();
+        wallet.reject(id, signer2, 0).unwrap();
+        let other = wallet.propose_with_kind(signer1, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
+        wallet.reject(other, signer3, 0).unwrap();
+
+        // the higher threshold makes the rejected proposals unreachable, but not the one being executed
+        assert_eq!(wallet.execute_config_change(id, 1), Ok(()));
+        assert_eq!(wallet.threshold, 3);
+        let proposal = wallet.get_proposal(id).unwrap();
+        assert_eq!(proposal.status, ProposalStatus::Executed);
+        assert_eq!(proposal.closed_at, Some(1));
+        assert_eq!(wallet.get_proposal(other).unwrap().status, ProposalStatus::Rejected);
+        assert_eq!(wallet.execute_config_change(id, 2), Err(WalletError::ProposalNotPending));
+    }
+
+    #[test]
+    fn test_execute_config_change_failure_keeps_proposal_pending() {
+        let mut wallet = Wallet::default();
+        let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        wallet.add_signer(signer);
+        wallet.set_default_threshold(1).unwrap();
+
+        let id = wallet.propose_with_kind(signer, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
+        wallet.approve(id, signer, 0).unwrap();
+
+        assert_eq!(wallet.execute_config_change(id, 1), Err(WalletError::NotAConfigChange));
+        let proposal = wallet.get_proposal(id).unwrap();
+        assert_eq!(proposal.status, ProposalStatus::Approved);
+        assert_eq!(proposal.closed_at, None);
+        assert_eq!(wallet.execute_config_change(42, 1), Err(WalletError::ProposalNotFound));
+    }
+
+    #[test]
+    fn test_threshold_change_rejects_unreachable() {
+        let mut wallet = Wallet::default();
+        let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        wallet.add_signer(signer1);
+        wallet.add_signer(signer2);
+        wallet.set_default_threshold(1).unwrap();
+
+        let id = wallet.propose_with_kind(signer1, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
+        wallet.reject(id, signer2, 0).unwrap();
+        assert_eq!(wallet.get_proposal(id).unwrap().status, ProposalStatus::Open);
+
+        wallet.apply_config_change(&ProposalKind::SetThreshold(2), 1).unwrap();
+
+        let proposal = wallet.get_proposal(id).unwrap();
+        assert_eq!(proposal.status, ProposalStatus::Rejected);
+        assert_eq!(proposal.closed_at, Some(1));
+    }
+
+    #[test]
+    fn test_repeated_approval_does_not_meet_threshold() {
+        let mut wallet = Wallet::default();
+        let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        let signer3 = Principal::from_str("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+        wallet.add_signer(signer1);
+        wallet.add_signer(signer2);
+        wallet.add_signer(signer3);
+        wallet.set_default_threshold(3).unwrap();
+
+        let id = wallet.propose_with_kind(signer1, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
+
+        // a single signer approving three times must not satisfy a threshold of three
+        assert_eq!(wallet.approve(id, signer1, 0), Ok(1));
+        assert_eq!(wallet.approve(id, signer1, 0), Err(WalletError::AlreadyApproved));
+        assert_eq!(wallet.approve(id, signer1, 0), Err(WalletError::AlreadyApproved));
+
+        assert!(!wallet.can_sign(id, 0));
+        assert_eq!(wallet.get_proposal(id).unwrap().approvals.len(), 1);
+        assert_eq!(wallet.get_proposal(id).unwrap().status, ProposalStatus::Open);
+
+        assert_eq!(wallet.approve(id, signer2, 0), Ok(2));
+        assert_eq!(wallet.approve(id, signer3, 0), Ok(3));
+        assert!(wallet.can_sign(id, 0));
+    }
+
+    #[test]
+    fn test_revoke_approval() {
+        let mut wallet = Wallet::default();
+        let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        wallet.add_signer(signer1);
+        wallet.add_signer(signer2);
+        wallet.set_default_threshold(2).unwrap();
+
+        let id = wallet.propose_with_kind(signer1, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
+        wallet.approve(id, signer1, 0).unwrap();
+        wallet.approve(id, signer2, 0).unwrap();
+        assert!(wallet.can_sign(id, 0));
+
+        assert_eq!(wallet.revoke_approval(id, signer2, 1), Ok(1));
+        assert!(!wallet.can_sign(id, 1));
+        assert_eq!(wallet.get_proposal(id).unwrap().status, ProposalStatus::Open);
+        assert_eq!(wallet.get_proposal(id).unwrap().approvals, HashSet::from([signer1]));
+        assert_eq!(wallet.revoke_approval(id, signer2, 1), Err(WalletError::NotApproved));
+
+        // the approval can be given again after revoking it
+        assert_eq!(wallet.approve(id, signer2, 2), Ok(2));
+        assert!(wallet.can_sign(id, 2));
+    }
+
+    #[test]
+    fn test_revoke_approval_after_execution() {
+        let mut wallet = Wallet::default();
+        let signer = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        wallet.add_signer(signer);
+        wallet.set_default_threshold(1).unwrap();
+
+        let id = wallet.propose_with_kind(signer, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
+        wallet.approve(id, signer, 0).unwrap();
+        wallet.mark_executed(id, None, None, 1).unwrap();
+
+        assert_eq!(wallet.revoke_approval(id, signer, 2), Err(WalletError::ProposalNotPending));
+        assert_eq!(wallet.revoke_approval(42, signer, 2), Err(WalletError::ProposalNotFound));
+    }
+
+    #[test]
+    fn test_removed_signer_approval_does_not_count() {
+        let mut wallet = Wallet::default();
+        let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        let signer3 = Principal::from_str("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+        wallet.add_signer(signer1);
+        wallet.add_signer(signer2);
+        wallet.add_signer(signer3);
+        wallet.set_default_threshold(2).unwrap();
+
+        let id = wallet.propose_with_kind(signer1, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
+        wallet.approve(id, signer3, 0).unwrap();
+
+        wallet.apply_config_change(&ProposalKind::RemoveSigner(signer3), 1).unwrap();
+
+        // the removed signer's approval is gone, one more approval alone is not enough
+        assert!(!wallet.get_proposal(id).unwrap().approvals.contains(&signer3));
+        assert_eq!(wallet.approve(id, signer1, 1), Ok(1));
+        assert!(!wallet.can_sign(id, 1));
+
+        // a re-added signer has to approve again
+        wallet.apply_config_change(&ProposalKind::AddSigner(signer3), 2).unwrap();
+        assert!(!wallet.can_sign(id, 2));
+        assert_eq!(wallet.approve(id, signer3, 2), Ok(2));
+        assert!(wallet.can_sign(id, 2));
+    }
+
+    #[test]
+    fn test_removed_signer_cannot_push_proposal_over_threshold() {
+        let mut wallet = Wallet::default();
+        let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        let signer3 = Principal::from_str("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+        wallet.add_signer(signer1);
+        wallet.add_signer(signer2);
+        wallet.add_signer(signer3);
+        wallet.set_default_threshold(2).unwrap();
+
+        let id = wallet.propose_with_kind(signer1, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
+        wallet.approve(id, signer1, 0).unwrap();
+        wallet.approve(id, signer3, 0).unwrap();
+        assert!(wallet.can_sign(id, 0));
+
+        // removing an approver drops the proposal back below the threshold
+        wallet.apply_config_change(&ProposalKind::RemoveSigner(signer3), 1).unwrap();
+        assert!(!wallet.can_sign(id, 1));
+        assert_eq!(wallet.get_proposal(id).unwrap().status, ProposalStatus::Open);
+
+        // and the removed signer can no longer vote
+        assert_eq!(wallet.approve(id, signer3, 1), Err(WalletError::InvalidSignature));
+        assert!(!wallet.can_sign(id, 1));
+    }
+
+    #[test]
+    fn test_stale_approvals_are_ignored() {
+        let mut wallet = Wallet::default();
+        let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        let former = Principal::from_str("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+        wallet.add_signer(signer1);
+        wallet.add_signer(signer2);
+        wallet.set_default_threshold(2).unwrap();
+
+        let id = wallet.propose_with_kind(signer1, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
+        // an approval recorded for a principal that is not a signer, e.g. restored from an older state
+        wallet.proposals.get_mut(&id).unwrap().approvals.insert(former);
+
+        assert_eq!(wallet.approve(id, signer1, 0), Ok(1));
+        assert!(!wallet.can_sign(id, 0));
+    }
+
+    #[test]
+    fn test_threshold_change_reevaluates_live_approvals() {
+        let mut wallet = Wallet::default();
+        let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        let signer3 = Principal::from_str("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+        wallet.add_signer(signer1);
+        wallet.add_signer(signer2);
+        wallet.add_signer(signer3);
+        wallet.set_default_threshold(3).unwrap();
+
+        let id = wallet.propose_with_kind(signer1, vec![1, 2, 3], ProposalKind::SignPayload, 0).unwrap();
+        wallet.approve(id, signer1, 0).unwrap();
+        wallet.approve(id, signer2, 0).unwrap();
+        assert!(!wallet.can_sign(id, 0));
+
+        wallet.apply_config_change(&ProposalKind::SetThreshold(2), 1).unwrap();
+        assert!(wallet.can_sign(id, 1));
+
+        wallet.apply_config_change(&ProposalKind::RemoveSigner(signer2), 2).unwrap();
+        assert!(!wallet.can_sign(id, 2));
+        assert_eq!(wallet.get_proposal(id).unwrap().status, ProposalStatus::Open);
+    }
+
+    #[test]
+    fn test_remove_signer_below_threshold() {
+        let mut wallet = Wallet::default();
+        let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        wallet.add_signer(signer1);
+        wallet.add_signer(signer2);
+        wallet.set_default_threshold(2).unwrap();
+
+        assert_eq!(wallet.remove_signer(signer2), Err(WalletError::NotEnoughSigners));
+        assert!(wallet.has_signer(signer2));
+        assert_eq!(wallet.remove_signer(Principal::anonymous()), Err(WalletError::SignerNotFound));
+
+        // the last signer can never be removed
+        wallet.set_default_threshold(1).unwrap();
+        assert_eq!(wallet.remove_signer(signer2), Ok(()));
+        assert_eq!(wallet.remove_signer(signer1), Err(WalletError::NotEnoughSigners));
+    }
+
+    #[test]
+    fn test_propose_invalid_config_change() {
+        let mut wallet = Wallet::default();
+        let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        wallet.add_signer(signer1);
+        wallet.add_signer(signer2);
+        wallet.set_default_threshold(2).unwrap();
+
+        assert_eq!(
+            wallet.propose_with_kind(signer1, Vec::new(), ProposalKind::RemoveSigner(signer2), 0),
+            Err(WalletError::NotEnoughSigners)
+        );
+        assert_eq!(
+            wallet.propose_with_kind(signer1, Vec::new(), ProposalKind::AddSigner(signer2), 0),
+            Err(WalletError::SignerAlreadyExists)
+        );
+        assert_eq!(
+            wallet.propose_with_kind(signer1, Vec::new(), ProposalKind::SetThreshold(0), 0),
+            Err(WalletError::InvalidThreshold)
+        );
+        assert_eq!(
+            wallet.propose_with_kind(signer1, Vec::new(), ProposalKind::SetThreshold(3), 0),
+            Err(WalletError::NotEnoughSigners)
+        );
+        assert!(wallet.get_proposals(0).is_empty());
+    }
+
+    #[test]
+    fn test_conflicting_removals_cannot_brick_wallet() {
+        let mut wallet = Wallet::default();
+        let signer1 = Principal::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let signer2 = Principal::from_str("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        let signer3 = Principal::from_str("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+        wallet.add_signer(signer1);
+        wallet.add_signer(signer2);
+        wallet.add_signer(signer3);
+        wallet.set_default_threshold(2).unwrap();
+
+        // each removal is fine on its own, but not both together
+        let remove2 = ProposalKind::RemoveSigner(signer2);
+        let remove3 = ProposalKind::RemoveSigner(signer3);
+        wallet.propose_with_kind(signer1, Vec::new(), remove2.clone(), 0).unwrap();
+        wallet.propose_with_kind(signer1, Vec::new(), remove3.clone(), 0).unwrap();
+
+        assert_eq!(wallet.apply_config_change(&remove2, 1), Ok(()));
+        assert_eq!(wallet.apply_config_change(&remove3, 1), Err(WalletError::NotEnoughSigners));
+        assert_eq!(wallet.get_signers().len(), 2);
     }
 }